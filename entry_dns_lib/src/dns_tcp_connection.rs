@@ -0,0 +1,520 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use forwarding_pool::ForwardingPool;
+use forwarding_pool::PendingResponse;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::logger::Logger;
+use sub_lib::tokio_wrappers::ReadHalfWrapper;
+use sub_lib::tokio_wrappers::WriteHalfWrapper;
+use tokio::prelude::Async;
+use tokio::prelude::Future;
+
+const MIN_MESSAGE_LENGTH: usize = 12;
+pub const TCP_IDLE_TIMEOUT_MS: u64 = 10000;
+
+// Over TCP, each DNS message is preceded by its length as a two-byte big-endian integer (RFC 1035 §4.2.2).
+// A client may send several requests on one connection, and may close its end once it has sent them.
+// Responses go back in the order the requests came in, even if an earlier one is waiting on an upstream server.
+// A connection that moves no data in either direction for TCP_IDLE_TIMEOUT_MS is closed, so that idle clients
+// can't hold on to connection slots.
+pub struct DnsTcpConnection {
+    reader: Box<ReadHalfWrapper>,
+    writer: Box<WriteHalfWrapper>,
+    peer_addr: SocketAddr,
+    incoming: Vec<u8>,
+    responses: VecDeque<PendingResponse>,
+    outgoing: Vec<u8>,
+    read_closed: bool,
+    last_activity: Instant,
+}
+
+impl DnsTcpConnection {
+    pub fn new(
+        reader: Box<ReadHalfWrapper>,
+        writer: Box<WriteHalfWrapper>,
+        peer_addr: SocketAddr,
+        now: Instant,
+    ) -> DnsTcpConnection {
+        DnsTcpConnection {
+            reader,
+            writer,
+            peer_addr,
+            incoming: vec![],
+            responses: VecDeque::new(),
+            outgoing: vec![],
+            read_closed: false,
+            last_activity: now,
+        }
+    }
+
+    pub fn idle_deadline(&self) -> Instant {
+        self.last_activity + Duration::from_millis(TCP_IDLE_TIMEOUT_MS)
+    }
+
+    // Returns false once the connection is finished and should be dropped
+    pub fn poll(
+        &mut self,
+        forwarding_pool: &ForwardingPool,
+        now: Instant,
+        logger: &Logger,
+    ) -> bool {
+        let mut chunk: [u8; 4096] = [0; 4096];
+        loop {
+            self.collect_responses();
+            if !self.flush(now, logger) {
+                return false;
+            }
+            if !self.outgoing.is_empty() || !self.responses.is_empty() {
+                // Don't read more requests until the client has taken the responses we have for it
+                return self.check_idle(now, logger);
+            }
+            if self.read_closed {
+                return false;
+            }
+            match self.reader.poll_read(&mut chunk) {
+                Ok(Async::NotReady) => return self.check_idle(now, logger),
+                Ok(Async::Ready(0)) => self.read_closed = true,
+                Ok(Async::Ready(length)) => {
+                    self.last_activity = now;
+                    self.incoming.extend_from_slice(&chunk[0..length]);
+                    if !self.process_messages(forwarding_pool, logger) {
+                        return false;
+                    }
+                }
+                Err(e) => {
+                    logger.warning(format!(
+                        "Error reading DNS request from {}: {}",
+                        self.peer_addr, e
+                    ));
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn process_messages(&mut self, forwarding_pool: &ForwardingPool, logger: &Logger) -> bool {
+        while self.incoming.len() >= 2 {
+            let length = ((self.incoming[0] as usize) << 8) | (self.incoming[1] as usize);
+            if length < MIN_MESSAGE_LENGTH {
+                logger.warning(format!(
+                    "Closing DNS connection from {}: {}-byte request is too short",
+                    self.peer_addr, length
+                ));
+                return false;
+            }
+            if self.incoming.len() < length + 2 {
+                break;
+            }
+            let request = Vec::from(&self.incoming[2..(length + 2)]);
+            self.incoming.drain(0..(length + 2));
+            self.responses
+                .push_back(forwarding_pool.process(request, self.peer_addr, logger));
+        }
+        true
+    }
+
+    fn collect_responses(&mut self) {
+        while let Some(pending_response) = self.responses.pop_front() {
+            let response = match pending_response {
+                PendingResponse::Ready(response) => response,
+                PendingResponse::Waiting(mut response_rx) => match response_rx.poll() {
+                    Ok(Async::Ready(response)) => response,
+                    Ok(Async::NotReady) => {
+                        self.responses
+                            .push_front(PendingResponse::Waiting(response_rx));
+                        return;
+                    }
+                    // The worker thread is gone, so this request will never be answered
+                    Err(_) => continue,
+                },
+            };
+            self.outgoing.push((response.len() >> 8) as u8);
+            self.outgoing.push((response.len() & 0xFF) as u8);
+            self.outgoing.extend_from_slice(&response);
+        }
+    }
+
+    fn check_idle(&self, now: Instant, logger: &Logger) -> bool {
+        if now < self.idle_deadline() {
+            return true;
+        }
+        logger.warning(format!(
+            "Closing DNS connection from {}: idle for {}ms",
+            self.peer_addr, TCP_IDLE_TIMEOUT_MS
+        ));
+        false
+    }
+
+    fn flush(&mut self, now: Instant, logger: &Logger) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.writer.poll_write(&self.outgoing) {
+                Ok(Async::NotReady) => return true,
+                Ok(Async::Ready(0)) => {
+                    logger.warning(format!(
+                        "Could not send DNS response to {}: connection closed",
+                        self.peer_addr
+                    ));
+                    return false;
+                }
+                Ok(Async::Ready(length)) => {
+                    self.last_activity = now;
+                    self.outgoing.drain(0..length);
+                }
+                Err(e) => {
+                    logger.warning(format!(
+                        "Could not send DNS response to {}: {}",
+                        self.peer_addr, e
+                    ));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use futures::sync::oneshot;
+    use processor::ProcessorTrait;
+    use std::io;
+    use std::io::ErrorKind;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use test_utils::logging::init_test_logging;
+    use test_utils::logging::TestLogHandler;
+    use test_utils::tokio_wrapper_mocks::ReadHalfWrapperMock;
+    use test_utils::tokio_wrapper_mocks::WriteHalfWrapperMock;
+
+    // Responds to each request with the request followed by a byte containing its length
+    struct ProcessorMock {
+        process_params: Arc<Mutex<Vec<(Vec<u8>, SocketAddr)>>>,
+    }
+
+    impl ProcessorTrait for ProcessorMock {
+        fn process(
+            &self,
+            buf: &mut [u8],
+            length: usize,
+            addr: &SocketAddr,
+            _logger: &Logger,
+        ) -> usize {
+            self.process_params
+                .lock()
+                .unwrap()
+                .push((Vec::from(&buf[0..length]), *addr));
+            buf[length] = length as u8;
+            length + 1
+        }
+
+        fn needs_upstream(&self, _request: &[u8]) -> bool {
+            false
+        }
+    }
+
+    impl ProcessorMock {
+        fn new() -> ProcessorMock {
+            ProcessorMock {
+                process_params: Arc::new(Mutex::new(vec![])),
+            }
+        }
+    }
+
+    fn make_pool(processor: ProcessorMock) -> ForwardingPool {
+        ForwardingPool::new(Arc::new(processor), 0, 1)
+    }
+
+    fn message(fill: u8, length: usize) -> Vec<u8> {
+        let mut result = vec![(length >> 8) as u8, (length & 0xFF) as u8];
+        result.extend(vec![fill; length]);
+        result
+    }
+
+    fn response(fill: u8, length: usize) -> Vec<u8> {
+        let mut result = message(fill, length + 1);
+        let last = result.len() - 1;
+        result[last] = length as u8;
+        result
+    }
+
+    #[test]
+    fn handles_several_requests_split_across_reads() {
+        let peer_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let mut data = message(0x11, 12);
+        data.extend(message(0x22, 20));
+        let write_params_arc = Arc::new(Mutex::new(vec![]));
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_ok(Vec::from(&data[0..1]))
+            .poll_read_ok(Vec::from(&data[1..20]))
+            .poll_read_ok(Vec::from(&data[20..]))
+            .poll_read_result(vec![], Ok(Async::NotReady));
+        let writer = WriteHalfWrapperMock::new()
+            .poll_write_params(&write_params_arc)
+            .poll_write_ok(15)
+            .poll_write_ok(23);
+        let processor = ProcessorMock::new();
+        let process_params_arc = processor.process_params.clone();
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            peer_addr,
+            Instant::now(),
+        );
+
+        let result = subject.poll(&make_pool(processor), Instant::now(), &Logger::new("test"));
+
+        assert_eq!(result, true);
+        assert_eq!(
+            *process_params_arc.lock().unwrap(),
+            vec![(vec![0x11; 12], peer_addr), (vec![0x22; 20], peer_addr)]
+        );
+        assert_eq!(
+            *write_params_arc.lock().unwrap(),
+            vec![response(0x11, 12), response(0x22, 20)]
+        );
+    }
+
+    #[test]
+    fn stops_reading_until_pending_responses_are_written() {
+        let peer_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let write_params_arc = Arc::new(Mutex::new(vec![]));
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_ok(message(0x33, 12))
+            .poll_read_result(vec![], Ok(Async::NotReady));
+        let writer = WriteHalfWrapperMock::new()
+            .poll_write_params(&write_params_arc)
+            .poll_write_ok(5)
+            .poll_write_result(Ok(Async::NotReady))
+            .poll_write_ok(10);
+        let forwarding_pool = make_pool(ProcessorMock::new());
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            peer_addr,
+            Instant::now(),
+        );
+
+        let first_result = subject.poll(&forwarding_pool, Instant::now(), &Logger::new("test"));
+        let second_result = subject.poll(&forwarding_pool, Instant::now(), &Logger::new("test"));
+
+        assert_eq!(first_result, true);
+        assert_eq!(second_result, true);
+        let expected = response(0x33, 12);
+        assert_eq!(
+            *write_params_arc.lock().unwrap(),
+            vec![
+                expected.clone(),
+                Vec::from(&expected[5..]),
+                Vec::from(&expected[5..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn closes_connection_when_client_closes_without_pending_responses() {
+        let reader = ReadHalfWrapperMock::new().poll_read_ok(vec![]);
+        let writer = WriteHalfWrapperMock::new();
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            Instant::now(),
+        );
+
+        let result = subject.poll(
+            &make_pool(ProcessorMock::new()),
+            Instant::now(),
+            &Logger::new("test"),
+        );
+
+        assert_eq!(result, false);
+    }
+
+    #[test]
+    fn closes_connection_on_request_too_short_to_be_dns() {
+        init_test_logging();
+        let reader = ReadHalfWrapperMock::new().poll_read_ok(message(0x44, 11));
+        let writer = WriteHalfWrapperMock::new();
+        let processor = ProcessorMock::new();
+        let process_params_arc = processor.process_params.clone();
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            Instant::now(),
+        );
+
+        let result = subject.poll(
+            &make_pool(processor),
+            Instant::now(),
+            &Logger::new("closes_connection_on_request_too_short_to_be_dns"),
+        );
+
+        assert_eq!(result, false);
+        assert_eq!(process_params_arc.lock().unwrap().len(), 0);
+        TestLogHandler::new().exists_log_containing("WARN: closes_connection_on_request_too_short_to_be_dns: Closing DNS connection from 1.2.3.4:5678: 11-byte request is too short");
+    }
+
+    #[test]
+    fn closes_connection_on_read_error() {
+        init_test_logging();
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_result(vec![], Err(io::Error::from(ErrorKind::ConnectionReset)));
+        let writer = WriteHalfWrapperMock::new();
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            Instant::now(),
+        );
+
+        let result = subject.poll(
+            &make_pool(ProcessorMock::new()),
+            Instant::now(),
+            &Logger::new("closes_connection_on_read_error"),
+        );
+
+        assert_eq!(result, false);
+        TestLogHandler::new().exists_log_containing(
+            "WARN: closes_connection_on_read_error: Error reading DNS request from 1.2.3.4:5678: ",
+        );
+    }
+
+    #[test]
+    fn closes_connection_on_write_error() {
+        init_test_logging();
+        let reader = ReadHalfWrapperMock::new().poll_read_ok(message(0x55, 12));
+        let writer = WriteHalfWrapperMock::new()
+            .poll_write_result(Err(io::Error::from(ErrorKind::BrokenPipe)));
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            Instant::now(),
+        );
+
+        let result = subject.poll(
+            &make_pool(ProcessorMock::new()),
+            Instant::now(),
+            &Logger::new("closes_connection_on_write_error"),
+        );
+
+        assert_eq!(result, false);
+        TestLogHandler::new().exists_log_containing(
+            "WARN: closes_connection_on_write_error: Could not send DNS response to 1.2.3.4:5678: ",
+        );
+    }
+
+    #[test]
+    fn holds_later_responses_until_a_forwarded_one_arrives() {
+        let peer_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let write_params_arc = Arc::new(Mutex::new(vec![]));
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_ok(message(0x77, 12))
+            .poll_read_result(vec![], Ok(Async::NotReady));
+        let writer = WriteHalfWrapperMock::new()
+            .poll_write_params(&write_params_arc)
+            .poll_write_ok(15)
+            .poll_write_ok(15);
+        let forwarding_pool = make_pool(ProcessorMock::new());
+        let (response_tx, response_rx) = oneshot::channel();
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            peer_addr,
+            Instant::now(),
+        );
+        subject
+            .responses
+            .push_back(PendingResponse::Waiting(response_rx));
+
+        // Polling a response that isn't there yet has to happen inside a task
+        let first_result = future::lazy(|| {
+            future::ok::<bool, ()>(subject.poll(
+                &forwarding_pool,
+                Instant::now(),
+                &Logger::new("test"),
+            ))
+        })
+        .wait()
+        .unwrap();
+        let written_before_response = write_params_arc.lock().unwrap().len();
+        response_tx
+            .send(Vec::from(&response(0x66, 12)[2..]))
+            .unwrap();
+        let second_result = subject.poll(&forwarding_pool, Instant::now(), &Logger::new("test"));
+
+        assert_eq!(first_result, true);
+        assert_eq!(second_result, true);
+        assert_eq!(written_before_response, 0);
+        assert_eq!(
+            *write_params_arc.lock().unwrap(),
+            vec![response(0x66, 12), response(0x77, 12)]
+        );
+    }
+
+    #[test]
+    fn closes_connection_that_stays_idle_past_the_timeout() {
+        init_test_logging();
+        let now = Instant::now();
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_result(vec![], Ok(Async::NotReady))
+            .poll_read_result(vec![], Ok(Async::NotReady));
+        let forwarding_pool = make_pool(ProcessorMock::new());
+        let logger = Logger::new("closes_connection_that_stays_idle_past_the_timeout");
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(WriteHalfWrapperMock::new()),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            now,
+        );
+
+        let before_timeout = subject.poll(
+            &forwarding_pool,
+            now + Duration::from_millis(TCP_IDLE_TIMEOUT_MS - 1),
+            &logger,
+        );
+        let at_timeout = subject.poll(
+            &forwarding_pool,
+            now + Duration::from_millis(TCP_IDLE_TIMEOUT_MS),
+            &logger,
+        );
+
+        assert_eq!(before_timeout, true);
+        assert_eq!(at_timeout, false);
+        TestLogHandler::new().exists_log_containing("WARN: closes_connection_that_stays_idle_past_the_timeout: Closing DNS connection from 1.2.3.4:5678: idle for 10000ms");
+    }
+
+    #[test]
+    fn traffic_pushes_back_the_idle_deadline() {
+        let now = Instant::now();
+        let later = now + Duration::from_millis(TCP_IDLE_TIMEOUT_MS - 1);
+        let reader = ReadHalfWrapperMock::new()
+            .poll_read_ok(message(0x88, 12))
+            .poll_read_result(vec![], Ok(Async::NotReady));
+        let writer = WriteHalfWrapperMock::new().poll_write_ok(15);
+        let mut subject = DnsTcpConnection::new(
+            Box::new(reader),
+            Box::new(writer),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            now,
+        );
+
+        let result = subject.poll(
+            &make_pool(ProcessorMock::new()),
+            later,
+            &Logger::new("test"),
+        );
+
+        assert_eq!(result, true);
+        assert_eq!(
+            subject.idle_deadline(),
+            later + Duration::from_millis(TCP_IDLE_TIMEOUT_MS)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use futures::sync::oneshot;
+use packet_facade::PacketFacade;
+use processor::ProcessorTrait;
+use std::net::SocketAddr;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use sub_lib::logger::Logger;
+
+pub const FORWARDING_THREADS: usize = 4;
+pub const FORWARDING_QUEUE_LIMIT: usize = 256;
+
+// Responses have to fit in the two-byte length prefix they get over TCP
+const MAX_MESSAGE_LENGTH: usize = 65535;
+
+pub enum PendingResponse {
+    Ready(Vec<u8>),
+    Waiting(oneshot::Receiver<Vec<u8>>),
+}
+
+struct ForwardingJob {
+    request: Vec<u8>,
+    addr: SocketAddr,
+    response_tx: oneshot::Sender<Vec<u8>>,
+}
+
+// Requests that can be answered locally are processed on the spot. Requests that need an upstream server are
+// handed to a fixed number of worker threads, so that waiting on the network never holds up the entry DNS
+// server; if too many are already waiting, the request gets SERVFAIL instead.
+pub struct ForwardingPool {
+    processor: Arc<ProcessorTrait>,
+    job_tx: SyncSender<ForwardingJob>,
+}
+
+impl ForwardingPool {
+    pub fn new(
+        processor: Arc<ProcessorTrait>,
+        thread_count: usize,
+        queue_limit: usize,
+    ) -> ForwardingPool {
+        let (job_tx, job_rx) = sync_channel(queue_limit);
+        let job_rx_arc = Arc::new(Mutex::new(job_rx));
+        for _ in 0..thread_count {
+            let processor_inner = processor.clone();
+            let job_rx_inner = job_rx_arc.clone();
+            thread::spawn(move || ForwardingPool::work(processor_inner, job_rx_inner));
+        }
+        ForwardingPool { processor, job_tx }
+    }
+
+    pub fn process(&self, request: Vec<u8>, addr: SocketAddr, logger: &Logger) -> PendingResponse {
+        if !self.processor.needs_upstream(&request) {
+            return PendingResponse::Ready(ForwardingPool::run(
+                self.processor.as_ref(),
+                request,
+                &addr,
+                logger,
+            ));
+        }
+        let (response_tx, response_rx) = oneshot::channel();
+        match self.job_tx.try_send(ForwardingJob {
+            request,
+            addr,
+            response_tx,
+        }) {
+            Ok(()) => PendingResponse::Waiting(response_rx),
+            Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => {
+                logger.warning(format!(
+                    "Too many queries are waiting on upstream DNS servers; failing query from {}",
+                    job.addr
+                ));
+                PendingResponse::Ready(ForwardingPool::make_server_failure(job.request))
+            }
+        }
+    }
+
+    // Worker threads exit once the ForwardingPool, and with it the sending end of the queue, is gone
+    fn work(processor: Arc<ProcessorTrait>, job_rx: Arc<Mutex<Receiver<ForwardingJob>>>) {
+        let logger = Logger::new("EntryDnsServer");
+        loop {
+            let job = match job_rx.lock().expect("Forwarding queue is poisoned").recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+            let response = ForwardingPool::run(processor.as_ref(), job.request, &job.addr, &logger);
+            // The client may have gone away while we were waiting
+            let _ = job.response_tx.send(response);
+        }
+    }
+
+    fn run(
+        processor: &ProcessorTrait,
+        mut request: Vec<u8>,
+        addr: &SocketAddr,
+        logger: &Logger,
+    ) -> Vec<u8> {
+        let length = request.len();
+        request.resize(MAX_MESSAGE_LENGTH, 0);
+        let response_length = processor.process(&mut request, length, addr, logger);
+        request.truncate(response_length);
+        request
+    }
+
+    fn make_server_failure(mut request: Vec<u8>) -> Vec<u8> {
+        let length = request.len();
+        let response_length = {
+            let mut facade = PacketFacade::new(&mut request, length);
+            facade.set_query(false);
+            facade.set_authoritative_answer(false);
+            facade.set_truncated(false);
+            facade.set_recursion_available(true);
+            facade.set_rcode(0x2);
+            facade.clear();
+            facade.get_length()
+        };
+        request.truncate(response_length);
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::mpsc;
+    use test_utils::logging::init_test_logging;
+    use test_utils::logging::TestLogHandler;
+    use tokio::prelude::Future;
+
+    // Answers by appending a byte to the request; any request starting with 0xFF needs an upstream server
+    struct ProcessorMock {
+        process_thread_names: Arc<Mutex<Vec<Option<String>>>>,
+        // If present, process() says it has started and then waits to be released
+        gate_opt: Option<(Mutex<mpsc::Sender<()>>, Mutex<mpsc::Receiver<()>>)>,
+    }
+
+    impl ProcessorTrait for ProcessorMock {
+        fn process(
+            &self,
+            buf: &mut [u8],
+            length: usize,
+            _addr: &SocketAddr,
+            _logger: &Logger,
+        ) -> usize {
+            self.process_thread_names
+                .lock()
+                .unwrap()
+                .push(thread::current().name().map(String::from));
+            if let Some((ref started_tx, ref release_rx)) = self.gate_opt {
+                started_tx.lock().unwrap().send(()).unwrap();
+                let _ = release_rx.lock().unwrap().recv();
+            }
+            buf[length] = 0x42;
+            length + 1
+        }
+
+        fn needs_upstream(&self, request: &[u8]) -> bool {
+            request[0] == 0xFF
+        }
+    }
+
+    impl ProcessorMock {
+        fn new() -> ProcessorMock {
+            ProcessorMock {
+                process_thread_names: Arc::new(Mutex::new(vec![])),
+                gate_opt: None,
+            }
+        }
+    }
+
+    fn request(first_byte: u8) -> Vec<u8> {
+        let mut result = vec![0u8; 12];
+        result[0] = first_byte;
+        result
+    }
+
+    #[test]
+    fn requests_that_need_no_upstream_are_processed_on_the_spot() {
+        let processor = ProcessorMock::new();
+        let process_thread_names_arc = processor.process_thread_names.clone();
+        let subject = ForwardingPool::new(Arc::new(processor), 1, 1);
+        let (tx, rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name(String::from("caller"))
+            .spawn(move || {
+                let result = subject.process(
+                    request(0x12),
+                    SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                    &Logger::new("test"),
+                );
+                tx.send(result).unwrap();
+            })
+            .unwrap();
+
+        match rx.recv().unwrap() {
+            PendingResponse::Ready(response) => {
+                let mut expected = request(0x12);
+                expected.push(0x42);
+                assert_eq!(response, expected);
+            }
+            PendingResponse::Waiting(_) => panic!("Expected a ready response"),
+        }
+        assert_eq!(
+            *process_thread_names_arc.lock().unwrap(),
+            vec![Some(String::from("caller"))]
+        );
+    }
+
+    #[test]
+    fn requests_that_need_upstream_are_processed_by_a_worker() {
+        let processor = ProcessorMock::new();
+        let process_thread_names_arc = processor.process_thread_names.clone();
+        let subject = ForwardingPool::new(Arc::new(processor), 1, 1);
+
+        let result = subject.process(
+            request(0xFF),
+            SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            &Logger::new("test"),
+        );
+
+        let response = match result {
+            PendingResponse::Waiting(response_rx) => response_rx.wait().unwrap(),
+            PendingResponse::Ready(_) => panic!("Expected a waiting response"),
+        };
+        let mut expected = request(0xFF);
+        expected.push(0x42);
+        assert_eq!(response, expected);
+        let process_thread_names = process_thread_names_arc.lock().unwrap();
+        assert_eq!(process_thread_names.len(), 1);
+        assert_ne!(
+            process_thread_names[0],
+            thread::current().name().map(String::from)
+        );
+    }
+
+    #[test]
+    fn requests_beyond_the_queue_limit_get_server_failure() {
+        init_test_logging();
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let mut processor = ProcessorMock::new();
+        processor.gate_opt = Some((Mutex::new(started_tx), Mutex::new(release_rx)));
+        let subject = ForwardingPool::new(Arc::new(processor), 1, 1);
+        let addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let logger = Logger::new("requests_beyond_the_queue_limit_get_server_failure");
+        let busy = subject.process(request(0xFF), addr, &logger);
+        started_rx.recv().unwrap();
+
+        let queued = subject.process(request(0xFF), addr, &logger);
+        let refused = subject.process(
+            vec![
+                0xFF, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
+            addr,
+            &logger,
+        );
+
+        release_tx.send(()).unwrap();
+        release_tx.send(()).unwrap();
+        vec![busy, queued]
+            .into_iter()
+            .for_each(|pending_response| match pending_response {
+                PendingResponse::Waiting(response_rx) => {
+                    response_rx.wait().unwrap();
+                }
+                PendingResponse::Ready(_) => panic!("Expected a waiting response"),
+            });
+        let mut response = match refused {
+            PendingResponse::Ready(response) => response,
+            PendingResponse::Waiting(_) => panic!("Expected a ready response"),
+        };
+        let facade = PacketFacade::new(&mut response, 12);
+        assert_eq!(facade.get_transaction_id(), Some(0xFF34));
+        assert_eq!(facade.is_query(), Some(false));
+        assert_eq!(facade.get_rcode(), Some(0x2));
+        TestLogHandler::new().exists_log_containing("WARN: requests_beyond_the_queue_limit_get_server_failure: Too many queries are waiting on upstream DNS servers; failing query from 1.2.3.4:5678");
+    }
+}
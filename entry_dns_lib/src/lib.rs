@@ -1,6 +1,13 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+extern crate chrono;
 #[cfg(unix)]
 extern crate daemonize;
+extern crate futures;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate sub_lib;
 extern crate tokio;
 
@@ -11,4 +18,10 @@ extern crate test_utils;
 pub mod packet_facade;
 
 pub mod dns_socket_server;
+pub mod dns_tcp_connection;
+pub mod forwarding_pool;
 pub mod processor;
+pub mod query_log;
+pub mod rate_limiter;
+pub mod record_policy;
+pub mod upstream_resolver;
@@ -3,6 +3,21 @@ use std::cmp::max;
 use std::ops::Add;
 use std::str::from_utf8;
 
+pub const A_RECORD_TYPE: u16 = 0x0001;
+pub const NS_RECORD_TYPE: u16 = 0x0002;
+pub const CNAME_RECORD_TYPE: u16 = 0x0005;
+pub const SOA_RECORD_TYPE: u16 = 0x0006;
+pub const PTR_RECORD_TYPE: u16 = 0x000C;
+pub const MX_RECORD_TYPE: u16 = 0x000F;
+pub const TXT_RECORD_TYPE: u16 = 0x0010;
+pub const AAAA_RECORD_TYPE: u16 = 0x001C;
+pub const SRV_RECORD_TYPE: u16 = 0x0021;
+pub const OPT_RECORD_TYPE: u16 = 0x0029;
+pub const INTERNET_CLASS: u16 = 0x0001;
+
+// The two high bits of a label length byte are set when the "label" is actually a pointer to a name elsewhere in the packet
+const POINTER_FLAGS: u8 = 0xC0;
+
 macro_rules! try_opt {
     ($e:expr) => {
         match $e {
@@ -103,7 +118,12 @@ impl ResourceRecord {
         if offset + length > buflen {
             return None;
         }
-        let rdata = Vec::from(&buf[(rdata_begin)..(rdata_end)]);
+        let rdata = try_opt!(PacketFacade::expand_rdata(
+            buf,
+            rtype,
+            rdata_begin,
+            rdata_end
+        ));
         Some(ResourceRecord {
             name,
             rtype,
@@ -482,6 +502,16 @@ impl<'a> PacketFacade<'a> {
         self.length
     }
 
+    // Removes answers, authorities and additionals, leaving the header and the queries
+    pub fn clear_resource_records(&mut self) -> bool {
+        let queries_end = try_flg!(self.find_queries_end());
+        PacketFacade::u16_to(0x0000, &mut self.buf, 6);
+        PacketFacade::u16_to(0x0000, &mut self.buf, 8);
+        PacketFacade::u16_to(0x0000, &mut self.buf, 10);
+        self.length = queries_end;
+        true
+    }
+
     pub fn clear(&mut self) {
         PacketFacade::u16_to(0x0000, &mut self.buf, 4);
         PacketFacade::u16_to(0x0000, &mut self.buf, 6);
@@ -583,13 +613,20 @@ impl<'a> PacketFacade<'a> {
     fn find_string_seq_end(buf: &[u8], offset: usize, buflen: usize) -> Option<usize> {
         let mut local_offset = offset;
         loop {
-            if local_offset > buflen {
+            if local_offset >= buflen {
                 return None;
             }
             let length = buf[local_offset] as usize;
             if length == 0x00 {
                 return Some(local_offset + 1);
             }
+            if buf[local_offset] & POINTER_FLAGS == POINTER_FLAGS {
+                return if local_offset + 2 > buflen {
+                    None
+                } else {
+                    Some(local_offset + 2)
+                };
+            }
             local_offset += length + 1;
         }
     }
@@ -597,13 +634,28 @@ impl<'a> PacketFacade<'a> {
     fn extract_string_seq(buf: &[u8], offset: usize, buflen: usize) -> Option<(String, usize)> {
         let mut local_offset = offset;
         let mut result = String::from("");
+        let mut end_opt: Option<usize> = None;
+        let mut pointer_limit = offset;
         loop {
             if local_offset >= buflen {
                 return None;
             }
             let length = buf[local_offset] as usize;
             if length == 0x00 {
-                return Some((result, local_offset + 1));
+                return Some((result, end_opt.unwrap_or(local_offset + 1)));
+            }
+            if buf[local_offset] & POINTER_FLAGS == POINTER_FLAGS {
+                let pointer = try_opt!(PacketFacade::u16_from(buf, local_offset, buflen)) & 0x3FFF;
+                // Each pointer must point farther back than the last, or a malicious packet could make us loop forever
+                if pointer as usize >= pointer_limit {
+                    return None;
+                }
+                pointer_limit = pointer as usize;
+                if end_opt.is_none() {
+                    end_opt = Some(local_offset + 2);
+                }
+                local_offset = pointer as usize;
+                continue;
             }
             if result.len() > 0 {
                 result = result.add(".");
@@ -619,6 +671,32 @@ impl<'a> PacketFacade<'a> {
         }
     }
 
+    // Names inside the rdata of some record types may be compressed with pointers into the rest of the packet;
+    // expand them so that the rdata can be copied into another packet.
+    fn expand_rdata(buf: &[u8], rtype: u16, begin: usize, end: usize) -> Option<Vec<u8>> {
+        let (prefix_length, name_count) = match rtype {
+            NS_RECORD_TYPE | CNAME_RECORD_TYPE | PTR_RECORD_TYPE => (0, 1),
+            SOA_RECORD_TYPE => (0, 2),
+            MX_RECORD_TYPE => (2, 1),
+            SRV_RECORD_TYPE => (6, 1),
+            _ => return Some(Vec::from(&buf[begin..end])),
+        };
+        if begin + prefix_length > end {
+            return None;
+        }
+        let mut result = Vec::from(&buf[begin..(begin + prefix_length)]);
+        let mut offset = begin + prefix_length;
+        for _ in 0..name_count {
+            let (name, name_end) = try_opt!(PacketFacade::extract_string_seq(buf, offset, end));
+            let mut name_buf = vec![0u8; name.len() + 2];
+            let name_length = try_opt!(PacketFacade::add_string_seq(&mut name_buf, 0, &name));
+            result.extend_from_slice(&name_buf[..name_length]);
+            offset = name_end;
+        }
+        result.extend_from_slice(&buf[offset..end]);
+        Some(result)
+    }
+
     fn add_string_seq(buf: &mut [u8], offset: usize, string: &str) -> Option<usize> {
         let mut local_offset = offset;
         if string.len() > 0 {
@@ -647,6 +725,8 @@ mod tests {
     use packet_facade::PacketFacade;
     use packet_facade::Query;
     use packet_facade::ResourceRecord;
+    use packet_facade::CNAME_RECORD_TYPE;
+    use packet_facade::OPT_RECORD_TYPE;
 
     #[test]
     fn query_complains_when_name_length_busts_length_limit() {
@@ -1803,6 +1883,113 @@ mod tests {
         assert_eq!(additionals.len(), 2);
     }
 
+    #[test]
+    fn can_access_answers_with_compressed_names() {
+        let mut buf: [u8; 500] = [0; 500];
+        let length = {
+            let mut adder = ByteHandle::new(&mut buf, 4);
+            adder.add_bytes(&vec![0x00, 0x01]); // 1 query
+            adder.add_bytes(&vec![0x00, 0x03]); // 3 answers
+            adder.skip_bytes(4);
+
+            adder.add_bytes(&vec![0x03, 0x77, 0x77, 0x77]); // www at offset 12
+            adder.add_bytes(&vec![0x06, 0x64, 0x6F, 0x6D, 0x61, 0x69, 0x6E]); // domain at offset 16
+            adder.add_bytes(&vec![0x03, 0x63, 0x6F, 0x6D, 0x00]); // com [end]
+            adder.add_bytes(&vec![0x00, 0x05, 0x00, 0x01]); // type CNAME, class IN
+
+            adder.add_bytes(&vec![0xC0, 0x0C]); // pointer to www.domain.com
+            adder.add_bytes(&vec![0x00, 0x05, 0x00, 0x01]); // type CNAME, class IN
+            adder.add_bytes(&vec![0x00, 0x00, 0x0E, 0x10]); // time to live
+            adder.add_bytes(&vec![0x00, 0x06]); // rdata length
+            adder.add_bytes(&vec![0x03, 0x63, 0x64, 0x6E]); // cdn
+            adder.add_bytes(&vec![0xC0, 0x10]); // pointer to domain.com
+
+            adder.add_bytes(&vec![0xC0, 0x10]); // pointer to domain.com
+            adder.add_bytes(&vec![0x00, 0x0F, 0x00, 0x01]); // type MX, class IN
+            adder.add_bytes(&vec![0x00, 0x00, 0x0E, 0x10]); // time to live
+            adder.add_bytes(&vec![0x00, 0x09]); // rdata length
+            adder.add_bytes(&vec![0x00, 0x0A]); // preference
+            adder.add_bytes(&vec![0x04, 0x6D, 0x61, 0x69, 0x6C]); // mail
+            adder.add_bytes(&vec![0xC0, 0x10]); // pointer to domain.com
+
+            adder.add_bytes(&vec![0xC0, 0x10]); // pointer to domain.com
+            adder.add_bytes(&vec![0x00, 0x10, 0x00, 0x01]); // type TXT, class IN
+            adder.add_bytes(&vec![0x00, 0x00, 0x0E, 0x10]); // time to live
+            adder.add_bytes(&vec![0x00, 0x03]); // rdata length
+            adder.add_bytes(&vec![0x02, 0xC0, 0x10]); // text that looks like a pointer
+
+            adder.get_offset()
+        };
+
+        let subject = PacketFacade::new(&mut buf, length);
+
+        let queries = subject.get_queries().unwrap();
+        assert_eq!(queries[0].get_query_name(), "www.domain.com");
+        let records = subject.get_answers().unwrap();
+        assert_eq!(records[0].get_name(), "www.domain.com");
+        assert_eq!(records[0].get_resource_type(), CNAME_RECORD_TYPE);
+        assert_eq!(
+            records[0].get_rdata(),
+            u8vec(&vec![
+                0x03, 0x63, 0x64, 0x6E, 0x06, 0x64, 0x6F, 0x6D, 0x61, 0x69, 0x6E, 0x03, 0x63, 0x6F,
+                0x6D, 0x00,
+            ])
+        );
+        assert_eq!(records[1].get_name(), "domain.com");
+        assert_eq!(
+            records[1].get_rdata(),
+            u8vec(&vec![
+                0x00, 0x0A, 0x04, 0x6D, 0x61, 0x69, 0x6C, 0x06, 0x64, 0x6F, 0x6D, 0x61, 0x69, 0x6E,
+                0x03, 0x63, 0x6F, 0x6D, 0x00,
+            ])
+        );
+        assert_eq!(records[2].get_name(), "domain.com");
+        assert_eq!(records[2].get_rdata(), u8vec(&vec![0x02, 0xC0, 0x10]));
+        assert_eq!(records.len(), 3);
+        assert_eq!(subject.get_authorities().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn rejects_pointers_that_do_not_point_backward() {
+        let mut buf: [u8; 100] = [0; 100];
+        let length = {
+            let mut adder = ByteHandle::new(&mut buf, 4);
+            adder.add_bytes(&vec![0x00, 0x01]); // 1 query
+            adder.skip_bytes(6);
+            adder.add_bytes(&vec![0x01, 0x61]); // a
+            adder.add_bytes(&vec![0xC0, 0x0C]); // pointer to a, making a loop
+            adder.add_bytes(&vec![0x00, 0x01, 0x00, 0x01]); // type A, class IN
+            adder.get_offset()
+        };
+
+        let subject = PacketFacade::new(&mut buf, length);
+
+        assert_eq!(subject.get_queries().is_none(), true);
+    }
+
+    #[test]
+    fn clears_resource_records_but_not_queries() {
+        let mut buf: [u8; 500] = [0; 500];
+        let length = {
+            let mut facade = PacketFacade::new(&mut buf, 12);
+            facade.add_query("www.domain.com", 0x0001, 0x0001);
+            facade.add_answer("www.domain.com", 0x0001, 0x0001, 1000, &[1, 2, 3, 4]);
+            facade.add_authority("domain.com", 0x0002, 0x0001, 1000, &[0]);
+            facade.add_additional("", OPT_RECORD_TYPE, 0x1000, 0, &[]);
+            facade.get_length()
+        };
+        let mut subject = PacketFacade::new(&mut buf, length);
+
+        let result = subject.clear_resource_records();
+
+        assert_eq!(result, true);
+        assert_eq!(subject.get_queries().unwrap().len(), 1);
+        assert_eq!(subject.get_answers().unwrap().len(), 0);
+        assert_eq!(subject.get_authorities().unwrap().len(), 0);
+        assert_eq!(subject.get_additionals().unwrap().len(), 0);
+        assert_eq!(subject.get_length(), 12 + 16 + 4);
+    }
+
     fn u8vec(buf: &[u8]) -> &[u8] {
         buf
     }
@@ -1,23 +1,65 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
 use packet_facade::PacketFacade;
 use packet_facade::Query;
 use packet_facade::ResourceRecord;
+use packet_facade::AAAA_RECORD_TYPE;
+use packet_facade::A_RECORD_TYPE;
+use packet_facade::INTERNET_CLASS;
+use packet_facade::OPT_RECORD_TYPE;
+use query_log::QueryLog;
+use query_log::QueryLogAnswer;
+use query_log::QueryLogEntry;
+use query_log::QueryLogQuery;
+use rand;
+use record_policy::RecordPolicy;
+use record_policy::RecordPolicyTable;
 use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::SocketAddr;
 use std::time::Instant;
+use std::time::SystemTime;
 use sub_lib::logger::Logger;
+use upstream_resolver::UpstreamResolver;
 
 pub trait ProcessorTrait: Send + Sync {
     fn process(&self, buf: &mut [u8], length: usize, addr: &SocketAddr, logger: &Logger) -> usize;
+    // True if processing this request will mean waiting on an upstream server
+    fn needs_upstream(&self, request: &[u8]) -> bool;
 }
 
 pub struct ProcessorReal {
-    target_ip: IpAddr,
+    ipv4_target_opt: Option<Ipv4Addr>,
+    ipv6_target_opt: Option<Ipv6Addr>,
+    record_policies: RecordPolicyTable,
+    upstream_resolver: Box<UpstreamResolver>,
+    query_log_opt: Option<Box<QueryLog>>,
 }
 
 impl ProcessorReal {
-    pub fn new(target_ip: IpAddr) -> ProcessorReal {
-        ProcessorReal { target_ip }
+    // A queries are answered only if target_ip is IPv4. AAAA queries are answered with ipv6_target_opt,
+    // or with target_ip if that is IPv6; otherwise they get a NOERROR response with no answer.
+    pub fn new(
+        target_ip: IpAddr,
+        ipv6_target_opt: Option<Ipv6Addr>,
+        record_policies: RecordPolicyTable,
+        upstream_resolver: Box<UpstreamResolver>,
+        query_log_opt: Option<Box<QueryLog>>,
+    ) -> ProcessorReal {
+        let (ipv4_target_opt, ipv6_target_opt) = match target_ip {
+            IpAddr::V4(ipv4) => (Some(ipv4), ipv6_target_opt),
+            IpAddr::V6(ipv6) => (None, ipv6_target_opt.or(Some(ipv6))),
+        };
+        ProcessorReal {
+            ipv4_target_opt,
+            ipv6_target_opt,
+            record_policies,
+            upstream_resolver,
+            query_log_opt,
+        }
     }
 }
 
@@ -26,11 +68,12 @@ impl ProcessorTrait for ProcessorReal {
         let mut facade = PacketFacade::new(buf, length);
         let request_record = RequestRecord {
             timestamp: Instant::now(),
+            start_time: SystemTime::now(),
             opcode: facade.get_opcode().unwrap_or(0xFF),
             queries: facade.get_queries().unwrap_or(vec![]),
         };
         let result: usize;
-        loop {
+        'processing: loop {
             if facade
                 .get_opcode()
                 .expect("The provided buffer must have more than 0 bytes")
@@ -56,20 +99,62 @@ impl ProcessorTrait for ProcessorReal {
                 }
                 Some(q) => q,
             };
+            // Drop anything else the client sent along, such as an EDNS OPT record
+            facade.clear_resource_records();
+            let mut authorities: Vec<ResourceRecord> = vec![];
+            let mut additionals: Vec<ResourceRecord> = vec![];
+            let mut rcode = 0x0;
             for query in queries {
-                if query.get_query_type() != 0x0001 {
-                    return ProcessorReal::make_not_implemented_error(&mut facade);
+                if query.get_query_class() != INTERNET_CLASS {
+                    result = ProcessorReal::make_not_implemented_error(&mut facade);
+                    break 'processing;
                 }
-                if query.get_query_class() != 0x0001 {
-                    return ProcessorReal::make_not_implemented_error(&mut facade);
+                match self.record_policies.policy_for(query.get_query_type()) {
+                    None => {
+                        result = ProcessorReal::make_not_implemented_error(&mut facade);
+                        break 'processing;
+                    }
+                    Some(RecordPolicy::Answer) => {
+                        if let Some(rdata) = self.target_rdata(query.get_query_type()) {
+                            facade.add_answer(
+                                &query.get_query_name(),
+                                query.get_query_type(),
+                                INTERNET_CLASS,
+                                3600,
+                                &rdata,
+                            );
+                        }
+                    }
+                    Some(RecordPolicy::Empty) => (),
+                    Some(RecordPolicy::Forward) if !self.upstream_resolver.has_servers() => (),
+                    Some(RecordPolicy::Forward) => match self.forward(&query, logger) {
+                        Some(response) => {
+                            if response.rcode != 0x0 {
+                                rcode = response.rcode;
+                            }
+                            response.answers.iter().for_each(|answer| {
+                                ProcessorReal::add_record(answer, |n, t, c, l, r| {
+                                    facade.add_answer(n, t, c, l, r)
+                                })
+                            });
+                            authorities.extend(response.authorities);
+                            additionals.extend(response.additionals);
+                        }
+                        None => rcode = 0x2,
+                    },
                 }
-                let octets = match self.target_ip {
-                    IpAddr::V4(ipv4) => ipv4.octets(),
-                    // crashpoint - make a card
-                    IpAddr::V6(_ipv6) => unimplemented!(),
-                };
-                facade.add_answer(&query.get_query_name(), 0x0001, 0x0001, 3600, &octets);
             }
+            authorities.iter().for_each(|authority| {
+                ProcessorReal::add_record(authority, |n, t, c, l, r| {
+                    facade.add_authority(n, t, c, l, r)
+                })
+            });
+            additionals.iter().for_each(|additional| {
+                ProcessorReal::add_record(additional, |n, t, c, l, r| {
+                    facade.add_additional(n, t, c, l, r)
+                })
+            });
+            facade.set_rcode(rcode);
 
             result = facade.get_length();
             break;
@@ -81,11 +166,124 @@ impl ProcessorTrait for ProcessorReal {
             answers: facade.get_answers().unwrap_or(vec![]),
         };
         ProcessorReal::write_log(&request_record, &response_record, addr, logger);
+        if let Some(ref query_log) = self.query_log_opt {
+            query_log.write(
+                &ProcessorReal::make_query_log_entry(&request_record, &response_record, addr),
+                logger,
+            );
+        }
         return result;
     }
+
+    fn needs_upstream(&self, request: &[u8]) -> bool {
+        if !self.upstream_resolver.has_servers() {
+            return false;
+        }
+        let mut buf = Vec::from(request);
+        let length = buf.len();
+        let facade = PacketFacade::new(&mut buf, length);
+        match (facade.get_opcode(), facade.get_queries()) {
+            (Some(0x0), Some(queries)) => queries.iter().any(|query| {
+                (query.get_query_class() == INTERNET_CLASS)
+                    && (self.record_policies.policy_for(query.get_query_type())
+                        == Some(RecordPolicy::Forward))
+            }),
+            _ => false,
+        }
+    }
 }
 
 impl ProcessorReal {
+    fn target_rdata(&self, query_type: u16) -> Option<Vec<u8>> {
+        match query_type {
+            A_RECORD_TYPE => self.ipv4_target_opt.map(|ipv4| ipv4.octets().to_vec()),
+            AAAA_RECORD_TYPE => self.ipv6_target_opt.map(|ipv6| ipv6.octets().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn forward(&self, query: &Query, logger: &Logger) -> Option<ForwardedResponse> {
+        let mut request_buf: [u8; 512] = [0; 512];
+        let request_length = {
+            let mut request = PacketFacade::new(&mut request_buf, 12);
+            let success = request.set_transaction_id(rand::random::<u16>())
+                && request.set_query(true)
+                && request.set_opcode(0x0)
+                && request.set_recursion_desired(true)
+                && request.add_query(
+                    query.get_query_name(),
+                    query.get_query_type(),
+                    query.get_query_class(),
+                );
+            if !success {
+                logger.error(format!(
+                    "Could not build upstream query for {}",
+                    query.get_query_name()
+                ));
+                return None;
+            }
+            request.get_length()
+        };
+        let mut response_buf = self
+            .upstream_resolver
+            .resolve(&request_buf[0..request_length], logger)?;
+        let response_length = response_buf.len();
+        let response = PacketFacade::new(&mut response_buf, response_length);
+        let forwarded_response = match (
+            response.is_query(),
+            response.get_queries(),
+            response.get_rcode(),
+            response.get_answers(),
+            response.get_authorities(),
+            response.get_additionals(),
+        ) {
+            (
+                Some(false),
+                Some(ref queries),
+                Some(rcode),
+                Some(answers),
+                Some(authorities),
+                Some(additionals),
+            ) if (queries.len() == 1)
+                && queries[0]
+                    .get_query_name()
+                    .eq_ignore_ascii_case(query.get_query_name())
+                && (queries[0].get_query_type() == query.get_query_type()) =>
+            {
+                ForwardedResponse {
+                    rcode,
+                    answers,
+                    authorities,
+                    additionals: additionals
+                        .into_iter()
+                        .filter(|additional| additional.get_resource_type() != OPT_RECORD_TYPE)
+                        .collect(),
+                }
+            }
+            _ => {
+                logger.error(format!(
+                    "Upstream DNS server sent an unusable response for {}",
+                    query.get_query_name()
+                ));
+                return None;
+            }
+        };
+        Some(forwarded_response)
+    }
+
+    fn add_record<F>(record: &ResourceRecord, mut adder: F)
+    where
+        F: FnMut(&str, u16, u16, u32, &[u8]) -> bool,
+    {
+        adder(
+            record.get_name(),
+            record.get_resource_type(),
+            record.get_resource_class(),
+            record.get_time_to_live(),
+            record.get_rdata(),
+        );
+    }
+
     fn make_format_error(facade: &mut PacketFacade) -> usize {
         facade.set_query(false);
         facade.set_authoritative_answer(false);
@@ -128,19 +326,72 @@ impl ProcessorReal {
             if !answer_list.is_empty() {
                 answer_list += ", "
             }
-            let rdata = answer.get_rdata();
-            // TODO: What if there aren't four elements in this array?
-            answer_list += &format!("{}.{}.{}.{}", rdata[0], rdata[1], rdata[2], rdata[3])
+            answer_list += &ProcessorReal::format_rdata(answer.get_rdata())
         }
         logger.info(format!(
             "{}ns: {} RQ{:X} ({}) -> RS{:X} ({})",
             to.latency_ns, addr, from.opcode, &query_list, to.rcode, &answer_list
         ));
     }
+
+    fn make_query_log_entry(
+        from: &RequestRecord,
+        to: &ResponseRecord,
+        addr: &SocketAddr,
+    ) -> QueryLogEntry {
+        QueryLogEntry {
+            timestamp: DateTime::<Utc>::from(from.start_time)
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            client: format!("{}", addr),
+            opcode: from.opcode,
+            queries: from
+                .queries
+                .iter()
+                .map(|query| QueryLogQuery {
+                    name: query.get_query_name().to_string(),
+                    query_type: query.get_query_type(),
+                    class: query.get_query_class(),
+                })
+                .collect(),
+            rcode: to.rcode,
+            answers: to
+                .answers
+                .iter()
+                .map(|answer| QueryLogAnswer {
+                    name: answer.get_name().to_string(),
+                    resource_type: answer.get_resource_type(),
+                    class: answer.get_resource_class(),
+                    ttl: answer.get_time_to_live(),
+                    data: ProcessorReal::format_rdata(answer.get_rdata()),
+                })
+                .collect(),
+            latency_ns: to.latency_ns,
+        }
+    }
+
+    fn format_rdata(rdata: &[u8]) -> String {
+        match rdata.len() {
+            4 => format!("{}", Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                format!("{}", Ipv6Addr::from(octets))
+            }
+            _ => format!("{:?}", rdata),
+        }
+    }
+}
+
+struct ForwardedResponse {
+    rcode: u8,
+    answers: Vec<ResourceRecord>,
+    authorities: Vec<ResourceRecord>,
+    additionals: Vec<ResourceRecord>,
 }
 
 struct RequestRecord {
     timestamp: Instant,
+    start_time: SystemTime,
     opcode: u8,
     queries: Vec<Query>,
 }
@@ -160,15 +411,94 @@ mod tests {
     use processor::ProcessorTrait;
     use processor::RequestRecord;
     use processor::ResponseRecord;
+    use query_log::QueryLog;
+    use query_log::QueryLogAnswer;
+    use query_log::QueryLogEntry;
+    use query_log::QueryLogQuery;
+    use record_policy::RecordPolicyTable;
     use std::net::IpAddr;
     use std::net::Ipv4Addr;
+    use std::net::Ipv6Addr;
     use std::net::SocketAddr;
     use std::net::SocketAddrV4;
     use std::str::FromStr;
+    use std::sync::Arc;
+    use std::sync::Mutex;
     use std::time::Instant;
+    use std::time::SystemTime;
     use sub_lib::logger::Logger;
     use test_utils::logging::init_test_logging;
     use test_utils::logging::TestLogHandler;
+    use upstream_resolver::UpstreamResolver;
+
+    struct UpstreamResolverMock {
+        has_servers: bool,
+        resolve_params: Arc<Mutex<Vec<Vec<u8>>>>,
+        resolve_results: Mutex<Vec<Option<Vec<u8>>>>,
+    }
+
+    impl UpstreamResolver for UpstreamResolverMock {
+        fn has_servers(&self) -> bool {
+            self.has_servers
+        }
+
+        fn resolve(&self, request: &[u8], _logger: &Logger) -> Option<Vec<u8>> {
+            self.resolve_params.lock().unwrap().push(Vec::from(request));
+            // A real resolver only returns responses whose transaction ID matches the request's
+            self.resolve_results
+                .lock()
+                .unwrap()
+                .remove(0)
+                .map(|mut response| {
+                    response[0] = request[0];
+                    response[1] = request[1];
+                    response
+                })
+        }
+    }
+
+    impl UpstreamResolverMock {
+        fn new(has_servers: bool) -> UpstreamResolverMock {
+            UpstreamResolverMock {
+                has_servers,
+                resolve_params: Arc::new(Mutex::new(vec![])),
+                resolve_results: Mutex::new(vec![]),
+            }
+        }
+
+        fn resolve_params(mut self, params: &Arc<Mutex<Vec<Vec<u8>>>>) -> UpstreamResolverMock {
+            self.resolve_params = params.clone();
+            self
+        }
+
+        fn resolve_result(self, result: Option<Vec<u8>>) -> UpstreamResolverMock {
+            self.resolve_results.lock().unwrap().push(result);
+            self
+        }
+    }
+
+    struct QueryLogMock {
+        write_params: Arc<Mutex<Vec<QueryLogEntry>>>,
+    }
+
+    impl QueryLog for QueryLogMock {
+        fn write(&self, entry: &QueryLogEntry, _logger: &Logger) {
+            self.write_params.lock().unwrap().push(entry.clone());
+        }
+    }
+
+    impl QueryLogMock {
+        fn new() -> QueryLogMock {
+            QueryLogMock {
+                write_params: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn write_params(mut self, params: &Arc<Mutex<Vec<QueryLogEntry>>>) -> QueryLogMock {
+            self.write_params = params.clone();
+            self
+        }
+    }
 
     #[test]
     fn returns_format_error_if_queries_overrun() {
@@ -187,7 +517,7 @@ mod tests {
         let truncated_length = correct_length - 1;
         let truncated_buf = &mut correct_buf[0..truncated_length];
         let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new(IpAddr::from_str("123.124.125.126").unwrap());
+        let subject = make_subject("123.124.125.126", None, RecordPolicyTable::default());
 
         let result = subject.process(truncated_buf, truncated_length, &addr, &Logger::new(""));
 
@@ -209,7 +539,7 @@ mod tests {
             facade.get_length()
         };
         let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new(IpAddr::from_str("18.52.86.120").unwrap());
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
 
         let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
 
@@ -231,7 +561,7 @@ mod tests {
             facade.get_length()
         };
         let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new(IpAddr::from_str("18.52.86.120").unwrap());
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
 
         let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
 
@@ -253,7 +583,7 @@ mod tests {
             facade.get_length()
         };
         let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new(IpAddr::from_str("18.52.86.120").unwrap());
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
 
         let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
 
@@ -279,7 +609,7 @@ mod tests {
         };
         let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
         let rsp_length = {
-            let subject = ProcessorReal::new(IpAddr::from_str("18.52.86.120").unwrap());
+            let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
 
             subject.process(
                 &mut buf,
@@ -337,11 +667,89 @@ mod tests {
         tlh.exists_log_containing ("101.102.103.104:53 RQ0 (1/1/ooga.com, 1/1/booga.com) -> RS0 (18.52.86.120, 18.52.86.120)");
     }
 
+    #[test]
+    fn aaaa_query_is_answered_with_ipv6_target() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("ooga.com", 0x001C)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let subject = make_subject(
+            "18.52.86.120",
+            Some("2001:db8::1234"),
+            RecordPolicyTable::default(),
+        );
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x0000));
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_name(), "ooga.com");
+        assert_eq!(answers[0].get_resource_class(), 0x0001);
+        assert_eq!(answers[0].get_resource_type(), 0x001C);
+        assert_eq!(answers[0].get_time_to_live(), 3600);
+        assert_eq!(
+            answers[0].get_rdata(),
+            &Ipv6Addr::from_str("2001:db8::1234").unwrap().octets()[..]
+        );
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[test]
+    fn aaaa_query_without_ipv6_target_gets_empty_answer() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("ooga.com", 0x0001), ("ooga.com", 0x001C)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x0000));
+        assert_eq!(response.get_queries().unwrap().len(), 2);
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_resource_type(), 0x0001);
+        assert_eq!(answers[0].get_rdata(), &[0x12, 0x34, 0x56, 0x78][..]);
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[test]
+    fn ipv6_dns_target_answers_aaaa_queries_and_not_a_queries() {
+        init_test_logging();
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("ooga.com", 0x0001), ("booga.com", 0x001C)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let subject = make_subject("fe80::1", None, RecordPolicyTable::default());
+
+        let rsp_length = subject.process(
+            &mut buf,
+            req_length,
+            &addr,
+            &Logger::new("ipv6_dns_target_answers_aaaa_queries_and_not_a_queries"),
+        );
+
+        {
+            let response = PacketFacade::new(&mut buf, rsp_length);
+            assert_eq!(response.get_rcode(), Some(0x0000));
+            let answers = response.get_answers().unwrap();
+            assert_eq!(answers[0].get_name(), "booga.com");
+            assert_eq!(answers[0].get_resource_type(), 0x001C);
+            assert_eq!(
+                answers[0].get_rdata(),
+                &Ipv6Addr::from_str("fe80::1").unwrap().octets()[..]
+            );
+            assert_eq!(answers.len(), 1);
+        }
+        TestLogHandler::new().exists_log_containing(
+            "101.102.103.104:53 RQ0 (1/1/ooga.com, 28/1/booga.com) -> RS0 (fe80::1)",
+        );
+    }
+
     #[test]
     fn write_log_produces_correct_text() {
         init_test_logging();
         let request_record = RequestRecord {
             timestamp: Instant::now(),
+            start_time: SystemTime::now(),
             opcode: 0x2,
             queries: vec![
                 Query::new_for_test(String::from("first"), 0x1234, 0x2345, 11),
@@ -383,6 +791,410 @@ mod tests {
         TestLogHandler::new ().exists_log_containing("2345ns: 101.102.103.104:53 RQ2 (4660/9029/first, 13398/17767/second) -> RS3 (123.124.125.126, 124.125.126.127)");
     }
 
+    #[test]
+    fn write_log_formats_ipv6_and_unrecognized_rdata() {
+        init_test_logging();
+        let request_record = RequestRecord {
+            timestamp: Instant::now(),
+            start_time: SystemTime::now(),
+            opcode: 0x0,
+            queries: vec![Query::new_for_test(
+                String::from("first"),
+                0x001C,
+                0x0001,
+                11,
+            )],
+        };
+        let response_record = ResponseRecord {
+            latency_ns: 3456,
+            rcode: 0x0,
+            answers: vec![
+                ResourceRecord::new_for_test(
+                    String::from("first"),
+                    0x001C,
+                    0x0001,
+                    3600,
+                    Ipv6Addr::from_str("2001:db8::1234")
+                        .unwrap()
+                        .octets()
+                        .to_vec(),
+                    21,
+                ),
+                ResourceRecord::new_for_test(
+                    String::from("first"),
+                    0x1234,
+                    0x0001,
+                    3600,
+                    vec![1, 2, 3],
+                    22,
+                ),
+            ],
+        };
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        ProcessorReal::write_log(
+            &request_record,
+            &response_record,
+            &addr,
+            &Logger::new("write_log_formats_ipv6_and_unrecognized_rdata"),
+        );
+
+        TestLogHandler::new().exists_log_containing(
+            "3456ns: 101.102.103.104:53 RQ0 (28/1/first) -> RS0 (2001:db8::1234, [1, 2, 3])",
+        );
+    }
+
+    #[test]
+    fn answered_query_is_written_to_query_log() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("ooga.com", 0x0001)]);
+        let addr = SocketAddr::from_str("101.102.103.104:5353").unwrap();
+        let write_params_arc = Arc::new(Mutex::new(vec![]));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(UpstreamResolverMock::new(false)),
+            Some(Box::new(
+                QueryLogMock::new().write_params(&write_params_arc),
+            )),
+        );
+
+        subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let write_params = write_params_arc.lock().unwrap();
+        assert_eq!(write_params.len(), 1);
+        let entry = &write_params[0];
+        // An RFC 3339 UTC timestamp with microseconds, like 2018-11-12T13:14:15.123456Z
+        assert_eq!(entry.timestamp.len(), 27, "{}", entry.timestamp);
+        assert_eq!(entry.timestamp.ends_with("Z"), true, "{}", entry.timestamp);
+        assert_eq!(entry.client, String::from("101.102.103.104:5353"));
+        assert_eq!(entry.opcode, 0x0);
+        assert_eq!(
+            entry.queries,
+            vec![QueryLogQuery {
+                name: String::from("ooga.com"),
+                query_type: 0x0001,
+                class: 0x0001,
+            }]
+        );
+        assert_eq!(entry.rcode, 0x0);
+        assert_eq!(
+            entry.answers,
+            vec![QueryLogAnswer {
+                name: String::from("ooga.com"),
+                resource_type: 0x0001,
+                class: 0x0001,
+                ttl: 3600,
+                data: String::from("18.52.86.120"),
+            }]
+        );
+    }
+
+    #[test]
+    fn not_implemented_query_is_written_to_query_log() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("ooga.com", 0x0001), ("booga.com", 0x0000)]);
+        let addr = SocketAddr::from_str("101.102.103.104:5353").unwrap();
+        let write_params_arc = Arc::new(Mutex::new(vec![]));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(UpstreamResolverMock::new(false)),
+            Some(Box::new(
+                QueryLogMock::new().write_params(&write_params_arc),
+            )),
+        );
+
+        subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let write_params = write_params_arc.lock().unwrap();
+        assert_eq!(write_params.len(), 1);
+        assert_eq!(write_params[0].queries.len(), 2);
+        assert_eq!(write_params[0].rcode, 0x4);
+        assert_eq!(write_params[0].answers, vec![]);
+    }
+
+    #[test]
+    fn forwards_query_and_relays_answers_authorities_and_additionals() {
+        let mut upstream_buf: [u8; 500] = [0; 500];
+        let upstream_length = {
+            let mut response = PacketFacade::new(&mut upstream_buf, 12);
+            response.set_query(false);
+            response.add_query("mail.com", 0x000F, 0x0001);
+            response.add_answer(
+                "mail.com",
+                0x000F,
+                0x0001,
+                300,
+                &[0x00, 0x0A, 0x01, 0x6D, 0x00],
+            );
+            response.add_authority("mail.com", 0x0002, 0x0001, 400, &[0x02, 0x6E, 0x73, 0x00]);
+            response.add_additional("ns", 0x0001, 0x0001, 500, &[1, 2, 3, 4]);
+            response.add_additional("", 0x0029, 0x1000, 0, &[]);
+            response.get_length()
+        };
+        let resolve_params_arc = Arc::new(Mutex::new(vec![]));
+        let upstream_resolver = UpstreamResolverMock::new(true)
+            .resolve_params(&resolve_params_arc)
+            .resolve_result(Some(Vec::from(&upstream_buf[0..upstream_length])));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(upstream_resolver),
+            None,
+        );
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("mail.com", 0x000F)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let mut resolve_params = resolve_params_arc.lock().unwrap();
+        let upstream_request_length = resolve_params[0].len();
+        let upstream_request = PacketFacade::new(&mut resolve_params[0], upstream_request_length);
+        assert_eq!(upstream_request.is_query(), Some(true));
+        assert_eq!(upstream_request.get_opcode(), Some(0x0));
+        assert_eq!(upstream_request.is_recursion_desired(), Some(true));
+        let upstream_queries = upstream_request.get_queries().unwrap();
+        assert_eq!(upstream_queries[0].get_query_name(), "mail.com");
+        assert_eq!(upstream_queries[0].get_query_type(), 0x000F);
+        assert_eq!(upstream_queries[0].get_query_class(), 0x0001);
+        assert_eq!(upstream_queries.len(), 1);
+        assert_eq!(upstream_request.get_answers().unwrap().len(), 0);
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_transaction_id(), Some(0x4321));
+        assert_eq!(response.is_query(), Some(false));
+        assert_eq!(response.get_rcode(), Some(0x0));
+        assert_eq!(response.get_queries().unwrap().len(), 1);
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_name(), "mail.com");
+        assert_eq!(answers[0].get_resource_type(), 0x000F);
+        assert_eq!(answers[0].get_time_to_live(), 300);
+        assert_eq!(answers[0].get_rdata(), &[0x00, 0x0A, 0x01, 0x6D, 0x00][..]);
+        assert_eq!(answers.len(), 1);
+        let authorities = response.get_authorities().unwrap();
+        assert_eq!(authorities[0].get_name(), "mail.com");
+        assert_eq!(authorities[0].get_resource_type(), 0x0002);
+        assert_eq!(authorities[0].get_rdata(), &[0x02, 0x6E, 0x73, 0x00][..]);
+        assert_eq!(authorities.len(), 1);
+        let additionals = response.get_additionals().unwrap();
+        assert_eq!(additionals[0].get_name(), "ns");
+        assert_eq!(additionals[0].get_rdata(), &[1, 2, 3, 4][..]);
+        assert_eq!(additionals.len(), 1);
+    }
+
+    #[test]
+    fn needs_upstream_only_for_forwarded_queries_with_servers_to_forward_to() {
+        let mut forwarded_buf: [u8; 500] = [0; 500];
+        let forwarded_length = make_request(
+            &mut forwarded_buf,
+            vec![("ooga.com", 0x0001), ("mail.com", 0x000F)],
+        );
+        let mut answered_buf: [u8; 500] = [0; 500];
+        let answered_length = make_request(&mut answered_buf, vec![("ooga.com", 0x0001)]);
+        let make_subject = |has_servers| {
+            ProcessorReal::new(
+                IpAddr::from_str("18.52.86.120").unwrap(),
+                None,
+                RecordPolicyTable::default(),
+                Box::new(UpstreamResolverMock::new(has_servers)),
+                None,
+            )
+        };
+        let with_servers = make_subject(true);
+        let without_servers = make_subject(false);
+
+        assert_eq!(
+            with_servers.needs_upstream(&forwarded_buf[0..forwarded_length]),
+            true
+        );
+        assert_eq!(
+            with_servers.needs_upstream(&answered_buf[0..answered_length]),
+            false
+        );
+        assert_eq!(with_servers.needs_upstream(&[0x12]), false);
+        assert_eq!(
+            without_servers.needs_upstream(&forwarded_buf[0..forwarded_length]),
+            false
+        );
+    }
+
+    #[test]
+    fn forward_relays_upstream_error_code() {
+        let mut upstream_buf: [u8; 500] = [0; 500];
+        let upstream_length = {
+            let mut response = PacketFacade::new(&mut upstream_buf, 12);
+            response.set_query(false);
+            response.set_rcode(0x3);
+            response.add_query("nowhere.com", 0x0010, 0x0001);
+            response.get_length()
+        };
+        let upstream_resolver = UpstreamResolverMock::new(true)
+            .resolve_result(Some(Vec::from(&upstream_buf[0..upstream_length])));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(upstream_resolver),
+            None,
+        );
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("nowhere.com", 0x0010)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x3));
+        assert_eq!(response.get_answers().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn forward_returns_server_failure_when_upstream_does_not_answer() {
+        let upstream_resolver = UpstreamResolverMock::new(true).resolve_result(None);
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(upstream_resolver),
+            None,
+        );
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("service.com", 0x0021)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x2));
+        assert_eq!(response.get_answers().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn forward_returns_server_failure_when_upstream_answers_a_different_question() {
+        init_test_logging();
+        let mut upstream_buf: [u8; 500] = [0; 500];
+        let upstream_length = {
+            let mut response = PacketFacade::new(&mut upstream_buf, 12);
+            response.set_query(false);
+            response.add_query("evil.com", 0x000C, 0x0001);
+            response.add_answer("evil.com", 0x000C, 0x0001, 300, &[0x00]);
+            response.get_length()
+        };
+        let upstream_resolver = UpstreamResolverMock::new(true)
+            .resolve_result(Some(Vec::from(&upstream_buf[0..upstream_length])));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::default(),
+            Box::new(upstream_resolver),
+            None,
+        );
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("good.com", 0x000C)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        let rsp_length = subject.process(
+            &mut buf,
+            req_length,
+            &addr,
+            &Logger::new("forward_returns_server_failure_when_upstream_answers"),
+        );
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x2));
+        assert_eq!(response.get_answers().unwrap().len(), 0);
+        TestLogHandler::new().exists_log_containing(
+            "ERROR: forward_returns_server_failure_when_upstream_answers: Upstream DNS server sent an unusable response for good.com",
+        );
+    }
+
+    #[test]
+    fn forward_without_upstream_servers_gets_empty_answer() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("text.com", 0x0010)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x0));
+        assert_eq!(response.get_queries().unwrap().len(), 1);
+        assert_eq!(response.get_answers().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn empty_policy_gets_empty_answer_without_consulting_upstream() {
+        let resolve_params_arc = Arc::new(Mutex::new(vec![]));
+        let subject = ProcessorReal::new(
+            IpAddr::from_str("18.52.86.120").unwrap(),
+            None,
+            RecordPolicyTable::from_overrides("A:empty,MX:empty").unwrap(),
+            Box::new(UpstreamResolverMock::new(true).resolve_params(&resolve_params_arc)),
+            None,
+        );
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_request(&mut buf, vec![("mail.com", 0x000F), ("mail.com", 0x0001)]);
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode(), Some(0x0));
+        assert_eq!(response.get_answers().unwrap().len(), 0);
+        assert_eq!(resolve_params_arc.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn resource_records_in_request_are_not_echoed_in_response() {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = {
+            let length = make_request(&mut buf, vec![("ooga.com", 0x0001)]);
+            let mut request = PacketFacade::new(&mut buf, length);
+            request.add_additional("", 0x0029, 0x1000, 0, &[]);
+            request.get_length()
+        };
+        let addr = SocketAddr::from(SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let subject = make_subject("18.52.86.120", None, RecordPolicyTable::default());
+
+        let rsp_length = subject.process(&mut buf, req_length, &addr, &Logger::new(""));
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_answers().unwrap().len(), 1);
+        assert_eq!(response.get_additionals().unwrap().len(), 0);
+        assert_eq!(rsp_length, 12 + 14 + 24);
+    }
+
+    fn make_subject(
+        target_ip: &str,
+        ipv6_target_opt: Option<&str>,
+        record_policies: RecordPolicyTable,
+    ) -> ProcessorReal {
+        ProcessorReal::new(
+            IpAddr::from_str(target_ip).unwrap(),
+            ipv6_target_opt.map(|ipv6| Ipv6Addr::from_str(ipv6).unwrap()),
+            record_policies,
+            Box::new(UpstreamResolverMock::new(false)),
+            None,
+        )
+    }
+
+    fn make_request(buf: &mut [u8], queries: Vec<(&str, u16)>) -> usize {
+        let length = buf.len();
+        let mut request = PacketFacade::new(buf, length);
+        request.set_transaction_id(0x4321);
+        request.set_query(true);
+        request.set_opcode(0x0);
+        queries.into_iter().for_each(|(name, query_type)| {
+            request.add_query(name, query_type, 0x0001);
+        });
+        request.get_length()
+    }
+
     fn check_format_error_message(mut buf: &mut [u8], transaction_id: u16) {
         let facade = PacketFacade::new(&mut buf, 12);
         assert_eq!(facade.get_transaction_id(), Some(transaction_id));
@@ -0,0 +1,250 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use serde_json;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use sub_lib::logger::Logger;
+
+pub const DEFAULT_QUERY_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+// Besides the live log, keep this many rotated logs: <path>.1 is the newest
+pub const ROTATED_QUERY_LOG_COUNT: usize = 5;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryLogEntry {
+    pub timestamp: String,
+    pub client: String,
+    pub opcode: u8,
+    pub queries: Vec<QueryLogQuery>,
+    pub rcode: u8,
+    pub answers: Vec<QueryLogAnswer>,
+    pub latency_ns: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryLogQuery {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub query_type: u16,
+    pub class: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryLogAnswer {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub resource_type: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: String,
+}
+
+pub trait QueryLog: Send + Sync {
+    fn write(&self, entry: &QueryLogEntry, logger: &Logger);
+}
+
+// Writes one JSON object per line. When the next line would push the file past max_size, the file is
+// renamed to <path>.1 (after <path>.1 becomes <path>.2 and so on) and a new file is started.
+pub struct QueryLogReal {
+    path: PathBuf,
+    max_size: u64,
+    state: Mutex<QueryLogState>,
+}
+
+struct QueryLogState {
+    file: Option<File>,
+    size: u64,
+}
+
+impl QueryLog for QueryLogReal {
+    fn write(&self, entry: &QueryLogEntry, logger: &Logger) {
+        let mut line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                logger.error(format!("Could not serialize DNS query log entry: {}", e));
+                return;
+            }
+        };
+        line.push('\n');
+        let mut state = self.state.lock().expect("DNS query log is poisoned");
+        if (state.size > 0) && (state.size + line.len() as u64 > self.max_size) {
+            if let Err(e) = self.rotate(&mut state) {
+                logger.error(format!(
+                    "Could not rotate DNS query log {}: {}",
+                    self.path.display(),
+                    e
+                ));
+            }
+        }
+        let result = match state.file {
+            Some(ref mut file) => file.write_all(line.as_bytes()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "log file is closed",
+            )),
+        };
+        match result {
+            Ok(()) => state.size += line.len() as u64,
+            Err(e) => logger.error(format!(
+                "Could not write to DNS query log {}: {}",
+                self.path.display(),
+                e
+            )),
+        }
+    }
+}
+
+impl QueryLogReal {
+    pub fn new(path: &Path, max_size: u64) -> io::Result<QueryLogReal> {
+        let file = QueryLogReal::open(path)?;
+        let size = file.metadata()?.len();
+        Ok(QueryLogReal {
+            path: PathBuf::from(path),
+            max_size,
+            state: Mutex::new(QueryLogState {
+                file: Some(file),
+                size,
+            }),
+        })
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotate(&self, state: &mut QueryLogState) -> io::Result<()> {
+        state.file = None;
+        let oldest = self.rotated_path(ROTATED_QUERY_LOG_COUNT);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..ROTATED_QUERY_LOG_COUNT).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        state.file = Some(QueryLogReal::open(&self.path)?);
+        state.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
+
+    fn make_entry(name: &str) -> QueryLogEntry {
+        QueryLogEntry {
+            timestamp: String::from("2018-11-12T13:14:15.123456Z"),
+            client: String::from("1.2.3.4:5678"),
+            opcode: 0,
+            queries: vec![QueryLogQuery {
+                name: String::from(name),
+                query_type: 1,
+                class: 1,
+            }],
+            rcode: 0,
+            answers: vec![QueryLogAnswer {
+                name: String::from(name),
+                resource_type: 1,
+                class: 1,
+                ttl: 3600,
+                data: String::from("5.6.7.8"),
+            }],
+            latency_ns: 1234,
+        }
+    }
+
+    #[test]
+    fn writes_one_json_line_per_entry() {
+        let home_dir =
+            ensure_node_home_directory_exists("query_log", "writes_one_json_line_per_entry");
+        let path = home_dir.join("queries.log");
+        let subject = QueryLogReal::new(&path, DEFAULT_QUERY_LOG_MAX_SIZE).unwrap();
+
+        subject.write(&make_entry("first.com"), &Logger::new("test"));
+        subject.write(&make_entry("second.com"), &Logger::new("test"));
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            String::from("{\"timestamp\":\"2018-11-12T13:14:15.123456Z\",\"client\":\"1.2.3.4:5678\",\"opcode\":0,\"queries\":[{\"name\":\"first.com\",\"type\":1,\"class\":1}],\"rcode\":0,\"answers\":[{\"name\":\"first.com\",\"type\":1,\"class\":1,\"ttl\":3600,\"data\":\"5.6.7.8\"}],\"latency_ns\":1234}\n")
+                + "{\"timestamp\":\"2018-11-12T13:14:15.123456Z\",\"client\":\"1.2.3.4:5678\",\"opcode\":0,\"queries\":[{\"name\":\"second.com\",\"type\":1,\"class\":1}],\"rcode\":0,\"answers\":[{\"name\":\"second.com\",\"type\":1,\"class\":1,\"ttl\":3600,\"data\":\"5.6.7.8\"}],\"latency_ns\":1234}\n"
+        );
+    }
+
+    #[test]
+    fn appends_to_existing_log_and_counts_its_size() {
+        let home_dir = ensure_node_home_directory_exists(
+            "query_log",
+            "appends_to_existing_log_and_counts_its_size",
+        );
+        let path = home_dir.join("queries.log");
+        fs::write(&path, "previous\n").unwrap();
+        let line_length = serde_json::to_string(&make_entry("a.com")).unwrap().len() as u64 + 1;
+        let subject = QueryLogReal::new(&path, line_length + 9).unwrap();
+
+        subject.write(&make_entry("a.com"), &Logger::new("test"));
+        subject.write(&make_entry("b.com"), &Logger::new("test"));
+
+        let rotated = read_to_string(home_dir.join("queries.log.1")).unwrap();
+        assert_eq!(rotated.starts_with("previous\n"), true);
+        assert_eq!(rotated.contains("a.com"), true);
+        let current = read_to_string(&path).unwrap();
+        assert_eq!(current.contains("b.com"), true);
+        assert_eq!(current.lines().count(), 1);
+    }
+
+    #[test]
+    fn rotation_shifts_older_logs_and_discards_the_oldest() {
+        let home_dir = ensure_node_home_directory_exists(
+            "query_log",
+            "rotation_shifts_older_logs_and_discards_the_oldest",
+        );
+        let path = home_dir.join("queries.log");
+        (1..(ROTATED_QUERY_LOG_COUNT + 1)).for_each(|n| {
+            fs::write(
+                home_dir.join(format!("queries.log.{}", n)),
+                format!("{}", n),
+            )
+            .unwrap()
+        });
+        let subject = QueryLogReal::new(&path, 1).unwrap();
+
+        subject.write(&make_entry("first.com"), &Logger::new("test"));
+        subject.write(&make_entry("second.com"), &Logger::new("test"));
+
+        assert_eq!(
+            read_to_string(home_dir.join("queries.log.1"))
+                .unwrap()
+                .contains("first.com"),
+            true
+        );
+        (2..(ROTATED_QUERY_LOG_COUNT + 1)).for_each(|n| {
+            assert_eq!(
+                read_to_string(home_dir.join(format!("queries.log.{}", n))).unwrap(),
+                format!("{}", n - 1)
+            )
+        });
+        assert_eq!(
+            home_dir
+                .join(format!("queries.log.{}", ROTATED_QUERY_LOG_COUNT + 1))
+                .exists(),
+            false
+        );
+        assert_eq!(read_to_string(&path).unwrap().contains("second.com"), true);
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::logger::Logger;
+
+pub const DEFAULT_CLIENT_RATE_LIMIT: u32 = 100;
+pub const DEFAULT_GLOBAL_RATE_LIMIT: u32 = 1000;
+pub const RATE_LIMIT_REPORT_INTERVAL_SECS: u64 = 10;
+// Beyond this many clients, buckets that have filled back up are forgotten at the next report
+const MAX_IDLE_CLIENT_BUCKETS: usize = 1024;
+// Never more than this many, however many clients turn up between reports; clients beyond it are held only to the
+// global limit
+const MAX_CLIENT_BUCKETS: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimitAction {
+    // Ignore the query, so that a spoofed source address gets nothing at all
+    Drop,
+    // Send back a bare REFUSED header, which is never bigger than the query
+    Refuse,
+}
+
+impl FromStr for RateLimitAction {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<RateLimitAction, String> {
+        match action {
+            "drop" => Ok(RateLimitAction::Drop),
+            "refuse" => Ok(RateLimitAction::Refuse),
+            _ => Err(format!(
+                "Rate limit action must be drop or refuse, not '{}'",
+                action
+            )),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: capacity as f64,
+            last_refill: now,
+        }
+    }
+
+    // Each bucket holds one second's worth of queries and refills continuously at the same rate
+    fn refill(&mut self, rate: u32, now: Instant) {
+        if now > self.last_refill {
+            let elapsed = now.duration_since(self.last_refill);
+            let seconds =
+                elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1_000_000_000.0);
+            self.tokens = (self.tokens + seconds * rate as f64).min(rate as f64);
+            self.last_refill = now;
+        }
+    }
+
+    fn take(&mut self, rate: u32, now: Instant) -> bool {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&mut self, rate: u32, now: Instant) -> bool {
+        self.refill(rate, now);
+        self.tokens >= rate as f64
+    }
+}
+
+pub struct RateLimiter {
+    client_rate: u32,
+    global_rate: u32,
+    client_buckets: HashMap<IpAddr, TokenBucket>,
+    global_bucket: TokenBucket,
+    client_limited_count: u64,
+    global_limited_count: u64,
+    limited_clients: HashSet<IpAddr>,
+    last_report: Instant,
+    last_prune: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(client_rate: u32, global_rate: u32, now: Instant) -> RateLimiter {
+        RateLimiter {
+            client_rate,
+            global_rate,
+            client_buckets: HashMap::new(),
+            global_bucket: TokenBucket::new(global_rate, now),
+            client_limited_count: 0,
+            global_limited_count: 0,
+            limited_clients: HashSet::new(),
+            last_report: now,
+            last_prune: now,
+        }
+    }
+
+    // Returns false if the query should not be answered
+    pub fn allow(&mut self, client: IpAddr, now: Instant) -> bool {
+        if !self.client_buckets.contains_key(&client) && !self.make_room_for_client(now) {
+            return self.allow_globally(now);
+        }
+        let client_rate = self.client_rate;
+        let client_allowed = self
+            .client_buckets
+            .entry(client)
+            .or_insert_with(|| TokenBucket::new(client_rate, now))
+            .take(client_rate, now);
+        if !client_allowed {
+            self.client_limited_count += 1;
+            if self.limited_clients.len() < MAX_CLIENT_BUCKETS {
+                self.limited_clients.insert(client);
+            }
+            return false;
+        }
+        self.allow_globally(now)
+    }
+
+    fn allow_globally(&mut self, now: Instant) -> bool {
+        if !self.global_bucket.take(self.global_rate, now) {
+            self.global_limited_count += 1;
+            return false;
+        }
+        true
+    }
+
+    // Forgets clients whose buckets have filled back up, but no more than once a second, since a full table of
+    // busy clients would otherwise be scanned for every new one
+    fn make_room_for_client(&mut self, now: Instant) -> bool {
+        if self.client_buckets.len() < MAX_CLIENT_BUCKETS {
+            return true;
+        }
+        if now >= self.last_prune + Duration::from_secs(1) {
+            let client_rate = self.client_rate;
+            self.client_buckets
+                .retain(|_, bucket| !bucket.is_full(client_rate, now));
+            self.last_prune = now;
+        }
+        self.client_buckets.len() < MAX_CLIENT_BUCKETS
+    }
+
+    // Logs how many queries were limited since the last report, if any were, and forgets idle clients
+    pub fn report(&mut self, now: Instant, logger: &Logger) {
+        if now.duration_since(self.last_report)
+            < Duration::from_secs(RATE_LIMIT_REPORT_INTERVAL_SECS)
+        {
+            return;
+        }
+        if (self.client_limited_count > 0) || (self.global_limited_count > 0) {
+            logger.warning(format!(
+                "Rate-limited {} DNS queries in the last {}s: {} from {} clients over the per-client limit of {}/s, {} over the global limit of {}/s",
+                self.client_limited_count + self.global_limited_count,
+                now.duration_since(self.last_report).as_secs(),
+                self.client_limited_count,
+                self.limited_clients.len(),
+                self.client_rate,
+                self.global_limited_count,
+                self.global_rate
+            ));
+        }
+        self.client_limited_count = 0;
+        self.global_limited_count = 0;
+        self.limited_clients.clear();
+        self.last_report = now;
+        if self.client_buckets.len() > MAX_IDLE_CLIENT_BUCKETS {
+            let client_rate = self.client_rate;
+            self.client_buckets
+                .retain(|_, bucket| !bucket.is_full(client_rate, now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use test_utils::logging::init_test_logging;
+    use test_utils::logging::TestLogHandler;
+
+    #[test]
+    fn action_parses_drop_and_refuse() {
+        assert_eq!(RateLimitAction::from_str("drop"), Ok(RateLimitAction::Drop));
+        assert_eq!(
+            RateLimitAction::from_str("refuse"),
+            Ok(RateLimitAction::Refuse)
+        );
+        assert_eq!(
+            RateLimitAction::from_str("booga"),
+            Err(String::from(
+                "Rate limit action must be drop or refuse, not 'booga'"
+            ))
+        );
+    }
+
+    #[test]
+    fn client_is_limited_after_its_burst_and_recovers_over_time() {
+        let now = Instant::now();
+        let client = IpAddr::from_str("1.2.3.4").unwrap();
+        let mut subject = RateLimiter::new(2, 1000, now);
+
+        let results = vec![
+            subject.allow(client, now),
+            subject.allow(client, now),
+            subject.allow(client, now),
+            subject.allow(client, now + Duration::from_millis(499)),
+            subject.allow(client, now + Duration::from_millis(501)),
+            subject.allow(client, now + Duration::from_millis(501)),
+        ];
+
+        assert_eq!(results, vec![true, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn clients_are_limited_independently() {
+        let now = Instant::now();
+        let mut subject = RateLimiter::new(1, 1000, now);
+
+        let results = vec![
+            subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now),
+            subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now),
+            subject.allow(IpAddr::from_str("2.3.4.5").unwrap(), now),
+        ];
+
+        assert_eq!(results, vec![true, false, true]);
+    }
+
+    #[test]
+    fn global_limit_applies_across_clients() {
+        let now = Instant::now();
+        let mut subject = RateLimiter::new(10, 2, now);
+
+        let results = vec![
+            subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now),
+            subject.allow(IpAddr::from_str("2.3.4.5").unwrap(), now),
+            subject.allow(IpAddr::from_str("3.4.5.6").unwrap(), now),
+            subject.allow(
+                IpAddr::from_str("3.4.5.6").unwrap(),
+                now + Duration::from_secs(1),
+            ),
+        ];
+
+        assert_eq!(results, vec![true, true, false, true]);
+    }
+
+    #[test]
+    fn report_logs_and_resets_counts_once_per_interval() {
+        init_test_logging();
+        let now = Instant::now();
+        let logger = Logger::new("report_logs_and_resets_counts_once_per_interval");
+        let mut subject = RateLimiter::new(1, 2, now);
+        subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now);
+        subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now);
+        subject.allow(IpAddr::from_str("1.2.3.4").unwrap(), now);
+        subject.allow(IpAddr::from_str("2.3.4.5").unwrap(), now);
+        subject.allow(IpAddr::from_str("3.4.5.6").unwrap(), now);
+
+        subject.report(now + Duration::from_secs(9), &logger);
+        let early_count = subject.client_limited_count + subject.global_limited_count;
+        subject.report(now + Duration::from_secs(10), &logger);
+
+        assert_eq!(early_count, 3);
+        assert_eq!(subject.client_limited_count, 0);
+        assert_eq!(subject.global_limited_count, 0);
+        assert_eq!(subject.limited_clients.len(), 0);
+        TestLogHandler::new().exists_log_containing("WARN: report_logs_and_resets_counts_once_per_interval: Rate-limited 3 DNS queries in the last 10s: 2 from 1 clients over the per-client limit of 1/s, 1 over the global limit of 2/s");
+    }
+
+    #[test]
+    fn report_forgets_idle_clients_when_there_are_many() {
+        let now = Instant::now();
+        let mut subject = RateLimiter::new(1, 100000, now);
+        (0..(MAX_IDLE_CLIENT_BUCKETS as u32 + 1)).for_each(|n| {
+            subject.allow(IpAddr::from(Ipv4Addr::from(n)), now);
+        });
+        subject.allow(
+            IpAddr::from_str("1.2.3.4").unwrap(),
+            now + Duration::from_secs(10),
+        );
+
+        subject.report(now + Duration::from_secs(10), &Logger::new("test"));
+
+        assert_eq!(subject.client_buckets.len(), 1);
+        assert_eq!(
+            subject
+                .client_buckets
+                .contains_key(&IpAddr::from_str("1.2.3.4").unwrap()),
+            true
+        );
+    }
+
+    #[test]
+    fn clients_beyond_the_bucket_limit_are_held_only_to_the_global_limit() {
+        let now = Instant::now();
+        let newcomer = IpAddr::from_str("255.255.255.255").unwrap();
+        let mut subject = RateLimiter::new(1, 100000, now);
+        (0..(MAX_CLIENT_BUCKETS as u32)).for_each(|n| {
+            subject.allow(IpAddr::from(Ipv4Addr::from(n)), now);
+        });
+
+        let results = vec![subject.allow(newcomer, now), subject.allow(newcomer, now)];
+
+        assert_eq!(results, vec![true, true]);
+        assert_eq!(subject.client_buckets.len(), MAX_CLIENT_BUCKETS);
+        assert_eq!(subject.client_buckets.contains_key(&newcomer), false);
+    }
+
+    #[test]
+    fn idle_clients_make_room_for_new_ones_when_the_bucket_limit_is_reached() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let newcomer = IpAddr::from_str("255.255.255.255").unwrap();
+        let mut subject = RateLimiter::new(1, 100000, now);
+        (0..(MAX_CLIENT_BUCKETS as u32)).for_each(|n| {
+            subject.allow(IpAddr::from(Ipv4Addr::from(n)), now);
+        });
+
+        let results = vec![
+            subject.allow(newcomer, later),
+            subject.allow(newcomer, later),
+        ];
+
+        assert_eq!(results, vec![true, false]);
+        assert_eq!(subject.client_buckets.len(), 1);
+        assert_eq!(subject.client_buckets.contains_key(&newcomer), true);
+    }
+}
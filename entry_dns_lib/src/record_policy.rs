@@ -0,0 +1,193 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use packet_facade::AAAA_RECORD_TYPE;
+use packet_facade::A_RECORD_TYPE;
+use packet_facade::CNAME_RECORD_TYPE;
+use packet_facade::MX_RECORD_TYPE;
+use packet_facade::NS_RECORD_TYPE;
+use packet_facade::PTR_RECORD_TYPE;
+use packet_facade::SOA_RECORD_TYPE;
+use packet_facade::SRV_RECORD_TYPE;
+use packet_facade::TXT_RECORD_TYPE;
+use std::collections::HashMap;
+
+const RECORD_TYPE_NAMES: [(&str, u16); 9] = [
+    ("A", A_RECORD_TYPE),
+    ("NS", NS_RECORD_TYPE),
+    ("CNAME", CNAME_RECORD_TYPE),
+    ("SOA", SOA_RECORD_TYPE),
+    ("PTR", PTR_RECORD_TYPE),
+    ("MX", MX_RECORD_TYPE),
+    ("TXT", TXT_RECORD_TYPE),
+    ("AAAA", AAAA_RECORD_TYPE),
+    ("SRV", SRV_RECORD_TYPE),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordPolicy {
+    // Answer with the Node's IP address
+    Answer,
+    // Respond NOERROR with no answer
+    Empty,
+    // Ask the upstream DNS servers and relay their response
+    Forward,
+}
+
+// Query types with no policy get a NOTIMP response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordPolicyTable {
+    policies: HashMap<u16, RecordPolicy>,
+}
+
+impl Default for RecordPolicyTable {
+    fn default() -> Self {
+        let mut policies = HashMap::new();
+        policies.insert(A_RECORD_TYPE, RecordPolicy::Answer);
+        policies.insert(AAAA_RECORD_TYPE, RecordPolicy::Answer);
+        policies.insert(CNAME_RECORD_TYPE, RecordPolicy::Forward);
+        policies.insert(MX_RECORD_TYPE, RecordPolicy::Forward);
+        policies.insert(TXT_RECORD_TYPE, RecordPolicy::Forward);
+        policies.insert(SRV_RECORD_TYPE, RecordPolicy::Forward);
+        policies.insert(PTR_RECORD_TYPE, RecordPolicy::Forward);
+        RecordPolicyTable { policies }
+    }
+}
+
+impl RecordPolicyTable {
+    // Overrides the defaults with a comma-separated list of <type>:<policy> pairs, like "MX:empty,SOA:forward".
+    // A type may be a name like "MX" or a number like "15".
+    pub fn from_overrides(overrides: &str) -> Result<RecordPolicyTable, String> {
+        let mut result = RecordPolicyTable::default();
+        for pair in overrides.split(',') {
+            let mut pieces = pair.splitn(2, ':');
+            let type_str = pieces.next().expect("Internal error");
+            let policy_str = match pieces.next() {
+                Some(policy_str) => policy_str,
+                None => return Err(format!("'{}' is not of the form <type>:<policy>", pair)),
+            };
+            let record_type = RecordPolicyTable::parse_record_type(type_str)?;
+            let policy = match policy_str {
+                "answer" => RecordPolicy::Answer,
+                "empty" => RecordPolicy::Empty,
+                "forward" => RecordPolicy::Forward,
+                _ => {
+                    return Err(format!(
+                        "Policy must be answer, empty, or forward, not '{}'",
+                        policy_str
+                    ))
+                }
+            };
+            if (policy == RecordPolicy::Answer)
+                && (record_type != A_RECORD_TYPE)
+                && (record_type != AAAA_RECORD_TYPE)
+            {
+                return Err(format!(
+                    "Only A and AAAA queries can be answered with the Node's IP address, not {}",
+                    type_str
+                ));
+            }
+            result.policies.insert(record_type, policy);
+        }
+        Ok(result)
+    }
+
+    pub fn policy_for(&self, record_type: u16) -> Option<RecordPolicy> {
+        self.policies.get(&record_type).cloned()
+    }
+
+    fn parse_record_type(type_str: &str) -> Result<u16, String> {
+        match RECORD_TYPE_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(type_str))
+        {
+            Some((_, record_type)) => Ok(*record_type),
+            None => match type_str.parse::<u16>() {
+                Ok(record_type) => Ok(record_type),
+                Err(_) => Err(format!("Unknown DNS record type '{}'", type_str)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_table_answers_addresses_and_forwards_other_common_types() {
+        let subject = RecordPolicyTable::default();
+
+        assert_eq!(
+            subject.policy_for(A_RECORD_TYPE),
+            Some(RecordPolicy::Answer)
+        );
+        assert_eq!(
+            subject.policy_for(AAAA_RECORD_TYPE),
+            Some(RecordPolicy::Answer)
+        );
+        vec![
+            CNAME_RECORD_TYPE,
+            MX_RECORD_TYPE,
+            TXT_RECORD_TYPE,
+            SRV_RECORD_TYPE,
+            PTR_RECORD_TYPE,
+        ]
+        .into_iter()
+        .for_each(|record_type| {
+            assert_eq!(
+                subject.policy_for(record_type),
+                Some(RecordPolicy::Forward),
+                "{}",
+                record_type
+            )
+        });
+        assert_eq!(subject.policy_for(SOA_RECORD_TYPE), None);
+        assert_eq!(subject.policy_for(0x0000), None);
+    }
+
+    #[test]
+    fn overrides_replace_and_extend_defaults() {
+        let subject = RecordPolicyTable::from_overrides("mx:empty,SOA:forward,65:empty").unwrap();
+
+        assert_eq!(
+            subject.policy_for(MX_RECORD_TYPE),
+            Some(RecordPolicy::Empty)
+        );
+        assert_eq!(
+            subject.policy_for(SOA_RECORD_TYPE),
+            Some(RecordPolicy::Forward)
+        );
+        assert_eq!(subject.policy_for(65), Some(RecordPolicy::Empty));
+        assert_eq!(
+            subject.policy_for(TXT_RECORD_TYPE),
+            Some(RecordPolicy::Forward)
+        );
+    }
+
+    #[test]
+    fn overrides_complain_about_malformed_pairs() {
+        assert_eq!(
+            RecordPolicyTable::from_overrides("MX"),
+            Err(String::from("'MX' is not of the form <type>:<policy>"))
+        );
+        assert_eq!(
+            RecordPolicyTable::from_overrides("BOOGA:empty"),
+            Err(String::from("Unknown DNS record type 'BOOGA'"))
+        );
+        assert_eq!(
+            RecordPolicyTable::from_overrides("MX:booga"),
+            Err(String::from(
+                "Policy must be answer, empty, or forward, not 'booga'"
+            ))
+        );
+    }
+
+    #[test]
+    fn overrides_refuse_to_answer_non_address_queries() {
+        assert_eq!(
+            RecordPolicyTable::from_overrides("AAAA:answer,TXT:answer"),
+            Err(String::from(
+                "Only A and AAAA queries can be answered with the Node's IP address, not TXT"
+            ))
+        );
+    }
+}
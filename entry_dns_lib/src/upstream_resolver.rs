@@ -0,0 +1,262 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::logger::Logger;
+
+pub const UPSTREAM_TIMEOUT_MS: u64 = 2000;
+pub const UPSTREAM_DEADLINE_MS: u64 = 5000;
+
+pub trait UpstreamResolver: Send + Sync {
+    fn has_servers(&self) -> bool;
+    fn resolve(&self, request: &[u8], logger: &Logger) -> Option<Vec<u8>>;
+}
+
+// Sends a request to each upstream server in turn until one of them responds. Each server gets at most timeout to
+// answer, and the whole request gives up once deadline has passed. This blocks while it waits, so it must not be
+// called on the entry DNS server's own thread.
+pub struct UpstreamResolverReal {
+    dns_servers: Vec<SocketAddr>,
+    timeout: Duration,
+    deadline: Duration,
+}
+
+impl UpstreamResolver for UpstreamResolverReal {
+    fn has_servers(&self) -> bool {
+        !self.dns_servers.is_empty()
+    }
+
+    fn resolve(&self, request: &[u8], logger: &Logger) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + self.deadline;
+        for dns_server in &self.dns_servers {
+            let now = Instant::now();
+            if now >= deadline {
+                logger.warning(format!(
+                    "Gave up on upstream DNS servers after {}ms",
+                    millis(self.deadline)
+                ));
+                break;
+            }
+            let server_timeout = if deadline - now < self.timeout {
+                deadline - now
+            } else {
+                self.timeout
+            };
+            match self.resolve_with(dns_server, request, now + server_timeout) {
+                Ok(Some(response)) => return Some(response),
+                Ok(None) => logger.warning(format!(
+                    "Upstream DNS server {} did not respond within {}ms",
+                    dns_server,
+                    millis(server_timeout)
+                )),
+                Err(e) => logger.error(format!(
+                    "Could not consult upstream DNS server {}: {}",
+                    dns_server, e
+                )),
+            }
+        }
+        None
+    }
+}
+
+impl UpstreamResolverReal {
+    pub fn new(dns_servers: Vec<SocketAddr>) -> UpstreamResolverReal {
+        UpstreamResolverReal::with_timeouts(
+            dns_servers,
+            Duration::from_millis(UPSTREAM_TIMEOUT_MS),
+            Duration::from_millis(UPSTREAM_DEADLINE_MS),
+        )
+    }
+
+    pub fn with_timeouts(
+        dns_servers: Vec<SocketAddr>,
+        timeout: Duration,
+        deadline: Duration,
+    ) -> UpstreamResolverReal {
+        UpstreamResolverReal {
+            dns_servers,
+            timeout,
+            deadline,
+        }
+    }
+
+    fn resolve_with(
+        &self,
+        dns_server: &SocketAddr,
+        request: &[u8],
+        give_up_at: Instant,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let local_ip = match dns_server {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(0)),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::from([0u8; 16])),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0)).map_err(|e| format!("{}", e))?;
+        socket
+            .send_to(request, dns_server)
+            .map_err(|e| format!("{}", e))?;
+        let mut buf = [0u8; 65536];
+        loop {
+            // Strangers' packets mustn't buy the server more time, so the timeout shrinks as we go
+            let now = Instant::now();
+            if now >= give_up_at {
+                return Ok(None);
+            }
+            socket
+                .set_read_timeout(Some(give_up_at - now))
+                .map_err(|e| format!("{}", e))?;
+            let (length, from) = match socket.recv_from(&mut buf) {
+                Ok(pair) => pair,
+                Err(_) => return Ok(None),
+            };
+            // Ignore anything that isn't the response to our request from the server we asked
+            if (from == *dns_server) && (length >= 2) && (buf[0..2] == request[0..2]) {
+                return Ok(Some(Vec::from(&buf[0..length])));
+            }
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use test_utils::logging::init_test_logging;
+    use test_utils::logging::TestLogHandler;
+
+    #[test]
+    fn has_servers_only_if_it_has_servers() {
+        assert_eq!(UpstreamResolverReal::new(vec![]).has_servers(), false);
+        assert_eq!(
+            UpstreamResolverReal::new(vec![SocketAddr::from_str("1.1.1.1:53").unwrap()])
+                .has_servers(),
+            true
+        );
+    }
+
+    #[test]
+    fn resolve_ignores_strangers_and_returns_matching_response() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 100];
+            let (length, from) = upstream.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[0..length], &[0x12, 0x34, 0x01, 0x00][..]);
+            let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+            stranger.send_to(&[0x12, 0x34, 0xFF], from).unwrap();
+            upstream.send_to(&[0x43, 0x21, 0xFF], from).unwrap();
+            upstream.send_to(&[0x12, 0x34, 0x81, 0x80], from).unwrap();
+        });
+        let subject = UpstreamResolverReal::with_timeouts(
+            vec![upstream_addr],
+            Duration::from_millis(5000),
+            Duration::from_millis(5000),
+        );
+
+        let result = subject.resolve(&[0x12, 0x34, 0x01, 0x00], &Logger::new("test"));
+
+        handle.join().unwrap();
+        assert_eq!(result, Some(vec![0x12, 0x34, 0x81, 0x80]));
+    }
+
+    #[test]
+    fn resolve_moves_on_to_next_server_after_timeout() {
+        init_test_logging();
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 100];
+            let (length, from) = upstream.recv_from(&mut buf).unwrap();
+            upstream.send_to(&buf[0..length], from).unwrap();
+        });
+        let subject = UpstreamResolverReal::with_timeouts(
+            vec![silent_addr, upstream_addr],
+            Duration::from_millis(100),
+            Duration::from_millis(5000),
+        );
+
+        let result = subject.resolve(
+            &[0x56, 0x78],
+            &Logger::new("resolve_moves_on_to_next_server_after_timeout"),
+        );
+
+        handle.join().unwrap();
+        assert_eq!(result, Some(vec![0x56, 0x78]));
+        TestLogHandler::new().exists_log_containing(&format!(
+            "WARN: resolve_moves_on_to_next_server_after_timeout: Upstream DNS server {} did not respond within 100ms",
+            silent_addr
+        ));
+    }
+
+    #[test]
+    fn resolve_returns_none_if_nobody_responds() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let subject = UpstreamResolverReal::with_timeouts(
+            vec![silent.local_addr().unwrap()],
+            Duration::from_millis(100),
+            Duration::from_millis(5000),
+        );
+
+        let result = subject.resolve(&[0x56, 0x78], &Logger::new("test"));
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn resolve_gives_up_at_the_deadline_even_if_strangers_keep_talking() {
+        init_test_logging();
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let never_asked = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let never_asked_addr = never_asked.local_addr().unwrap();
+        let chatterbox_done = Arc::new(AtomicBool::new(false));
+        let chatterbox_done_inner = chatterbox_done.clone();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 100];
+            let (_, from) = silent.recv_from(&mut buf).unwrap();
+            let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+            while !chatterbox_done_inner.load(Ordering::Relaxed) {
+                stranger.send_to(&[0x99, 0x99, 0xFF], from).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let subject = UpstreamResolverReal::with_timeouts(
+            vec![silent_addr, never_asked_addr],
+            Duration::from_millis(5000),
+            Duration::from_millis(300),
+        );
+        let started = Instant::now();
+
+        let result = subject.resolve(
+            &[0x56, 0x78],
+            &Logger::new("resolve_gives_up_at_the_deadline"),
+        );
+
+        let elapsed = started.elapsed();
+        chatterbox_done.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+        assert_eq!(result, None);
+        assert!(elapsed < Duration::from_millis(4000));
+        let tlh = TestLogHandler::new();
+        tlh.exists_log_matching(&format!(
+            "WARN: resolve_gives_up_at_the_deadline: Upstream DNS server {} did not respond within \\d+ms",
+            silent_addr
+        ));
+        tlh.exists_log_containing(
+            "WARN: resolve_gives_up_at_the_deadline: Gave up on upstream DNS servers after 300ms",
+        );
+    }
+}
@@ -7,6 +7,7 @@ use sub_lib::cryptde::Key;
 use sub_lib::cryptde_null::CryptDENull;
 use sub_lib::dispatcher::Component;
 use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::neighborhood::ExitPolicy;
 use sub_lib::route::Route;
 use sub_lib::route::RouteSegment;
 use substratum_node::SubstratumNode;
@@ -35,6 +36,7 @@ impl GossipBuilder {
                 is_bootstrap_node: is_bootstrap,
                 neighbors: vec![],
                 version: 0,
+                exit_policy: ExitPolicy::allow_all(),
             },
             cryptde: Box::new(CryptDENull::from(&node.public_key())),
         });
@@ -57,31 +59,32 @@ impl GossipBuilder {
     }
 
     pub fn build(self) -> Gossip {
-        let mut node_records: Vec<GossipNodeRecord> = self
-            .node_info
-            .into_iter()
-            .map(|node_info| {
-                let signatures =
-                    NodeSignatures::from(node_info.cryptde.as_ref(), &node_info.node_record_inner);
-                GossipNodeRecord {
-                    inner: node_info.node_record_inner,
-                    signatures,
-                }
-            })
-            .collect();
-
+        let mut node_info = self.node_info;
         self.connection_pairs.iter ().for_each (|pair_ref| {
             let from_key = pair_ref.0.clone ();
-            let from_node_ref_opt = node_records.iter_mut ().find (|n| n.inner.public_key == from_key);
+            let from_node_ref_opt = node_info.iter_mut ().find (|n| n.node_record_inner.public_key == from_key);
             let to_key = pair_ref.1.clone ();
             if let Some (from_node_ref) = from_node_ref_opt {
-                from_node_ref.inner.neighbors.push (to_key);
+                from_node_ref.node_record_inner.neighbors.push (to_key);
             }
             else {
                 panic! ("You directed that {:?} should be made a neighbor of {:?}, but {:?} was never added to the GossipBuilder",
                     to_key, from_key, from_key)
             }
         });
+
+        // Signed only now that the neighbors are in place, so that the signatures match what's sent
+        let node_records: Vec<GossipNodeRecord> = node_info
+            .into_iter()
+            .map(|node_info| {
+                let signatures =
+                    NodeSignatures::from(node_info.cryptde.as_ref(), &node_info.node_record_inner);
+                GossipNodeRecord {
+                    inner: node_info.node_record_inner,
+                    signatures,
+                }
+            })
+            .collect();
         Gossip { node_records }
     }
 
@@ -79,6 +79,9 @@ impl NodeStartupConfig {
         args.push(format!("{}", self.dns_port));
         args.push("--log_level".to_string());
         args.push("trace".to_string());
+        // Mock Nodes in these tests read CORES packages with CryptDENull
+        args.push("--cryptde".to_string());
+        args.push("null".to_string());
         args
     }
 
@@ -626,7 +629,9 @@ mod tests {
                 "--dns_port",
                 "53",
                 "--log_level",
-                "trace"
+                "trace",
+                "--cryptde",
+                "null"
             ))
         );
     }
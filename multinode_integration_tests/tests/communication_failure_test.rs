@@ -14,12 +14,15 @@ use multinode_integration_tests_lib::substratum_node::PortSelector;
 use multinode_integration_tests_lib::substratum_node::SubstratumNode;
 use multinode_integration_tests_lib::substratum_node_cluster::SubstratumNodeCluster;
 use multinode_integration_tests_lib::substratum_real_node::NodeStartupConfigBuilder;
-use neighborhood_lib::gossip::Gossip;
+use neighborhood_lib::gossip::GossipDigest;
+use neighborhood_lib::gossip::GossipRequest;
+use neighborhood_lib::gossip::GossipResponse;
 use neighborhood_lib::neighborhood_database::NodeRecordInner;
 use node_lib::json_masquerader::JsonMasquerader;
 use std::time::Duration;
 use sub_lib::dispatcher::Component;
 use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::neighborhood::ExitPolicy;
 use sub_lib::node_addr::NodeAddr;
 use sub_lib::route::Route;
 use sub_lib::route::RouteSegment;
@@ -48,6 +51,7 @@ fn neighborhood_notified_of_missing_node_when_connection_refused() {
             is_bootstrap_node: false,
             neighbors: vec![],
             version: 0,
+            exit_policy: ExitPolicy::allow_all(),
         })
         .add_connection(&mock_bootstrap.public_key(), &subject.public_key())
         .add_connection(&subject.public_key(), &refusing_node_key)
@@ -93,13 +97,43 @@ fn neighborhood_notified_of_missing_node_when_connection_refused() {
     let _rebroadcast_bootstrap_gossip_package =
         mock_bootstrap.wait_for_package(&JsonMasquerader::new(), Duration::from_millis(1000));
 
-    let (_, _, new_neighborhood_gossip_package) = mock_bootstrap
+    let (_, _, new_neighborhood_digest_package) = mock_bootstrap
         .wait_for_package(&JsonMasquerader::new(), Duration::from_millis(1000))
         .unwrap();
-    let cores_package = new_neighborhood_gossip_package.to_expired(mock_bootstrap.cryptde());
-    let gossip: Gossip = cores_package.payload().unwrap();
-    let subject_record = gossip
-        .node_records
+    let cores_package = new_neighborhood_digest_package.to_expired(mock_bootstrap.cryptde());
+    let _digest: GossipDigest = cores_package.payload().unwrap();
+    let request = GossipRequest {
+        sender: mock_bootstrap.public_key(),
+        requested_keys: vec![subject.public_key()],
+    };
+    let request_package = IncipientCoresPackage::new(
+        Route::new(
+            vec![RouteSegment::new(
+                vec![&mock_bootstrap.public_key(), &subject.public_key()],
+                Component::Neighborhood,
+            )],
+            mock_bootstrap.cryptde(),
+        )
+        .unwrap(),
+        request,
+        &subject.public_key(),
+    );
+    mock_bootstrap
+        .transmit_package(
+            5550,
+            request_package,
+            &masquerader,
+            &subject.public_key(),
+            subject.socket_addr(PortSelector::First),
+        )
+        .unwrap();
+    let (_, _, response_package) = mock_bootstrap
+        .wait_for_package(&JsonMasquerader::new(), Duration::from_millis(1000))
+        .unwrap();
+    let cores_package = response_package.to_expired(mock_bootstrap.cryptde());
+    let response: GossipResponse = cores_package.payload().unwrap();
+    let subject_record = response
+        .requested_records
         .iter()
         .find(|&x| x.inner.public_key == subject.public_key())
         .expect("should have the subject node record");
@@ -29,6 +29,7 @@ use sub_lib::cryptde_null::CryptDENull;
 use sub_lib::dispatcher::Component;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::http_server_impersonator;
+use sub_lib::neighborhood::ExitPolicy;
 use sub_lib::proxy_client::ClientResponsePayload;
 use sub_lib::proxy_server::ClientRequestPayload;
 use sub_lib::proxy_server::ProxyProtocol;
@@ -62,6 +63,7 @@ fn http_request_to_cores_package_and_cores_package_to_http_response_test() {
         is_bootstrap_node: false,
         neighbors: vec![mock_bootstrap.public_key()],
         version: 0,
+        exit_policy: ExitPolicy::allow_all(),
     };
     let cryptde = CryptDENull::from(&subject.public_key());
     let complete_signature = inner.generate_signature(&cryptde);
@@ -72,6 +74,7 @@ fn http_request_to_cores_package_and_cores_package_to_http_response_test() {
         is_bootstrap_node: false,
         neighbors: vec![mock_bootstrap.public_key()],
         version: 0,
+        exit_policy: ExitPolicy::allow_all(),
     };
     let obscured_signature = obscured_inner.generate_signature(&cryptde);
     assert_eq!(
@@ -455,6 +458,7 @@ fn make_gossip(pairs: Vec<(&NodeReference, bool)>) -> Gossip {
             is_bootstrap_node: false,
             neighbors: vec![],
             version: 0,
+            exit_policy: ExitPolicy::allow_all(),
         };
         let (complete_signature, obscured_signature) = {
             let mut nr = NodeRecord::new(
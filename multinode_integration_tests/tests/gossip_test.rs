@@ -9,17 +9,31 @@ extern crate serde_cbor;
 extern crate sub_lib;
 extern crate test_utils;
 
+use multinode_integration_tests_lib::gossip_builder::GossipBuilder;
 use multinode_integration_tests_lib::substratum_cores_server::SubstratumCoresServer;
+use multinode_integration_tests_lib::substratum_mock_node::SubstratumMockNode;
+use multinode_integration_tests_lib::substratum_node::PortSelector;
 use multinode_integration_tests_lib::substratum_node::SubstratumNode;
 use multinode_integration_tests_lib::substratum_node_cluster::SubstratumNodeCluster;
 use multinode_integration_tests_lib::substratum_real_node::NodeStartupConfigBuilder;
 use neighborhood_lib::gossip::Gossip;
+use neighborhood_lib::gossip::GossipDigest;
 use neighborhood_lib::gossip::GossipNodeRecord;
+use neighborhood_lib::gossip::GossipRequest;
+use neighborhood_lib::gossip::GossipResponse;
 use neighborhood_lib::neighborhood_database::NodeRecord;
 use neighborhood_lib::neighborhood_database::NodeRecordInner;
 use neighborhood_lib::neighborhood_database::NodeSignatures;
+use node_lib::json_masquerader::JsonMasquerader;
 use std::time::Duration;
+use sub_lib::cryptde::Key;
+use sub_lib::cryptde::PlainData;
 use sub_lib::cryptde_null::CryptDENull;
+use sub_lib::dispatcher::Component;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::neighborhood::ExitPolicy;
+use sub_lib::route::Route;
+use sub_lib::route::RouteSegment;
 use test_utils::test_utils::assert_contains;
 
 #[test]
@@ -44,6 +58,7 @@ fn when_bootstrapping_from_a_node_then_the_node_sends_gossip_upon_startup() {
         is_bootstrap_node: false,
         neighbors: vec![bootstrap_node_ref.public_key.clone()],
         version: 0,
+        exit_policy: ExitPolicy::allow_all(),
     };
     let (complete_signature, obscured_signature) = {
         let mut nr = NodeRecord::new(
@@ -67,3 +82,122 @@ fn when_bootstrapping_from_a_node_then_the_node_sends_gossip_upon_startup() {
         },
     );
 }
+
+#[test]
+fn gossip_digest_and_response_are_smaller_than_full_gossip() {
+    let masquerader = JsonMasquerader::new();
+    let mut cluster = SubstratumNodeCluster::start().unwrap();
+    let mock_bootstrap = cluster.start_mock_node(vec![5550]);
+    let subject = cluster.start_real_node(
+        NodeStartupConfigBuilder::standard()
+            .bootstrap_from(mock_bootstrap.node_reference())
+            .build(),
+    );
+    let _bootstrap_gossip_package = mock_bootstrap
+        .wait_for_package(&masquerader, Duration::from_millis(1000))
+        .unwrap();
+    let fictional_keys: Vec<Key> = (1..6).map(|n| Key::new(&[n; 32])).collect();
+    let gossip_package = fictional_keys
+        .iter()
+        .fold(
+            GossipBuilder::new()
+                .add_node(&mock_bootstrap, true, true)
+                .add_node(&subject, false, true)
+                .add_connection(&mock_bootstrap.public_key(), &subject.public_key()),
+            |builder, key| {
+                builder
+                    .add_fictional_node(NodeRecordInner {
+                        public_key: key.clone(),
+                        node_addr_opt: None,
+                        is_bootstrap_node: false,
+                        neighbors: vec![mock_bootstrap.public_key()],
+                        version: 0,
+                        exit_policy: ExitPolicy::allow_all(),
+                    })
+                    .add_connection(&mock_bootstrap.public_key(), key)
+            },
+        )
+        .build_cores_package(&mock_bootstrap.public_key(), &subject.public_key());
+    send_to_subject(&mock_bootstrap, &subject, gossip_package);
+
+    let digest_data = wait_for_payload(&mock_bootstrap);
+    let digest: GossipDigest = serde_cbor::de::from_slice(&digest_data.data[..]).unwrap();
+    assert_eq!(digest.entries.len(), 7);
+    let request_for = |requested_keys: Vec<Key>| {
+        IncipientCoresPackage::new(
+            single_hop_route(&mock_bootstrap, &subject),
+            GossipRequest {
+                sender: mock_bootstrap.public_key(),
+                requested_keys,
+            },
+            &subject.public_key(),
+        )
+    };
+    send_to_subject(
+        &mock_bootstrap,
+        &subject,
+        request_for(vec![subject.public_key()]),
+    );
+    let partial_response_data = wait_for_payload(&mock_bootstrap);
+    send_to_subject(
+        &mock_bootstrap,
+        &subject,
+        request_for(
+            digest
+                .entries
+                .iter()
+                .map(|entry| entry.public_key.clone())
+                .collect(),
+        ),
+    );
+    let full_response_data = wait_for_payload(&mock_bootstrap);
+
+    let partial_response: GossipResponse =
+        serde_cbor::de::from_slice(&partial_response_data.data[..]).unwrap();
+    let full_response: GossipResponse =
+        serde_cbor::de::from_slice(&full_response_data.data[..]).unwrap();
+    assert_eq!(partial_response.requested_records.len(), 1);
+    assert_eq!(full_response.requested_records.len(), 7);
+    let digest_phase_bytes = digest_data.data.len() + partial_response_data.data.len();
+    let full_gossip_bytes = full_response_data.data.len();
+    assert!(
+        digest_phase_bytes < full_gossip_bytes,
+        "Digest and response: {} bytes; full Gossip: {} bytes",
+        digest_phase_bytes,
+        full_gossip_bytes
+    );
+}
+
+fn single_hop_route(from: &SubstratumMockNode, to: &SubstratumNode) -> Route {
+    Route::new(
+        vec![RouteSegment::new(
+            vec![&from.public_key(), &to.public_key()],
+            Component::Neighborhood,
+        )],
+        from.cryptde(),
+    )
+    .unwrap()
+}
+
+fn send_to_subject(
+    mock_node: &SubstratumMockNode,
+    subject: &SubstratumNode,
+    package: IncipientCoresPackage,
+) {
+    mock_node
+        .transmit_package(
+            mock_node.port_list()[0],
+            package,
+            &JsonMasquerader::new(),
+            &subject.public_key(),
+            subject.socket_addr(PortSelector::First),
+        )
+        .unwrap();
+}
+
+fn wait_for_payload(mock_node: &SubstratumMockNode) -> PlainData {
+    let (_, _, package) = mock_node
+        .wait_for_package(&JsonMasquerader::new(), Duration::from_millis(1000))
+        .unwrap();
+    package.to_expired(mock_node.cryptde()).payload
+}
@@ -37,6 +37,7 @@ impl GossipNodeRecord {
                 is_bootstrap_node: node_record_ref.is_bootstrap_node(),
                 neighbors: node_record_ref.neighbors().clone(),
                 version: node_record_ref.version(),
+                exit_policy: node_record_ref.exit_policy().clone(),
             },
             // crashpoint
             signatures: node_record_ref
@@ -56,6 +57,7 @@ impl GossipNodeRecord {
         node_record
             .neighbors_mut()
             .extend(self.inner.neighbors.clone());
+        node_record.set_exit_policy(self.inner.exit_policy.clone());
         node_record
     }
 
@@ -75,6 +77,7 @@ impl GossipNodeRecord {
         ));
         human_readable.push_str(&format!("\n\t\tneighbors: {:?},", self.inner.neighbors));
         human_readable.push_str(&format!("\n\t\tversion: {:?},", self.inner.version));
+        human_readable.push_str(&format!("\n\t\texit_policy: {:?},", self.inner.exit_policy));
         human_readable.push_str("\n\t},");
         human_readable.push_str("\n\tsignatures: Signatures {");
         human_readable.push_str(&format!(
@@ -100,6 +103,43 @@ pub struct Gossip {
     pub node_records: Vec<GossipNodeRecord>,
 }
 
+// The first phase of Gossip after a database change: which records the sender holds, and at which versions, without
+// the records themselves. reveals_node_addr is set where the sender would reveal the record's NodeAddr to the target.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GossipDigest {
+    pub sender: Key,
+    pub entries: Vec<GossipDigestEntry>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GossipDigestEntry {
+    pub public_key: Key,
+    pub version: u32,
+    pub reveals_node_addr: bool,
+}
+
+// The second phase: the records from a GossipDigest that its target is missing or holds older versions of
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GossipRequest {
+    pub sender: Key,
+    pub requested_keys: Vec<Key>,
+}
+
+// The third phase: the records named in a GossipRequest. Unlike Gossip, a GossipResponse is never taken as a newly
+// arrived Node introducing itself.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GossipResponse {
+    pub requested_records: Vec<GossipNodeRecord>,
+}
+
+impl GossipResponse {
+    pub fn to_gossip(self) -> Gossip {
+        Gossip {
+            node_records: self.requested_records,
+        }
+    }
+}
+
 pub fn to_dot_graph(gossip: Gossip, target: &Key, source: Key) -> String {
     let mut bootstrap_keys = vec![];
     for item in gossip.node_records.clone() {
@@ -303,7 +343,7 @@ mod tests {
         let result = format!("{:?}", gossip);
         let expected = format!(
             "\nGossipNodeRecord {{{}{}\n}}",
-            "\n\tinner: NodeRecordInner {\n\t\tpublic_key: AQIDBA,\n\t\tnode_addr_opt: Some(1.2.3.4:[1234]),\n\t\tis_bootstrap_node: false,\n\t\tneighbors: [],\n\t\tversion: 0,\n\t},",
+            "\n\tinner: NodeRecordInner {\n\t\tpublic_key: AQIDBA,\n\t\tnode_addr_opt: Some(1.2.3.4:[1234]),\n\t\tis_bootstrap_node: false,\n\t\tneighbors: [],\n\t\tversion: 0,\n\t\texit_policy: ExitPolicy { allowed_ports: [], allowed_hostnames: [] },\n\t},",
             "\n\tsignatures: Signatures {\n\t\tcomplete: CryptData { data: [115, 105, 103, 110, 101, 100] },\n\t\tobscured: CryptData { data: [115, 105, 103, 110, 101, 100] },\n\t},"
         );
 
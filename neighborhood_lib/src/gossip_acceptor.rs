@@ -1,23 +1,55 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use gossip::Gossip;
+use gossip::GossipDigest;
+use gossip::GossipDigestEntry;
 use gossip::GossipNodeRecord;
+use gossip::GossipRequest;
 use neighborhood_database::NeighborhoodDatabase;
 use neighborhood_database::NeighborhoodDatabaseError;
 use neighborhood_database::NodeRecord;
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Instant;
+use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::Key;
 use sub_lib::logger::Logger;
+use sub_lib::neighborhood::subnet_of;
+use sub_lib::neighborhood::AdmissionLimits;
 
 pub trait GossipAcceptor {
     // Philosophy of handling Gossip messages that are malformed: Don't spend effort on rejecting
     // malformed Gossip for security reasons. Do whatever's easiest. An attacker might send
     // malformed Gossip accidentally at the beginning, but he will soon learn to generate valid
-    // Gossip, whereupon effort spent detecting malformed Gossip will be wasted.
-    fn handle(&self, database: &mut NeighborhoodDatabase, gossip: Gossip) -> bool;
+    // Gossip, whereupon effort spent detecting malformed Gossip will be wasted. Forged Gossip is
+    // different: records whose signatures don't match their contents are always rejected. So are new Nodes beyond
+    // the AdmissionLimits, so that no single source can flood the database with Nodes of its own invention.
+    fn handle(
+        &mut self,
+        database: &mut NeighborhoodDatabase,
+        gossip: Gossip,
+        source: IpAddr,
+    ) -> bool;
+    fn handle_digest(
+        &self,
+        database: &NeighborhoodDatabase,
+        digest: &GossipDigest,
+    ) -> GossipRequest;
+    // Forged records are never accepted, but whoever sent them may deserve to hear about it
+    fn find_forgeries(&self, gossip: &Gossip) -> Vec<Key>;
 }
 
 pub struct GossipAcceptorReal {
     pub logger: Logger,
+    cryptde: Box<CryptDE>,
+    admission_limits: AdmissionLimits,
+    source_windows: HashMap<IpAddr, SourceWindow>,
+}
+
+// How many new Nodes a source has introduced since the start of its current window
+struct SourceWindow {
+    started_at: Instant,
+    admitted: u32,
 }
 
 impl GossipAcceptor for GossipAcceptorReal {
@@ -27,66 +59,249 @@ impl GossipAcceptor for GossipAcceptorReal {
             anything special with the Gossip, just records any new information, but it does not change already known information
             e.g. it will add an IP addr to a known neighbor without one, but it will not change a known IP addr of a known neighbor
             it will also add to its own neighbor list any nodes in the Gossip that include NodeAddr information
+            new nodes are only added within the AdmissionLimits, and the database may forget a node to make room
         params:
             `database`: the DB that contains this node's known neighborhood
             `gossip`: the Gossip message with which to update the DB
+            `source`: the IP address the Gossip came from, which is remembered as the introducer of any new nodes
     */
-    fn handle(&self, database: &mut NeighborhoodDatabase, gossip: Gossip) -> bool {
-        let mut changed = self.handle_node_records(database, &gossip);
+    fn handle(
+        &mut self,
+        database: &mut NeighborhoodDatabase,
+        gossip: Gossip,
+        source: IpAddr,
+    ) -> bool {
+        let gossip = Gossip {
+            node_records: gossip
+                .node_records
+                .into_iter()
+                .filter(|gnr_ref| self.is_not_invalid(gnr_ref))
+                .collect(),
+        };
+        let mut changed = self.handle_node_records(database, &gossip, source, Instant::now());
         changed = self.add_ip_neighbors(database, &gossip) || changed;
         self.logger
             .debug(format!("Database after accepting Gossip: {:?}", database));
         changed
     }
+
+    /*
+        `handle_digest`
+            decides which of the records listed in a GossipDigest are worth asking for: those this node doesn't know,
+            those it holds an older version of, and those whose NodeAddr the sender would reveal to a node that isn't
+            yet a neighbor of this one, since receiving them is what makes it a neighbor.
+        params:
+            `database`: the DB that contains this node's known neighborhood
+            `digest`: the GossipDigest that arrived
+        returns:
+            a GossipRequest for the sender of the digest; it may be empty
+    */
+    fn handle_digest(
+        &self,
+        database: &NeighborhoodDatabase,
+        digest: &GossipDigest,
+    ) -> GossipRequest {
+        let requested_keys = digest
+            .entries
+            .iter()
+            .filter(|entry| self.is_wanted(database, entry))
+            .map(|entry| entry.public_key.clone())
+            .collect();
+        GossipRequest {
+            sender: database.root().public_key().clone(),
+            requested_keys,
+        }
+    }
+
+    /*
+        `find_forgeries`
+            checks the signature of every record in the Gossip against the record's own public key: the complete
+            signature if the record reveals its NodeAddr, the obscured one if it doesn't.
+        returns:
+            the public keys of the records whose signatures don't match their contents
+    */
+    fn find_forgeries(&self, gossip: &Gossip) -> Vec<Key> {
+        gossip
+            .node_records
+            .iter()
+            .filter(|gnr_ref| !self.is_authentic(gnr_ref))
+            .map(|gnr_ref| gnr_ref.public_key())
+            .collect()
+    }
 }
 
 impl GossipAcceptorReal {
-    pub fn new() -> GossipAcceptorReal {
+    pub fn new(cryptde: &CryptDE, admission_limits: &AdmissionLimits) -> GossipAcceptorReal {
         GossipAcceptorReal {
             logger: Logger::new("GossipAcceptorReal"),
+            cryptde: cryptde.dup(),
+            admission_limits: admission_limits.clone(),
+            source_windows: HashMap::new(),
         }
     }
 
     fn handle_node_records(
-        &self,
+        &mut self,
         database: &mut NeighborhoodDatabase,
         gossip_ref: &Gossip,
+        source: IpAddr,
+        now: Instant,
     ) -> bool {
+        self.forget_expired_windows(now);
         let mut changed = false;
-        gossip_ref
-            .node_records
-            .iter()
-            .filter(|gnr_ref_ref| self.is_not_invalid(&gnr_ref_ref))
-            .for_each(|gnr_ref| {
-                changed = if database.keys().contains(&gnr_ref.inner.public_key) {
-                    let node_record = database
-                        .node_by_key_mut(&gnr_ref.inner.public_key)
-                        .expect("Key magically disappeared");
-                    let node_addr_changed = self.update_node_addrs(gnr_ref, node_record);
-                    if node_record.version() < gnr_ref.inner.version {
-                        self.update_version(gnr_ref, node_record);
-                        node_addr_changed
-                            || self.update_neighbors(gnr_ref, node_record)
-                            || self.update_signatures(gnr_ref, node_record)
-                            || changed
-                    } else {
-                        node_addr_changed || changed
-                    }
+        gossip_ref.node_records.iter().for_each(|gnr_ref| {
+            changed = if database.keys().contains(&gnr_ref.inner.public_key) {
+                let node_record = database
+                    .node_by_key_mut(&gnr_ref.inner.public_key)
+                    .expect("Key magically disappeared");
+                let node_addr_changed = self.update_node_addrs(gnr_ref, node_record);
+                if node_record.version() < gnr_ref.inner.version {
+                    self.update_version(gnr_ref, node_record);
+                    let exit_policy_changed = self.update_exit_policy(gnr_ref, node_record);
+                    let neighbors_changed = self.update_neighbors(gnr_ref, node_record);
+                    // The old signatures don't match the new version, whatever else has changed
+                    let signatures_changed = self.update_signatures(gnr_ref, node_record);
+                    node_addr_changed
+                        || exit_policy_changed
+                        || neighbors_changed
+                        || signatures_changed
+                        || changed
                 } else {
-                    database
-                        .add_node(&gnr_ref.to_node_record())
-                        .expect("Key magically appeared");
-                    true
+                    node_addr_changed || changed
                 }
-            });
+            } else if self.admits_new_node(database, gnr_ref, source, now) {
+                database
+                    .add_node(&gnr_ref.to_node_record())
+                    .expect("Key magically appeared");
+                database.set_introducer(&gnr_ref.inner.public_key, source);
+                true
+            } else {
+                changed
+            }
+        });
         changed
     }
 
+    // A new Node is refused if its source has already introduced too many in the current window, or if too many
+    // known Nodes share its subnet. If the database is full, some other Node is forgotten to make room for it.
+    fn admits_new_node(
+        &mut self,
+        database: &mut NeighborhoodDatabase,
+        gnr_ref: &GossipNodeRecord,
+        source: IpAddr,
+        now: Instant,
+    ) -> bool {
+        let new_node_window = self.admission_limits.new_node_window;
+        let admitted = self
+            .source_windows
+            .entry(source)
+            .or_insert(SourceWindow {
+                started_at: now,
+                admitted: 0,
+            })
+            .admitted;
+        if admitted >= self.admission_limits.new_nodes_per_source {
+            self.logger.warning(format!(
+                "Rejecting new Node {}: {} has already introduced {} new Nodes in {:?}",
+                &gnr_ref.inner.public_key, source, admitted, new_node_window
+            ));
+            return false;
+        }
+        if let Some(ref node_addr) = gnr_ref.inner.node_addr_opt {
+            let subnet_count = self.count_nodes_in_subnet(database, &node_addr.ip_addr());
+            if subnet_count >= self.admission_limits.nodes_per_subnet as usize {
+                self.logger.warning(format!(
+                    "Rejecting new Node {}: already know {} Nodes in the subnet of {}",
+                    &gnr_ref.inner.public_key,
+                    subnet_count,
+                    node_addr.ip_addr()
+                ));
+                return false;
+            }
+        }
+        if (database.keys().len() >= self.admission_limits.max_database_size)
+            && !self.evict_node(database)
+        {
+            self.logger.warning(format!(
+                "Rejecting new Node {}: NeighborhoodDatabase is full and no Node can be forgotten",
+                &gnr_ref.inner.public_key
+            ));
+            return false;
+        }
+        if let Some(window) = self.source_windows.get_mut(&source) {
+            window.admitted += 1;
+        }
+        true
+    }
+
+    // A source whose window has run out starts over with a new one the next time it introduces a Node, so there's
+    // no point remembering it until then
+    fn forget_expired_windows(&mut self, now: Instant) {
+        let new_node_window = self.admission_limits.new_node_window;
+        self.source_windows
+            .retain(|_, window| now.duration_since(window.started_at) < new_node_window);
+    }
+
+    fn count_nodes_in_subnet(&self, database: &NeighborhoodDatabase, ip_addr: &IpAddr) -> usize {
+        let root_key_ref = database.root().public_key();
+        let subnet = subnet_of(*ip_addr);
+        database
+            .keys()
+            .into_iter()
+            .filter(|key_ref| key_ref != &root_key_ref)
+            .filter_map(|key_ref| database.node_by_key(key_ref))
+            .filter_map(|node_record_ref| node_record_ref.node_addr_opt())
+            .filter(|node_addr| subnet_of(node_addr.ip_addr()) == subnet)
+            .count()
+    }
+
+    // Forgets one of the Nodes introduced by whichever source has introduced the most. Neighbors of this Node are
+    // never forgotten, nor are Nodes whose introducer isn't known, such as those we were configured with.
+    fn evict_node(&self, database: &mut NeighborhoodDatabase) -> bool {
+        let victim_opt = {
+            let root = database.root();
+            let mut by_introducer: HashMap<IpAddr, Vec<&Key>> = HashMap::new();
+            database
+                .keys()
+                .into_iter()
+                .filter(|key_ref| (*key_ref != root.public_key()) && !root.has_neighbor(key_ref))
+                .for_each(|key_ref| {
+                    if let Some(introducer) = database.introducer_of(key_ref) {
+                        by_introducer
+                            .entry(introducer)
+                            .or_insert_with(|| vec![])
+                            .push(key_ref)
+                    }
+                });
+            by_introducer
+                .into_iter()
+                .max_by_key(|(introducer, keys)| (keys.len(), *introducer))
+                .and_then(|(introducer, keys)| {
+                    keys.into_iter()
+                        .min_by(|a, b| a.data.cmp(&b.data))
+                        .map(|key_ref| (key_ref.clone(), introducer))
+                })
+        };
+        match victim_opt {
+            Some((victim, introducer)) => {
+                self.logger.info(format!(
+                    "Forgetting Node {} introduced by {} to make room in the NeighborhoodDatabase",
+                    victim, introducer
+                ));
+                database.remove_node(&victim)
+            }
+            None => false,
+        }
+    }
+
     fn add_ip_neighbors(&self, database: &mut NeighborhoodDatabase, gossip_ref: &Gossip) -> bool {
         let mut changed = false;
         let root_key_ref = database.root().public_key().clone();
         gossip_ref.node_records.iter().for_each(|gnr_ref| {
-            if gnr_ref.inner.node_addr_opt.is_some() && (&gnr_ref.inner.public_key != &root_key_ref)
+            // Nodes that weren't admitted to the database can't be neighbors
+            if gnr_ref.inner.node_addr_opt.is_some()
+                && (&gnr_ref.inner.public_key != &root_key_ref)
+                && database.node_by_key(&gnr_ref.inner.public_key).is_some()
             {
                 changed = database
                     .add_neighbor(&root_key_ref, &gnr_ref.inner.public_key)
@@ -100,6 +315,23 @@ impl GossipAcceptorReal {
         changed
     }
 
+    fn is_wanted(&self, database: &NeighborhoodDatabase, entry: &GossipDigestEntry) -> bool {
+        let root = database.root();
+        if entry.public_key.data.is_empty() || (&entry.public_key == root.public_key()) {
+            false
+        } else {
+            match database.node_by_key(&entry.public_key) {
+                None => true,
+                Some(node_record) => {
+                    (node_record.version() < entry.version)
+                        || (entry.reveals_node_addr
+                            && (node_record.node_addr_opt().is_none()
+                                || !root.has_neighbor(&entry.public_key)))
+                }
+            }
+        }
+    }
+
     fn is_not_invalid(&self, gnr: &GossipNodeRecord) -> bool {
         let empty_key = Key::new(&[]);
         if gnr.inner.public_key.data.is_empty() {
@@ -117,11 +349,25 @@ impl GossipAcceptorReal {
                 &gnr.inner.public_key
             ));
             false
+        } else if !self.is_authentic(gnr) {
+            self.logger.error(format!(
+                "Rejecting GossipNodeRecord for {} whose signature doesn't match its contents",
+                &gnr.inner.public_key
+            ));
+            false
         } else {
             true
         }
     }
 
+    fn is_authentic(&self, gnr: &GossipNodeRecord) -> bool {
+        let signature = match gnr.inner.node_addr_opt {
+            Some(_) => gnr.signatures.complete(),
+            None => gnr.signatures.obscured(),
+        };
+        gnr.inner.verify_signature(signature, self.cryptde.as_ref())
+    }
+
     fn update_node_addrs(&self, gnr_ref: &GossipNodeRecord, node_record: &mut NodeRecord) -> bool {
         if let Some(new_node_addr_ref) = gnr_ref.inner.node_addr_opt.as_ref() {
             match node_record.set_node_addr(new_node_addr_ref) {
@@ -158,6 +404,15 @@ impl GossipAcceptorReal {
         }
     }
 
+    fn update_exit_policy(&self, gnr_ref: &GossipNodeRecord, node_record: &mut NodeRecord) -> bool {
+        if node_record.exit_policy() == &gnr_ref.inner.exit_policy {
+            false
+        } else {
+            node_record.set_exit_policy(gnr_ref.inner.exit_policy.clone());
+            true
+        }
+    }
+
     fn update_signatures(&self, gnr_ref: &GossipNodeRecord, node_record: &mut NodeRecord) -> bool {
         node_record.set_signatures(gnr_ref.signatures.clone())
     }
@@ -174,22 +429,31 @@ mod tests {
     use gossip::GossipBuilder;
     use gossip::GossipNodeRecord;
     use neighborhood_database::NodeRecord;
+    use neighborhood_database::NodeRecordInner;
     use neighborhood_database::NodeSignatures;
     use neighborhood_test_utils::make_node_record;
     use neighborhood_test_utils::*;
     use std::net::IpAddr;
     use std::net::Ipv4Addr;
     use std::str::FromStr;
+    use std::time::Duration;
     use sub_lib::cryptde::CryptData;
     use sub_lib::cryptde::Key;
+    use sub_lib::cryptde_real::CryptDEReal;
+    use sub_lib::neighborhood::ExitPolicy;
     use sub_lib::node_addr::NodeAddr;
     use test_utils::logging::init_test_logging;
     use test_utils::logging::TestLogHandler;
     use test_utils::test_utils::cryptde;
 
+    fn gossip_source() -> IpAddr {
+        IpAddr::from_str("1.2.3.4").unwrap()
+    }
+
     #[test]
     fn add_ip_neighbors_does_not_add_neighbors_without_ip() {
-        let subject: GossipAcceptorReal = GossipAcceptorReal::new();
+        let subject: GossipAcceptorReal =
+            GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
         let this_addr = NodeAddr::new(&IpAddr::from_str("5.7.3.4").unwrap(), &vec![13]);
         let mut db = NeighborhoodDatabase::new(&Key::new(b"scrud"), &this_addr, false, cryptde());
 
@@ -261,9 +525,9 @@ mod tests {
             .node(&incoming_far_right, false)
             .node(&bad_record_with_blank_key, false)
             .build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         assert_eq!(
             database.keys(),
@@ -320,9 +584,9 @@ mod tests {
             .node(&not_a_neighbor_one, false)
             .node(&not_a_neighbor_two, false)
             .build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         assert_eq!(
             neighbor_keys_of(&database, &existing_node),
@@ -368,9 +632,9 @@ mod tests {
             false,
         );
         let gossip = GossipBuilder::new().node(&new_node, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         let existing_node_ref = database.node_by_key(existing_node.public_key()).unwrap();
         let existing_node_addr = existing_node_ref.node_addr_opt().unwrap();
@@ -396,9 +660,9 @@ mod tests {
         database.add_node(&existing_node).unwrap();
 
         let gossip = GossipBuilder::new().node(&incoming_node, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         let incoming_node_ref = database.node_by_key(incoming_node.public_key()).unwrap();
         let incoming_node_addr = incoming_node_ref.node_addr_opt().unwrap();
@@ -443,9 +707,9 @@ mod tests {
         let gossip = Gossip {
             node_records: vec![GossipNodeRecord::from(&invalid_record, true)],
         };
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         // existing_neighbor in the database is untouched by the invalid Gossip.
         assert_eq!(
@@ -489,9 +753,9 @@ mod tests {
         signed_neighbor.sign(cryptde());
 
         let gossip = GossipBuilder::new().node(&signed_neighbor, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         let neighbor_in_db = database.node_by_key(neighbor.public_key()).unwrap();
         assert!(
@@ -501,6 +765,43 @@ mod tests {
         assert_eq!(neighbor_in_db.signatures(), signed_neighbor.signatures());
     }
 
+    #[test]
+    fn handle_returns_true_when_an_existing_node_record_updates_its_exit_policy() {
+        let this_node = make_node_record(1234, true, false);
+        let neighbor = make_node_record(2345, true, false);
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            &this_node.node_addr_opt().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        database.add_node(&neighbor).unwrap();
+        database
+            .add_neighbor(this_node.public_key(), neighbor.public_key())
+            .unwrap();
+        let exit_policy = ExitPolicy {
+            allowed_ports: vec![80, 443],
+            allowed_hostnames: vec![String::from("*.booga.com")],
+        };
+        let mut updated_neighbor = neighbor.clone();
+        updated_neighbor.set_exit_policy(exit_policy.clone());
+        updated_neighbor.increment_version();
+        updated_neighbor.sign(cryptde());
+        let gossip = GossipBuilder::new().node(&updated_neighbor, false).build();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
+
+        let result = subject.handle(&mut database, gossip, gossip_source());
+
+        assert_eq!(result, true);
+        assert_eq!(
+            database
+                .node_by_key(neighbor.public_key())
+                .unwrap()
+                .exit_policy(),
+            &exit_policy
+        );
+    }
+
     #[test]
     fn handle_returns_true_when_a_new_node_record_is_added_without_a_node_addr_or_new_edges() {
         let this_node = make_node_record(1234, true, false);
@@ -512,9 +813,9 @@ mod tests {
             cryptde(),
         );
         let gossip = GossipBuilder::new().node(&incoming_node, false).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         let incoming_node_ref = database.node_by_key(incoming_node.public_key()).unwrap();
         let incoming_node_addr = incoming_node_ref.node_addr_opt();
@@ -545,9 +846,9 @@ mod tests {
             .node(&this_node, true)
             .node(&existing_node_with_ip, true)
             .build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert!(
             database.has_neighbor(
@@ -578,9 +879,9 @@ mod tests {
         );
 
         let gossip = GossipBuilder::new().node(&incoming_node, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert_eq!(
             database.has_neighbor(this_node.public_key(), incoming_node.public_key()),
@@ -606,9 +907,9 @@ mod tests {
         database.add_node(&existing_node).unwrap();
 
         let gossip = GossipBuilder::new().node(&existing_node, false).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert!(
             !result,
@@ -635,9 +936,9 @@ mod tests {
             .unwrap();
 
         let gossip = GossipBuilder::new().node(&existing_node, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert!(
             database.has_neighbor(this_node.public_key(), existing_node.public_key()),
@@ -669,9 +970,9 @@ mod tests {
             .unwrap();
 
         let gossip = GossipBuilder::new().node(&neighbor, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        subject.handle(&mut database, gossip);
+        subject.handle(&mut database, gossip, gossip_source());
 
         TestLogHandler::new().exists_no_log_containing(&format!("ERROR: GossipAcceptorReal: Gossip tried to modify signatures of node CQgHBg from {:?} to {:?}", neighbor.signatures().clone().unwrap(), neighbor.signatures().clone().unwrap()));
     }
@@ -690,7 +991,7 @@ mod tests {
         );
 
         let gossip = GossipBuilder::new().node(&incoming_node, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
         assert_eq!(
             database
@@ -701,7 +1002,7 @@ mod tests {
             "Initial version should be zero. Failed to set up test"
         );
 
-        let _result = subject.handle(&mut database, gossip);
+        let _result = subject.handle(&mut database, gossip, gossip_source());
 
         assert_eq!(
             database
@@ -736,9 +1037,9 @@ mod tests {
             .unwrap();
 
         let gossip = GossipBuilder::new().node(&older_version, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert!(
             database.has_neighbor(existing_node.public_key(), this_node.public_key()),
@@ -777,9 +1078,9 @@ mod tests {
             .unwrap();
 
         let gossip = GossipBuilder::new().node(&newer_version, true).build();
-        let subject = GossipAcceptorReal::new();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
 
-        let result = subject.handle(&mut database, gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
 
         assert!(
             !result,
@@ -793,4 +1094,517 @@ mod tests {
             newer_version.version()
         );
     }
+
+    #[test]
+    fn handle_digest_requests_unknown_newer_and_newly_revealed_records() {
+        let this_node = make_node_record(1234, true, false);
+        let current_node = make_node_record(2345, true, false);
+        let mut outdated_node = make_node_record(3456, true, false);
+        outdated_node.set_version(2);
+        let addressless_node = make_node_record(4567, false, false);
+        let unconnected_node = make_node_record(5678, true, false);
+        let unknown_node = make_node_record(6789, true, false);
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        database.add_node(&current_node).unwrap();
+        database.add_node(&outdated_node).unwrap();
+        database.add_node(&addressless_node).unwrap();
+        database.add_node(&unconnected_node).unwrap();
+        database
+            .add_neighbor(this_node.public_key(), current_node.public_key())
+            .unwrap();
+        let entry = |node: &NodeRecord, version: u32, reveals_node_addr: bool| GossipDigestEntry {
+            public_key: node.public_key().clone(),
+            version,
+            reveals_node_addr,
+        };
+        let digest = GossipDigest {
+            sender: current_node.public_key().clone(),
+            entries: vec![
+                entry(&this_node, 7, true),
+                entry(&current_node, 0, true),
+                entry(&outdated_node, 3, false),
+                entry(&addressless_node, 0, true),
+                entry(&unconnected_node, 0, true),
+                entry(&unknown_node, 0, false),
+                GossipDigestEntry {
+                    public_key: Key::new(&[]),
+                    version: 0,
+                    reveals_node_addr: false,
+                },
+            ],
+        };
+        let subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
+
+        let result = subject.handle_digest(&database, &digest);
+
+        assert_eq!(
+            result,
+            GossipRequest {
+                sender: this_node.public_key().clone(),
+                requested_keys: vec![
+                    outdated_node.public_key().clone(),
+                    addressless_node.public_key().clone(),
+                    unconnected_node.public_key().clone(),
+                    unknown_node.public_key().clone(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn handle_digest_requests_nothing_when_database_is_up_to_date() {
+        let this_node = make_node_record(1234, true, false);
+        let neighbor = make_node_record(2345, true, false);
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        database.add_node(&neighbor).unwrap();
+        database
+            .add_neighbor(this_node.public_key(), neighbor.public_key())
+            .unwrap();
+        let digest = GossipDigest {
+            sender: neighbor.public_key().clone(),
+            entries: vec![
+                GossipDigestEntry {
+                    public_key: neighbor.public_key().clone(),
+                    version: 0,
+                    reveals_node_addr: true,
+                },
+                GossipDigestEntry {
+                    public_key: this_node.public_key().clone(),
+                    version: 0,
+                    reveals_node_addr: true,
+                },
+            ],
+        };
+        let subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
+
+        let result = subject.handle_digest(&database, &digest);
+
+        assert_eq!(result.requested_keys, vec![]);
+    }
+
+    fn make_real_gossip_node_record(
+        cryptde: &CryptDE,
+        node_addr_opt: Option<NodeAddr>,
+        neighbors: Vec<Key>,
+    ) -> GossipNodeRecord {
+        let inner = NodeRecordInner {
+            public_key: cryptde.public_key(),
+            node_addr_opt,
+            is_bootstrap_node: false,
+            neighbors,
+            version: 0,
+            exit_policy: ExitPolicy::allow_all(),
+        };
+        let signatures = NodeSignatures::from(cryptde, &inner);
+        GossipNodeRecord { inner, signatures }
+    }
+
+    fn make_real_cryptde() -> CryptDEReal {
+        let mut cryptde = CryptDEReal::new();
+        cryptde.generate_key_pair();
+        cryptde
+    }
+
+    #[test]
+    fn handle_rejects_records_whose_signatures_do_not_match() {
+        init_test_logging();
+        let this_node = make_node_record(1234, true, false);
+        let honest_cryptde = make_real_cryptde();
+        let forged_cryptde = make_real_cryptde();
+        let obscured_cryptde = make_real_cryptde();
+        let honest_record = make_real_gossip_node_record(
+            &honest_cryptde,
+            Some(NodeAddr::new(
+                &IpAddr::from_str("2.3.4.5").unwrap(),
+                &vec![2345],
+            )),
+            vec![],
+        );
+        let mut forged_record = make_real_gossip_node_record(
+            &forged_cryptde,
+            Some(NodeAddr::new(
+                &IpAddr::from_str("3.4.5.6").unwrap(),
+                &vec![3456],
+            )),
+            vec![],
+        );
+        forged_record
+            .inner
+            .neighbors
+            .push(honest_cryptde.public_key());
+        let obscured_record = make_real_gossip_node_record(
+            &obscured_cryptde,
+            None,
+            vec![honest_cryptde.public_key()],
+        );
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        let gossip = Gossip {
+            node_records: vec![
+                honest_record.clone(),
+                forged_record.clone(),
+                obscured_record.clone(),
+            ],
+        };
+        let mut subject = GossipAcceptorReal::new(&make_real_cryptde(), &AdmissionLimits::new());
+
+        let forgeries = subject.find_forgeries(&gossip);
+        let result = subject.handle(&mut database, gossip, gossip_source());
+
+        assert_eq!(forgeries, vec![forged_record.public_key()]);
+        assert_eq!(result, true);
+        assert!(database.node_by_key(&honest_record.public_key()).is_some());
+        assert!(database
+            .node_by_key(&obscured_record.public_key())
+            .is_some());
+        assert!(database.node_by_key(&forged_record.public_key()).is_none());
+        assert!(!database.has_neighbor(this_node.public_key(), &forged_record.public_key()));
+        TestLogHandler::new().exists_log_containing(&format!(
+            "ERROR: GossipAcceptorReal: Rejecting GossipNodeRecord for {} whose signature doesn't match its contents",
+            forged_record.public_key()
+        ));
+    }
+
+    #[test]
+    fn handle_rejects_record_whose_address_was_revealed_after_signing() {
+        let this_node = make_node_record(1234, true, false);
+        let other_cryptde = make_real_cryptde();
+        let mut record = make_real_gossip_node_record(&other_cryptde, None, vec![]);
+        record.signatures = NodeSignatures::new(
+            record.signatures.obscured().clone(),
+            record.signatures.obscured().clone(),
+        );
+        record.inner.node_addr_opt = Some(NodeAddr::new(
+            &IpAddr::from_str("2.3.4.5").unwrap(),
+            &vec![2345],
+        ));
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        let mut subject = GossipAcceptorReal::new(&make_real_cryptde(), &AdmissionLimits::new());
+
+        let result = subject.handle(
+            &mut database,
+            Gossip {
+                node_records: vec![record.clone()],
+            },
+            gossip_source(),
+        );
+
+        assert_eq!(result, false);
+        assert!(database.node_by_key(&record.public_key()).is_none());
+    }
+
+    #[test]
+    fn newer_version_replaces_signatures_along_with_neighbors() {
+        let this_node = make_node_record(1234, true, false);
+        let existing_node = make_node_record(2345, true, false);
+        let mut newer_node = existing_node.clone();
+        newer_node
+            .neighbors_mut()
+            .push(this_node.public_key().clone());
+        newer_node.increment_version();
+        let newer_signatures = NodeSignatures::new(
+            CryptData::new(b"newer complete"),
+            CryptData::new(b"newer obscured"),
+        );
+        let mut newer_gnr = GossipNodeRecord::from(&newer_node, true);
+        newer_gnr.signatures = newer_signatures.clone();
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        );
+        database.add_node(&existing_node).unwrap();
+        let mut subject = GossipAcceptorReal::new(cryptde(), &AdmissionLimits::new());
+
+        subject.handle(
+            &mut database,
+            Gossip {
+                node_records: vec![newer_gnr],
+            },
+            gossip_source(),
+        );
+
+        let result = database.node_by_key(existing_node.public_key()).unwrap();
+        assert_eq!(result.neighbors(), newer_node.neighbors());
+        assert_eq!(result.signatures(), Some(newer_signatures));
+    }
+
+    fn make_admission_database(this_node: &NodeRecord) -> NeighborhoodDatabase {
+        NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            cryptde(),
+        )
+    }
+
+    fn obscured_gossip(node_records: &Vec<NodeRecord>) -> Gossip {
+        node_records
+            .iter()
+            .fold(GossipBuilder::new(), |builder, node_record| {
+                builder.node(node_record, false)
+            })
+            .build()
+    }
+
+    #[test]
+    fn new_nodes_from_one_source_are_limited_per_window() {
+        init_test_logging();
+        let this_node = make_node_record(1234, true, false);
+        let mut database = make_admission_database(&this_node);
+        let new_nodes: Vec<NodeRecord> = (0..3)
+            .map(|n| make_node_record(5670 + n, false, false))
+            .collect();
+        let other_source_node = make_node_record(6789, false, false);
+        let gossip = obscured_gossip(&new_nodes);
+        let other_source = IpAddr::from_str("2.3.4.5").unwrap();
+        let mut subject = GossipAcceptorReal::new(
+            cryptde(),
+            &AdmissionLimits {
+                new_nodes_per_source: 2,
+                new_node_window: Duration::from_secs(60),
+                ..AdmissionLimits::new()
+            },
+        );
+        let start = Instant::now();
+
+        let first_result =
+            subject.handle_node_records(&mut database, &gossip, gossip_source(), start);
+        let first_count = database.keys().len();
+        let second_result = subject.handle_node_records(
+            &mut database,
+            &gossip,
+            gossip_source(),
+            start + Duration::from_secs(30),
+        );
+        let other_source_result = subject.handle_node_records(
+            &mut database,
+            &obscured_gossip(&vec![other_source_node.clone()]),
+            other_source,
+            start + Duration::from_secs(30),
+        );
+        let third_result = subject.handle_node_records(
+            &mut database,
+            &gossip,
+            gossip_source(),
+            start + Duration::from_secs(60),
+        );
+
+        assert_eq!(first_result, true);
+        assert_eq!(first_count, 3);
+        assert_eq!(second_result, false);
+        assert_eq!(other_source_result, true);
+        assert_eq!(third_result, true);
+        assert_eq!(database.keys().len(), 5);
+        assert_eq!(
+            database.introducer_of(new_nodes[2].public_key()),
+            Some(gossip_source())
+        );
+        assert_eq!(
+            database.introducer_of(other_source_node.public_key()),
+            Some(other_source)
+        );
+        TestLogHandler::new().exists_log_containing(&format!(
+            "WARN: GossipAcceptorReal: Rejecting new Node {}: 1.2.3.4 has already introduced 2 new Nodes in 60s",
+            new_nodes[2].public_key()
+        ));
+    }
+
+    #[test]
+    fn source_windows_are_forgotten_once_they_run_out() {
+        let this_node = make_node_record(1234, true, false);
+        let mut database = make_admission_database(&this_node);
+        let first_source = IpAddr::from_str("2.3.4.5").unwrap();
+        let second_source = IpAddr::from_str("3.4.5.6").unwrap();
+        let mut subject = GossipAcceptorReal::new(
+            cryptde(),
+            &AdmissionLimits {
+                new_node_window: Duration::from_secs(60),
+                ..AdmissionLimits::new()
+            },
+        );
+        let start = Instant::now();
+        subject.handle_node_records(
+            &mut database,
+            &obscured_gossip(&vec![make_node_record(5678, false, false)]),
+            first_source,
+            start,
+        );
+        subject.handle_node_records(
+            &mut database,
+            &obscured_gossip(&vec![make_node_record(6789, false, false)]),
+            second_source,
+            start + Duration::from_secs(30),
+        );
+        let before_expiry: HashSet<IpAddr> = subject.source_windows.keys().cloned().collect();
+
+        subject.handle_node_records(
+            &mut database,
+            &obscured_gossip(&vec![]),
+            first_source,
+            start + Duration::from_secs(60),
+        );
+
+        assert_eq!(
+            before_expiry,
+            vec![first_source, second_source].into_iter().collect()
+        );
+        assert_eq!(
+            subject.source_windows.keys().collect::<Vec<&IpAddr>>(),
+            vec![&second_source]
+        );
+    }
+
+    #[test]
+    fn new_nodes_in_a_crowded_subnet_are_rejected() {
+        init_test_logging();
+        let this_node = make_node_record(1234, true, false);
+        let mut database = make_admission_database(&this_node);
+        let crowded_nodes: Vec<NodeRecord> = (1..4)
+            .map(|n| make_node_record(2340 + n, true, false))
+            .collect();
+        let elsewhere_node = make_node_record(3456, true, false);
+        let gossip = GossipBuilder::new()
+            .node(&crowded_nodes[0], true)
+            .node(&crowded_nodes[1], true)
+            .node(&crowded_nodes[2], true)
+            .node(&elsewhere_node, true)
+            .build();
+        let mut subject = GossipAcceptorReal::new(
+            cryptde(),
+            &AdmissionLimits {
+                nodes_per_subnet: 2,
+                ..AdmissionLimits::new()
+            },
+        );
+
+        let result = subject.handle(&mut database, gossip, gossip_source());
+
+        assert_eq!(result, true);
+        assert_eq!(
+            database.keys(),
+            vec_to_set(vec![
+                this_node.public_key(),
+                crowded_nodes[0].public_key(),
+                crowded_nodes[1].public_key(),
+                elsewhere_node.public_key(),
+            ])
+        );
+        assert_eq!(
+            database.root().has_neighbor(crowded_nodes[2].public_key()),
+            false
+        );
+        TestLogHandler::new().exists_log_containing(
+            "WARN: GossipAcceptorReal: Rejecting new Node AgMEAw: already know 2 Nodes in the subnet of 2.3.4.3",
+        );
+    }
+
+    #[test]
+    fn full_database_forgets_a_node_from_the_source_that_introduced_the_most() {
+        let this_node = make_node_record(1234, true, false);
+        let neighbor = make_node_record(2345, true, false);
+        let first_source = IpAddr::from_str("3.3.3.3").unwrap();
+        let second_source = IpAddr::from_str("4.4.4.4").unwrap();
+        let first_source_node = make_node_record(3456, false, false);
+        let second_source_nodes = vec![
+            make_node_record(4567, false, false),
+            make_node_record(4568, false, false),
+        ];
+        let new_node = make_node_record(5678, false, false);
+        let mut database = make_admission_database(&this_node);
+        database.add_node(&neighbor).unwrap();
+        database.set_introducer(neighbor.public_key(), second_source);
+        database
+            .add_neighbor(this_node.public_key(), neighbor.public_key())
+            .unwrap();
+        database.add_node(&first_source_node).unwrap();
+        database.set_introducer(first_source_node.public_key(), first_source);
+        second_source_nodes.iter().for_each(|node_record| {
+            database.add_node(node_record).unwrap();
+            database.set_introducer(node_record.public_key(), second_source);
+        });
+        let mut subject = GossipAcceptorReal::new(
+            cryptde(),
+            &AdmissionLimits {
+                max_database_size: 5,
+                ..AdmissionLimits::new()
+            },
+        );
+
+        let result = subject.handle(
+            &mut database,
+            obscured_gossip(&vec![new_node.clone()]),
+            first_source,
+        );
+
+        assert_eq!(result, true);
+        assert_eq!(
+            database.keys(),
+            vec_to_set(vec![
+                this_node.public_key(),
+                neighbor.public_key(),
+                first_source_node.public_key(),
+                second_source_nodes[1].public_key(),
+                new_node.public_key(),
+            ])
+        );
+        assert_eq!(
+            database.introducer_of(new_node.public_key()),
+            Some(first_source)
+        );
+    }
+
+    #[test]
+    fn full_database_rejects_new_nodes_when_only_neighbors_could_be_forgotten() {
+        init_test_logging();
+        let this_node = make_node_record(1234, true, false);
+        let neighbor = make_node_record(2345, true, false);
+        let new_node = make_node_record(5679, false, false);
+        let mut database = make_admission_database(&this_node);
+        database.add_node(&neighbor).unwrap();
+        database.set_introducer(neighbor.public_key(), gossip_source());
+        database
+            .add_neighbor(this_node.public_key(), neighbor.public_key())
+            .unwrap();
+        let mut subject = GossipAcceptorReal::new(
+            cryptde(),
+            &AdmissionLimits {
+                max_database_size: 2,
+                ..AdmissionLimits::new()
+            },
+        );
+
+        let result = subject.handle(
+            &mut database,
+            obscured_gossip(&vec![new_node.clone()]),
+            gossip_source(),
+        );
+
+        assert_eq!(result, false);
+        assert_eq!(database.node_by_key(new_node.public_key()), None);
+        TestLogHandler::new().exists_log_containing(&format!(
+            "WARN: GossipAcceptorReal: Rejecting new Node {}: NeighborhoodDatabase is full and no Node can be forgotten",
+            new_node.public_key()
+        ));
+    }
 }
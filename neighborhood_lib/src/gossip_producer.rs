@@ -2,8 +2,13 @@
 use gossip::to_dot_graph;
 use gossip::Gossip;
 use gossip::GossipBuilder;
+use gossip::GossipDigest;
+use gossip::GossipDigestEntry;
+use gossip::GossipRequest;
+use gossip::GossipResponse;
 use neighborhood_database::NeighborhoodDatabase;
 use neighborhood_database::NodeRecord;
+use std::collections::HashSet;
 use sub_lib::cryptde::Key;
 use sub_lib::logger::Logger;
 
@@ -11,6 +16,12 @@ static MINIMUM_NEIGHBORS: usize = 3;
 
 pub trait GossipProducer {
     fn produce(&self, database: &NeighborhoodDatabase, target: &Key) -> Gossip;
+    fn produce_digest(&self, database: &NeighborhoodDatabase, target: &Key) -> GossipDigest;
+    fn produce_response(
+        &self,
+        database: &NeighborhoodDatabase,
+        request: &GossipRequest,
+    ) -> GossipResponse;
 }
 
 pub struct GossipProducerReal {
@@ -31,11 +42,7 @@ impl GossipProducer for GossipProducerReal {
             a Gossip message representing the current neighborhood for a target node
     */
     fn produce(&self, database: &NeighborhoodDatabase, target: &Key) -> Gossip {
-        let target_node_ref = match database.node_by_key(target) {
-            Some(node_ref) => node_ref,
-            None => panic!("Target node {:?} not in NeighborhoodDatabase", target),
-        };
-
+        let target_node_ref = self.find_target(database, target);
         let introducees = self.choose_introductions(database, target_node_ref);
         let builder = database
             .keys()
@@ -44,9 +51,8 @@ impl GossipProducer for GossipProducerReal {
                 let node_record_ref = database
                     .node_by_key(key_ref)
                     .expect("Key magically disappeared");
-                let reveal_node_addr = node_record_ref.has_neighbor(target_node_ref.public_key())
-                    || target_node_ref.has_neighbor(node_record_ref.public_key())
-                    || introducees.contains(&key_ref);
+                let reveal_node_addr =
+                    self.reveals_node_addr(node_record_ref, target_node_ref, &introducees);
                 so_far.node(node_record_ref, reveal_node_addr)
             });
         let gossip = builder.build();
@@ -56,6 +62,65 @@ impl GossipProducer for GossipProducerReal {
         ));
         gossip
     }
+
+    /*
+        `produce_digest`
+            produces the same view of the neighborhood that `produce` would for `target`, but as (public key, version)
+            pairs instead of whole records, so that `target` can ask only for what it doesn't already have.
+    */
+    fn produce_digest(&self, database: &NeighborhoodDatabase, target: &Key) -> GossipDigest {
+        let target_node_ref = self.find_target(database, target);
+        let introducees = self.choose_introductions(database, target_node_ref);
+        let entries = database
+            .keys()
+            .into_iter()
+            .map(|key_ref| {
+                database
+                    .node_by_key(key_ref)
+                    .expect("Key magically disappeared")
+            })
+            .filter(|node_record_ref| node_record_ref.signatures().is_some())
+            .map(|node_record_ref| GossipDigestEntry {
+                public_key: node_record_ref.public_key().clone(),
+                version: node_record_ref.version(),
+                reveals_node_addr: self.reveals_node_addr(
+                    node_record_ref,
+                    target_node_ref,
+                    &introducees,
+                ),
+            })
+            .collect();
+        GossipDigest {
+            sender: database.root().public_key().clone(),
+            entries,
+        }
+    }
+
+    /*
+        `produce_response`
+            produces the records named in `request`, revealing NodeAddrs just as `produce` would for the requester.
+            Records this Node doesn't have (any more) are left out, as are repeated requests for the same record.
+    */
+    fn produce_response(
+        &self,
+        database: &NeighborhoodDatabase,
+        request: &GossipRequest,
+    ) -> GossipResponse {
+        let target_node_ref = self.find_target(database, &request.sender);
+        let introducees = self.choose_introductions(database, target_node_ref);
+        let requested_keys: HashSet<&Key> = request.requested_keys.iter().collect();
+        let builder = requested_keys
+            .into_iter()
+            .filter_map(|key_ref| database.node_by_key(key_ref))
+            .fold(GossipBuilder::new(), |so_far, node_record_ref| {
+                let reveal_node_addr =
+                    self.reveals_node_addr(node_record_ref, target_node_ref, &introducees);
+                so_far.node(node_record_ref, reveal_node_addr)
+            });
+        GossipResponse {
+            requested_records: builder.build().node_records,
+        }
+    }
 }
 
 impl GossipProducerReal {
@@ -65,6 +130,24 @@ impl GossipProducerReal {
         }
     }
 
+    fn find_target<'a>(&self, database: &'a NeighborhoodDatabase, target: &Key) -> &'a NodeRecord {
+        match database.node_by_key(target) {
+            Some(node_ref) => node_ref,
+            None => panic!("Target node {:?} not in NeighborhoodDatabase", target),
+        }
+    }
+
+    fn reveals_node_addr(
+        &self,
+        node_record_ref: &NodeRecord,
+        target_node_ref: &NodeRecord,
+        introducees: &Vec<&Key>,
+    ) -> bool {
+        node_record_ref.has_neighbor(target_node_ref.public_key())
+            || target_node_ref.has_neighbor(node_record_ref.public_key())
+            || introducees.contains(&node_record_ref.public_key())
+    }
+
     pub fn choose_introductions<'a>(
         &self,
         database: &'a NeighborhoodDatabase,
@@ -75,7 +158,7 @@ impl GossipProducerReal {
             .iter()
             .filter(|key| match database.node_by_key(key) {
                 Some(node) => !node.is_bootstrap_node(),
-                None => unimplemented!(), // we don't know this node, so we should assume it is not a bootstrap node
+                None => true, // we don't know this node, so we should assume it is not a bootstrap node
             })
             .count();
 
@@ -1037,4 +1120,141 @@ mod tests {
         TestLogHandler::new().await_log_containing("\"AQIDBA\" -> \"AgMEBQ\" [style=dashed];", 500);
         TestLogHandler::new().await_log_containing("\"AQIDBA\" -> \"BAUGBw\" [style=dashed];", 500);
     }
+
+    fn make_digest_database() -> (NeighborhoodDatabase, NodeRecord, NodeRecord, NodeRecord) {
+        let this_node = make_node_record(1234, true, false);
+        let near_node = make_node_record(2345, true, false);
+        let far_node = make_node_record(3456, true, false);
+        let target = make_node_record(4567, true, false);
+        let unsigned_node = NodeRecord::new(&Key::new(&[5, 6, 7, 8]), None, false, None, 0);
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            &CryptDENull::from(this_node.public_key()),
+        );
+        database.add_node(&near_node).unwrap();
+        database.add_node(&far_node).unwrap();
+        database.add_node(&target).unwrap();
+        database.add_node(&unsigned_node).unwrap();
+        database
+            .add_neighbor(this_node.public_key(), target.public_key())
+            .unwrap();
+        database
+            .add_neighbor(near_node.public_key(), target.public_key())
+            .unwrap();
+        database
+            .add_neighbor(far_node.public_key(), near_node.public_key())
+            .unwrap();
+        database
+            .node_by_key_mut(far_node.public_key())
+            .unwrap()
+            .set_version(5);
+        (database, near_node, far_node, target)
+    }
+
+    #[test]
+    fn produce_digest_lists_signed_records_with_versions_and_reveals() {
+        let (database, near_node, far_node, target) = make_digest_database();
+        let subject = GossipProducerReal::new();
+
+        let result = subject.produce_digest(&database, target.public_key());
+
+        assert_eq!(&result.sender, database.root().public_key());
+        assert_contains(
+            &result.entries,
+            &GossipDigestEntry {
+                public_key: database.root().public_key().clone(),
+                version: 0,
+                reveals_node_addr: true,
+            },
+        );
+        assert_contains(
+            &result.entries,
+            &GossipDigestEntry {
+                public_key: near_node.public_key().clone(),
+                version: 0,
+                reveals_node_addr: true,
+            },
+        );
+        assert_contains(
+            &result.entries,
+            &GossipDigestEntry {
+                public_key: far_node.public_key().clone(),
+                version: 5,
+                reveals_node_addr: false,
+            },
+        );
+        assert_contains(
+            &result.entries,
+            &GossipDigestEntry {
+                public_key: target.public_key().clone(),
+                version: 0,
+                reveals_node_addr: false,
+            },
+        );
+        assert_eq!(result.entries.len(), 4);
+    }
+
+    #[test]
+    fn produce_response_includes_only_requested_records_that_are_known() {
+        let (database, near_node, far_node, target) = make_digest_database();
+        let request = GossipRequest {
+            sender: target.public_key().clone(),
+            requested_keys: vec![
+                near_node.public_key().clone(),
+                far_node.public_key().clone(),
+                near_node.public_key().clone(),
+                Key::new(&[9, 9, 9, 9]),
+            ],
+        };
+        let subject = GossipProducerReal::new();
+
+        let result = subject.produce_response(&database, &request);
+
+        let near_node_record = database.node_by_key(near_node.public_key()).unwrap();
+        let far_node_record = database.node_by_key(far_node.public_key()).unwrap();
+        assert_contains(
+            &result.requested_records,
+            &GossipNodeRecord::from(near_node_record, true),
+        );
+        assert_contains(
+            &result.requested_records,
+            &GossipNodeRecord::from(far_node_record, false),
+        );
+        assert_eq!(result.requested_records.len(), 2);
+    }
+
+    #[test]
+    fn choose_introductions_counts_unknown_neighbors_of_target_as_standard_nodes() {
+        let this_node = make_node_record(1234, true, false);
+        let first_neighbor = make_node_record(2345, true, false);
+        let second_neighbor = make_node_record(3456, true, false);
+        let mut target = make_node_record(4567, true, false);
+        target.neighbors_mut().push(this_node.public_key().clone());
+        target.neighbors_mut().push(Key::new(&[9, 9, 9, 9]));
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            this_node.is_bootstrap_node(),
+            &CryptDENull::from(this_node.public_key()),
+        );
+        database.add_node(&first_neighbor).unwrap();
+        database.add_node(&second_neighbor).unwrap();
+        database.add_node(&target).unwrap();
+        database
+            .add_neighbor(this_node.public_key(), first_neighbor.public_key())
+            .unwrap();
+        database
+            .add_neighbor(this_node.public_key(), second_neighbor.public_key())
+            .unwrap();
+        database
+            .add_neighbor(this_node.public_key(), target.public_key())
+            .unwrap();
+        let subject = GossipProducerReal::new();
+
+        let result = subject.choose_introductions(&database, &target);
+
+        assert_eq!(result.len(), 1);
+    }
 }
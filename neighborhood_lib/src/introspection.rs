@@ -0,0 +1,631 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use actix::Message;
+use actix::Recipient;
+use actix::Syn;
+use futures::Future;
+use keepalive::KeepaliveTracker;
+use neighborhood_database::NeighborhoodDatabase;
+use neighborhood_database::NodeRecord;
+use serde::Serialize;
+use serde_json;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use sub_lib::logger::Logger;
+use sub_lib::neighborhood::ExitDestination;
+use sub_lib::neighborhood::ExitPolicy;
+use sub_lib::neighborhood::HopRange;
+use sub_lib::neighborhood::RouteConstraint;
+use sub_lib::neighborhood::RoutePolicy;
+use sub_lib::neighborhood::RouteQueryMessage;
+use sub_lib::route::RouteSegment;
+
+// The same minimum hop count the ProxyServer asks for in a decentralized Node
+pub const DEFAULT_DRY_RUN_HOP_COUNT: usize = 2;
+const MAX_REQUEST_HEAD_LENGTH: usize = 8192;
+const READ_TIMEOUT_SECS: u64 = 5;
+
+#[derive(PartialEq, Debug)]
+pub enum IntrospectionRequest {
+    Database,
+    Root,
+    Neighbors,
+    // Find the route the Neighborhood would choose for this query, without using it
+    Route(RouteQueryMessage),
+}
+
+// Answered by the Neighborhood with a JSON document
+#[derive(PartialEq, Debug)]
+pub struct IntrospectionMessage {
+    pub request: IntrospectionRequest,
+}
+
+impl Message for IntrospectionMessage {
+    type Result = String;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum IntrospectionError {
+    MethodNotAllowed(String),
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl IntrospectionError {
+    fn status(&self) -> &'static str {
+        match self {
+            IntrospectionError::MethodNotAllowed(_) => "405 Method Not Allowed",
+            IntrospectionError::NotFound(_) => "404 Not Found",
+            IntrospectionError::BadRequest(_) => "400 Bad Request",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            IntrospectionError::MethodNotAllowed(msg) => msg,
+            IntrospectionError::NotFound(msg) => msg,
+            IntrospectionError::BadRequest(msg) => msg,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct NodeRecordView {
+    pub public_key: String,
+    pub node_addr: Option<String>,
+    pub is_bootstrap_node: bool,
+    pub version: u32,
+    pub neighbors: Vec<String>,
+    pub exit_policy: ExitPolicy,
+    pub latency_ms: Option<u64>,
+    pub introducer: Option<IpAddr>,
+}
+
+impl NodeRecordView {
+    pub fn new(database: &NeighborhoodDatabase, node_record: &NodeRecord) -> NodeRecordView {
+        NodeRecordView {
+            public_key: format!("{}", node_record.public_key()),
+            node_addr: node_record
+                .node_addr_opt()
+                .map(|node_addr| format!("{}", node_addr)),
+            is_bootstrap_node: node_record.is_bootstrap_node(),
+            version: node_record.version(),
+            neighbors: node_record
+                .neighbors()
+                .iter()
+                .map(|key| format!("{}", key))
+                .collect(),
+            exit_policy: node_record.exit_policy().clone(),
+            latency_ms: node_record.latency_opt().map(to_millis),
+            introducer: database.introducer_of(node_record.public_key()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct DatabaseView {
+    pub root: String,
+    pub nodes: Vec<NodeRecordView>,
+}
+
+impl DatabaseView {
+    // Nodes are sorted by public key, so that the same database always looks the same
+    pub fn new(database: &NeighborhoodDatabase) -> DatabaseView {
+        let mut nodes: Vec<NodeRecordView> = database
+            .keys()
+            .into_iter()
+            .filter_map(|key| database.node_by_key(key))
+            .map(|node_record| NodeRecordView::new(database, node_record))
+            .collect();
+        nodes.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+        DatabaseView {
+            root: format!("{}", database.root().public_key()),
+            nodes,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct NeighborView {
+    pub public_key: String,
+    pub node_addr: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub missed_keepalives: u32,
+    pub awaiting_keepalive_reply: bool,
+}
+
+impl NeighborView {
+    pub fn list(
+        database: &NeighborhoodDatabase,
+        keepalive_tracker: &KeepaliveTracker,
+    ) -> Vec<NeighborView> {
+        database
+            .root()
+            .neighbors()
+            .iter()
+            .filter_map(|key| database.node_by_key(key))
+            .map(|neighbor| {
+                let (missed_keepalives, awaiting_keepalive_reply) =
+                    match keepalive_tracker.status(neighbor.public_key()) {
+                        Some(status) => (status.missed, status.awaiting_reply),
+                        None => (0, false),
+                    };
+                NeighborView {
+                    public_key: format!("{}", neighbor.public_key()),
+                    node_addr: neighbor
+                        .node_addr_opt()
+                        .map(|node_addr| format!("{}", node_addr)),
+                    latency_ms: neighbor.latency_opt().map(to_millis),
+                    missed_keepalives,
+                    awaiting_keepalive_reply,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RouteSegmentView {
+    pub keys: Vec<String>,
+    pub recipient: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RouteView {
+    pub found: bool,
+    pub segments: Vec<RouteSegmentView>,
+}
+
+impl RouteView {
+    pub fn new(segments_opt: Option<Vec<RouteSegment>>) -> RouteView {
+        match segments_opt {
+            Some(segments) => RouteView {
+                found: true,
+                segments: segments
+                    .iter()
+                    .map(|segment| RouteSegmentView {
+                        keys: segment.keys.iter().map(|key| format!("{}", key)).collect(),
+                        recipient: format!("{:?}", segment.recipient),
+                    })
+                    .collect(),
+            },
+            None => RouteView {
+                found: false,
+                segments: vec![],
+            },
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+struct ErrorView {
+    error: String,
+}
+
+pub fn to_json<T: Serialize>(view: &T) -> String {
+    serde_json::to_string(view).expect("Introspection view can't be serialized")
+}
+
+fn to_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+// Understands the request line of an HTTP GET, such as "GET /route?hops=3&host=example.com&port=443 HTTP/1.1"
+pub fn parse_request(request_line: &str) -> Result<IntrospectionRequest, IntrospectionError> {
+    let mut pieces = request_line.split_whitespace();
+    let method = pieces.next().unwrap_or("");
+    let target = pieces.next().unwrap_or("");
+    if method != "GET" {
+        return Err(IntrospectionError::MethodNotAllowed(format!(
+            "Only GET is supported, not '{}'",
+            method
+        )));
+    }
+    let (path, query) = match target.find('?') {
+        Some(index) => (&target[..index], &target[(index + 1)..]),
+        None => (target, ""),
+    };
+    match path {
+        "/database" => Ok(IntrospectionRequest::Database),
+        "/root" => Ok(IntrospectionRequest::Root),
+        "/neighbors" => Ok(IntrospectionRequest::Neighbors),
+        "/route" => parse_route_query(query).map(IntrospectionRequest::Route),
+        _ => Err(IntrospectionError::NotFound(format!(
+            "Nothing to see at '{}': try /database, /root, /neighbors, or /route",
+            path
+        ))),
+    }
+}
+
+fn parse_route_query(query: &str) -> Result<RouteQueryMessage, IntrospectionError> {
+    let mut hops = DEFAULT_DRY_RUN_HOP_COUNT;
+    let mut max_hops_opt: Option<usize> = None;
+    let mut host_opt: Option<String> = None;
+    let mut port_opt: Option<u16> = None;
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let mut name_and_value = parameter.splitn(2, '=');
+        let name = name_and_value.next().unwrap_or("");
+        let value = name_and_value.next().unwrap_or("");
+        match name {
+            "hops" => {
+                hops = value.parse::<usize>().map_err(|_| {
+                    IntrospectionError::BadRequest(format!("hops needs a number, not '{}'", value))
+                })?
+            }
+            "max_hops" => {
+                max_hops_opt = Some(value.parse::<usize>().map_err(|_| {
+                    IntrospectionError::BadRequest(format!(
+                        "max_hops needs a number, not '{}'",
+                        value
+                    ))
+                })?)
+            }
+            "host" if !value.is_empty() => host_opt = Some(value.to_string()),
+            "port" => match value.parse::<u16>() {
+                Ok(port) if port > 0 => port_opt = Some(port),
+                _ => {
+                    return Err(IntrospectionError::BadRequest(format!(
+                        "port needs a port number, not '{}'",
+                        value
+                    )))
+                }
+            },
+            _ => {
+                return Err(IntrospectionError::BadRequest(format!(
+                    "Unknown route parameter '{}'",
+                    parameter
+                )))
+            }
+        }
+    }
+    let exit_destination_opt = match (host_opt, port_opt) {
+        (None, None) => None,
+        (hostname_opt, Some(port)) => Some(ExitDestination { hostname_opt, port }),
+        (Some(_), None) => {
+            return Err(IntrospectionError::BadRequest(String::from(
+                "host needs a port to go with it",
+            )))
+        }
+    };
+    let hop_range = HopRange {
+        minimum: hops,
+        maximum_opt: max_hops_opt,
+    };
+    Ok(RouteQueryMessage::data_indefinite_route_request(
+        RoutePolicy {
+            over: hop_range,
+            back: hop_range,
+        },
+        RouteConstraint {
+            exit_destination_opt,
+            ..RouteConstraint::new()
+        },
+    ))
+}
+
+// Answers introspection requests on a port that only this computer can reach. Requests are answered one at a time
+// on a thread of the server's own, since they're meant for the Node's owner rather than for the Network.
+pub struct IntrospectionServer {}
+
+impl IntrospectionServer {
+    // Returns the address the server is listening on
+    pub fn start(
+        port: u16,
+        recipient: Recipient<Syn, IntrospectionMessage>,
+    ) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port,
+        ))?;
+        let local_addr = listener.local_addr()?;
+        thread::spawn(move || {
+            let logger = Logger::new("IntrospectionServer");
+            listener
+                .incoming()
+                .for_each(|stream_result| match stream_result {
+                    Ok(stream) => IntrospectionServer::serve(stream, &recipient, &logger),
+                    Err(e) => logger.warning(format!("Could not accept connection: {}", e)),
+                });
+        });
+        Ok(local_addr)
+    }
+
+    fn serve(
+        mut stream: TcpStream,
+        recipient: &Recipient<Syn, IntrospectionMessage>,
+        logger: &Logger,
+    ) {
+        let request_line = match IntrospectionServer::read_request_line(&mut stream) {
+            Ok(request_line) => request_line,
+            Err(e) => {
+                logger.warning(format!("Could not read introspection request: {}", e));
+                return;
+            }
+        };
+        logger.debug(format!("Introspection request: {}", request_line));
+        let (status, body) = match parse_request(&request_line) {
+            Ok(request) => match recipient.send(IntrospectionMessage { request }).wait() {
+                Ok(json) => ("200 OK", json),
+                Err(e) => (
+                    "503 Service Unavailable",
+                    to_json(&ErrorView {
+                        error: format!("Neighborhood didn't answer: {:?}", e),
+                    }),
+                ),
+            },
+            Err(e) => (
+                e.status(),
+                to_json(&ErrorView {
+                    error: e.message().to_string(),
+                }),
+            ),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        if let Err(e) = stream.write_all(response.as_bytes()) {
+            logger.warning(format!("Could not write introspection response: {}", e));
+        }
+    }
+
+    // Reads until the end of the request head, but only the first line is of interest
+    fn read_request_line(stream: &mut TcpStream) -> io::Result<String> {
+        stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+        let mut head: Vec<u8> = vec![];
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            if head.len() > MAX_REQUEST_HEAD_LENGTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request head is too long",
+                ));
+            }
+            let len = stream.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..len]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        Ok(head.lines().next().unwrap_or("").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actix::Addr;
+    use actix::Context;
+    use actix::Handler;
+    use actix::System;
+    use neighborhood_test_utils::make_node_record;
+    use std::str::FromStr;
+    use std::sync::mpsc;
+    use sub_lib::cryptde::Key;
+    use test_utils::test_utils::cryptde;
+
+    struct IntrospectionResponder {}
+
+    impl Actor for IntrospectionResponder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<IntrospectionMessage> for IntrospectionResponder {
+        type Result = String;
+
+        fn handle(&mut self, msg: IntrospectionMessage, _ctx: &mut Self::Context) -> String {
+            format!("\"{:?}\"", msg.request)
+        }
+    }
+
+    fn request(server_addr: SocketAddr, request_head: &str) -> String {
+        let mut stream = TcpStream::connect(server_addr).unwrap();
+        stream.write_all(request_head.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn parse_request_recognizes_every_path() {
+        assert_eq!(
+            parse_request("GET /database HTTP/1.1"),
+            Ok(IntrospectionRequest::Database)
+        );
+        assert_eq!(
+            parse_request("GET /root HTTP/1.1"),
+            Ok(IntrospectionRequest::Root)
+        );
+        assert_eq!(
+            parse_request("GET /neighbors HTTP/1.1"),
+            Ok(IntrospectionRequest::Neighbors)
+        );
+        assert_eq!(
+            parse_request("GET /route HTTP/1.1"),
+            Ok(IntrospectionRequest::Route(
+                RouteQueryMessage::data_indefinite_route_request(
+                    RoutePolicy::at_least(DEFAULT_DRY_RUN_HOP_COUNT),
+                    RouteConstraint::new()
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_request_builds_route_query_from_parameters() {
+        let result =
+            parse_request("GET /route?hops=3&max_hops=4&host=example.com&port=443 HTTP/1.1");
+
+        let hop_range = HopRange {
+            minimum: 3,
+            maximum_opt: Some(4),
+        };
+        assert_eq!(
+            result,
+            Ok(IntrospectionRequest::Route(
+                RouteQueryMessage::data_indefinite_route_request(
+                    RoutePolicy {
+                        over: hop_range,
+                        back: hop_range,
+                    },
+                    RouteConstraint {
+                        exit_destination_opt: Some(ExitDestination {
+                            hostname_opt: Some(String::from("example.com")),
+                            port: 443,
+                        }),
+                        ..RouteConstraint::new()
+                    }
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_request_complains_about_bad_requests() {
+        assert_eq!(
+            parse_request("POST /database HTTP/1.1"),
+            Err(IntrospectionError::MethodNotAllowed(String::from(
+                "Only GET is supported, not 'POST'"
+            )))
+        );
+        assert_eq!(
+            parse_request("GET /secrets HTTP/1.1"),
+            Err(IntrospectionError::NotFound(String::from(
+                "Nothing to see at '/secrets': try /database, /root, /neighbors, or /route"
+            )))
+        );
+        assert_eq!(
+            parse_request("GET /route?hops=many HTTP/1.1"),
+            Err(IntrospectionError::BadRequest(String::from(
+                "hops needs a number, not 'many'"
+            )))
+        );
+        assert_eq!(
+            parse_request("GET /route?port=0 HTTP/1.1"),
+            Err(IntrospectionError::BadRequest(String::from(
+                "port needs a port number, not '0'"
+            )))
+        );
+        assert_eq!(
+            parse_request("GET /route?host=example.com HTTP/1.1"),
+            Err(IntrospectionError::BadRequest(String::from(
+                "host needs a port to go with it"
+            )))
+        );
+        assert_eq!(
+            parse_request("GET /route?booga=1 HTTP/1.1"),
+            Err(IntrospectionError::BadRequest(String::from(
+                "Unknown route parameter 'booga=1'"
+            )))
+        );
+    }
+
+    #[test]
+    fn database_view_lists_nodes_in_key_order_with_their_introducers() {
+        let this_node = make_node_record(2345, true, false);
+        let other_node = make_node_record(1234, false, false);
+        let mut database = NeighborhoodDatabase::new(
+            this_node.public_key(),
+            this_node.node_addr_opt().as_ref().unwrap(),
+            false,
+            cryptde(),
+        );
+        database.add_node(&other_node).unwrap();
+        database.set_introducer(
+            other_node.public_key(),
+            IpAddr::from_str("3.4.5.6").unwrap(),
+        );
+        database
+            .add_neighbor(this_node.public_key(), other_node.public_key())
+            .unwrap();
+
+        let result = DatabaseView::new(&database);
+
+        assert_eq!(
+            result,
+            DatabaseView {
+                root: String::from("AgMEBQ"),
+                nodes: vec![
+                    NodeRecordView {
+                        public_key: String::from("AQIDBA"),
+                        node_addr: None,
+                        is_bootstrap_node: false,
+                        version: 0,
+                        neighbors: vec![],
+                        exit_policy: ExitPolicy::allow_all(),
+                        latency_ms: None,
+                        introducer: Some(IpAddr::from_str("3.4.5.6").unwrap()),
+                    },
+                    NodeRecordView {
+                        public_key: String::from("AgMEBQ"),
+                        node_addr: Some(String::from("2.3.4.5:2345")),
+                        is_bootstrap_node: false,
+                        version: 0,
+                        neighbors: vec![String::from("AQIDBA")],
+                        exit_policy: ExitPolicy::allow_all(),
+                        latency_ms: None,
+                        introducer: None,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn route_view_shows_segments_or_their_absence() {
+        let segments = vec![RouteSegment::new(
+            vec![&Key::new(&[1, 2, 3, 4]), &Key::new(&[2, 3, 4, 5])],
+            ::sub_lib::dispatcher::Component::ProxyClient,
+        )];
+
+        let found = to_json(&RouteView::new(Some(segments)));
+        let not_found = to_json(&RouteView::new(None));
+
+        assert_eq!(
+            found,
+            r#"{"found":true,"segments":[{"keys":["AQIDBA","AgMEBQ"],"recipient":"ProxyClient"}]}"#
+        );
+        assert_eq!(not_found, r#"{"found":false,"segments":[]}"#);
+    }
+
+    #[test]
+    fn server_answers_local_requests_with_what_the_neighborhood_says() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let system =
+                System::new("server_answers_local_requests_with_what_the_neighborhood_says");
+            let addr: Addr<Syn, IntrospectionResponder> = IntrospectionResponder {}.start();
+            let server_addr = IntrospectionServer::start(0, addr.recipient()).unwrap();
+            tx.send(server_addr).unwrap();
+            system.run();
+        });
+        let server_addr = rx.recv().unwrap();
+
+        let good_response = request(server_addr, "GET /root HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let bad_response = request(server_addr, "GET /booga HTTP/1.1\r\n\r\n");
+
+        assert_eq!(server_addr.ip(), IpAddr::from_str("127.0.0.1").unwrap());
+        assert_eq!(
+            good_response,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 6\r\nConnection: close\r\n\r\n\"Root\""
+        );
+        assert_eq!(bad_response.starts_with("HTTP/1.1 404 Not Found\r\n"), true);
+        assert_eq!(
+            bad_response.ends_with(
+                "{\"error\":\"Nothing to see at '/booga': try /database, /root, /neighbors, or /route\"}"
+            ),
+            true
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::Key;
+
+// Sent to a neighbor in a CORES package, which the neighbor sends back with is_reply set
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Keepalive {
+    pub sender: Key,
+    pub serial: u32,
+    pub is_reply: bool,
+}
+
+// How a neighbor has been answering keepalives, for anyone who asks
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeepaliveStatus {
+    pub missed: u32,
+    pub awaiting_reply: bool,
+}
+
+struct NeighborState {
+    serial: u32,
+    sent_at: Instant,
+    awaiting_reply: bool,
+    missed: u32,
+}
+
+// Keeps track of the keepalives that have been sent to neighbors, and which neighbors haven't been answering them.
+pub struct KeepaliveTracker {
+    max_missed: u32,
+    neighbor_states: HashMap<Key, NeighborState>,
+}
+
+impl KeepaliveTracker {
+    pub fn new(max_missed: u32) -> KeepaliveTracker {
+        KeepaliveTracker {
+            max_missed,
+            neighbor_states: HashMap::new(),
+        }
+    }
+
+    // Returns the keepalives to send now, paired with the neighbors to send them to, and the neighbors that have
+    // missed too many replies in a row. Neighbors that have gone from the list are forgotten.
+    pub fn tick(
+        &mut self,
+        cryptde: &CryptDE,
+        neighbors: &[Key],
+        now: Instant,
+    ) -> (Vec<(Key, Keepalive)>, Vec<Key>) {
+        self.neighbor_states
+            .retain(|key, _| neighbors.contains(key));
+        let mut keepalives = vec![];
+        let mut dead_neighbors = vec![];
+        for neighbor in neighbors {
+            let max_missed = self.max_missed;
+            let state = self
+                .neighbor_states
+                .entry(neighbor.clone())
+                .or_insert(NeighborState {
+                    serial: 0,
+                    sent_at: now,
+                    awaiting_reply: false,
+                    missed: 0,
+                });
+            if state.awaiting_reply {
+                state.missed += 1;
+            }
+            if state.missed >= max_missed {
+                dead_neighbors.push(neighbor.clone());
+                continue;
+            }
+            state.serial = KeepaliveTracker::random_serial(cryptde);
+            state.sent_at = now;
+            state.awaiting_reply = true;
+            keepalives.push((
+                neighbor.clone(),
+                Keepalive {
+                    sender: cryptde.public_key(),
+                    serial: state.serial,
+                    is_reply: false,
+                },
+            ));
+        }
+        dead_neighbors.iter().for_each(|neighbor| {
+            self.neighbor_states.remove(neighbor);
+        });
+        (keepalives, dead_neighbors)
+    }
+
+    // Returns the round-trip time if the reply answers the latest keepalive sent to its sender
+    pub fn reply_received(&mut self, reply: &Keepalive, now: Instant) -> Option<Duration> {
+        match self.neighbor_states.get_mut(&reply.sender) {
+            Some(ref mut state) if state.awaiting_reply && (state.serial == reply.serial) => {
+                state.awaiting_reply = false;
+                state.missed = 0;
+                Some(now.duration_since(state.sent_at))
+            }
+            _ => None,
+        }
+    }
+
+    // None if no keepalive has been sent to the neighbor yet
+    pub fn status(&self, neighbor: &Key) -> Option<KeepaliveStatus> {
+        self.neighbor_states
+            .get(neighbor)
+            .map(|state| KeepaliveStatus {
+                missed: state.missed,
+                awaiting_reply: state.awaiting_reply,
+            })
+    }
+
+    // Serials are random so that nobody who didn't see a keepalive can forge the reply to it
+    fn random_serial(cryptde: &CryptDE) -> u32 {
+        let mut bytes = [0u8; 4];
+        cryptde.random(&mut bytes[..]);
+        bytes
+            .iter()
+            .fold(0, |serial, byte| (serial << 8) | u32::from(*byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sub_lib::cryptde_null::CryptDENull;
+    use sub_lib::cryptde_real::CryptDEReal;
+
+    fn keys() -> (Key, Key) {
+        (Key::new(&[2]), Key::new(&[3]))
+    }
+
+    fn make_cryptde() -> CryptDEReal {
+        let mut cryptde = CryptDEReal::new();
+        cryptde.generate_key_pair();
+        cryptde
+    }
+
+    fn reply(sender: &Key, serial: u32) -> Keepalive {
+        Keepalive {
+            sender: sender.clone(),
+            serial,
+            is_reply: true,
+        }
+    }
+
+    #[test]
+    fn tick_sends_a_keepalive_to_every_neighbor() {
+        let (a, b) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(3);
+
+        let (keepalives, dead_neighbors) =
+            subject.tick(&cryptde, &[a.clone(), b.clone()], Instant::now());
+
+        assert_eq!(
+            keepalives
+                .iter()
+                .map(|(neighbor, _)| neighbor.clone())
+                .collect::<Vec<Key>>(),
+            vec![a, b]
+        );
+        keepalives.iter().for_each(|(_, keepalive)| {
+            assert_eq!(keepalive.sender, cryptde.public_key());
+            assert_eq!(keepalive.is_reply, false);
+        });
+        assert_ne!(keepalives[0].1.serial, keepalives[1].1.serial);
+        assert_eq!(dead_neighbors, vec![]);
+    }
+
+    #[test]
+    fn serials_come_from_the_cryptde() {
+        let (a, _) = keys();
+        let mut subject = KeepaliveTracker::new(3);
+
+        let (keepalives, _) = subject.tick(&CryptDENull::new(), &[a], Instant::now());
+
+        // CryptDENull's random bytes are all '4'
+        assert_eq!(keepalives[0].1.serial, 0x34343434);
+    }
+
+    #[test]
+    fn reply_to_latest_keepalive_reports_round_trip_time() {
+        let (a, _) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(3);
+        let sent_at = Instant::now();
+        let (keepalives, _) = subject.tick(&cryptde, &[a.clone()], sent_at);
+        let serial = keepalives[0].1.serial;
+
+        let result =
+            subject.reply_received(&reply(&a, serial), sent_at + Duration::from_millis(40));
+        let repeated_result =
+            subject.reply_received(&reply(&a, serial), sent_at + Duration::from_millis(50));
+
+        assert_eq!(result, Some(Duration::from_millis(40)));
+        assert_eq!(repeated_result, None);
+    }
+
+    #[test]
+    fn replies_to_old_keepalives_and_from_strangers_are_ignored() {
+        let (a, b) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(3);
+        let now = Instant::now();
+        let (old_keepalives, _) = subject.tick(&cryptde, &[a.clone()], now);
+        let (keepalives, _) = subject.tick(&cryptde, &[a.clone()], now);
+        let old_serial = old_keepalives[0].1.serial;
+        let serial = keepalives[0].1.serial;
+
+        assert_eq!(subject.reply_received(&reply(&a, old_serial), now), None);
+        assert_eq!(subject.reply_received(&reply(&b, serial), now), None);
+        assert_eq!(
+            subject.reply_received(&reply(&a, serial), now),
+            Some(Duration::from_millis(0))
+        );
+    }
+
+    #[test]
+    fn neighbor_that_misses_too_many_replies_in_a_row_is_declared_dead() {
+        let (a, b) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(2);
+        let neighbors = vec![a.clone(), b.clone()];
+        let now = Instant::now();
+
+        let (first_keepalives, _) = subject.tick(&cryptde, &neighbors, now);
+        subject.reply_received(&reply(&b, first_keepalives[1].1.serial), now);
+        let (second_keepalives, first_dead) = subject.tick(&cryptde, &neighbors, now);
+        subject.reply_received(&reply(&b, second_keepalives[1].1.serial), now);
+        let (keepalives, second_dead) = subject.tick(&cryptde, &neighbors, now);
+
+        assert_eq!(first_dead, vec![]);
+        assert_eq!(second_dead, vec![a]);
+        assert_eq!(keepalives.len(), 1);
+        assert_eq!(keepalives[0].0, b);
+        assert_eq!(keepalives[0].1.sender, cryptde.public_key());
+        assert_eq!(keepalives[0].1.is_reply, false);
+    }
+
+    #[test]
+    fn a_reply_forgives_earlier_misses() {
+        let (a, _) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(2);
+        let now = Instant::now();
+
+        subject.tick(&cryptde, &[a.clone()], now);
+        let (keepalives, _) = subject.tick(&cryptde, &[a.clone()], now);
+        subject.reply_received(&reply(&a, keepalives[0].1.serial), now);
+        subject.tick(&cryptde, &[a.clone()], now);
+        let (keepalives, dead_neighbors) = subject.tick(&cryptde, &[a.clone()], now);
+
+        assert_eq!(keepalives.len(), 1);
+        assert_eq!(dead_neighbors, vec![]);
+    }
+
+    #[test]
+    fn status_reports_misses_and_outstanding_keepalives() {
+        let (a, b) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(3);
+        let now = Instant::now();
+        subject.tick(&cryptde, &[a.clone()], now);
+        let (keepalives, _) = subject.tick(&cryptde, &[a.clone()], now);
+
+        assert_eq!(
+            subject.status(&a),
+            Some(KeepaliveStatus {
+                missed: 1,
+                awaiting_reply: true,
+            })
+        );
+        subject.reply_received(&reply(&a, keepalives[0].1.serial), now);
+        assert_eq!(
+            subject.status(&a),
+            Some(KeepaliveStatus {
+                missed: 0,
+                awaiting_reply: false,
+            })
+        );
+        assert_eq!(subject.status(&b), None);
+    }
+
+    #[test]
+    fn former_neighbors_are_forgotten() {
+        let (a, _) = keys();
+        let cryptde = make_cryptde();
+        let mut subject = KeepaliveTracker::new(1);
+        let now = Instant::now();
+        subject.tick(&cryptde, &[a.clone()], now);
+
+        subject.tick(&cryptde, &[], now);
+        let (keepalives, dead_neighbors) = subject.tick(&cryptde, &[a.clone()], now);
+
+        assert_eq!(keepalives.len(), 1);
+        assert_eq!(dead_neighbors, vec![]);
+    }
+}
@@ -1,10 +1,13 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 extern crate actix;
 extern crate futures;
+extern crate rand;
 extern crate regex;
+extern crate serde;
 extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate sub_lib;
 extern crate tokio;
 
@@ -16,8 +19,13 @@ extern crate test_utils;
 pub mod gossip;
 pub mod gossip_acceptor;
 pub mod gossip_producer;
+pub mod introspection;
+pub mod keepalive;
 pub mod neighborhood;
 pub mod neighborhood_database;
+pub mod neighborhood_store;
+pub mod route_search;
+pub mod route_selector;
 
 #[cfg(test)]
 mod neighborhood_test_utils;
@@ -1,6 +1,13 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use actix::actors::signal::ProcessSignals;
+use actix::actors::signal::Signal;
+use actix::actors::signal::SignalType;
+use actix::actors::signal::Subscribe;
+use actix::msgs;
 use actix::Actor;
 use actix::Addr;
+use actix::Arbiter;
+use actix::AsyncContext;
 use actix::Context;
 use actix::Handler;
 use actix::MessageResult;
@@ -8,12 +15,40 @@ use actix::Recipient;
 use actix::Syn;
 use gossip::to_dot_graph;
 use gossip::Gossip;
+use gossip::GossipDigest;
+use gossip::GossipRequest;
+use gossip::GossipResponse;
 use gossip_acceptor::GossipAcceptor;
 use gossip_acceptor::GossipAcceptorReal;
 use gossip_producer::GossipProducer;
 use gossip_producer::GossipProducerReal;
+use introspection::to_json;
+use introspection::DatabaseView;
+use introspection::IntrospectionMessage;
+use introspection::IntrospectionRequest;
+use introspection::IntrospectionServer;
+use introspection::NeighborView;
+use introspection::NodeRecordView;
+use introspection::RouteView;
+use keepalive::Keepalive;
+use keepalive::KeepaliveTracker;
 use neighborhood_database::NeighborhoodDatabase;
 use neighborhood_database::NodeRecord;
+use neighborhood_store::NeighborhoodStore;
+use neighborhood_store::NeighborhoodStoreError;
+use neighborhood_store::RECORD_EXPIRY_SECS;
+use neighborhood_store::SAVE_INTERVAL_SECS;
+use rand::rngs::StdRng;
+use rand::FromEntropy;
+use route_search;
+use route_search::RouteSearchLimits;
+use route_selector::RouteSelector;
+use route_selector::RouteSelectorReal;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::Key;
 use sub_lib::dispatcher::Component;
@@ -23,11 +58,13 @@ use sub_lib::logger::Logger;
 use sub_lib::neighborhood::sentinel_ip_addr;
 use sub_lib::neighborhood::BootstrapNeighborhoodNowMessage;
 use sub_lib::neighborhood::DispatcherNodeQueryMessage;
+use sub_lib::neighborhood::HopRange;
 use sub_lib::neighborhood::NeighborhoodConfig;
 use sub_lib::neighborhood::NeighborhoodSubs;
 use sub_lib::neighborhood::NodeDescriptor;
 use sub_lib::neighborhood::NodeQueryMessage;
 use sub_lib::neighborhood::RemoveNeighborMessage;
+use sub_lib::neighborhood::RouteConstraint;
 use sub_lib::neighborhood::RouteQueryMessage;
 use sub_lib::neighborhood::RouteQueryResponse;
 use sub_lib::neighborhood::RouteType;
@@ -40,12 +77,21 @@ use sub_lib::stream_handler_pool::DispatcherNodeQueryResponse;
 use sub_lib::utils::plus;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
 
+const MAX_FORGED_GOSSIP_MESSAGES: u32 = 3;
+
 pub struct Neighborhood {
     cryptde: &'static CryptDE,
     hopper: Option<Recipient<Syn, IncipientCoresPackage>>,
     gossip_acceptor: Box<GossipAcceptor>,
     gossip_producer: Box<GossipProducer>,
+    route_selector: Box<RouteSelector>,
+    route_search_limits: RouteSearchLimits,
     neighborhood_database: NeighborhoodDatabase,
+    store_opt: Option<NeighborhoodStore>,
+    keepalive_interval: Duration,
+    keepalive_tracker: KeepaliveTracker,
+    forgery_counts: HashMap<IpAddr, u32>,
+    introspection_port_opt: Option<u16>,
     logger: Logger,
 }
 
@@ -59,10 +105,47 @@ impl Handler<BindMessage> for Neighborhood {
     fn handle(&mut self, msg: BindMessage, ctx: &mut Self::Context) -> Self::Result {
         ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
         self.hopper = Some(msg.peer_actors.hopper.from_hopper_client);
+        Neighborhood::schedule_keepalives(ctx, self.keepalive_interval);
+        if let Some(port) = self.introspection_port_opt {
+            let addr: Addr<Syn, Neighborhood> = ctx.address();
+            match IntrospectionServer::start(port, addr.recipient::<IntrospectionMessage>()) {
+                Ok(local_addr) => self.logger.info(format!(
+                    "Answering introspection requests on {}",
+                    local_addr
+                )),
+                Err(e) => self.logger.error(format!(
+                    "Could not answer introspection requests on port {}: {}",
+                    port, e
+                )),
+            }
+        }
+        if let Some(save_interval) = self.store_opt.as_ref().map(|store| store.save_interval()) {
+            Neighborhood::schedule_save(ctx, save_interval);
+            let addr: Addr<Syn, Neighborhood> = ctx.address();
+            Arbiter::system_registry()
+                .get::<ProcessSignals>()
+                .do_send(Subscribe(addr.recipient::<Signal>()));
+        }
         ()
     }
 }
 
+// Subscribing to these signals replaces the default handling, which would simply have ended the process: so once
+// the database is saved, the actor system is stopped, and whoever started it ends the process.
+impl Handler<Signal> for Neighborhood {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, _ctx: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            SignalType::Int | SignalType::Term | SignalType::Quit | SignalType::Hup => {
+                self.save_database();
+                Arbiter::system().do_send(msgs::SystemExit(0));
+            }
+            SignalType::Child => (),
+        }
+    }
+}
+
 impl Handler<BootstrapNeighborhoodNowMessage> for Neighborhood {
     type Result = ();
 
@@ -201,20 +284,38 @@ impl Handler<RouteQueryMessage> for Neighborhood {
         _ctx: &mut Self::Context,
     ) -> <Self as Handler<RouteQueryMessage>>::Result {
         let msg_str = format!("{:?}", msg);
-        let result = if msg.minimum_hop_count == 0 {
-            Some(self.zero_hop_route_response())
-        } else {
-            match msg.route_type {
-                RouteType::OneWay => self.make_one_way_route(msg),
-                RouteType::RoundTrip => self.make_round_trip_route(msg),
-            }
-        };
+        let result = self
+            .make_route_segments(&msg, false)
+            .map(|segments| self.make_route_query_response(segments));
         self.logger
             .trace(format!("Processed {} into {:?}", msg_str, result));
         MessageResult(result)
     }
 }
 
+impl Handler<IntrospectionMessage> for Neighborhood {
+    type Result = String;
+
+    fn handle(&mut self, msg: IntrospectionMessage, _ctx: &mut Self::Context) -> Self::Result {
+        match msg.request {
+            IntrospectionRequest::Database => {
+                to_json(&DatabaseView::new(&self.neighborhood_database))
+            }
+            IntrospectionRequest::Root => to_json(&NodeRecordView::new(
+                &self.neighborhood_database,
+                self.neighborhood_database.root(),
+            )),
+            IntrospectionRequest::Neighbors => to_json(&NeighborView::list(
+                &self.neighborhood_database,
+                &self.keepalive_tracker,
+            )),
+            IntrospectionRequest::Route(route_query) => to_json(&RouteView::new(
+                self.make_route_segments(&route_query, true),
+            )),
+        }
+    }
+}
+
 impl Handler<ExpiredCoresPackagePackage> for Neighborhood {
     type Result = ();
 
@@ -223,13 +324,28 @@ impl Handler<ExpiredCoresPackagePackage> for Neighborhood {
         msg: ExpiredCoresPackagePackage,
         _ctx: &mut Self::Context,
     ) -> Self::Result {
-        let incoming_gossip: Gossip = match msg.expired_cores_package.payload() {
-            Ok(p) => p,
-            Err(_) => {
-                self.logger
-                    .error(format!("Unintelligible Gossip message received: ignoring"));
-                return ();
-            }
+        if self.is_shunned(&msg.sender_ip) {
+            self.logger.debug(format!(
+                "Ignoring message from {}, which has sent too much forged Gossip",
+                msg.sender_ip
+            ));
+            return ();
+        }
+        let package = &msg.expired_cores_package;
+        let (incoming_gossip, is_response) = if let Ok(gossip) = package.payload::<Gossip>() {
+            (gossip, false)
+        } else if let Ok(response) = package.payload::<GossipResponse>() {
+            (response.to_gossip(), true)
+        } else if let Ok(digest) = package.payload::<GossipDigest>() {
+            return self.handle_gossip_digest(digest);
+        } else if let Ok(request) = package.payload::<GossipRequest>() {
+            return self.handle_gossip_request(request);
+        } else if let Ok(keepalive) = package.payload::<Keepalive>() {
+            return self.handle_keepalive(keepalive, msg.sender_ip);
+        } else {
+            self.logger
+                .error(format!("Unintelligible Gossip message received: ignoring"));
+            return ();
         };
         self.logger.trace(format!(
             "Received Gossip: {}",
@@ -247,12 +363,29 @@ impl Handler<ExpiredCoresPackagePackage> for Neighborhood {
         self.logger
             .info(format!("Processing Gossip about {} Nodes", num_nodes));
 
-        let db_changed = self
-            .gossip_acceptor
-            .handle(&mut self.neighborhood_database, incoming_gossip);
+        let forgeries = self.gossip_acceptor.find_forgeries(&incoming_gossip);
+        if !forgeries.is_empty() {
+            self.penalize_forger(msg.sender_ip, &forgeries);
+            if self.is_shunned(&msg.sender_ip) {
+                return ();
+            }
+        }
+        let db_changed = self.gossip_acceptor.handle(
+            &mut self.neighborhood_database,
+            incoming_gossip,
+            msg.sender_ip,
+        );
+        if let Some(ref mut store) = self.store_opt {
+            let now = SystemTime::now();
+            gossip_records
+                .iter()
+                .filter(|gnr| !forgeries.contains(&gnr.inner.public_key))
+                .for_each(|gnr| store.heard_about(&gnr.inner.public_key, now));
+        }
         if db_changed {
+            self.neighborhood_database.root_mut().sign(self.cryptde);
             match gossip_records.as_slice() {
-                [only] => self.gossip_to(&vec![only.public_key()]),
+                [only] if !is_response => self.gossip_to(&vec![only.public_key()]),
                 _ => self.gossip_to_neighbors(),
             };
         }
@@ -268,18 +401,7 @@ impl Handler<RemoveNeighborMessage> for Neighborhood {
     type Result = ();
 
     fn handle(&mut self, msg: RemoveNeighborMessage, _ctx: &mut Self::Context) -> Self::Result {
-        let public_key = &msg.public_key;
-        match self.neighborhood_database.remove_neighbor(public_key) {
-            Err(s) => self.logger.error(s),
-            Ok(db_changed) => {
-                if db_changed {
-                    self.neighborhood_database.root_mut().increment_version();
-                    self.gossip_to_neighbors();
-                    self.logger
-                        .info(format!("removed neighbor by public key: {}", public_key))
-                }
-            }
-        }
+        self.remove_neighbor(&msg.public_key);
         ()
     }
 }
@@ -306,7 +428,8 @@ impl Neighborhood {
         {
             panic! ("An --ip setting indicates that you want to decentralize, but you also need at least one --neighbor or --bootstrap_from setting or --node_type bootstrap for that, and a --port_count greater than 0")
         }
-        let gossip_acceptor: Box<GossipAcceptor> = Box::new(GossipAcceptorReal::new());
+        let gossip_acceptor: Box<GossipAcceptor> =
+            Box::new(GossipAcceptorReal::new(cryptde, &config.admission_limits));
         let gossip_producer = Box::new(GossipProducerReal::new());
         let local_node_addr = NodeAddr::new(&config.local_ip_addr, &config.clandestine_port_list);
         let mut neighborhood_database = NeighborhoodDatabase::new(
@@ -315,6 +438,9 @@ impl Neighborhood {
             config.is_bootstrap_node,
             cryptde,
         );
+        neighborhood_database
+            .root_mut()
+            .set_exit_policy(config.exit_policy.clone());
 
         let add_node = |neighborhood_database: &mut NeighborhoodDatabase,
                         neighbor: &(Key, NodeAddr),
@@ -349,18 +475,300 @@ impl Neighborhood {
             .iter()
             .for_each(|neighbor| add_node(&mut neighborhood_database, neighbor, true));
 
+        let logger = Logger::new("Neighborhood");
+        let store_opt = match config.data_directory_opt {
+            Some(ref data_directory) if config.is_decentralized() => {
+                let mut store = NeighborhoodStore::new(
+                    data_directory,
+                    Duration::from_secs(SAVE_INTERVAL_SECS),
+                    Duration::from_secs(RECORD_EXPIRY_SECS),
+                );
+                match store.load(&mut neighborhood_database, SystemTime::now()) {
+                    Ok(count) => logger.info(format!(
+                        "Loaded {} NodeRecords from {:?}",
+                        count,
+                        store.file_path()
+                    )),
+                    Err(NeighborhoodStoreError::NotFound(_)) => (),
+                    Err(e) => {
+                        logger.warning(format!("Ignoring saved Neighborhood database: {:?}", e))
+                    }
+                }
+                Some(store)
+            }
+            _ => None,
+        };
+        neighborhood_database.root_mut().sign(cryptde);
+
         Neighborhood {
             cryptde,
             hopper: None,
             gossip_acceptor,
             gossip_producer,
+            route_selector: Box::new(RouteSelectorReal::new(StdRng::from_entropy())),
+            route_search_limits: RouteSearchLimits {
+                max_hops_per_source: config.admission_limits.max_hops_per_source,
+                ..RouteSearchLimits::new()
+            },
             neighborhood_database,
-            logger: Logger::new("Neighborhood"),
+            store_opt,
+            keepalive_interval: config.keepalive_config.interval,
+            keepalive_tracker: KeepaliveTracker::new(config.keepalive_config.max_missed),
+            forgery_counts: HashMap::new(),
+            introspection_port_opt: config.introspection_port_opt,
+            logger,
+        }
+    }
+
+    fn remove_neighbor(&mut self, public_key: &Key) {
+        match self.neighborhood_database.remove_neighbor(public_key) {
+            Err(s) => self.logger.error(s),
+            Ok(db_changed) => {
+                if db_changed {
+                    self.neighborhood_database.root_mut().increment_version();
+                    self.neighborhood_database.root_mut().sign(self.cryptde);
+                    self.gossip_to_neighbors();
+                    self.logger
+                        .info(format!("removed neighbor by public key: {}", public_key))
+                }
+            }
+        }
+    }
+
+    // Every Gossip message with forged records in it counts against its sender; after too many, the sender is dropped
+    // as a neighbor and nothing more it sends is believed.
+    fn penalize_forger(&mut self, sender_ip: IpAddr, forgeries: &[Key]) {
+        let count = {
+            let count = self.forgery_counts.entry(sender_ip).or_insert(0);
+            *count += 1;
+            *count
+        };
+        self.logger.warning(format!(
+            "Gossip from {} contained {} forged NodeRecords: {:?}",
+            sender_ip,
+            forgeries.len(),
+            forgeries
+        ));
+        if count < MAX_FORGED_GOSSIP_MESSAGES {
+            return;
+        }
+        self.logger.warning(format!(
+            "Shunning {} after {} Gossip messages with forged NodeRecords",
+            sender_ip, count
+        ));
+        let forger_key_opt = self
+            .neighborhood_database
+            .node_by_ip(&sender_ip)
+            .map(|node_record| node_record.public_key().clone());
+        if let Some(forger_key) = forger_key_opt {
+            if self.neighborhood_database.root().has_neighbor(&forger_key) {
+                self.remove_neighbor(&forger_key);
+            }
+        }
+    }
+
+    fn is_shunned(&self, sender_ip: &IpAddr) -> bool {
+        match self.forgery_counts.get(sender_ip) {
+            Some(count) => *count >= MAX_FORGED_GOSSIP_MESSAGES,
+            None => false,
+        }
+    }
+
+    fn schedule_keepalives(ctx: &mut Context<Neighborhood>, keepalive_interval: Duration) {
+        ctx.run_later(keepalive_interval, move |neighborhood, ctx| {
+            neighborhood.send_keepalives();
+            Neighborhood::schedule_keepalives(ctx, keepalive_interval);
+        });
+    }
+
+    fn send_keepalives(&mut self) {
+        let neighbors = self.neighborhood_database.root().neighbors().clone();
+        let (keepalives, dead_neighbors) =
+            self.keepalive_tracker
+                .tick(self.cryptde, &neighbors, Instant::now());
+        dead_neighbors.iter().for_each(|neighbor| {
+            self.logger.warning(format!(
+                "Neighbor {} has stopped answering keepalives",
+                neighbor
+            ));
+            self.remove_neighbor(neighbor);
+        });
+        keepalives
+            .into_iter()
+            .for_each(|(neighbor, keepalive)| self.send_keepalive(&neighbor, keepalive));
+    }
+
+    fn send_keepalive(&self, neighbor: &Key, keepalive: Keepalive) {
+        let route = self.create_single_hop_route(neighbor);
+        let package = IncipientCoresPackage::new(route, keepalive, neighbor);
+        self.hopper
+            .as_ref()
+            .expect("unbound hopper")
+            .try_send(package)
+            .expect("hopper is dead");
+    }
+
+    fn handle_keepalive(&mut self, keepalive: Keepalive, sender_ip: IpAddr) {
+        if keepalive.is_reply {
+            let known_ip_opt = self
+                .neighborhood_database
+                .node_by_key(&keepalive.sender)
+                .and_then(|node| node.node_addr_opt())
+                .map(|node_addr| node_addr.ip_addr());
+            // Keepalives go straight to neighbors, so a genuine reply comes straight back from one
+            if known_ip_opt != Some(sender_ip) {
+                self.logger.debug(format!(
+                    "Ignoring keepalive reply from {} claiming to be from {}",
+                    sender_ip, keepalive.sender
+                ));
+                return ();
+            }
+            match self
+                .keepalive_tracker
+                .reply_received(&keepalive, Instant::now())
+            {
+                Some(round_trip) => {
+                    if let Some(node_record) = self
+                        .neighborhood_database
+                        .node_by_key_mut(&keepalive.sender)
+                    {
+                        node_record.add_latency_sample(round_trip);
+                    }
+                }
+                None => self.logger.debug(format!(
+                    "Ignoring stale keepalive reply {} from {}",
+                    keepalive.serial, keepalive.sender
+                )),
+            }
+        } else if self
+            .neighborhood_database
+            .node_by_key(&keepalive.sender)
+            .is_some()
+        {
+            let reply = Keepalive {
+                sender: self.cryptde.public_key(),
+                serial: keepalive.serial,
+                is_reply: true,
+            };
+            self.send_keepalive(&keepalive.sender, reply);
+        } else {
+            self.logger.debug(format!(
+                "Ignoring keepalive from unknown Node {}",
+                keepalive.sender
+            ));
+        }
+    }
+
+    fn schedule_save(ctx: &mut Context<Neighborhood>, save_interval: Duration) {
+        ctx.run_later(save_interval, move |neighborhood, ctx| {
+            neighborhood.save_database();
+            Neighborhood::schedule_save(ctx, save_interval);
+        });
+    }
+
+    fn save_database(&self) {
+        if let Some(ref store) = self.store_opt {
+            match store.save(&self.neighborhood_database, SystemTime::now()) {
+                Ok(()) => self.logger.debug(format!(
+                    "Saved Neighborhood database to {:?}",
+                    store.file_path()
+                )),
+                Err(e) => self
+                    .logger
+                    .error(format!("Couldn't save Neighborhood database: {:?}", e)),
+            }
         }
     }
 
     fn gossip_to_neighbors(&self) {
-        self.gossip_to(self.neighborhood_database.root().neighbors());
+        self.neighborhood_database
+            .root()
+            .neighbors()
+            .iter()
+            .for_each(|neighbor| {
+                let digest = self
+                    .gossip_producer
+                    .produce_digest(&self.neighborhood_database, neighbor);
+                let digest_len = digest.entries.len();
+                let route = self.create_single_hop_route(neighbor);
+                let package = IncipientCoresPackage::new(route, digest, neighbor);
+                self.logger.info(format!(
+                    "Sending Gossip digest about {} nodes to {}",
+                    digest_len, neighbor
+                ));
+                self.hopper
+                    .as_ref()
+                    .expect("unbound hopper")
+                    .try_send(package)
+                    .expect("hopper is dead");
+            });
+    }
+
+    fn handle_gossip_digest(&mut self, digest: GossipDigest) {
+        if self
+            .neighborhood_database
+            .node_by_key(&digest.sender)
+            .is_none()
+        {
+            self.logger.debug(format!(
+                "Ignoring Gossip digest from unknown Node {}",
+                digest.sender
+            ));
+            return;
+        }
+        let request = self
+            .gossip_acceptor
+            .handle_digest(&self.neighborhood_database, &digest);
+        if request.requested_keys.is_empty() {
+            self.logger.debug(format!(
+                "Gossip digest about {} nodes from {} holds nothing new",
+                digest.entries.len(),
+                digest.sender
+            ));
+            return;
+        }
+        self.logger.info(format!(
+            "Requesting {} of {} nodes in Gossip digest from {}",
+            request.requested_keys.len(),
+            digest.entries.len(),
+            digest.sender
+        ));
+        let route = self.create_single_hop_route(&digest.sender);
+        let package = IncipientCoresPackage::new(route, request, &digest.sender);
+        self.hopper
+            .as_ref()
+            .expect("unbound hopper")
+            .try_send(package)
+            .expect("hopper is dead");
+    }
+
+    fn handle_gossip_request(&mut self, request: GossipRequest) {
+        if self
+            .neighborhood_database
+            .node_by_key(&request.sender)
+            .is_none()
+        {
+            self.logger.debug(format!(
+                "Ignoring Gossip request from unknown Node {}",
+                request.sender
+            ));
+            return;
+        }
+        let response = self
+            .gossip_producer
+            .produce_response(&self.neighborhood_database, &request);
+        self.logger.info(format!(
+            "Answering Gossip request from {} with {} nodes",
+            request.sender,
+            response.requested_records.len()
+        ));
+        let route = self.create_single_hop_route(&request.sender);
+        let package = IncipientCoresPackage::new(route, response, &request.sender);
+        self.hopper
+            .as_ref()
+            .expect("unbound hopper")
+            .try_send(package)
+            .expect("hopper is dead");
     }
 
     fn gossip_to(&self, neighbors: &Vec<Key>) {
@@ -407,166 +815,155 @@ impl Neighborhood {
         .expect("route creation error")
     }
 
-    fn zero_hop_route_response(&self) -> RouteQueryResponse {
-        let route = Route::new(
-            vec![
-                RouteSegment::new(
-                    vec![&self.cryptde.public_key(), &self.cryptde.public_key()],
-                    Component::ProxyClient,
-                ),
-                RouteSegment::new(
-                    vec![&self.cryptde.public_key(), &self.cryptde.public_key()],
-                    Component::ProxyServer,
-                ),
-            ],
-            self.cryptde,
-        )
-        .expect("Couldn't create route");
+    fn make_route_query_response(&self, segments: Vec<RouteSegment>) -> RouteQueryResponse {
+        let segment_endpoints = segments
+            .iter()
+            .map(|segment| segment.keys.last().expect("Empty segment").clone())
+            .collect();
         RouteQueryResponse {
-            route,
-            segment_endpoints: vec![self.cryptde.public_key(), self.cryptde.public_key()],
+            route: Route::new(segments, self.cryptde).expect("Bad route"),
+            segment_endpoints,
+        }
+    }
+
+    // A dry run previews the routes that would be chosen without counting their relays against later routes
+    fn make_route_segments(
+        &mut self,
+        msg: &RouteQueryMessage,
+        dry_run: bool,
+    ) -> Option<Vec<RouteSegment>> {
+        if msg.route_policy.is_zero_hop() {
+            Some(self.zero_hop_route_segments())
+        } else {
+            match msg.route_type {
+                RouteType::OneWay => self.make_one_way_route_segments(msg, dry_run),
+                RouteType::RoundTrip => self.make_round_trip_route_segments(msg, dry_run),
+            }
         }
     }
 
-    fn make_one_way_route(&self, msg: RouteQueryMessage) -> Option<RouteQueryResponse> {
-        match self.make_route_segment(
+    fn zero_hop_route_segments(&self) -> Vec<RouteSegment> {
+        vec![
+            RouteSegment::new(
+                vec![&self.cryptde.public_key(), &self.cryptde.public_key()],
+                Component::ProxyClient,
+            ),
+            RouteSegment::new(
+                vec![&self.cryptde.public_key(), &self.cryptde.public_key()],
+                Component::ProxyServer,
+            ),
+        ]
+    }
+
+    fn make_one_way_route_segments(
+        &mut self,
+        msg: &RouteQueryMessage,
+        dry_run: bool,
+    ) -> Option<Vec<RouteSegment>> {
+        self.make_route_segment(
             &self.cryptde.public_key(),
             msg.target_key_opt.as_ref(),
             msg.target_type,
-            msg.minimum_hop_count,
+            msg.route_policy.over,
             msg.target_component,
-        ) {
-            Some(segment) => {
-                let segment_endpoint = segment.keys.last().expect("empty segment").clone();
-                Some(RouteQueryResponse {
-                    route: Route::new(vec![segment], self.cryptde).expect("bad route"),
-                    segment_endpoints: vec![segment_endpoint],
-                })
-            }
-            None => None,
-        }
+            &msg.constraint,
+            dry_run,
+        )
+        .map(|segment| vec![segment])
     }
 
-    fn make_round_trip_route(&self, msg: RouteQueryMessage) -> Option<RouteQueryResponse> {
+    fn make_round_trip_route_segments(
+        &mut self,
+        msg: &RouteQueryMessage,
+        dry_run: bool,
+    ) -> Option<Vec<RouteSegment>> {
         let local_target_type = if self.neighborhood_database.root().is_bootstrap_node() {
             TargetType::Bootstrap
         } else {
             TargetType::Standard
         };
-        let mut segment_endpoints: Vec<Key> = vec![];
+        // The exit Node's policy matters only on the way over; the way back ends here
+        let back_constraint = RouteConstraint {
+            exit_destination_opt: None,
+            ..msg.constraint.clone()
+        };
         if let Some(over) = self.make_route_segment(
             &self.cryptde.public_key(),
             msg.target_key_opt.as_ref(),
             msg.target_type,
-            msg.minimum_hop_count,
+            msg.route_policy.over,
             msg.target_component,
+            &msg.constraint,
+            dry_run,
         ) {
-            segment_endpoints.push(over.keys.last().expect("empty segment").clone());
             self.logger.debug(format!("Route over: {:?}", over));
             if let Some(back) = self.make_route_segment(
                 over.keys.last().expect("Empty segment"),
                 Some(&self.cryptde.public_key()),
                 local_target_type,
-                msg.minimum_hop_count,
+                msg.route_policy.back,
                 msg.return_component_opt.expect("No return component"),
+                &back_constraint,
+                dry_run,
             ) {
-                segment_endpoints.push(back.keys.last().expect("empty segment").clone());
                 self.logger.debug(format!("Route back: {:?}", back));
-                return Some(RouteQueryResponse {
-                    route: Route::new(vec![over, back], self.cryptde).expect("Bad route"),
-                    segment_endpoints,
-                });
+                return Some(vec![over, back]);
             }
         }
         None
     }
 
     fn make_route_segment(
-        &self,
+        &mut self,
         origin: &Key,
         target: Option<&Key>,
         target_type: TargetType,
-        minimum_hop_count: usize,
+        hop_range: HopRange,
         target_component: Component,
+        constraint: &RouteConstraint,
+        dry_run: bool,
     ) -> Option<RouteSegment> {
-        let mut node_seqs =
-            self.complete_routes(vec![origin], target, target_type, minimum_hop_count);
+        let node_seqs = self.complete_routes(origin, target, target_type, hop_range, constraint);
         if node_seqs.is_empty() {
             return None;
         }
-        let chosen_node_seq = node_seqs.remove(0);
-        Some(RouteSegment::new(chosen_node_seq, target_component))
-    }
-
-    fn route_length_qualifies(&self, hops_remaining: usize) -> bool {
-        hops_remaining == 0
-    }
-
-    fn last_key_qualifies(
-        &self,
-        last_node_ref: &NodeRecord,
-        target_key_ref_opt: Option<&Key>,
-    ) -> bool {
-        match target_key_ref_opt {
-            Some(target_key_ref) => last_node_ref.public_key() == target_key_ref,
-            None => true,
-        }
-    }
-
-    fn last_type_qualifies(&self, last_node_ref: &NodeRecord, target_type: TargetType) -> bool {
-        (target_type == TargetType::Bootstrap) == last_node_ref.is_bootstrap_node()
+        let chosen_index = if dry_run {
+            self.route_selector
+                .preview(&self.neighborhood_database, &node_seqs)
+        } else {
+            self.route_selector
+                .select(&self.neighborhood_database, &node_seqs)
+        };
+        Some(RouteSegment::new(
+            node_seqs[chosen_index].iter().collect(),
+            target_component,
+        ))
     }
 
-    // Main recursive routing engine. Supply origin key as single-element vector in prefix,
-    // target key, if any, in target, and minimum hop count in hops_remaining. Return value is
-    // a list of all the node sequences that will either go from the origin to the target in
-    // hops_remaining or more hops with no cycles, or from the origin hops_remaining hops out into
-    // the Substratum Network. No round trips; if you want a round trip, call this method twice.
-    // If the return value is empty, no qualifying route was found.
-    fn complete_routes<'a>(
-        &'a self,
-        prefix: Vec<&'a Key>,
-        target: Option<&'a Key>,
+    // Returns routes from origin whose hop counts hop_range allows that end at target (if any) on a Node of
+    // type target_type, subject to constraint. The search is bounded by route_search_limits, so in a large
+    // Neighborhood this is a sample of the qualifying routes rather than all of them.
+    fn complete_routes(
+        &mut self,
+        origin: &Key,
+        target: Option<&Key>,
         target_type: TargetType,
-        hops_remaining: usize,
-    ) -> Vec<Vec<&'a Key>> {
-        let last_node_ref = self
-            .neighborhood_database
-            .node_by_key(prefix.last().expect("Empty prefix"))
-            .expect("Node magically disappeared");
-        // Check to see if we're done. If we are, all three of these qualifications will pass.
-        if self.route_length_qualifies(hops_remaining)
-            && self.last_key_qualifies(last_node_ref, target)
-            && self.last_type_qualifies(last_node_ref, target_type)
-        {
-            vec![prefix]
-        }
-        // If we're not done, then last_node is for routing, and bootstrap Nodes don't route.
-        else if last_node_ref.is_bootstrap_node() {
-            vec![]
-        }
-        // Go through all the neighbors and compute shorter routes through all the ones we're not already using.
-        else {
-            last_node_ref
-                .neighbors()
-                .iter()
-                .filter(|neighbor_key_ref_ref| !prefix.contains(neighbor_key_ref_ref))
-                .flat_map(|neighbor_key_ref_ref| {
-                    let mut new_prefix = prefix.clone();
-                    new_prefix.push(neighbor_key_ref_ref);
-                    self.complete_routes(
-                        new_prefix.clone(),
-                        target,
-                        target_type,
-                        if hops_remaining == 0 {
-                            0
-                        } else {
-                            hops_remaining - 1
-                        },
-                    )
-                })
-                .collect()
-        }
+        hop_range: HopRange,
+        constraint: &RouteConstraint,
+    ) -> Vec<Vec<Key>> {
+        route_search::complete_routes(
+            &self.neighborhood_database,
+            &mut *self.route_selector,
+            origin,
+            target,
+            target_type,
+            hop_range,
+            constraint,
+            &self.route_search_limits,
+        )
+        .into_iter()
+        .map(|node_seq| node_seq.into_iter().cloned().collect())
+        .collect()
     }
 }
 
@@ -578,17 +975,34 @@ mod tests {
     use actix::Recipient;
     use actix::System;
     use gossip::GossipBuilder;
+    use gossip::GossipDigestEntry;
     use gossip::GossipNodeRecord;
+    use neighborhood_database::NodeRecordInner;
+    use neighborhood_database::NodeSignatures;
+    use neighborhood_store::NEIGHBORHOOD_STORE_FILENAME;
     use neighborhood_test_utils::make_node_record;
+    use neighborhood_test_utils::vec_to_set;
+    use rand::SeedableRng;
     use serde_cbor;
+    use serde_json;
+    use std::collections::HashSet;
+    use std::fs;
     use std::net::IpAddr;
+    use std::path::PathBuf;
     use std::str::FromStr;
+    use std::sync::mpsc;
     use std::thread;
     use sub_lib::cryptde::PlainData;
     use sub_lib::cryptde_null::CryptDENull;
+    use sub_lib::cryptde_real::CryptDEReal;
     use sub_lib::dispatcher::Endpoint;
     use sub_lib::hopper::ExpiredCoresPackage;
     use sub_lib::neighborhood::sentinel_ip_addr;
+    use sub_lib::neighborhood::AdmissionLimits;
+    use sub_lib::neighborhood::ExitPolicy;
+    use sub_lib::neighborhood::KeepaliveConfig;
+    use sub_lib::neighborhood::RoutePolicy;
+    use sub_lib::neighborhood::DEFAULT_KEEPALIVE_INTERVAL_SECS;
     use sub_lib::stream_handler_pool::TransmitDataMsg;
     use test_utils::logging::init_test_logging;
     use test_utils::logging::TestLogHandler;
@@ -598,6 +1012,7 @@ mod tests {
     use test_utils::recorder::Recording;
     use test_utils::test_utils::assert_contains;
     use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
     use test_utils::test_utils::make_meaningless_route;
     use tokio::prelude::Future;
 
@@ -620,6 +1035,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -643,6 +1063,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -662,6 +1087,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![1234],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -681,6 +1111,11 @@ mod tests {
                 is_bootstrap_node: true,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -704,6 +1139,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("2.3.4.5").unwrap(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -727,6 +1167,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("2.3.4.5").unwrap(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -747,6 +1192,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("2.3.4.5").unwrap(),
                 clandestine_port_list: vec![2345],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
     }
@@ -764,6 +1214,11 @@ mod tests {
                 is_bootstrap_node: true,
                 local_ip_addr: this_node_addr.ip_addr(),
                 clandestine_port_list: this_node_addr.ports().clone(),
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
 
@@ -775,6 +1230,196 @@ mod tests {
         assert_eq!(root_node_record_ref.neighbors().len(), 0);
     }
 
+    #[test]
+    fn neighborhood_puts_configured_exit_policy_into_its_own_signed_node_record() {
+        let cryptde = cryptde();
+        let exit_policy = ExitPolicy {
+            allowed_ports: vec![80, 443],
+            allowed_hostnames: vec![String::from("*.booga.com")],
+        };
+        let subject = Neighborhood::new(
+            cryptde,
+            NeighborhoodConfig {
+                neighbor_configs: vec![],
+                bootstrap_configs: vec![],
+                is_bootstrap_node: true,
+                local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                clandestine_port_list: vec![5678],
+                exit_policy: exit_policy.clone(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
+            },
+        );
+
+        let root_node_record_ref = subject.neighborhood_database.root();
+
+        assert_eq!(root_node_record_ref.exit_policy(), &exit_policy);
+        let mut expected_record = root_node_record_ref.clone();
+        expected_record.sign(cryptde);
+        assert_eq!(
+            root_node_record_ref.signatures(),
+            expected_record.signatures()
+        );
+        let gossip = GossipNodeRecord::from(root_node_record_ref, true);
+        assert_eq!(gossip.inner.exit_policy, exit_policy);
+    }
+
+    fn make_store_config(neighbor: &NodeRecord, data_directory: &PathBuf) -> NeighborhoodConfig {
+        NeighborhoodConfig {
+            neighbor_configs: vec![node_record_to_pair(neighbor)],
+            bootstrap_configs: vec![],
+            is_bootstrap_node: false,
+            local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+            clandestine_port_list: vec![5678],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: Some(data_directory.clone()),
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
+        }
+    }
+
+    #[test]
+    fn neighborhood_loads_saved_database_from_data_directory() {
+        let cryptde = cryptde();
+        let home_dir = ensure_node_home_directory_exists("neighborhood", "loads_saved_database");
+        let neighbor = make_node_record(1234, true, false);
+        let distant_node = make_node_record(2345, false, false);
+        let mut database = NeighborhoodDatabase::new(
+            &cryptde.public_key(),
+            &NodeAddr::new(&IpAddr::from_str("5.4.3.2").unwrap(), &vec![5678]),
+            false,
+            cryptde,
+        );
+        database.add_node(&neighbor).unwrap();
+        database.add_node(&distant_node).unwrap();
+        database
+            .add_neighbor(&cryptde.public_key(), neighbor.public_key())
+            .unwrap();
+        database
+            .add_neighbor(neighbor.public_key(), distant_node.public_key())
+            .unwrap();
+        NeighborhoodStore::new(
+            &home_dir,
+            Duration::from_secs(SAVE_INTERVAL_SECS),
+            Duration::from_secs(RECORD_EXPIRY_SECS),
+        )
+        .save(&database, SystemTime::now())
+        .unwrap();
+
+        let subject = Neighborhood::new(cryptde, make_store_config(&neighbor, &home_dir));
+
+        let database = &subject.neighborhood_database;
+        assert_eq!(database.keys().len(), 3);
+        assert_eq!(
+            database.has_neighbor(neighbor.public_key(), distant_node.public_key()),
+            true
+        );
+        assert_eq!(
+            database
+                .node_by_key(neighbor.public_key())
+                .unwrap()
+                .signatures(),
+            neighbor.signatures()
+        );
+        assert_eq!(
+            database.root().neighbors(),
+            &vec![neighbor.public_key().clone()]
+        );
+        assert_eq!(database.root().version(), 1);
+        let mut expected_root = database.root().clone();
+        expected_root.sign(cryptde);
+        assert_eq!(database.root().signatures(), expected_root.signatures());
+    }
+
+    #[test]
+    fn neighborhood_ignores_corrupt_saved_database() {
+        init_test_logging();
+        let cryptde = cryptde();
+        let home_dir =
+            ensure_node_home_directory_exists("neighborhood", "ignores_corrupt_database");
+        let neighbor = make_node_record(1234, true, false);
+        fs::write(home_dir.join(NEIGHBORHOOD_STORE_FILENAME), b"booga").unwrap();
+
+        let subject = Neighborhood::new(cryptde, make_store_config(&neighbor, &home_dir));
+
+        assert_eq!(subject.neighborhood_database.keys().len(), 2);
+        TestLogHandler::new().exists_log_containing(
+            "WARN: Neighborhood: Ignoring saved Neighborhood database: Corrupt(",
+        );
+    }
+
+    #[test]
+    fn neighborhood_saves_database_periodically_once_bound() {
+        let cryptde = cryptde();
+        let home_dir = ensure_node_home_directory_exists("neighborhood", "saves_periodically");
+        let neighbor = make_node_record(1234, true, false);
+        let config = make_store_config(&neighbor, &home_dir);
+        let home_dir_inside = home_dir.clone();
+
+        thread::spawn(move || {
+            let system = System::new("neighborhood_saves_database_periodically_once_bound");
+            let mut subject = Neighborhood::new(cryptde, config);
+            subject.store_opt = Some(NeighborhoodStore::new(
+                &home_dir_inside,
+                Duration::from_millis(10),
+                Duration::from_secs(RECORD_EXPIRY_SECS),
+            ));
+            let addr: Addr<Syn, Neighborhood> = subject.start();
+            let peer_actors = make_peer_actors_from(None, None, None, None, None);
+            addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            system.run();
+        });
+
+        let file_path = home_dir.join(NEIGHBORHOOD_STORE_FILENAME);
+        let mut tries_left = 500;
+        while !file_path.exists() && tries_left > 0 {
+            thread::sleep(Duration::from_millis(10));
+            tries_left -= 1;
+        }
+        let mut database = NeighborhoodDatabase::new(
+            &cryptde.public_key(),
+            &NodeAddr::new(&IpAddr::from_str("5.4.3.2").unwrap(), &vec![5678]),
+            false,
+            cryptde,
+        );
+        let result = NeighborhoodStore::new(
+            &home_dir,
+            Duration::from_secs(SAVE_INTERVAL_SECS),
+            Duration::from_secs(RECORD_EXPIRY_SECS),
+        )
+        .load(&mut database, SystemTime::now());
+        assert_eq!(result, Ok(0));
+        assert_eq!(database.root().version(), 1);
+    }
+
+    #[test]
+    fn neighborhood_saves_database_and_stops_actor_system_when_told_to_terminate() {
+        let cryptde = cryptde();
+        let home_dir = ensure_node_home_directory_exists("neighborhood", "saves_on_termination");
+        let neighbor = make_node_record(1234, true, false);
+        let config = make_store_config(&neighbor, &home_dir);
+        let system = System::new(
+            "neighborhood_saves_database_and_stops_actor_system_when_told_to_terminate",
+        );
+        let mut subject = Neighborhood::new(cryptde, config);
+        subject.store_opt = Some(NeighborhoodStore::new(
+            &home_dir,
+            Duration::from_secs(SAVE_INTERVAL_SECS),
+            Duration::from_secs(RECORD_EXPIRY_SECS),
+        ));
+        let addr: Addr<Syn, Neighborhood> = subject.start();
+
+        addr.try_send(Signal(SignalType::Term)).unwrap();
+
+        let exit_code = system.run();
+        assert_eq!(exit_code, 0);
+        assert_eq!(home_dir.join(NEIGHBORHOOD_STORE_FILENAME).exists(), true);
+    }
+
     #[test]
     fn bootstrap_node_with_no_bootstrap_nodes_ignores_bootstrap_neighborhood_now_message() {
         init_test_logging();
@@ -788,6 +1433,11 @@ mod tests {
                 is_bootstrap_node: true,
                 local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                 clandestine_port_list: vec![5678],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -832,6 +1482,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: this_node_addr.ip_addr(),
                 clandestine_port_list: this_node_addr.ports().clone(),
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
 
@@ -894,6 +1549,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: this_node_addr.ip_addr(),
                 clandestine_port_list: this_node_addr.ports().clone(),
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
 
@@ -944,6 +1604,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -972,6 +1637,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                 clandestine_port_list: vec![5678],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1002,6 +1672,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                 clandestine_port_list: vec![5678],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1023,6 +1698,105 @@ mod tests {
         );
     }
 
+    #[test]
+    fn introspection_reports_root_record_and_neighbor_status() {
+        let cryptde = cryptde();
+        let system = System::new("introspection_reports_root_record_and_neighbor_status");
+        let neighbor = make_node_record(2345, true, false);
+        let subject = Neighborhood::new(
+            cryptde,
+            NeighborhoodConfig {
+                neighbor_configs: vec![node_record_to_pair(&neighbor)],
+                bootstrap_configs: vec![],
+                is_bootstrap_node: false,
+                local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                clandestine_port_list: vec![5678],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
+            },
+        );
+        let addr: Addr<Syn, Neighborhood> = subject.start();
+        let sub: Recipient<Syn, IntrospectionMessage> = addr.recipient::<IntrospectionMessage>();
+
+        let root_future = sub.send(IntrospectionMessage {
+            request: IntrospectionRequest::Root,
+        });
+        let neighbors_future = sub.send(IntrospectionMessage {
+            request: IntrospectionRequest::Neighbors,
+        });
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+        let root: serde_json::Value = serde_json::from_str(&root_future.wait().unwrap()).unwrap();
+        let neighbors: serde_json::Value =
+            serde_json::from_str(&neighbors_future.wait().unwrap()).unwrap();
+        assert_eq!(root["public_key"], format!("{}", cryptde.public_key()));
+        assert_eq!(root["node_addr"], "5.4.3.2:5678");
+        assert_eq!(root["version"], 0);
+        assert_eq!(root["neighbors"][0], format!("{}", neighbor.public_key()));
+        assert_eq!(
+            neighbors[0]["public_key"],
+            format!("{}", neighbor.public_key())
+        );
+        assert_eq!(neighbors[0]["node_addr"], "2.3.4.5:2345");
+        assert_eq!(neighbors[0]["missed_keepalives"], 0);
+        assert_eq!(neighbors[0]["awaiting_keepalive_reply"], false);
+    }
+
+    #[test]
+    fn introspection_dry_run_shows_the_route_that_would_be_chosen() {
+        let cryptde = cryptde();
+        let system = System::new("introspection_dry_run_shows_the_route_that_would_be_chosen");
+        let subject = Neighborhood::new(
+            cryptde,
+            NeighborhoodConfig {
+                neighbor_configs: vec![],
+                bootstrap_configs: vec![],
+                is_bootstrap_node: false,
+                local_ip_addr: sentinel_ip_addr(),
+                clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
+            },
+        );
+        let addr: Addr<Syn, Neighborhood> = subject.start();
+        let sub: Recipient<Syn, IntrospectionMessage> = addr.recipient::<IntrospectionMessage>();
+
+        let zero_hop_future = sub.send(IntrospectionMessage {
+            request: IntrospectionRequest::Route(RouteQueryMessage::data_indefinite_route_request(
+                RoutePolicy::zero_hop(),
+                RouteConstraint::new(),
+            )),
+        });
+        let two_hop_future = sub.send(IntrospectionMessage {
+            request: IntrospectionRequest::Route(RouteQueryMessage::data_indefinite_route_request(
+                RoutePolicy::at_least(2),
+                RouteConstraint::new(),
+            )),
+        });
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+        let me = format!("{}", cryptde.public_key());
+        assert_eq!(
+            zero_hop_future.wait().unwrap(),
+            format!(
+                "{{\"found\":true,\"segments\":[{{\"keys\":[\"{}\",\"{}\"],\"recipient\":\"ProxyClient\"}},{{\"keys\":[\"{}\",\"{}\"],\"recipient\":\"ProxyServer\"}}]}}",
+                me, me, me, me
+            )
+        );
+        assert_eq!(
+            two_hop_future.wait().unwrap(),
+            "{\"found\":false,\"segments\":[]}"
+        );
+    }
+
     #[test]
     fn node_query_responds_with_none_when_ip_address_query_matches_no_configured_data() {
         let cryptde = cryptde();
@@ -1038,6 +1812,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                 clandestine_port_list: vec![5678],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1081,6 +1860,11 @@ mod tests {
                     .unwrap()
                     .ports()
                     .clone(),
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1114,12 +1898,20 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
         let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage>();
 
-        let future = sub.send(RouteQueryMessage::data_indefinite_route_request(5));
+        let future = sub.send(RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy::at_least(5),
+            RouteConstraint::new(),
+        ));
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
         system.run();
@@ -1139,12 +1931,20 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
         let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage>();
 
-        let future = sub.send(RouteQueryMessage::data_indefinite_route_request(0));
+        let future = sub.send(RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy::zero_hop(),
+            RouteConstraint::new(),
+        ));
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
         system.run();
@@ -1191,6 +1991,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let b = &make_node_record(1234, true, true);
@@ -1218,7 +2023,28 @@ mod tests {
         let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage>();
 
         let gossip_route = sub.send(RouteQueryMessage::gossip_route_request(b.public_key(), 4));
-        let data_route = sub.send(RouteQueryMessage::data_indefinite_route_request(2));
+        let data_route = sub.send(RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy::at_least(2),
+            RouteConstraint::new(),
+        ));
+        let one_hop = HopRange {
+            minimum: 1,
+            maximum_opt: Some(1),
+        };
+        let one_hop_data_route = sub.send(RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy {
+                over: one_hop,
+                back: one_hop,
+            },
+            RouteConstraint::new(),
+        ));
+        let impossible_data_route = sub.send(RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy {
+                over: one_hop,
+                back: HopRange::at_least(3),
+            },
+            RouteConstraint::new(),
+        ));
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
         system.run();
@@ -1253,6 +2079,23 @@ mod tests {
             segment_endpoints: vec![r.public_key().clone(), p.public_key().clone()],
         };
         assert_eq!(result, expected_response);
+
+        let result = one_hop_data_route.wait().unwrap().unwrap();
+        let expected_response = RouteQueryResponse {
+            route: Route::new(
+                vec![
+                    segment(vec![p, q], Component::ProxyClient),
+                    segment(vec![q, p], Component::ProxyServer),
+                ],
+                cryptde,
+            )
+            .unwrap(),
+            segment_endpoints: vec![q.public_key().clone(), p.public_key().clone()],
+        };
+        assert_eq!(result, expected_response);
+
+        let result = impossible_data_route.wait().unwrap();
+        assert_eq!(result, None);
     }
 
     /*
@@ -1277,6 +2120,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let b = &make_node_record(1234, true, true);
@@ -1302,14 +2150,22 @@ mod tests {
             dual_edge(s, r);
         }
 
-        let contains = |routes: &Vec<Vec<&Key>>, expected_nodes: Vec<&NodeRecord>| {
-            let expected_keys: Vec<&Key> =
-                expected_nodes.into_iter().map(|n| n.public_key()).collect();
+        let contains = |routes: &Vec<Vec<Key>>, expected_nodes: Vec<&NodeRecord>| {
+            let expected_keys: Vec<Key> = expected_nodes
+                .into_iter()
+                .map(|n| n.public_key().clone())
+                .collect();
             assert_contains(&routes, &expected_keys);
         };
 
         // At least two hops from P to anywhere standard
-        let routes = subject.complete_routes(vec![p.public_key()], None, TargetType::Standard, 2);
+        let routes = subject.complete_routes(
+            p.public_key(),
+            None,
+            TargetType::Standard,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
+        );
 
         contains(&routes, vec![p, s, t]);
         contains(&routes, vec![p, r, s]);
@@ -1318,10 +2174,11 @@ mod tests {
 
         // At least two hops from P to T
         let routes = subject.complete_routes(
-            vec![p.public_key()],
+            p.public_key(),
             Some(t.public_key()),
             TargetType::Standard,
-            2,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
         );
 
         contains(&routes, vec![p, s, t]);
@@ -1330,10 +2187,11 @@ mod tests {
 
         // At least two hops from P to B (bootstrap)
         let routes = subject.complete_routes(
-            vec![p.public_key()],
+            p.public_key(),
             Some(b.public_key()),
             TargetType::Bootstrap,
-            2,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
         );
 
         contains(&routes, vec![p, r, b]);
@@ -1341,7 +2199,13 @@ mod tests {
         assert_eq!(routes.len(), 2);
 
         // At least two hops from P to anywhere bootstrap
-        let routes = subject.complete_routes(vec![p.public_key()], None, TargetType::Bootstrap, 2);
+        let routes = subject.complete_routes(
+            p.public_key(),
+            None,
+            TargetType::Bootstrap,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
+        );
 
         contains(&routes, vec![p, r, b]);
         contains(&routes, vec![p, s, r, b]);
@@ -1349,10 +2213,11 @@ mod tests {
 
         // At least two hops from P to S - one choice
         let routes = subject.complete_routes(
-            vec![p.public_key()],
+            p.public_key(),
             Some(s.public_key()),
             TargetType::Standard,
-            2,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
         );
 
         contains(&routes, vec![p, r, s]);
@@ -1360,23 +2225,102 @@ mod tests {
 
         // At least two hops from P to Q - impossible
         let routes = subject.complete_routes(
-            vec![p.public_key()],
+            p.public_key(),
             Some(q.public_key()),
             TargetType::Standard,
-            2,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
+        );
+
+        assert_eq!(routes.len(), 0);
+
+        // At least two hops from P to R (bootstrap) - impossible
+        let routes = subject.complete_routes(
+            p.public_key(),
+            Some(r.public_key()),
+            TargetType::Bootstrap,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
+        );
+
+        assert_eq!(routes.len(), 0);
+    }
+
+    /*
+            Database, where all Nodes are standard:
+
+            Q---P---R
+                |   |
+            T---S---+
+
+            Test is written from the standpoint of P
+    */
+
+    #[test]
+    fn route_segments_are_chosen_among_all_qualifying_routes() {
+        let mut subject = Neighborhood::new(
+            cryptde(),
+            NeighborhoodConfig {
+                neighbor_configs: vec![],
+                bootstrap_configs: vec![],
+                is_bootstrap_node: false,
+                local_ip_addr: sentinel_ip_addr(),
+                clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
+            },
         );
+        subject.route_selector = Box::new(RouteSelectorReal::new(StdRng::from_seed([0; 32])));
+        let p = &subject.neighborhood_database.root().clone();
+        let q = &make_node_record(3456, true, false);
+        let r = &make_node_record(4567, false, false);
+        let s = &make_node_record(5678, false, false);
+        let t = &make_node_record(6789, false, false);
+        {
+            let db = &mut subject.neighborhood_database;
+            db.add_node(q).unwrap();
+            db.add_node(r).unwrap();
+            db.add_node(s).unwrap();
+            db.add_node(t).unwrap();
+            let mut dual_edge = |a: &NodeRecord, b: &NodeRecord| dual_edge_func(db, a, b);
+            dual_edge(q, p);
+            dual_edge(p, r);
+            dual_edge(p, s);
+            dual_edge(t, s);
+            dual_edge(s, r);
+        }
+        let keys = |nodes: Vec<&NodeRecord>| -> Vec<Key> {
+            nodes.into_iter().map(|n| n.public_key().clone()).collect()
+        };
 
-        assert_eq!(routes.len(), 0);
+        let chosen_routes: HashSet<Vec<Key>> = (0..30)
+            .map(|_| {
+                subject
+                    .make_route_segment(
+                        &p.public_key().clone(),
+                        None,
+                        TargetType::Standard,
+                        HopRange::at_least(2),
+                        Component::ProxyClient,
+                        &RouteConstraint::new(),
+                        false,
+                    )
+                    .unwrap()
+                    .keys
+            })
+            .collect();
 
-        // At least two hops from P to R (bootstrap) - impossible
-        let routes = subject.complete_routes(
-            vec![p.public_key()],
-            Some(r.public_key()),
-            TargetType::Bootstrap,
-            2,
+        assert_eq!(
+            chosen_routes,
+            vec_to_set(vec![
+                keys(vec![p, s, t]),
+                keys(vec![p, r, s]),
+                keys(vec![p, s, r]),
+            ])
         );
-
-        assert_eq!(routes.len(), 0);
     }
 
     #[test]
@@ -1399,6 +2343,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1442,6 +2391,11 @@ mod tests {
                     is_bootstrap_node: this_node_inside.is_bootstrap_node(),
                     local_ip_addr: this_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
 
@@ -1480,16 +2434,236 @@ mod tests {
         hopper_awaiter.await_message_count(1);
         let locked_recording = hopper_recording.lock().unwrap();
         let package: &IncipientCoresPackage = locked_recording.get_record(0);
-        let gossip: Gossip = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
-        let the_node_record = gossip
-            .node_records
+        assert_eq!(locked_recording.len(), 1);
+        assert_eq!(
+            find_package_target(package),
+            make_node_record(3456, true, false).public_key().clone()
+        );
+        let digest: GossipDigest = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+        assert_eq!(digest.sender, cryptde.public_key());
+        assert!(digest.entries.iter().any(|entry| entry.public_key
+            == removed_neighbor.public_key().clone()
+            && !entry.reveals_node_addr));
+    }
+
+    #[test]
+    fn neighborhood_answers_keepalive_from_known_node() {
+        let cryptde = cryptde();
+        let neighbor = make_node_record(4567, true, false);
+        let keepalive = Keepalive {
+            sender: neighbor.public_key().clone(),
+            serial: 7,
+            is_reply: false,
+        };
+        let cores_package = ExpiredCoresPackagePackage {
+            expired_cores_package: ExpiredCoresPackage::new(
+                make_meaningless_route(),
+                PlainData::new(&serde_cbor::ser::to_vec(&keepalive).unwrap()[..]),
+            ),
+            sender_ip: IpAddr::from_str("4.5.6.7").unwrap(),
+        };
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+        let neighbor_inside = neighbor.clone();
+        thread::spawn(move || {
+            let system = System::new("neighborhood_answers_keepalive_from_known_node");
+            let mut subject = Neighborhood::new(
+                cryptde,
+                NeighborhoodConfig {
+                    neighbor_configs: vec![],
+                    bootstrap_configs: vec![],
+                    is_bootstrap_node: true,
+                    local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                    clandestine_port_list: vec![1234],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
+                },
+            );
+            subject
+                .neighborhood_database
+                .add_node(&neighbor_inside)
+                .unwrap();
+            let addr: Addr<Syn, Neighborhood> = subject.start();
+            let peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
+            addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            addr.try_send(cores_package).unwrap();
+
+            system.run();
+        });
+
+        hopper_awaiter.await_message_count(1);
+        let locked_recording = hopper_recording.lock().unwrap();
+        let package: &IncipientCoresPackage = locked_recording.get_record(0);
+        check_direct_route_to(&package.route, neighbor.public_key());
+        let reply: Keepalive = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+        assert_eq!(
+            reply,
+            Keepalive {
+                sender: cryptde.public_key(),
+                serial: 7,
+                is_reply: true,
+            }
+        );
+    }
+
+    #[test]
+    fn neighborhood_measures_neighbors_with_keepalives_and_removes_silent_ones() {
+        let cryptde = cryptde();
+        let silent_neighbor = make_node_record(2345, true, false);
+        let live_neighbor = make_node_record(3456, true, false);
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+        let (tx, rx) = mpsc::channel();
+        let silent_neighbor_inside = silent_neighbor.clone();
+        let live_neighbor_inside = live_neighbor.clone();
+        thread::spawn(move || {
+            let system = System::new("neighborhood_measures_neighbors_with_keepalives");
+            let mut subject = Neighborhood::new(
+                cryptde,
+                NeighborhoodConfig {
+                    neighbor_configs: vec![],
+                    bootstrap_configs: vec![],
+                    is_bootstrap_node: true,
+                    local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                    clandestine_port_list: vec![1234],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig {
+                        interval: Duration::from_secs(DEFAULT_KEEPALIVE_INTERVAL_SECS),
+                        max_missed: 2,
+                    },
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
+                },
+            );
+            vec![&silent_neighbor_inside, &live_neighbor_inside]
+                .into_iter()
+                .for_each(|neighbor| {
+                    subject.neighborhood_database.add_node(neighbor).unwrap();
+                    subject
+                        .neighborhood_database
+                        .add_neighbor(&cryptde.public_key(), neighbor.public_key())
+                        .unwrap();
+                });
+            let peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
+            subject.hopper = Some(peer_actors.hopper.from_hopper_client);
+            // CryptDENull's random bytes are all '4', so every keepalive it sends has the same serial
+            let reply = Keepalive {
+                sender: live_neighbor_inside.public_key().clone(),
+                serial: 0x34343434,
+                is_reply: true,
+            };
+            let live_neighbor_ip = live_neighbor_inside.node_addr_opt().unwrap().ip_addr();
+
+            subject.send_keepalives();
+            subject.handle_keepalive(reply.clone(), live_neighbor_ip);
+            subject.send_keepalives();
+            subject.handle_keepalive(reply, live_neighbor_ip);
+            subject.send_keepalives();
+
+            tx.send((
+                subject.neighborhood_database.root().neighbors().clone(),
+                subject
+                    .neighborhood_database
+                    .node_by_key(live_neighbor_inside.public_key())
+                    .unwrap()
+                    .latency_opt()
+                    .is_some(),
+            ))
+            .unwrap();
+            system.run();
+        });
+
+        let (neighbors, latency_measured) = rx.recv().unwrap();
+        assert_eq!(neighbors, vec![live_neighbor.public_key().clone()]);
+        assert_eq!(latency_measured, true);
+        hopper_awaiter.await_message_count(6);
+        let locked_recording = hopper_recording.lock().unwrap();
+        let gossip_package: &IncipientCoresPackage = locked_recording.get_record(4);
+        assert_eq!(
+            &find_package_target(gossip_package),
+            live_neighbor.public_key()
+        );
+        let digest: GossipDigest =
+            serde_cbor::de::from_slice(&gossip_package.payload.data[..]).unwrap();
+        let silent_neighbor_entry = digest
+            .entries
             .iter()
-            .find(|&x| x.inner.public_key == cryptde.public_key())
-            .expect("should have the node record");
-        assert!(!the_node_record
-            .inner
-            .neighbors
-            .contains(&removed_neighbor.public_key()));
+            .find(|entry| &entry.public_key == silent_neighbor.public_key())
+            .unwrap();
+        assert_eq!(silent_neighbor_entry.reveals_node_addr, false);
+        let keepalive_package: &IncipientCoresPackage = locked_recording.get_record(5);
+        assert_eq!(
+            &find_package_target(keepalive_package),
+            live_neighbor.public_key()
+        );
+        let keepalive: Keepalive =
+            serde_cbor::de::from_slice(&keepalive_package.payload.data[..]).unwrap();
+        assert_eq!(keepalive.serial, 0x34343434);
+    }
+
+    #[test]
+    fn neighborhood_ignores_keepalive_replies_that_come_from_somewhere_other_than_the_neighbor() {
+        init_test_logging();
+        let cryptde = cryptde();
+        let neighbor = make_node_record(2345, true, false);
+        let _system = System::new("neighborhood_ignores_keepalive_replies_from_elsewhere");
+        let mut subject = Neighborhood::new(
+            cryptde,
+            NeighborhoodConfig {
+                neighbor_configs: vec![],
+                bootstrap_configs: vec![],
+                is_bootstrap_node: true,
+                local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                clandestine_port_list: vec![1234],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig {
+                    interval: Duration::from_secs(DEFAULT_KEEPALIVE_INTERVAL_SECS),
+                    max_missed: 2,
+                },
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
+            },
+        );
+        subject.neighborhood_database.add_node(&neighbor).unwrap();
+        subject
+            .neighborhood_database
+            .add_neighbor(&cryptde.public_key(), neighbor.public_key())
+            .unwrap();
+        let (hopper, _, _) = make_recorder();
+        let peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
+        subject.hopper = Some(peer_actors.hopper.from_hopper_client);
+        let forged_reply = Keepalive {
+            sender: neighbor.public_key().clone(),
+            serial: 0x34343434,
+            is_reply: true,
+        };
+
+        subject.send_keepalives();
+        subject.handle_keepalive(forged_reply, IpAddr::from_str("6.6.6.6").unwrap());
+        subject.send_keepalives();
+        subject.send_keepalives();
+
+        assert_eq!(subject.neighborhood_database.root().neighbors(), &vec![]);
+        assert_eq!(
+            subject
+                .neighborhood_database
+                .node_by_key(neighbor.public_key())
+                .unwrap()
+                .latency_opt(),
+            None
+        );
+        TestLogHandler::new().exists_log_containing(&format!(
+            "DEBUG: Neighborhood: Ignoring keepalive reply from 6.6.6.6 claiming to be from {}",
+            neighbor.public_key()
+        ));
     }
 
     #[test]
@@ -1530,6 +2704,11 @@ mod tests {
                     is_bootstrap_node: this_node_inside.is_bootstrap_node(),
                     local_ip_addr: this_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1571,6 +2750,273 @@ mod tests {
         );
     }
 
+    #[test]
+    fn neighborhood_shuns_a_neighbor_that_keeps_sending_forged_gossip() {
+        init_test_logging();
+        let cryptde = cryptde();
+        let forger = make_node_record(2345, true, false);
+        let other_neighbor = make_node_record(3456, true, false);
+        let mut victim_cryptde = CryptDEReal::new();
+        victim_cryptde.generate_key_pair();
+        let victim_inner = NodeRecordInner {
+            public_key: victim_cryptde.public_key(),
+            node_addr_opt: None,
+            is_bootstrap_node: false,
+            neighbors: vec![],
+            version: 0,
+            exit_policy: ExitPolicy::allow_all(),
+        };
+        let signatures = NodeSignatures::from(&victim_cryptde, &victim_inner);
+        let mut forged_inner = victim_inner.clone();
+        forged_inner.neighbors.push(forger.public_key().clone());
+        let forged_gossip = Gossip {
+            node_records: vec![GossipNodeRecord {
+                inner: forged_inner,
+                signatures,
+            }],
+        };
+        let forged_package = ExpiredCoresPackagePackage {
+            expired_cores_package: ExpiredCoresPackage::new(
+                make_meaningless_route(),
+                PlainData::new(&serde_cbor::ser::to_vec(&forged_gossip).unwrap()[..]),
+            ),
+            sender_ip: forger.node_addr_opt().unwrap().ip_addr(),
+        };
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+        let forger_inside = forger.clone();
+        let other_neighbor_inside = other_neighbor.clone();
+        thread::spawn(move || {
+            let system =
+                System::new("neighborhood_shuns_a_neighbor_that_keeps_sending_forged_gossip");
+            let mut subject = Neighborhood::new(
+                cryptde,
+                NeighborhoodConfig {
+                    neighbor_configs: vec![],
+                    bootstrap_configs: vec![],
+                    is_bootstrap_node: true,
+                    local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                    clandestine_port_list: vec![1234],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
+                },
+            );
+            subject.gossip_acceptor = Box::new(GossipAcceptorReal::new(
+                &CryptDEReal::new(),
+                &AdmissionLimits::new(),
+            ));
+            vec![&forger_inside, &other_neighbor_inside]
+                .into_iter()
+                .for_each(|neighbor| {
+                    subject.neighborhood_database.add_node(neighbor).unwrap();
+                    subject
+                        .neighborhood_database
+                        .add_neighbor(&cryptde.public_key(), neighbor.public_key())
+                        .unwrap();
+                });
+            let addr: Addr<Syn, Neighborhood> = subject.start();
+            let peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
+            addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            (0..4).for_each(|_| addr.try_send(forged_package.clone()).unwrap());
+
+            system.run();
+        });
+
+        let tlh = TestLogHandler::new();
+        tlh.await_log_containing(
+            "DEBUG: Neighborhood: Ignoring message from 2.3.4.5, which has sent too much forged Gossip",
+            1000,
+        );
+        tlh.assert_logs_contain_in_order(vec![
+            &format!(
+                "WARN: Neighborhood: Gossip from 2.3.4.5 contained 1 forged NodeRecords: [{}]",
+                victim_cryptde.public_key()
+            ),
+            &format!(
+                "WARN: Neighborhood: Gossip from 2.3.4.5 contained 1 forged NodeRecords: [{}]",
+                victim_cryptde.public_key()
+            ),
+            "WARN: Neighborhood: Shunning 2.3.4.5 after 3 Gossip messages with forged NodeRecords",
+        ]);
+        hopper_awaiter.await_message_count(1);
+        let locked_recording = hopper_recording.lock().unwrap();
+        assert_eq!(locked_recording.len(), 1);
+        let package: &IncipientCoresPackage = locked_recording.get_record(0);
+        assert_eq!(&find_package_target(package), other_neighbor.public_key());
+    }
+
+    fn start_subject_with_neighbors(
+        neighbors: Vec<NodeRecord>,
+        payload: PlainData,
+        hopper: Recorder,
+    ) {
+        let cores_package = ExpiredCoresPackagePackage {
+            expired_cores_package: ExpiredCoresPackage::new(make_meaningless_route(), payload),
+            sender_ip: IpAddr::from_str("1.2.3.4").unwrap(),
+        };
+        thread::spawn(move || {
+            let system = System::new("start_subject_with_neighbors");
+            let cryptde = cryptde();
+            let mut subject = Neighborhood::new(
+                cryptde,
+                NeighborhoodConfig {
+                    neighbor_configs: vec![],
+                    bootstrap_configs: vec![],
+                    is_bootstrap_node: true,
+                    local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
+                    clandestine_port_list: vec![1234],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
+                },
+            );
+            neighbors.iter().for_each(|neighbor| {
+                subject.neighborhood_database.add_node(neighbor).unwrap();
+                subject
+                    .neighborhood_database
+                    .add_neighbor(&cryptde.public_key(), neighbor.public_key())
+                    .unwrap();
+            });
+            let addr: Addr<Syn, Neighborhood> = subject.start();
+            let peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
+            addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            addr.try_send(cores_package).unwrap();
+
+            system.run();
+        });
+    }
+
+    #[test]
+    fn neighborhood_requests_records_it_lacks_from_gossip_digest() {
+        let neighbor = make_node_record(2345, true, false);
+        let unknown_node = make_node_record(3456, false, false);
+        let digest = GossipDigest {
+            sender: neighbor.public_key().clone(),
+            entries: vec![
+                GossipDigestEntry {
+                    public_key: neighbor.public_key().clone(),
+                    version: 0,
+                    reveals_node_addr: true,
+                },
+                GossipDigestEntry {
+                    public_key: unknown_node.public_key().clone(),
+                    version: 0,
+                    reveals_node_addr: false,
+                },
+                GossipDigestEntry {
+                    public_key: cryptde().public_key(),
+                    version: 0,
+                    reveals_node_addr: true,
+                },
+            ],
+        };
+        let payload = PlainData::new(&serde_cbor::ser::to_vec(&digest).unwrap()[..]);
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+
+        start_subject_with_neighbors(vec![neighbor.clone()], payload, hopper);
+
+        hopper_awaiter.await_message_count(1);
+        let locked_recording = hopper_recording.lock().unwrap();
+        let package: &IncipientCoresPackage = locked_recording.get_record(0);
+        assert_eq!(&find_package_target(package), neighbor.public_key());
+        check_direct_route_to(&package.route, neighbor.public_key());
+        let request: GossipRequest = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+        assert_eq!(
+            request,
+            GossipRequest {
+                sender: cryptde().public_key(),
+                requested_keys: vec![unknown_node.public_key().clone()],
+            }
+        );
+    }
+
+    #[test]
+    fn neighborhood_answers_gossip_request_with_requested_records() {
+        let neighbor = make_node_record(2345, true, false);
+        let request = GossipRequest {
+            sender: neighbor.public_key().clone(),
+            requested_keys: vec![cryptde().public_key()],
+        };
+        let payload = PlainData::new(&serde_cbor::ser::to_vec(&request).unwrap()[..]);
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+
+        start_subject_with_neighbors(vec![neighbor.clone()], payload, hopper);
+
+        hopper_awaiter.await_message_count(1);
+        let locked_recording = hopper_recording.lock().unwrap();
+        let package: &IncipientCoresPackage = locked_recording.get_record(0);
+        assert_eq!(&find_package_target(package), neighbor.public_key());
+        let response: GossipResponse =
+            serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+        assert_eq!(response.requested_records.len(), 1);
+        let root_record = &response.requested_records[0];
+        assert_eq!(root_record.inner.public_key, cryptde().public_key());
+        assert_eq!(
+            root_record.inner.node_addr_opt,
+            Some(NodeAddr::new(
+                &IpAddr::from_str("5.4.3.2").unwrap(),
+                &vec![1234]
+            ))
+        );
+    }
+
+    #[test]
+    fn neighborhood_sends_digests_rather_than_introduction_gossip_after_single_record_response() {
+        let first_neighbor = make_node_record(2345, true, false);
+        let second_neighbor = make_node_record(3456, true, false);
+        let mut new_node = make_node_record(4567, false, false);
+        new_node
+            .neighbors_mut()
+            .push(first_neighbor.public_key().clone());
+        let response = GossipResponse {
+            requested_records: vec![GossipNodeRecord::from(&new_node, true)],
+        };
+        let payload = PlainData::new(&serde_cbor::ser::to_vec(&response).unwrap()[..]);
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let hopper_recording = hopper.get_recording();
+
+        start_subject_with_neighbors(
+            vec![first_neighbor.clone(), second_neighbor.clone()],
+            payload,
+            hopper,
+        );
+
+        hopper_awaiter.await_message_count(2);
+        let locked_recording = hopper_recording.lock().unwrap();
+        let targets: HashSet<Key> = (0..2)
+            .map(|index| {
+                let package: &IncipientCoresPackage = locked_recording.get_record(index);
+                let digest: GossipDigest =
+                    serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+                assert!(digest
+                    .entries
+                    .iter()
+                    .any(|entry| &entry.public_key == new_node.public_key()));
+                find_package_target(package)
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            vec_to_set(vec![
+                first_neighbor.public_key().clone(),
+                second_neighbor.public_key().clone()
+            ])
+        );
+    }
+
     #[test]
     fn standard_node_requests_bootstrap_properly() {
         let cryptde = cryptde();
@@ -1592,6 +3038,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                     clandestine_port_list: vec![1234],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1666,6 +3117,11 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: sentinel_ip_addr(),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
         );
         let n = &subject.neighborhood_database.root().clone();
@@ -1700,8 +3156,9 @@ mod tests {
             target_type: TargetType::Standard,
             target_key_opt: Some(c.public_key().clone()),
             target_component: Component::ProxyClient,
-            minimum_hop_count: 3,
+            route_policy: RoutePolicy::at_least(3),
             return_component_opt: None,
+            constraint: RouteConstraint::new(),
         };
         let unsuccessful_three_hop_route = addr.send(three_hop_route_request);
         let public_key_query = addr.send(NodeQueryMessage::PublicKey(a.public_key().clone()));
@@ -1769,6 +3226,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: sentinel_ip_addr(),
                     clandestine_port_list: vec![],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1819,6 +3281,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                     clandestine_port_list: vec![5678],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1876,6 +3343,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                     clandestine_port_list: vec![5678],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1925,6 +3397,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: IpAddr::from_str("5.4.3.2").unwrap(),
                     clandestine_port_list: vec![5678],
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -1995,6 +3472,11 @@ mod tests {
                         .unwrap()
                         .ports()
                         .clone(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -2065,6 +3547,11 @@ mod tests {
                     is_bootstrap_node: false,
                     local_ip_addr: this_node.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             subject
@@ -2141,6 +3628,11 @@ mod tests {
                     is_bootstrap_node: this_node_inside.is_bootstrap_node(),
                     local_ip_addr: this_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -2206,6 +3698,11 @@ mod tests {
                     is_bootstrap_node: bootstrap_node_inside.is_bootstrap_node(),
                     local_ip_addr: bootstrap_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: bootstrap_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             subject
@@ -2297,6 +3794,11 @@ mod tests {
                     is_bootstrap_node: this_node_inside.is_bootstrap_node(),
                     local_ip_addr: this_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
             let addr: Addr<Syn, Neighborhood> = subject.start();
@@ -2351,6 +3853,11 @@ mod tests {
                     is_bootstrap_node: this_node_inside.is_bootstrap_node(),
                     local_ip_addr: this_node_inside.node_addr_opt().unwrap().ip_addr(),
                     clandestine_port_list: this_node_inside.node_addr_opt().unwrap().ports(),
+                    exit_policy: ExitPolicy::allow_all(),
+                    data_directory_opt: None,
+                    keepalive_config: KeepaliveConfig::new(),
+                    admission_limits: AdmissionLimits::new(),
+                    introspection_port_opt: None,
                 },
             );
 
@@ -2389,12 +3896,12 @@ mod tests {
         hopper_awaiter.await_message_count(1);
         let locked_recording = hopper_recording.lock().unwrap();
         let package: &IncipientCoresPackage = locked_recording.get_record(0);
-        let gossip: Gossip = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
-        let the_node_record = gossip
-            .node_records
+        let digest: GossipDigest = serde_cbor::de::from_slice(&package.payload.data[..]).unwrap();
+        let root_entry = digest
+            .entries
             .iter()
-            .find(|&x| x.inner.public_key == cryptde.public_key())
-            .expect("should have the node record");
-        assert_eq!(the_node_record.inner.version, 1);
+            .find(|&x| x.public_key == cryptde.public_key())
+            .expect("should have the root entry");
+        assert_eq!(root_entry.version, 1);
     }
 }
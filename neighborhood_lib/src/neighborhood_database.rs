@@ -8,10 +8,12 @@ use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
 use std::net::IpAddr;
+use std::time::Duration;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::CryptData;
 use sub_lib::cryptde::Key;
 use sub_lib::cryptde::PlainData;
+use sub_lib::neighborhood::ExitPolicy;
 use sub_lib::node_addr::NodeAddr;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
@@ -21,6 +23,7 @@ pub struct NodeRecordInner {
     pub is_bootstrap_node: bool,
     pub neighbors: Vec<Key>,
     pub version: u32,
+    pub exit_policy: ExitPolicy,
 }
 
 impl NodeRecordInner {
@@ -28,6 +31,18 @@ impl NodeRecordInner {
     // For now, this is only called at initialization time (NeighborhoodDatabase) and in tests, so panicking is OK.
     // When we start signing NodeRecords at other times, we should probably not panic
     pub fn generate_signature(&self, cryptde: &CryptDE) -> CryptData {
+        cryptde.sign(&self.signable_data()).expect(&format!(
+            "NodeRecord content {:?} could not be signed",
+            &self
+        ))
+    }
+
+    // True if signature was made from exactly this content with the private key that goes with public_key
+    pub fn verify_signature(&self, signature: &CryptData, cryptde: &CryptDE) -> bool {
+        cryptde.verify_signature(&self.signable_data(), signature, &self.public_key)
+    }
+
+    fn signable_data(&self) -> PlainData {
         let serialized = match serde_cbor::ser::to_vec(&self) {
             Ok(inner) => inner,
             Err(_) => panic!("NodeRecord content {:?} could not be serialized", &self),
@@ -35,13 +50,7 @@ impl NodeRecordInner {
 
         let mut hash = sha1::Sha1::new();
         hash.update(&serialized[..]);
-
-        cryptde
-            .sign(&PlainData::new(&hash.digest().bytes()))
-            .expect(&format!(
-                "NodeRecord content {:?} could not be signed",
-                &self
-            ))
+        PlainData::new(&hash.digest().bytes())
     }
 }
 
@@ -65,6 +74,7 @@ impl NodeSignatures {
             is_bootstrap_node: node_record_inner.is_bootstrap_node,
             neighbors: node_record_inner.neighbors.clone(),
             version: node_record_inner.version,
+            exit_policy: node_record_inner.exit_policy.clone(),
         };
         let obscured_signature = obscured_inner.generate_signature(cryptde);
 
@@ -85,6 +95,8 @@ pub struct NodeRecord {
     inner: NodeRecordInner,
     // TODO: Replace this with a retransmittable representation of the signed packet/signature from the incoming Gossip.
     signatures: Option<NodeSignatures>,
+    // Measured here by keepalives, not Gossiped
+    latency_opt: Option<Duration>,
 }
 
 impl NodeRecord {
@@ -105,8 +117,10 @@ impl NodeRecord {
                 is_bootstrap_node,
                 neighbors: vec![],
                 version,
+                exit_policy: ExitPolicy::allow_all(),
             },
             signatures,
+            latency_opt: None,
         }
     }
 
@@ -186,6 +200,26 @@ impl NodeRecord {
         self.signatures = Some(NodeSignatures::from(cryptde, &self.inner))
     }
 
+    pub fn exit_policy(&self) -> &ExitPolicy {
+        &self.inner.exit_policy
+    }
+
+    pub fn set_exit_policy(&mut self, exit_policy: ExitPolicy) {
+        self.inner.exit_policy = exit_policy;
+    }
+
+    pub fn latency_opt(&self) -> Option<Duration> {
+        self.latency_opt
+    }
+
+    // Smoothed the way TCP smooths its round-trip time, so that one slow reply doesn't count for too much
+    pub fn add_latency_sample(&mut self, round_trip: Duration) {
+        self.latency_opt = Some(match self.latency_opt {
+            None => round_trip,
+            Some(latency) => (latency * 7 + round_trip) / 8,
+        });
+    }
+
     pub fn version(&self) -> u32 {
         self.inner.version
     }
@@ -203,6 +237,8 @@ pub struct NeighborhoodDatabase {
     this_node: Key,
     by_public_key: HashMap<Key, NodeRecord>,
     by_ip_addr: HashMap<IpAddr, Key>,
+    // The IP address of whoever sent the Gossip from which each Node was first learned about
+    introducers: HashMap<Key, IpAddr>,
 }
 
 impl Debug for NeighborhoodDatabase {
@@ -222,6 +258,7 @@ impl NeighborhoodDatabase {
             this_node: public_key.clone(),
             by_public_key: HashMap::new(),
             by_ip_addr: HashMap::new(),
+            introducers: HashMap::new(),
         };
 
         let mut node_record =
@@ -314,6 +351,32 @@ impl NeighborhoodDatabase {
         Ok(self.root_mut().remove_neighbor(node_key))
     }
 
+    // Forgets a Node entirely. Other Nodes' neighbor lists are signed by those Nodes, so they may go on mentioning it.
+    pub fn remove_node(&mut self, node_key: &Key) -> bool {
+        if node_key == &self.this_node {
+            return false;
+        }
+        match self.by_public_key.remove(node_key) {
+            Some(node_record) => {
+                if let Some(node_addr) = node_record.node_addr_opt() {
+                    self.by_ip_addr.remove(&node_addr.ip_addr());
+                }
+                self.introducers.remove(node_key);
+                self.root_mut().remove_neighbor(node_key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn introducer_of(&self, node_key: &Key) -> Option<IpAddr> {
+        self.introducers.get(node_key).cloned()
+    }
+
+    pub fn set_introducer(&mut self, node_key: &Key, introducer: IpAddr) {
+        self.introducers.insert(node_key.clone(), introducer);
+    }
+
     pub fn add_neighbor(
         &mut self,
         node_key: &Key,
@@ -389,6 +452,7 @@ mod tests {
     use std::iter::FromIterator;
     use std::str::FromStr;
     use sub_lib::cryptde_null::CryptDENull;
+    use sub_lib::cryptde_real::CryptDEReal;
 
     #[test]
     fn a_brand_new_database_has_the_expected_contents() {
@@ -774,6 +838,10 @@ mod tests {
             is_bootstrap_node: true,
             neighbors: Vec::new(),
             version: 0,
+            exit_policy: ExitPolicy {
+                allowed_ports: vec![443],
+                allowed_hostnames: vec![],
+            },
         };
         let cryptde = CryptDENull::from(&to_be_signed.public_key);
 
@@ -791,6 +859,31 @@ mod tests {
         )
     }
 
+    #[test]
+    fn node_record_inner_signatures_verify_only_against_unaltered_content() {
+        let mut cryptde = CryptDEReal::new();
+        cryptde.generate_key_pair();
+        let signed = NodeRecordInner {
+            public_key: cryptde.public_key(),
+            node_addr_opt: None,
+            is_bootstrap_node: false,
+            neighbors: vec![Key::new(&[1, 2, 3, 4])],
+            version: 3,
+            exit_policy: ExitPolicy::allow_all(),
+        };
+        let signature = signed.generate_signature(&cryptde);
+        let mut altered = signed.clone();
+        altered.neighbors.push(Key::new(&[5, 6, 7, 8]));
+        let mut impostor = CryptDEReal::new();
+        impostor.generate_key_pair();
+        let mut misattributed = signed.clone();
+        misattributed.public_key = impostor.public_key();
+
+        assert_eq!(signed.verify_signature(&signature, &cryptde), true);
+        assert_eq!(altered.verify_signature(&signature, &cryptde), false);
+        assert_eq!(misattributed.verify_signature(&signature, &cryptde), false);
+    }
+
     #[test]
     fn node_record_partial_eq() {
         let exemplar = NodeRecord::new(
@@ -1080,6 +1173,40 @@ mod tests {
         assert!(result.ok().expect("should be ok"));
     }
 
+    #[test]
+    fn remove_node_forgets_everything_about_a_node_but_the_root() {
+        let this_node = make_node_record(123, true, false);
+        let mut subject = NeighborhoodDatabase::new(
+            &this_node.inner.public_key,
+            this_node.inner.node_addr_opt.as_ref().unwrap(),
+            false,
+            &CryptDENull::from(this_node.public_key()),
+        );
+        let other_node = make_node_record(2345, true, false);
+        let introducer = IpAddr::from_str("3.4.5.6").unwrap();
+        subject.add_node(&other_node).unwrap();
+        subject.set_introducer(other_node.public_key(), introducer);
+        subject
+            .add_neighbor(&this_node.public_key(), &other_node.public_key())
+            .unwrap();
+        let introducer_before = subject.introducer_of(other_node.public_key());
+
+        let result = subject.remove_node(other_node.public_key());
+        let root_result = subject.remove_node(this_node.public_key());
+
+        assert_eq!(result, true);
+        assert_eq!(root_result, false);
+        assert_eq!(introducer_before, Some(introducer));
+        assert_eq!(subject.introducer_of(other_node.public_key()), None);
+        assert_eq!(subject.node_by_key(other_node.public_key()), None);
+        assert_eq!(
+            subject.node_by_ip(&other_node.node_addr_opt().unwrap().ip_addr()),
+            None
+        );
+        assert_eq!(subject.root().has_neighbor(other_node.public_key()), false);
+        assert_eq!(subject.remove_node(other_node.public_key()), false);
+    }
+
     #[test]
     fn remove_neighbor_returns_false_when_neighbor_was_not_removed() {
         let this_node = make_node_record(123, true, false);
@@ -1133,4 +1260,16 @@ mod tests {
 
         assert_eq!(this_node.version(), 10000);
     }
+
+    #[test]
+    fn latency_starts_with_the_first_sample_and_then_moves_slowly_toward_new_ones() {
+        let mut this_node = make_node_record(123, true, false);
+        assert_eq!(this_node.latency_opt(), None);
+
+        this_node.add_latency_sample(Duration::from_millis(80));
+        assert_eq!(this_node.latency_opt(), Some(Duration::from_millis(80)));
+
+        this_node.add_latency_sample(Duration::from_millis(160));
+        assert_eq!(this_node.latency_opt(), Some(Duration::from_millis(90)));
+    }
 }
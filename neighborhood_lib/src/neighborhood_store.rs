@@ -0,0 +1,471 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use gossip::GossipNodeRecord;
+use neighborhood_database::NeighborhoodDatabase;
+use serde_cbor;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use sub_lib::cryptde::Key;
+
+pub const NEIGHBORHOOD_STORE_FILENAME: &str = "neighborhood_database.cbor";
+// How often a running Node writes its NeighborhoodDatabase to the data directory
+pub const SAVE_INTERVAL_SECS: u64 = 60;
+// Saved NodeRecords nobody has Gossiped about for this long are left behind when the database is loaded
+pub const RECORD_EXPIRY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum NeighborhoodStoreError {
+    NotFound(String),
+    Io(String),
+    Corrupt(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredNodeRecord {
+    node_record: GossipNodeRecord,
+    last_heard_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreContents {
+    root_key: Key,
+    node_records: Vec<StoredNodeRecord>,
+}
+
+// Keeps a snapshot of the NeighborhoodDatabase in the data directory so that a restarted Node can pick up where
+// it left off instead of starting over from its --neighbor and --bootstrap_from settings.
+pub struct NeighborhoodStore {
+    file_path: PathBuf,
+    save_interval: Duration,
+    record_expiry: Duration,
+    last_heard: HashMap<Key, SystemTime>,
+}
+
+impl NeighborhoodStore {
+    pub fn new(
+        data_directory: &Path,
+        save_interval: Duration,
+        record_expiry: Duration,
+    ) -> NeighborhoodStore {
+        NeighborhoodStore {
+            file_path: data_directory.join(NEIGHBORHOOD_STORE_FILENAME),
+            save_interval,
+            record_expiry,
+            last_heard: HashMap::new(),
+        }
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        self.save_interval
+    }
+
+    pub fn heard_about(&mut self, public_key: &Key, now: SystemTime) {
+        self.last_heard.insert(public_key.clone(), now);
+    }
+
+    // Unsigned NodeRecords came from the command line, and will come from there again; so they're not saved.
+    pub fn save(
+        &self,
+        database: &NeighborhoodDatabase,
+        now: SystemTime,
+    ) -> Result<(), NeighborhoodStoreError> {
+        let root_key = database.root().public_key();
+        let node_records = database
+            .keys()
+            .into_iter()
+            .filter_map(|key| database.node_by_key(key))
+            .filter(|node_record| node_record.signatures().is_some())
+            .map(|node_record| {
+                let last_heard = if node_record.public_key() == root_key {
+                    now
+                } else {
+                    *self
+                        .last_heard
+                        .get(node_record.public_key())
+                        .unwrap_or(&now)
+                };
+                StoredNodeRecord {
+                    node_record: GossipNodeRecord::from(node_record, true),
+                    last_heard_secs: secs_since_epoch(last_heard),
+                }
+            })
+            .filter(|stored| !self.is_expired(stored, now))
+            .collect();
+        let contents = StoreContents {
+            root_key: root_key.clone(),
+            node_records,
+        };
+        let bytes = serde_cbor::ser::to_vec(&contents).expect("Serialization failed");
+        self.write_file(&bytes[..])
+    }
+
+    // Returns the number of NodeRecords added to or filled in the database. Records that have expired, and the
+    // saved record of whatever Node this used to be, are skipped; but if this Node's key hasn't changed, its
+    // version is moved past the saved one so that the rest of the Network will believe its next Gossip.
+    pub fn load(
+        &mut self,
+        database: &mut NeighborhoodDatabase,
+        now: SystemTime,
+    ) -> Result<usize, NeighborhoodStoreError> {
+        let contents = self.read_contents()?;
+        let root_key = database.root().public_key().clone();
+        let mut loaded_count = 0;
+        let mut saved_root_opt = None;
+        for stored in contents.node_records {
+            let public_key = stored.node_record.public_key();
+            if public_key == contents.root_key {
+                saved_root_opt = Some(stored.node_record);
+                continue;
+            }
+            if public_key.data.is_empty()
+                || (public_key == root_key)
+                || self.is_expired(&stored, now)
+            {
+                continue;
+            }
+            if NeighborhoodStore::load_node_record(database, &stored.node_record) {
+                self.last_heard.insert(
+                    public_key,
+                    UNIX_EPOCH + Duration::from_secs(stored.last_heard_secs),
+                );
+                loaded_count += 1;
+            }
+        }
+        match saved_root_opt {
+            Some(ref saved_root) if saved_root.public_key() == root_key => {
+                NeighborhoodStore::restore_root(database, saved_root)
+            }
+            _ => (),
+        }
+        Ok(loaded_count)
+    }
+
+    // Nodes configured on the command line are already in the database, but without the neighbors and signatures
+    // they had when the database was saved.
+    fn load_node_record(database: &mut NeighborhoodDatabase, saved: &GossipNodeRecord) -> bool {
+        if let Some(existing) = database.node_by_key_mut(&saved.inner.public_key) {
+            if existing.signatures().is_some() {
+                return false;
+            }
+            existing.set_version(saved.inner.version);
+            existing.neighbors_mut().clear();
+            existing
+                .neighbors_mut()
+                .extend(saved.inner.neighbors.clone());
+            existing.set_exit_policy(saved.inner.exit_policy.clone());
+            existing.set_signatures(saved.signatures.clone());
+            return true;
+        }
+        database
+            .add_node(&saved.to_node_record())
+            .expect("Key magically appeared");
+        true
+    }
+
+    fn restore_root(database: &mut NeighborhoodDatabase, saved_root: &GossipNodeRecord) {
+        let root_key = database.root().public_key().clone();
+        let known_neighbors: Vec<Key> = saved_root
+            .inner
+            .neighbors
+            .iter()
+            .filter(|neighbor| database.keys().contains(neighbor))
+            .cloned()
+            .collect();
+        known_neighbors.iter().for_each(|neighbor| {
+            database
+                .add_neighbor(&root_key, neighbor)
+                .expect("Node magically disappeared");
+        });
+        if database.root().version() <= saved_root.inner.version {
+            database
+                .root_mut()
+                .set_version(saved_root.inner.version + 1);
+        }
+    }
+
+    fn is_expired(&self, stored: &StoredNodeRecord, now: SystemTime) -> bool {
+        stored.last_heard_secs + self.record_expiry.as_secs() < secs_since_epoch(now)
+    }
+
+    fn read_contents(&self) -> Result<StoreContents, NeighborhoodStoreError> {
+        let bytes = match fs::read(&self.file_path) {
+            Ok(bytes) => bytes,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(NeighborhoodStoreError::NotFound(format!(
+                    "{:?}",
+                    self.file_path
+                )))
+            }
+            Err(e) => {
+                return Err(NeighborhoodStoreError::Io(format!(
+                    "{:?}: {}",
+                    self.file_path, e
+                )))
+            }
+        };
+        match serde_cbor::de::from_slice::<StoreContents>(&bytes[..]) {
+            Ok(contents) => Ok(contents),
+            Err(e) => Err(NeighborhoodStoreError::Corrupt(format!(
+                "{:?}: {}",
+                self.file_path, e
+            ))),
+        }
+    }
+
+    // Written to a temporary file first, so that a crash in the middle of a save can't spoil the last snapshot
+    fn write_file(&self, bytes: &[u8]) -> Result<(), NeighborhoodStoreError> {
+        let temp_path = self.file_path.with_extension("tmp");
+        let result = fs::File::create(&temp_path)
+            .and_then(|mut file| file.write_all(bytes))
+            .and_then(|_| fs::rename(&temp_path, &self.file_path));
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(NeighborhoodStoreError::Io(format!(
+                    "{:?}: {}",
+                    self.file_path, e
+                )))
+            }
+        }
+    }
+}
+
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neighborhood_database::NodeRecord;
+    use neighborhood_test_utils::make_node_record;
+    use sub_lib::neighborhood::ExitPolicy;
+    use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
+
+    const EXPIRY_SECS: u64 = 1000;
+
+    fn make_subject(home_dir: &Path) -> NeighborhoodStore {
+        NeighborhoodStore::new(
+            home_dir,
+            Duration::from_secs(SAVE_INTERVAL_SECS),
+            Duration::from_secs(EXPIRY_SECS),
+        )
+    }
+
+    fn make_database(root: &NodeRecord) -> NeighborhoodDatabase {
+        NeighborhoodDatabase::new(
+            root.public_key(),
+            root.node_addr_opt().as_ref().unwrap(),
+            false,
+            cryptde(),
+        )
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn saved_database_can_be_loaded_into_a_new_one() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "save_and_load");
+        let root = make_node_record(1234, true, false);
+        let mut this_node = make_node_record(2345, true, false);
+        this_node.set_exit_policy(ExitPolicy {
+            allowed_ports: vec![443],
+            allowed_hostnames: vec![],
+        });
+        let that_node = make_node_record(3456, false, false);
+        let mut database = make_database(&root);
+        database.add_node(&this_node).unwrap();
+        database.add_node(&that_node).unwrap();
+        database
+            .add_neighbor(root.public_key(), this_node.public_key())
+            .unwrap();
+        database
+            .add_neighbor(this_node.public_key(), that_node.public_key())
+            .unwrap();
+        database.root_mut().set_version(7);
+        database.root_mut().sign(cryptde());
+        make_subject(&home_dir).save(&database, at(5000)).unwrap();
+        let mut new_database = make_database(&root);
+        let mut subject = make_subject(&home_dir);
+
+        let result = subject.load(&mut new_database, at(5000));
+
+        assert_eq!(result, Ok(2));
+        let loaded_this_node = new_database.node_by_key(this_node.public_key()).unwrap();
+        assert_eq!(
+            loaded_this_node.neighbors(),
+            &vec![that_node.public_key().clone()]
+        );
+        assert_eq!(loaded_this_node.exit_policy(), this_node.exit_policy());
+        assert_eq!(loaded_this_node.signatures(), this_node.signatures());
+        assert_eq!(loaded_this_node.node_addr_opt(), this_node.node_addr_opt());
+        assert_eq!(
+            new_database.node_by_key(that_node.public_key()).is_some(),
+            true
+        );
+        assert_eq!(
+            new_database.root().neighbors(),
+            &vec![this_node.public_key().clone()]
+        );
+        assert_eq!(new_database.root().version(), 8);
+    }
+
+    #[test]
+    fn records_not_heard_about_recently_expire() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "expiry");
+        let root = make_node_record(1234, true, false);
+        let stale_node = make_node_record(2345, true, false);
+        let fresh_node = make_node_record(3456, true, false);
+        let mut database = make_database(&root);
+        database.add_node(&stale_node).unwrap();
+        database.add_node(&fresh_node).unwrap();
+        let mut subject = make_subject(&home_dir);
+        subject.heard_about(stale_node.public_key(), at(1000));
+        subject.heard_about(fresh_node.public_key(), at(1500));
+        subject.save(&database, at(1600)).unwrap();
+        let mut new_database = make_database(&root);
+
+        let result = make_subject(&home_dir).load(&mut new_database, at(1000 + EXPIRY_SECS + 1));
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(
+            new_database.node_by_key(stale_node.public_key()).is_none(),
+            true
+        );
+        assert_eq!(
+            new_database.node_by_key(fresh_node.public_key()).is_some(),
+            true
+        );
+    }
+
+    #[test]
+    fn configured_nodes_are_filled_in_from_saved_records_but_not_duplicated() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "configured");
+        let root = make_node_record(1234, true, false);
+        let mut neighbor = make_node_record(2345, true, false);
+        neighbor
+            .neighbors_mut()
+            .push(make_node_record(3456, false, false).public_key().clone());
+        neighbor.set_version(3);
+        let mut database = make_database(&root);
+        database.add_node(&neighbor).unwrap();
+        make_subject(&home_dir).save(&database, at(1000)).unwrap();
+        let mut new_database = make_database(&root);
+        new_database
+            .add_node(&NodeRecord::new(
+                neighbor.public_key(),
+                neighbor.node_addr_opt().as_ref(),
+                false,
+                None,
+                0,
+            ))
+            .unwrap();
+
+        let result = make_subject(&home_dir).load(&mut new_database, at(1000));
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(new_database.keys().len(), 2);
+        let loaded_neighbor = new_database.node_by_key(neighbor.public_key()).unwrap();
+        assert_eq!(loaded_neighbor.version(), 3);
+        assert_eq!(loaded_neighbor.neighbors(), neighbor.neighbors());
+        assert_eq!(loaded_neighbor.signatures(), neighbor.signatures());
+    }
+
+    #[test]
+    fn unsigned_records_are_not_saved() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "unsigned");
+        let root = make_node_record(1234, true, false);
+        let unsigned = make_node_record(2345, true, false);
+        let mut database = make_database(&root);
+        database
+            .add_node(&NodeRecord::new(
+                unsigned.public_key(),
+                unsigned.node_addr_opt().as_ref(),
+                false,
+                None,
+                0,
+            ))
+            .unwrap();
+        make_subject(&home_dir).save(&database, at(1000)).unwrap();
+        let mut new_database = make_database(&root);
+
+        let result = make_subject(&home_dir).load(&mut new_database, at(1000));
+
+        assert_eq!(result, Ok(0));
+        assert_eq!(new_database.keys().len(), 1);
+    }
+
+    #[test]
+    fn saved_record_of_a_different_root_is_not_loaded() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "different_root");
+        let old_root = make_node_record(1234, true, false);
+        let new_root = make_node_record(4321, true, false);
+        let mut database = make_database(&old_root);
+        database.root_mut().set_version(7);
+        database.root_mut().sign(cryptde());
+        make_subject(&home_dir).save(&database, at(1000)).unwrap();
+        let mut new_database = make_database(&new_root);
+
+        let result = make_subject(&home_dir).load(&mut new_database, at(1000));
+
+        assert_eq!(result, Ok(0));
+        assert_eq!(
+            new_database.node_by_key(old_root.public_key()).is_none(),
+            true
+        );
+        assert_eq!(new_database.root().version(), 0);
+    }
+
+    #[test]
+    fn missing_file_is_reported_as_not_found() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "missing");
+        let root = make_node_record(1234, true, false);
+        let mut database = make_database(&root);
+        let mut subject = make_subject(&home_dir);
+
+        let result = subject.load(&mut database, at(1000));
+
+        assert_eq!(
+            result,
+            Err(NeighborhoodStoreError::NotFound(format!(
+                "{:?}",
+                home_dir.join(NEIGHBORHOOD_STORE_FILENAME)
+            )))
+        );
+    }
+
+    #[test]
+    fn corrupt_file_is_reported_and_leaves_the_database_alone() {
+        let home_dir = ensure_node_home_directory_exists("neighborhood_store", "corrupt");
+        let root = make_node_record(1234, true, false);
+        let mut database = make_database(&root);
+        let mut subject = make_subject(&home_dir);
+        fs::write(subject.file_path(), b"\xA2booga").unwrap();
+
+        let result = subject.load(&mut database, at(1000));
+
+        match result {
+            Err(NeighborhoodStoreError::Corrupt(_)) => (),
+            x => panic!("Expected Corrupt, got {:?}", x),
+        }
+        assert_eq!(database.keys().len(), 1);
+        assert_eq!(database.root().version(), 0);
+    }
+}
@@ -0,0 +1,837 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use neighborhood_database::NeighborhoodDatabase;
+use neighborhood_database::NodeRecord;
+use route_selector::RouteSelector;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::cryptde::Key;
+use sub_lib::neighborhood::HopRange;
+use sub_lib::neighborhood::RouteConstraint;
+use sub_lib::neighborhood::TargetType;
+use sub_lib::neighborhood::DEFAULT_MAX_HOPS_PER_SOURCE;
+
+pub const DEFAULT_MAX_ROUTES: usize = 64;
+pub const DEFAULT_MAX_SEARCH_STEPS: usize = 20_000;
+pub const DEFAULT_SEARCH_TIME_BUDGET_MS: u64 = 100;
+
+// A route search stops as soon as it has found max_routes routes, has extended a partial route max_steps
+// times, or has run for time_budget, whichever comes first. Each neighbor of a Node gets an equal share of the
+// routes still wanted through that Node, so that the routes found don't all start the same way. No route found
+// will have more than max_hops_per_source hops whose Nodes were introduced by the same source of Gossip.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteSearchLimits {
+    pub max_routes: usize,
+    pub max_steps: usize,
+    pub time_budget: Duration,
+    pub max_hops_per_source: usize,
+}
+
+impl RouteSearchLimits {
+    pub fn new() -> RouteSearchLimits {
+        RouteSearchLimits {
+            max_routes: DEFAULT_MAX_ROUTES,
+            max_steps: DEFAULT_MAX_SEARCH_STEPS,
+            time_budget: Duration::from_millis(DEFAULT_SEARCH_TIME_BUDGET_MS),
+            max_hops_per_source: DEFAULT_MAX_HOPS_PER_SOURCE,
+        }
+    }
+}
+
+// Depth-first search for cycle-free routes from origin whose hop counts hop_range allows that end at a Node
+// that qualifies as the target and whose ExitPolicy allows the constraint's exit destination, if any, without
+// passing through any of the constraint's excluded Nodes. The neighbors of each Node are explored in an order
+// chosen by the RouteSelector, so that a search that stops early hasn't only looked down one branch.
+// Neighbors that can't reach any qualifying Node are never explored, and once the route is long enough,
+// neighbors closer to a qualifying Node are explored first.
+pub fn complete_routes<'a>(
+    database: &'a NeighborhoodDatabase,
+    route_selector: &mut RouteSelector,
+    origin: &'a Key,
+    target: Option<&'a Key>,
+    target_type: TargetType,
+    hop_range: HopRange,
+    constraint: &RouteConstraint,
+    limits: &RouteSearchLimits,
+) -> Vec<Vec<&'a Key>> {
+    let mut search = RouteSearch {
+        database,
+        route_selector,
+        target,
+        target_type,
+        constraint,
+        distances: distances_to_targets(database, target, target_type, constraint),
+        max_routes: limits.max_routes,
+        max_steps: limits.max_steps,
+        max_hops_per_source: limits.max_hops_per_source,
+        maximum_hop_count_opt: hop_range.maximum_opt,
+        deadline: Instant::now() + limits.time_budget,
+        steps: 0,
+        routes: vec![],
+    };
+    search.extend(&mut vec![origin], hop_range.minimum, limits.max_routes);
+    search.routes
+}
+
+struct RouteSearch<'a, 'b> {
+    database: &'a NeighborhoodDatabase,
+    route_selector: &'b mut RouteSelector,
+    target: Option<&'a Key>,
+    target_type: TargetType,
+    constraint: &'b RouteConstraint,
+    distances: HashMap<&'a Key, usize>,
+    max_routes: usize,
+    max_steps: usize,
+    max_hops_per_source: usize,
+    maximum_hop_count_opt: Option<usize>,
+    deadline: Instant,
+    steps: usize,
+    routes: Vec<Vec<&'a Key>>,
+}
+
+impl<'a, 'b> RouteSearch<'a, 'b> {
+    // Returns the number of routes found that start with prefix, which will be no more than quota
+    fn extend(&mut self, prefix: &mut Vec<&'a Key>, hops_remaining: usize, quota: usize) -> usize {
+        self.steps += 1;
+        let last_node_ref = self
+            .database
+            .node_by_key(prefix.last().expect("Empty prefix"))
+            .expect("Node magically disappeared");
+        if (hops_remaining == 0) && self.qualifies(last_node_ref) {
+            self.routes.push(prefix.clone());
+            return 1;
+        }
+        // If we're not done, then last_node is for routing, and bootstrap Nodes don't route.
+        if last_node_ref.is_bootstrap_node() {
+            return 0;
+        }
+        let mut neighbors: Vec<&'a Key> = last_node_ref
+            .neighbors()
+            .iter()
+            .filter(|neighbor_key_ref| !prefix.contains(neighbor_key_ref))
+            .filter(|neighbor_key_ref| self.distances.contains_key(neighbor_key_ref))
+            .filter(|neighbor_key_ref| self.is_within_hop_limit(prefix, neighbor_key_ref))
+            .filter(|neighbor_key_ref| self.is_within_source_limit(prefix, neighbor_key_ref))
+            .collect();
+        self.route_selector.shuffle(&mut neighbors);
+        if hops_remaining <= 1 {
+            let distances = &self.distances;
+            neighbors.sort_by_key(|neighbor_key_ref| distances[neighbor_key_ref]);
+        }
+        let neighbor_count = neighbors.len();
+        let mut found = 0;
+        for (index, neighbor_key_ref) in neighbors.into_iter().enumerate() {
+            if (found >= quota) || self.is_exhausted() {
+                break;
+            }
+            let neighbors_left = neighbor_count - index;
+            let share = (quota - found + neighbors_left - 1) / neighbors_left;
+            prefix.push(neighbor_key_ref);
+            found += self.extend(prefix, hops_remaining.saturating_sub(1), share);
+            prefix.pop();
+        }
+        found
+    }
+
+    // A neighbor is one more hop, and it's at least its distance away from any Node that qualifies
+    fn is_within_hop_limit(&self, prefix: &[&'a Key], candidate: &Key) -> bool {
+        match self.maximum_hop_count_opt {
+            None => true,
+            Some(maximum) => prefix.len() + self.distances[candidate] <= maximum,
+        }
+    }
+
+    // The origin is our own Node, so whoever introduced it doesn't matter
+    fn is_within_source_limit(&self, prefix: &[&'a Key], candidate: &Key) -> bool {
+        match self.database.introducer_of(candidate) {
+            None => true,
+            Some(introducer) => {
+                let hops_from_introducer = prefix
+                    .iter()
+                    .skip(1)
+                    .filter(|key_ref| self.database.introducer_of(key_ref) == Some(introducer))
+                    .count();
+                hops_from_introducer < self.max_hops_per_source
+            }
+        }
+    }
+
+    fn qualifies(&self, node_ref: &NodeRecord) -> bool {
+        node_qualifies(node_ref, self.target, self.target_type, self.constraint)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        (self.routes.len() >= self.max_routes)
+            || (self.steps >= self.max_steps)
+            || (Instant::now() >= self.deadline)
+    }
+}
+
+fn node_qualifies(
+    node_ref: &NodeRecord,
+    target: Option<&Key>,
+    target_type: TargetType,
+    constraint: &RouteConstraint,
+) -> bool {
+    let key_qualifies = match target {
+        Some(target_key_ref) => node_ref.public_key() == target_key_ref,
+        None => true,
+    };
+    let exit_policy_qualifies = match constraint.exit_destination_opt {
+        Some(ref exit_destination) => node_ref.exit_policy().allows(exit_destination),
+        None => true,
+    };
+    key_qualifies
+        && exit_policy_qualifies
+        && ((target_type == TargetType::Bootstrap) == node_ref.is_bootstrap_node())
+}
+
+// Breadth-first search backward from every qualifying Node. Nodes that don't appear in the result can't reach
+// a qualifying Node at all; bootstrap Nodes don't route, so they appear only if they qualify themselves, and
+// excluded Nodes never appear.
+fn distances_to_targets<'a>(
+    database: &'a NeighborhoodDatabase,
+    target: Option<&'a Key>,
+    target_type: TargetType,
+    constraint: &RouteConstraint,
+) -> HashMap<&'a Key, usize> {
+    let mut referrers: HashMap<&'a Key, Vec<&'a Key>> = HashMap::new();
+    let mut distances: HashMap<&'a Key, usize> = HashMap::new();
+    let mut queue: VecDeque<&'a Key> = VecDeque::new();
+    database
+        .keys()
+        .into_iter()
+        .filter(|key| !constraint.excluded_keys.contains(key))
+        .for_each(|key| {
+            let node_ref = database
+                .node_by_key(key)
+                .expect("Node magically disappeared");
+            if !node_ref.is_bootstrap_node() {
+                node_ref.neighbors().iter().for_each(|neighbor_key_ref| {
+                    referrers
+                        .entry(neighbor_key_ref)
+                        .or_insert_with(|| vec![])
+                        .push(key)
+                });
+            }
+            if node_qualifies(node_ref, target, target_type, constraint) {
+                distances.insert(key, 0);
+                queue.push_back(key);
+            }
+        });
+    while let Some(key) = queue.pop_front() {
+        let distance = distances[key] + 1;
+        if let Some(referrer_keys) = referrers.get(key) {
+            referrer_keys.iter().for_each(|referrer_key| {
+                if !distances.contains_key(referrer_key) {
+                    distances.insert(referrer_key, distance);
+                    queue.push_back(referrer_key);
+                }
+            });
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neighborhood_test_utils::make_node_record;
+    use neighborhood_test_utils::vec_to_set;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+    use route_selector::RouteSelectorReal;
+    use std::collections::HashSet;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+    use sub_lib::neighborhood::ExitPolicy;
+    use test_utils::test_utils::cryptde;
+
+    #[test]
+    fn stops_searching_when_it_has_found_enough_routes() {
+        let database = make_mesh_database(20, 19, 0);
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let limits = RouteSearchLimits {
+            max_routes: 5,
+            ..RouteSearchLimits::new()
+        };
+
+        let routes = complete_routes(
+            &database,
+            &mut route_selector,
+            origin,
+            None,
+            TargetType::Standard,
+            HopRange::at_least(3),
+            &RouteConstraint::new(),
+            &limits,
+        );
+
+        assert_eq!(routes.len(), 5);
+        check_routes(&database, &routes, None, TargetType::Standard, 3);
+    }
+
+    /*
+            Database, where all Nodes are standard:
+
+            P---Q---R---S---T
+
+            Test is written from the standpoint of P
+    */
+
+    #[test]
+    fn stops_searching_when_it_has_taken_too_many_steps() {
+        let database = make_line_database(5);
+        let origin = database.root().public_key();
+        let target = make_node_record(1004, true, false).public_key().clone();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let limits = RouteSearchLimits {
+            max_steps: 4,
+            ..RouteSearchLimits::new()
+        };
+
+        let short_routes = complete_routes(
+            &database,
+            &mut route_selector,
+            origin,
+            Some(&target),
+            TargetType::Standard,
+            HopRange::at_least(1),
+            &RouteConstraint::new(),
+            &limits,
+        );
+        let limits = RouteSearchLimits {
+            max_steps: 5,
+            ..RouteSearchLimits::new()
+        };
+        let long_enough_routes = complete_routes(
+            &database,
+            &mut route_selector,
+            origin,
+            Some(&target),
+            TargetType::Standard,
+            HopRange::at_least(1),
+            &RouteConstraint::new(),
+            &limits,
+        );
+
+        assert_eq!(short_routes.len(), 0);
+        assert_eq!(long_enough_routes.len(), 1);
+    }
+
+    #[test]
+    fn refuses_routes_with_more_hops_than_the_maximum() {
+        let database = make_line_database(5);
+        let origin = database.root().public_key();
+        let target = make_node_record(1004, true, false).public_key().clone();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let mut find_routes = |maximum| {
+            complete_routes(
+                &database,
+                &mut route_selector,
+                origin,
+                Some(&target),
+                TargetType::Standard,
+                HopRange {
+                    minimum: 1,
+                    maximum_opt: Some(maximum),
+                },
+                &RouteConstraint::new(),
+                &RouteSearchLimits::new(),
+            )
+        };
+        let too_short_routes = find_routes(3);
+        let long_enough_routes = find_routes(4);
+
+        let whole_line: Vec<Key> = (1000..1005).map(node_key).collect();
+        assert_eq!(too_short_routes.len(), 0);
+        assert_eq!(
+            long_enough_routes,
+            vec![whole_line.iter().collect::<Vec<&Key>>()]
+        );
+    }
+
+    #[test]
+    fn refuses_routes_with_too_many_hops_introduced_by_the_same_source() {
+        let mut database = make_line_database(5);
+        let first_source = IpAddr::from_str("1.1.1.1").unwrap();
+        let second_source = IpAddr::from_str("2.2.2.2").unwrap();
+        database.set_introducer(&node_key(1000), second_source);
+        database.set_introducer(&node_key(1001), first_source);
+        (1002..1005).for_each(|n| database.set_introducer(&node_key(n), second_source));
+        let origin = database.root().public_key().clone();
+        let target = node_key(1004);
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let limits = RouteSearchLimits {
+            max_hops_per_source: 2,
+            ..RouteSearchLimits::new()
+        };
+        let mut search = |database: &NeighborhoodDatabase| {
+            complete_routes(
+                database,
+                &mut route_selector,
+                &origin,
+                Some(&target),
+                TargetType::Standard,
+                HopRange::at_least(1),
+                &RouteConstraint::new(),
+                &limits,
+            )
+            .len()
+        };
+
+        let routes_before = search(&database);
+        database.set_introducer(&node_key(1003), first_source);
+        let routes_after = search(&database);
+
+        assert_eq!(routes_before, 0);
+        assert_eq!(routes_after, 1);
+    }
+
+    #[test]
+    fn stops_searching_when_it_runs_out_of_time() {
+        let database = make_mesh_database(100, 10, 0);
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let limits = RouteSearchLimits {
+            time_budget: Duration::from_millis(0),
+            ..RouteSearchLimits::new()
+        };
+
+        let routes = complete_routes(
+            &database,
+            &mut route_selector,
+            origin,
+            None,
+            TargetType::Standard,
+            HopRange::at_least(3),
+            &RouteConstraint::new(),
+            &limits,
+        );
+
+        assert_eq!(routes.len(), 0);
+    }
+
+    #[test]
+    fn never_explores_neighbors_that_cannot_reach_the_target() {
+        let mut database = make_line_database(3);
+        (0..10).for_each(|n| {
+            let dead_end = make_node_record(2000 + n, true, false);
+            database.add_node(&dead_end).unwrap();
+            database
+                .add_neighbor(&node_key(1000), dead_end.public_key())
+                .unwrap();
+        });
+        let origin = database.root().public_key().clone();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let limits = RouteSearchLimits {
+            max_steps: 3,
+            ..RouteSearchLimits::new()
+        };
+        let target = node_key(1002);
+
+        let routes = complete_routes(
+            &database,
+            &mut route_selector,
+            &origin,
+            Some(&target),
+            TargetType::Standard,
+            HopRange::at_least(2),
+            &RouteConstraint::new(),
+            &limits,
+        );
+
+        assert_eq!(
+            routes,
+            vec![vec![&node_key(1000), &node_key(1001), &target]]
+        );
+    }
+
+    /*
+            Database, where all Nodes are standard:
+
+                    R
+                    |
+            P---Q---+
+                    |
+                    S
+
+            Test is written from the standpoint of P
+    */
+
+    #[test]
+    fn only_exit_nodes_whose_policy_allows_the_destination_qualify() {
+        let mut database = make_fork_database();
+        database
+            .node_by_key_mut(&node_key(1002))
+            .unwrap()
+            .set_exit_policy(ExitPolicy {
+                allowed_ports: vec![443],
+                allowed_hostnames: vec![],
+            });
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let mut search = |constraint: RouteConstraint| {
+            vec_to_set(complete_routes(
+                &database,
+                &mut route_selector,
+                origin,
+                None,
+                TargetType::Standard,
+                HopRange::at_least(2),
+                &constraint,
+                &RouteSearchLimits::new(),
+            ))
+        };
+
+        let http_routes = search(RouteConstraint::for_exit_to(
+            Some(String::from("booga.com")),
+            80,
+        ));
+        let tls_routes = search(RouteConstraint::for_exit_to(
+            Some(String::from("booga.com")),
+            443,
+        ));
+
+        let (p, q, r, s) = (
+            node_key(1000),
+            node_key(1001),
+            node_key(1002),
+            node_key(1003),
+        );
+        assert_eq!(http_routes, vec_to_set(vec![vec![&p, &q, &s]]));
+        assert_eq!(
+            tls_routes,
+            vec_to_set(vec![vec![&p, &q, &r], vec![&p, &q, &s]])
+        );
+    }
+
+    #[test]
+    fn excluded_nodes_never_appear_in_routes() {
+        let database = make_fork_database();
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let mut search = |excluded_keys: Vec<Key>| {
+            complete_routes(
+                &database,
+                &mut route_selector,
+                origin,
+                None,
+                TargetType::Standard,
+                HopRange::at_least(2),
+                &RouteConstraint {
+                    exit_destination_opt: None,
+                    excluded_keys,
+                },
+                &RouteSearchLimits::new(),
+            )
+        };
+
+        let without_exit = search(vec![node_key(1003)]);
+        let without_relay = search(vec![node_key(1001)]);
+
+        assert_eq!(
+            without_exit,
+            vec![vec![&node_key(1000), &node_key(1001), &node_key(1002)]]
+        );
+        assert_eq!(without_relay.is_empty(), true);
+    }
+
+    #[test]
+    fn searching_all_routes_in_a_small_neighborhood_does_not_depend_on_the_order_of_neighbors() {
+        let database = make_mesh_database(8, 4, 1);
+        let origin = database.root().public_key();
+        let search = |seed: u8| {
+            let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([seed; 32]));
+            let limits = RouteSearchLimits {
+                max_routes: 1_000_000,
+                max_steps: 1_000_000,
+                time_budget: Duration::from_secs(60),
+                max_hops_per_source: DEFAULT_MAX_HOPS_PER_SOURCE,
+            };
+            let routes: HashSet<Vec<&Key>> = complete_routes(
+                &database,
+                &mut route_selector,
+                origin,
+                None,
+                TargetType::Standard,
+                HopRange::at_least(3),
+                &RouteConstraint::new(),
+                &limits,
+            )
+            .into_iter()
+            .collect();
+            routes
+        };
+
+        let first_routes = search(1);
+        let second_routes = search(2);
+
+        assert_eq!(first_routes.is_empty(), false);
+        assert_eq!(first_routes, second_routes);
+    }
+
+    // An exhaustive search of any of these Neighborhoods would not finish in our lifetimes. The time budget is
+    // lifted here so that the results don't depend on how busy the machine running the tests is.
+
+    #[test]
+    fn finds_indefinite_routes_in_a_large_neighborhood() {
+        let database = make_mesh_database(500, 10, 5);
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let routes =
+            search_indefinite_routes(&database, origin, &mut route_selector, &untimed_limits());
+
+        assert_eq!(routes.len(), DEFAULT_MAX_ROUTES);
+        check_routes(&database, &routes, None, TargetType::Standard, 6);
+        assert_eq!(
+            vec_to_set(routes.iter().map(|route| route[1]).collect::<Vec<&Key>>()).len() > 1,
+            true
+        );
+    }
+
+    #[test]
+    fn finds_routes_to_a_distant_target_in_a_large_neighborhood() {
+        let database = make_mesh_database(500, 6, 0);
+        let origin = database.root().public_key();
+        let target = node_key(1499);
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let routes = search_targeted_routes(
+            &database,
+            origin,
+            &target,
+            &mut route_selector,
+            &untimed_limits(),
+        );
+
+        assert_eq!(routes.is_empty(), false);
+        check_routes(&database, &routes, Some(&target), TargetType::Standard, 3);
+    }
+
+    #[test]
+    fn finds_routes_to_bootstrap_nodes_in_a_large_neighborhood() {
+        let database = make_mesh_database(800, 8, 3);
+        let origin = database.root().public_key();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let routes =
+            search_bootstrap_routes(&database, origin, &mut route_selector, &untimed_limits());
+
+        assert_eq!(routes.is_empty(), false);
+        check_routes(&database, &routes, None, TargetType::Bootstrap, 4);
+    }
+
+    // Depends on the speed of the machine, so it has to be asked for: cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn searches_of_large_neighborhoods_stay_close_to_the_time_budget() {
+        let limit = Duration::from_millis(DEFAULT_SEARCH_TIME_BUDGET_MS * 5);
+        let limits = RouteSearchLimits::new();
+        let mut route_selector = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let indefinite_database = make_mesh_database(500, 10, 5);
+        let targeted_database = make_mesh_database(500, 6, 0);
+        let bootstrap_database = make_mesh_database(800, 8, 3);
+        let target = node_key(1499);
+
+        let indefinite_elapsed = timed(|| {
+            search_indefinite_routes(
+                &indefinite_database,
+                indefinite_database.root().public_key(),
+                &mut route_selector,
+                &limits,
+            )
+        });
+        let targeted_elapsed = timed(|| {
+            search_targeted_routes(
+                &targeted_database,
+                targeted_database.root().public_key(),
+                &target,
+                &mut route_selector,
+                &limits,
+            )
+        });
+        let bootstrap_elapsed = timed(|| {
+            search_bootstrap_routes(
+                &bootstrap_database,
+                bootstrap_database.root().public_key(),
+                &mut route_selector,
+                &limits,
+            )
+        });
+
+        assert_eq!(indefinite_elapsed < limit, true, "{:?}", indefinite_elapsed);
+        assert_eq!(targeted_elapsed < limit, true, "{:?}", targeted_elapsed);
+        assert_eq!(bootstrap_elapsed < limit, true, "{:?}", bootstrap_elapsed);
+    }
+
+    fn untimed_limits() -> RouteSearchLimits {
+        RouteSearchLimits {
+            time_budget: Duration::from_secs(3600),
+            ..RouteSearchLimits::new()
+        }
+    }
+
+    fn search_indefinite_routes<'a>(
+        database: &'a NeighborhoodDatabase,
+        origin: &'a Key,
+        route_selector: &mut RouteSelector,
+        limits: &RouteSearchLimits,
+    ) -> Vec<Vec<&'a Key>> {
+        complete_routes(
+            database,
+            route_selector,
+            origin,
+            None,
+            TargetType::Standard,
+            HopRange::at_least(6),
+            &RouteConstraint::new(),
+            limits,
+        )
+    }
+
+    fn search_targeted_routes<'a>(
+        database: &'a NeighborhoodDatabase,
+        origin: &'a Key,
+        target: &'a Key,
+        route_selector: &mut RouteSelector,
+        limits: &RouteSearchLimits,
+    ) -> Vec<Vec<&'a Key>> {
+        complete_routes(
+            database,
+            route_selector,
+            origin,
+            Some(target),
+            TargetType::Standard,
+            HopRange::at_least(3),
+            &RouteConstraint::new(),
+            limits,
+        )
+    }
+
+    fn search_bootstrap_routes<'a>(
+        database: &'a NeighborhoodDatabase,
+        origin: &'a Key,
+        route_selector: &mut RouteSelector,
+        limits: &RouteSearchLimits,
+    ) -> Vec<Vec<&'a Key>> {
+        complete_routes(
+            database,
+            route_selector,
+            origin,
+            None,
+            TargetType::Bootstrap,
+            HopRange::at_least(4),
+            &RouteConstraint::new(),
+            limits,
+        )
+    }
+
+    fn node_key(n: u16) -> Key {
+        make_node_record(n, true, false).public_key().clone()
+    }
+
+    fn dual_edge(database: &mut NeighborhoodDatabase, a: &Key, b: &Key) {
+        database.add_neighbor(a, b).unwrap();
+        database.add_neighbor(b, a).unwrap();
+    }
+
+    fn make_database(node_count: u16, bootstrap_count: u16) -> NeighborhoodDatabase {
+        let root = make_node_record(1000, true, false);
+        let mut database = NeighborhoodDatabase::new(
+            root.public_key(),
+            root.node_addr_opt().as_ref().unwrap(),
+            false,
+            cryptde(),
+        );
+        (1..node_count).for_each(|n| {
+            let is_bootstrap_node = n >= (node_count - bootstrap_count);
+            database
+                .add_node(&make_node_record(1000 + n, true, is_bootstrap_node))
+                .unwrap();
+        });
+        database
+    }
+
+    fn make_fork_database() -> NeighborhoodDatabase {
+        let mut database = make_database(4, 0);
+        dual_edge(&mut database, &node_key(1000), &node_key(1001));
+        dual_edge(&mut database, &node_key(1001), &node_key(1002));
+        dual_edge(&mut database, &node_key(1001), &node_key(1003));
+        database
+    }
+
+    // Nodes 1000 through 1000 + node_count - 1 in a line, with 1000 as the root
+    fn make_line_database(node_count: u16) -> NeighborhoodDatabase {
+        let mut database = make_database(node_count, 0);
+        (1..node_count)
+            .for_each(|n| dual_edge(&mut database, &node_key(999 + n), &node_key(1000 + n)));
+        database
+    }
+
+    // Nodes 1000 through 1000 + node_count - 1, each connected to about degree others at random, with the
+    // last bootstrap_count of them bootstrap Nodes and 1000 as the root. A line runs through all of them so
+    // that the Neighborhood is connected.
+    fn make_mesh_database(
+        node_count: u16,
+        degree: u16,
+        bootstrap_count: u16,
+    ) -> NeighborhoodDatabase {
+        let mut database = make_database(node_count, bootstrap_count);
+        let mut rng = StdRng::from_seed([42; 32]);
+        (1..node_count)
+            .for_each(|n| dual_edge(&mut database, &node_key(999 + n), &node_key(1000 + n)));
+        (0..node_count).for_each(|n| {
+            (0..(degree / 2)).for_each(|_| {
+                let other = rng.gen_range(0, node_count);
+                if other != n {
+                    dual_edge(&mut database, &node_key(1000 + n), &node_key(1000 + other))
+                }
+            })
+        });
+        database
+    }
+
+    fn timed<T, F: FnOnce() -> T>(f: F) -> Duration {
+        let begin = Instant::now();
+        f();
+        Instant::now().duration_since(begin)
+    }
+
+    fn check_routes(
+        database: &NeighborhoodDatabase,
+        routes: &Vec<Vec<&Key>>,
+        target: Option<&Key>,
+        target_type: TargetType,
+        minimum_hop_count: usize,
+    ) {
+        routes.iter().for_each(|route| {
+            assert_eq!(route[0], database.root().public_key());
+            assert_eq!(route.len() > minimum_hop_count, true, "{:?}", route);
+            assert_eq!(vec_to_set(route.clone()).len(), route.len(), "{:?}", route);
+            route.windows(2).for_each(|pair| {
+                assert_eq!(database.has_neighbor(pair[0], pair[1]), true, "{:?}", route)
+            });
+            route[..(route.len() - 1)].iter().for_each(|key| {
+                assert_eq!(
+                    database.node_by_key(key).unwrap().is_bootstrap_node(),
+                    false,
+                    "{:?}",
+                    route
+                )
+            });
+            let last_node_ref = database.node_by_key(route.last().unwrap()).unwrap();
+            assert_eq!(
+                node_qualifies(last_node_ref, target, target_type, &RouteConstraint::new()),
+                true,
+                "{:?}",
+                route
+            );
+        });
+    }
+}
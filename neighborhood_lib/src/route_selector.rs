@@ -0,0 +1,271 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use neighborhood_database::NeighborhoodDatabase;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use sub_lib::cryptde::Key;
+use sub_lib::neighborhood::subnet_of;
+
+// How many of the relays in the most recently chosen routes count against new routes
+pub const RECENT_RELAY_MEMORY: usize = 32;
+// A route's weight is divided by (1 + this) for every time one of its relays appears in recent memory
+const RECENT_RELAY_PENALTY: f64 = 1.0;
+// ...and by (1 + this) for every pair of its Nodes that share a /24 (IPv4) or /64 (IPv6) subnet
+const SHARED_SUBNET_PENALTY: f64 = 3.0;
+// ...and by (1 + latency / this) for the measured round-trip time to each of its relays that's a neighbor
+const LATENCY_PENALTY_MS: f64 = 100.0;
+
+pub trait RouteSelector {
+    // candidates will never be empty; returns the index of the chosen candidate
+    fn select(&mut self, database: &NeighborhoodDatabase, candidates: &[Vec<Key>]) -> usize;
+    // Like select, but the chosen candidate's relays don't count against later routes
+    fn preview(&mut self, database: &NeighborhoodDatabase, candidates: &[Vec<Key>]) -> usize;
+    // Puts keys in the order in which a route search should try them
+    fn shuffle(&mut self, keys: &mut [&Key]);
+}
+
+// Chooses among qualifying routes at random, favoring routes whose relays haven't been used lately and whose
+// Nodes are spread across different subnets, so that the traffic from one Node doesn't all pass through the same
+// few relays.
+pub struct RouteSelectorReal {
+    rng: StdRng,
+    recently_used: VecDeque<Key>,
+}
+
+impl RouteSelector for RouteSelectorReal {
+    fn select(&mut self, database: &NeighborhoodDatabase, candidates: &[Vec<Key>]) -> usize {
+        let chosen = self.preview(database, candidates);
+        self.remember(database, &candidates[chosen]);
+        chosen
+    }
+
+    fn preview(&mut self, database: &NeighborhoodDatabase, candidates: &[Vec<Key>]) -> usize {
+        let weights: Vec<f64> = candidates
+            .iter()
+            .map(|candidate| self.weight(database, candidate))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let mut point = self.rng.gen_range(0.0, total);
+        weights
+            .iter()
+            .position(|weight| {
+                if point < *weight {
+                    true
+                } else {
+                    point -= *weight;
+                    false
+                }
+            })
+            .unwrap_or(candidates.len() - 1)
+    }
+
+    fn shuffle(&mut self, keys: &mut [&Key]) {
+        self.rng.shuffle(keys);
+    }
+}
+
+impl RouteSelectorReal {
+    // Production code uses StdRng::from_entropy(); tests use StdRng::from_seed() so that choices are repeatable
+    pub fn new(rng: StdRng) -> RouteSelectorReal {
+        RouteSelectorReal {
+            rng,
+            recently_used: VecDeque::new(),
+        }
+    }
+
+    fn weight(&self, database: &NeighborhoodDatabase, candidate: &[Key]) -> f64 {
+        let reuse_count: usize = RouteSelectorReal::relays(database, candidate)
+            .map(|relay| {
+                self.recently_used
+                    .iter()
+                    .filter(|used| *used == relay)
+                    .count()
+            })
+            .sum();
+        let subnets: Vec<IpAddr> = candidate
+            .iter()
+            .filter_map(|key| database.node_by_key(key))
+            .filter_map(|node| node.node_addr_opt())
+            .map(|node_addr| subnet_of(node_addr.ip_addr()))
+            .collect();
+        let shared_subnet_count: usize = (0..subnets.len())
+            .map(|i| {
+                subnets[(i + 1)..]
+                    .iter()
+                    .filter(|subnet| **subnet == subnets[i])
+                    .count()
+            })
+            .sum();
+        let latency_ms: f64 = RouteSelectorReal::relays(database, candidate)
+            .filter_map(|relay| database.node_by_key(relay))
+            .filter_map(|node| node.latency_opt())
+            .map(|latency| latency.as_secs() as f64 * 1000.0 + latency.subsec_millis() as f64)
+            .sum();
+        1.0 / ((1.0 + RECENT_RELAY_PENALTY * reuse_count as f64)
+            * (1.0 + SHARED_SUBNET_PENALTY * shared_subnet_count as f64)
+            * (1.0 + latency_ms / LATENCY_PENALTY_MS))
+    }
+
+    fn remember(&mut self, database: &NeighborhoodDatabase, chosen: &[Key]) {
+        let relays: Vec<Key> = RouteSelectorReal::relays(database, chosen)
+            .cloned()
+            .collect();
+        self.recently_used.extend(relays);
+        while self.recently_used.len() > RECENT_RELAY_MEMORY {
+            self.recently_used.pop_front();
+        }
+    }
+
+    // Every Node in a route except this one
+    fn relays<'a>(
+        database: &'a NeighborhoodDatabase,
+        route: &'a [Key],
+    ) -> impl Iterator<Item = &'a Key> {
+        let root_key = database.root().public_key();
+        route.iter().filter(move |key| *key != root_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neighborhood_test_utils::make_node_record;
+    use rand::SeedableRng;
+    use std::time::Duration;
+    use test_utils::test_utils::cryptde;
+
+    fn make_database(ns: Vec<u16>) -> NeighborhoodDatabase {
+        let root = make_node_record(9999, true, false);
+        let mut database = NeighborhoodDatabase::new(
+            root.public_key(),
+            root.node_addr_opt().as_ref().unwrap(),
+            false,
+            cryptde(),
+        );
+        ns.into_iter().for_each(|n| {
+            database
+                .add_node(&make_node_record(n, true, false))
+                .unwrap();
+        });
+        database
+    }
+
+    fn route(database: &NeighborhoodDatabase, ns: Vec<u16>) -> Vec<Key> {
+        let mut result = vec![database.root().public_key().clone()];
+        result.extend(
+            ns.into_iter()
+                .map(|n| make_node_record(n, true, false).public_key().clone()),
+        );
+        result
+    }
+
+    #[test]
+    fn routes_through_shared_subnets_weigh_less() {
+        let database = make_database(vec![1234, 1235, 2345]);
+        let subject = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let diverse_weight = subject.weight(&database, &route(&database, vec![1234, 2345]));
+        let shared_weight = subject.weight(&database, &route(&database, vec![1234, 1235]));
+
+        assert_eq!(diverse_weight, 1.0);
+        assert_eq!(shared_weight, 1.0 / (1.0 + SHARED_SUBNET_PENALTY));
+    }
+
+    #[test]
+    fn routes_through_slow_neighbors_weigh_less() {
+        let mut database = make_database(vec![1234, 2345]);
+        database
+            .node_by_key_mut(make_node_record(1234, true, false).public_key())
+            .unwrap()
+            .add_latency_sample(Duration::from_millis(300));
+        let subject = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+
+        let slow_weight = subject.weight(&database, &route(&database, vec![1234]));
+        let unmeasured_weight = subject.weight(&database, &route(&database, vec![2345]));
+
+        assert_eq!(slow_weight, 1.0 / (1.0 + 300.0 / LATENCY_PENALTY_MS));
+        assert_eq!(unmeasured_weight, 1.0);
+    }
+
+    #[test]
+    fn recently_used_relays_weigh_less_until_they_are_forgotten() {
+        let database = make_database(vec![1234, 2345, 3456]);
+        let mut subject = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let used_route = route(&database, vec![1234, 2345]);
+
+        subject.select(&database, &[used_route.clone()]);
+        let weight_after_use = subject.weight(&database, &used_route);
+        let other_weight = subject.weight(&database, &route(&database, vec![3456]));
+        (0..RECENT_RELAY_MEMORY).for_each(|_| {
+            subject.select(&database, &[route(&database, vec![3456])]);
+        });
+        let weight_after_forgetting = subject.weight(&database, &used_route);
+
+        assert_eq!(weight_after_use, 1.0 / (1.0 + 2.0 * RECENT_RELAY_PENALTY));
+        assert_eq!(other_weight, 1.0);
+        assert_eq!(weight_after_forgetting, 1.0);
+    }
+
+    #[test]
+    fn previewed_routes_are_weighed_like_selected_ones_but_not_remembered() {
+        let database = make_database(vec![1234, 2345, 3456]);
+        let mut subject = RouteSelectorReal::new(StdRng::from_seed([0; 32]));
+        let used_route = route(&database, vec![1234, 2345]);
+        let other_route = route(&database, vec![3456]);
+        subject.select(&database, &[used_route.clone()]);
+
+        let previewed_count = (0..100)
+            .filter(|_| subject.preview(&database, &[used_route.clone(), other_route.clone()]) == 0)
+            .count();
+
+        assert_eq!(previewed_count < 50, true, "{}", previewed_count);
+        assert_eq!(subject.weight(&database, &other_route), 1.0);
+        assert_eq!(
+            subject.weight(&database, &used_route),
+            1.0 / (1.0 + 2.0 * RECENT_RELAY_PENALTY)
+        );
+    }
+
+    #[test]
+    fn selection_is_random_but_repeatable_with_the_same_seed() {
+        let database = make_database(vec![1234, 2345, 3456, 4567]);
+        let candidates = vec![
+            route(&database, vec![1234]),
+            route(&database, vec![2345]),
+            route(&database, vec![3456]),
+            route(&database, vec![4567]),
+        ];
+        let choices = |seed: u8| {
+            let mut subject = RouteSelectorReal::new(StdRng::from_seed([seed; 32]));
+            (0..20)
+                .map(|_| subject.select(&database, &candidates))
+                .collect::<Vec<usize>>()
+        };
+
+        let first_choices = choices(1);
+        let second_choices = choices(1);
+
+        assert_eq!(first_choices, second_choices);
+        (0..candidates.len())
+            .for_each(|index| assert_eq!(first_choices.contains(&index), true, "{}", index));
+    }
+
+    #[test]
+    fn diverse_and_unused_routes_are_chosen_most_often() {
+        let database = make_database(vec![1234, 1235, 2345]);
+        let crowded_route = route(&database, vec![1234, 1235]);
+        let diverse_route = route(&database, vec![1234, 2345]);
+        let mut crowded_count = 0;
+
+        (0u8..100).for_each(|seed| {
+            let mut subject = RouteSelectorReal::new(StdRng::from_seed([seed; 32]));
+            if subject.select(&database, &[crowded_route.clone(), diverse_route.clone()]) == 0 {
+                crowded_count += 1;
+            }
+        });
+
+        assert_eq!(crowded_count < 40, true, "{}", crowded_count);
+        assert_eq!(crowded_count > 0, true, "{}", crowded_count);
+    }
+}
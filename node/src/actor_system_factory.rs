@@ -12,7 +12,6 @@ use hopper_lib::hopper::Hopper;
 use neighborhood_lib::neighborhood::Neighborhood;
 use proxy_client_lib::proxy_client::ProxyClient;
 use proxy_server_lib::proxy_server::ProxyServer;
-use std::net::SocketAddr;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 use std::thread;
@@ -20,16 +19,22 @@ use stream_handler_pool::StreamHandlerPool;
 use stream_handler_pool::StreamHandlerPoolSubs;
 use stream_messages::PoolBindMessage;
 use sub_lib::cryptde::CryptDE;
-use sub_lib::cryptde_null::CryptDENull;
 use sub_lib::dispatcher::DispatcherSubs;
+use sub_lib::dns_server::DnsServer;
 use sub_lib::hopper::HopperSubs;
 use sub_lib::neighborhood::BootstrapNeighborhoodNowMessage;
 use sub_lib::neighborhood::NeighborhoodConfig;
 use sub_lib::neighborhood::NeighborhoodSubs;
+use sub_lib::neighborhood::RoutePolicies;
+use sub_lib::neighborhood::RoutePolicy;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::peer_actors::PeerActors;
+use sub_lib::proxy_client::DnsCacheConfig;
+use sub_lib::proxy_client::ExitFilter;
 use sub_lib::proxy_client::ProxyClientSubs;
+use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::proxy_server::ProxyServerSubs;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
 
 pub trait ActorSystemFactory: Send {
     fn make_and_start_actors(
@@ -47,8 +52,12 @@ impl ActorSystemFactory for ActorSystemFactoryReal {
         config: BootstrapperConfig,
         actor_factory: Box<ActorFactory>,
     ) -> StreamHandlerPoolSubs {
-        let cryptde: &'static CryptDENull =
-            unsafe { bootstrapper::CRYPT_DE_OPT.as_ref().expect("Internal error") };
+        let cryptde: &'static CryptDE = unsafe {
+            bootstrapper::CRYPT_DE_OPT
+                .as_ref()
+                .expect("Internal error")
+                .as_ref()
+        };
         let (tx, rx) = mpsc::channel();
 
         // TODO: this thread::spawn goes away with actix 0.7
@@ -59,7 +68,11 @@ impl ActorSystemFactory for ActorSystemFactoryReal {
 
             // TODO: System::new and system.run() are handled by actix::run in actix 0.7+ and might not live here
             //run the actor system
-            system.run()
+            let exit_code = system.run();
+
+            // The actor system only stops when an actor is done with it, e.g. the Neighborhood after saving its
+            // database on a termination signal; that's the end of the Node.
+            ::std::process::exit(exit_code)
         });
 
         rx.recv().expect("Internal error: actor-system init thread died before initializing StreamHandlerPool subscribers")
@@ -75,10 +88,25 @@ impl ActorSystemFactoryReal {
     ) {
         // make all the actors
         let (dispatcher_subs, pool_bind_sub) = actor_factory.make_and_start_dispatcher();
-        let proxy_server_subs = actor_factory
-            .make_and_start_proxy_server(cryptde, config.neighborhood_config.is_decentralized());
-        let proxy_client_subs =
-            actor_factory.make_and_start_proxy_client(cryptde, config.dns_servers);
+        // Without a Network to route through, every route has to stay inside this Node
+        let route_policies = if config.neighborhood_config.is_decentralized() {
+            config.route_policies
+        } else {
+            RoutePolicies::new(RoutePolicy::zero_hop())
+        };
+        let proxy_server_subs = actor_factory.make_and_start_proxy_server(
+            cryptde,
+            route_policies,
+            config.proxied_ports,
+            config.socks_port_opt,
+        );
+        let proxy_client_subs = actor_factory.make_and_start_proxy_client(
+            cryptde,
+            config.dns_servers,
+            config.exit_filter,
+            config.dns_cache_config,
+            config.connection_attempt_config,
+        );
         let hopper_subs = actor_factory
             .make_and_start_hopper(cryptde, config.neighborhood_config.is_bootstrap_node);
         let neighborhood_subs =
@@ -162,7 +190,9 @@ pub trait ActorFactory: Send {
     fn make_and_start_proxy_server(
         &self,
         cryptde: &'static CryptDE,
-        is_decentralized: bool,
+        route_policies: RoutePolicies,
+        proxied_ports: Vec<(u16, ProxyProtocol)>,
+        socks_port_opt: Option<u16>,
     ) -> ProxyServerSubs;
     fn make_and_start_hopper(
         &self,
@@ -181,7 +211,10 @@ pub trait ActorFactory: Send {
     fn make_and_start_proxy_client(
         &self,
         cryptde: &'static CryptDE,
-        dns_servers: Vec<SocketAddr>,
+        dns_servers: Vec<DnsServer>,
+        exit_filter: ExitFilter,
+        dns_cache_config: DnsCacheConfig,
+        connection_attempt_config: ConnectionAttemptConfig,
     ) -> ProxyClientSubs;
 }
 
@@ -200,9 +233,12 @@ impl ActorFactory for ActorFactoryReal {
     fn make_and_start_proxy_server(
         &self,
         cryptde: &'static CryptDE,
-        is_decentralized: bool,
+        route_policies: RoutePolicies,
+        proxied_ports: Vec<(u16, ProxyProtocol)>,
+        socks_port_opt: Option<u16>,
     ) -> ProxyServerSubs {
-        let proxy_server = ProxyServer::new(cryptde, is_decentralized);
+        let proxy_server =
+            ProxyServer::new(cryptde, route_policies, &proxied_ports, socks_port_opt);
         let addr: Addr<Syn, ProxyServer> = proxy_server.start();
         ProxyServer::make_subs_from(&addr)
     }
@@ -239,9 +275,18 @@ impl ActorFactory for ActorFactoryReal {
     fn make_and_start_proxy_client(
         &self,
         cryptde: &'static CryptDE,
-        dns_servers: Vec<SocketAddr>,
+        dns_servers: Vec<DnsServer>,
+        exit_filter: ExitFilter,
+        dns_cache_config: DnsCacheConfig,
+        connection_attempt_config: ConnectionAttemptConfig,
     ) -> ProxyClientSubs {
-        let proxy_client = ProxyClient::new(cryptde, dns_servers);
+        let proxy_client = ProxyClient::new(
+            cryptde,
+            dns_servers,
+            exit_filter,
+            dns_cache_config,
+            connection_attempt_config,
+        );
         let addr: Addr<Syn, ProxyClient> = proxy_client.start();
         ProxyClient::make_subs_from(&addr)
     }
@@ -262,15 +307,21 @@ mod tests {
     use stream_messages::AddStreamMsg;
     use stream_messages::RemoveStreamMsg;
     use sub_lib::crash_point::CrashPoint;
+    use sub_lib::cryptde::Key;
     use sub_lib::cryptde::PlainData;
+    use sub_lib::cryptde_null::CryptDENull;
     use sub_lib::dispatcher::InboundClientData;
     use sub_lib::hopper::ExpiredCoresPackage;
     use sub_lib::hopper::ExpiredCoresPackagePackage;
     use sub_lib::hopper::IncipientCoresPackage;
+    use sub_lib::neighborhood::AdmissionLimits;
     use sub_lib::neighborhood::DispatcherNodeQueryMessage;
+    use sub_lib::neighborhood::ExitPolicy;
+    use sub_lib::neighborhood::KeepaliveConfig;
     use sub_lib::neighborhood::NodeQueryMessage;
     use sub_lib::neighborhood::RemoveNeighborMessage;
     use sub_lib::neighborhood::RouteQueryMessage;
+    use sub_lib::node_addr::NodeAddr;
     use sub_lib::stream_handler_pool::DispatcherNodeQueryResponse;
     use sub_lib::stream_handler_pool::TransmitDataMsg;
     use test_utils::recorder::Recorder;
@@ -302,13 +353,15 @@ mod tests {
         fn make_and_start_proxy_server(
             &self,
             cryptde: &'a CryptDE,
-            is_decentralized: bool,
+            route_policies: RoutePolicies,
+            proxied_ports: Vec<(u16, ProxyProtocol)>,
+            socks_port_opt: Option<u16>,
         ) -> ProxyServerSubs {
             self.parameters
                 .proxy_server_params
                 .lock()
                 .unwrap()
-                .get_or_insert((cryptde, is_decentralized));
+                .get_or_insert((cryptde, route_policies, proxied_ports, socks_port_opt));
             let addr: Addr<Syn, Recorder> = ActorFactoryMock::start_recorder(&self.proxy_server);
             ProxyServerSubs {
                 bind: addr.clone().recipient::<BindMessage>(),
@@ -375,13 +428,22 @@ mod tests {
         fn make_and_start_proxy_client(
             &self,
             cryptde: &'a CryptDE,
-            dns_servers: Vec<SocketAddr>,
+            dns_servers: Vec<DnsServer>,
+            exit_filter: ExitFilter,
+            dns_cache_config: DnsCacheConfig,
+            connection_attempt_config: ConnectionAttemptConfig,
         ) -> ProxyClientSubs {
             self.parameters
                 .proxy_client_params
                 .lock()
                 .unwrap()
-                .get_or_insert((cryptde, dns_servers));
+                .get_or_insert((
+                    cryptde,
+                    dns_servers,
+                    exit_filter,
+                    dns_cache_config,
+                    connection_attempt_config,
+                ));
             let addr: Addr<Syn, Recorder> = ActorFactoryMock::start_recorder(&self.proxy_client);
             ProxyClientSubs {
                 bind: addr.clone().recipient::<BindMessage>(),
@@ -401,8 +463,27 @@ mod tests {
 
     #[derive(Clone)]
     struct Parameters<'a> {
-        proxy_client_params: Arc<Mutex<Option<(&'a CryptDE, Vec<SocketAddr>)>>>,
-        proxy_server_params: Arc<Mutex<Option<(&'a CryptDE, bool)>>>,
+        proxy_client_params: Arc<
+            Mutex<
+                Option<(
+                    &'a CryptDE,
+                    Vec<DnsServer>,
+                    ExitFilter,
+                    DnsCacheConfig,
+                    ConnectionAttemptConfig,
+                )>,
+            >,
+        >,
+        proxy_server_params: Arc<
+            Mutex<
+                Option<(
+                    &'a CryptDE,
+                    RoutePolicies,
+                    Vec<(u16, ProxyProtocol)>,
+                    Option<u16>,
+                )>,
+            >,
+        >,
         hopper_params: Arc<Mutex<Option<(&'a CryptDE, bool)>>>,
         neighborhood_params: Arc<Mutex<Option<(&'a CryptDE, NeighborhoodConfig)>>>,
     }
@@ -475,12 +556,25 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
             clandestine_discriminator_factories: Vec::new(),
+            cryptde_null: false,
+            data_directory_opt: None,
+            proxied_ports: vec![],
+            socks_port_opt: None,
+            route_policies: RoutePolicies::new(RoutePolicy::at_least(2)),
+            exit_filter: ExitFilter::new(ExitPolicy::allow_all()),
+            dns_cache_config: DnsCacheConfig::new(),
+            connection_attempt_config: ConnectionAttemptConfig::new(),
         };
         let subject = ActorSystemFactoryReal {};
         unsafe {
-            CRYPT_DE_OPT = Some(CryptDENull::new());
+            CRYPT_DE_OPT = Some(Box::new(CryptDENull::new()));
         }
 
         subject.make_and_start_actors(config, Box::new(actor_factory));
@@ -509,8 +603,33 @@ mod tests {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
             clandestine_discriminator_factories: Vec::new(),
+            cryptde_null: false,
+            data_directory_opt: None,
+            proxied_ports: vec![(8080, ProxyProtocol::HTTP)],
+            socks_port_opt: Some(1080),
+            route_policies: RoutePolicies::new(RoutePolicy::at_least(2)),
+            exit_filter: ExitFilter {
+                exit_policy: ExitPolicy::allow_all(),
+                blocked_ports: vec![25, 22],
+                blocked_hostnames: vec![String::from("*.corp.example.com")],
+                blocked_ranges: vec![],
+            },
+            dns_cache_config: DnsCacheConfig {
+                min_ttl: Duration::from_secs(5),
+                max_ttl: Duration::from_secs(600),
+                negative_ttl: Duration::from_secs(0),
+            },
+            connection_attempt_config: ConnectionAttemptConfig {
+                attempt_delay: Duration::from_millis(100),
+                attempt_timeout: Duration::from_millis(3000),
+            },
         };
         let (tx, rx) = mpsc::channel();
         let system = System::new("SubstratumNode");
@@ -532,13 +651,22 @@ mod tests {
         let (cryptde, is_bootstrap_node) = Parameters::get(parameters.hopper_params);
         check_cryptde(cryptde);
         assert_eq!(is_bootstrap_node, false);
-        let (cryptde, dns_servers) = Parameters::get(parameters.proxy_client_params);
+        let (cryptde, dns_servers, exit_filter, dns_cache_config, connection_attempt_config) =
+            Parameters::get(parameters.proxy_client_params);
         check_cryptde(cryptde);
         assert_eq!(dns_servers, config.dns_servers);
-        let (actual_cryptde, actual_is_decentralized) =
+        assert_eq!(exit_filter, config.exit_filter);
+        assert_eq!(dns_cache_config, config.dns_cache_config);
+        assert_eq!(connection_attempt_config, config.connection_attempt_config);
+        let (actual_cryptde, actual_route_policies, actual_proxied_ports, actual_socks_port_opt) =
             Parameters::get(parameters.proxy_server_params);
         check_cryptde(actual_cryptde);
-        assert_eq!(actual_is_decentralized, false);
+        assert_eq!(
+            actual_route_policies,
+            RoutePolicies::new(RoutePolicy::zero_hop())
+        );
+        assert_eq!(actual_proxied_ports, vec![(8080, ProxyProtocol::HTTP)]);
+        assert_eq!(actual_socks_port_opt, Some(1080));
         let (cryptde, neighborhood_config) = Parameters::get(parameters.neighborhood_params);
         check_cryptde(cryptde);
         assert_eq!(neighborhood_config, config.neighborhood_config);
@@ -546,6 +674,38 @@ mod tests {
         // more...more...what? How to check contents of _stream_handler_pool_subs?
     }
 
+    #[test]
+    fn prepare_initial_messages_gives_decentralized_proxy_server_the_configured_route_policies() {
+        let actor_factory = ActorFactoryMock::new();
+        let parameters = actor_factory.make_parameters();
+        let mut route_policies = RoutePolicies::new(RoutePolicy::at_least(3));
+        route_policies
+            .hostname_policies
+            .push((String::from("*.booga.com"), RoutePolicy::at_least(4)));
+        let mut config = BootstrapperConfig::new();
+        config.neighborhood_config.neighbor_configs = vec![(
+            Key::new(&b"key"[..]),
+            NodeAddr::new(&IpAddr::V4(Ipv4Addr::new(2, 3, 4, 5)), &vec![2345]),
+        )];
+        config.neighborhood_config.local_ip_addr = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        config.neighborhood_config.clandestine_port_list = vec![1234];
+        config.route_policies = route_policies.clone();
+        let (tx, _rx) = mpsc::channel();
+        let system = System::new("SubstratumNode");
+
+        ActorSystemFactoryReal::prepare_initial_messages(
+            cryptde(),
+            config,
+            Box::new(actor_factory),
+            tx,
+        );
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+        let (_, actual_route_policies, _, _) = Parameters::get(parameters.proxy_server_params);
+        assert_eq!(actual_route_policies, route_policies);
+    }
+
     fn check_bind_message(recording: &Arc<Mutex<Recording>>) {
         let bind_message = Recording::get::<BindMessage>(recording, 0);
         let _peer_actors = bind_message.peer_actors;
@@ -9,35 +9,60 @@ use discriminator::DiscriminatorFactory;
 use listener_handler::ListenerHandler;
 use listener_handler::ListenerHandlerFactory;
 use listener_handler::ListenerHandlerFactoryReal;
+use std::env;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use std::vec::Vec;
 use sub_lib::crash_point::CrashPoint;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::Key;
 use sub_lib::cryptde_null::CryptDENull;
+use sub_lib::cryptde_real::CryptDEReal;
+use sub_lib::dns_server::DnsServer;
+use sub_lib::keystore::Keystore;
+use sub_lib::keystore::PASSPHRASE_ENV_VAR;
 use sub_lib::logger::Logger;
 use sub_lib::main_tools::StdStreams;
 use sub_lib::neighborhood::sentinel_ip_addr;
+use sub_lib::neighborhood::AdmissionLimits;
+use sub_lib::neighborhood::ExitPolicy;
+use sub_lib::neighborhood::HopRange;
+use sub_lib::neighborhood::KeepaliveConfig;
 use sub_lib::neighborhood::NeighborhoodConfig;
+use sub_lib::neighborhood::RoutePolicies;
+use sub_lib::neighborhood::RoutePolicy;
 use sub_lib::node_addr::NodeAddr;
 use sub_lib::parameter_finder::ParameterFinder;
+use sub_lib::proxy_client::DnsCacheConfig;
+use sub_lib::proxy_client::ExitFilter;
+use sub_lib::proxy_client::IpRange;
+use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::socket_server::SocketServer;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
 use tokio::prelude::stream::futures_unordered::FuturesUnordered;
 use tokio::prelude::Async;
 use tokio::prelude::Future;
 use tokio::prelude::Stream;
 
-pub static mut CRYPT_DE_OPT: Option<CryptDENull> = None;
+pub static mut CRYPT_DE_OPT: Option<Box<CryptDE>> = None;
 
 #[derive(Clone)]
 pub struct BootstrapperConfig {
-    pub dns_servers: Vec<SocketAddr>,
+    pub dns_servers: Vec<DnsServer>,
     pub neighborhood_config: NeighborhoodConfig,
     pub crash_point: CrashPoint,
     pub clandestine_discriminator_factories: Vec<Box<DiscriminatorFactory>>,
+    pub cryptde_null: bool,
+    pub data_directory_opt: Option<PathBuf>,
+    pub proxied_ports: Vec<(u16, ProxyProtocol)>,
+    pub socks_port_opt: Option<u16>,
+    pub route_policies: RoutePolicies,
+    pub exit_filter: ExitFilter,
+    pub dns_cache_config: DnsCacheConfig,
+    pub connection_attempt_config: ConnectionAttemptConfig,
 }
 
 impl BootstrapperConfig {
@@ -50,9 +75,22 @@ impl BootstrapperConfig {
                 is_bootstrap_node: false,
                 local_ip_addr: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
                 clandestine_port_list: vec![],
+                exit_policy: ExitPolicy::allow_all(),
+                data_directory_opt: None,
+                keepalive_config: KeepaliveConfig::new(),
+                admission_limits: AdmissionLimits::new(),
+                introspection_port_opt: None,
             },
             crash_point: CrashPoint::None,
             clandestine_discriminator_factories: vec![],
+            cryptde_null: false,
+            data_directory_opt: None,
+            proxied_ports: vec![],
+            socks_port_opt: None,
+            route_policies: RoutePolicies::unconfigured(),
+            exit_filter: ExitFilter::new(ExitPolicy::allow_all()),
+            dns_cache_config: DnsCacheConfig::new(),
+            connection_attempt_config: ConnectionAttemptConfig::new(),
         }
     }
 }
@@ -87,10 +125,12 @@ impl SocketServer for Bootstrapper {
     fn initialize_as_privileged(&mut self, args: &Vec<String>, streams: &mut StdStreams) {
         let mut configuration = Configuration::new();
         configuration.establish(args);
-        let cryptde_ref = Bootstrapper::initialize_cryptde();
         let mut config = BootstrapperConfig::new();
         Bootstrapper::parse_args(args, &mut config);
+        let cryptde_ref = Bootstrapper::initialize_cryptde(&config);
         Bootstrapper::add_clandestine_port_info(&configuration, &mut config);
+        config.proxied_ports = configuration.proxied_ports.clone();
+        config.socks_port_opt = configuration.socks_port_opt;
         Bootstrapper::report_local_descriptor(
             cryptde_ref,
             config.neighborhood_config.local_ip_addr,
@@ -150,12 +190,27 @@ impl Bootstrapper {
         let local_ip_addr = Bootstrapper::parse_ip(&finder);
         config.crash_point = Bootstrapper::parse_crash_point(&finder);
         config.dns_servers = Bootstrapper::parse_dns_servers(&finder);
+        config.dns_cache_config = Bootstrapper::parse_dns_cache_config(&finder);
         config.neighborhood_config.neighbor_configs =
             Bootstrapper::parse_neighbor_configs(&finder, "--neighbor");
         config.neighborhood_config.bootstrap_configs =
             Bootstrapper::parse_neighbor_configs(&finder, "--bootstrap_from");
         config.neighborhood_config.is_bootstrap_node = Bootstrapper::parse_node_type(&finder);
         config.neighborhood_config.local_ip_addr = local_ip_addr;
+        config.neighborhood_config.exit_policy = Bootstrapper::parse_exit_policy(&finder);
+        config.exit_filter =
+            Bootstrapper::parse_exit_filter(&finder, &config.neighborhood_config.exit_policy);
+        config.connection_attempt_config = Bootstrapper::parse_connection_attempt_config(&finder);
+        config.neighborhood_config.keepalive_config = Bootstrapper::parse_keepalive_config(&finder);
+        config.neighborhood_config.admission_limits = Bootstrapper::parse_admission_limits(&finder);
+        config.neighborhood_config.introspection_port_opt =
+            Bootstrapper::parse_introspection_port(&finder);
+        config.route_policies = Bootstrapper::parse_route_policies(&finder);
+        config.cryptde_null = Bootstrapper::parse_cryptde_type(&finder);
+        config.data_directory_opt = finder
+            .find_value_for("--data_directory", "--data_directory <directory>")
+            .map(PathBuf::from);
+        config.neighborhood_config.data_directory_opt = config.data_directory_opt.clone();
     }
 
     fn parse_crash_point(finder: &ParameterFinder) -> CrashPoint {
@@ -186,30 +241,63 @@ impl Bootstrapper {
         }
     }
 
-    fn parse_dns_servers(finder: &ParameterFinder) -> Vec<SocketAddr> {
+    fn parse_dns_servers(finder: &ParameterFinder) -> Vec<DnsServer> {
         let parameter_tag = "--dns_servers";
         let usage =
-            "--dns_servers <servers> where 'servers' is a comma-separated list of IP addresses";
+            "--dns_servers <servers> where 'servers' is a comma-separated list of IP addresses, \
+             each optionally written as tls://<IP address>[:<port>]@<hostname> or \
+             https://<IP address>[:<port>]@<hostname> for an encrypted upstream";
 
         let dns_server_strings: Vec<String> = match finder.find_value_for(parameter_tag, usage) {
             Some(dns_server_string) => dns_server_string
                 .split(",")
                 .map(|s| String::from(s))
                 .collect(),
-            None => panic!(usage),
+            None => panic!("{}", usage),
         };
         dns_server_strings
             .iter()
-            .map(|string| match IpAddr::from_str(string) {
-                Ok(addr) => SocketAddr::new(addr, 53),
-                Err(_) => panic!(
-                    "Invalid IP address for --dns_servers <servers>: '{}'",
-                    string
-                ),
+            .map(|string| match DnsServer::from_str(string) {
+                Ok(dns_server) => dns_server,
+                Err(msg) => panic!("{}", msg),
             })
             .collect()
     }
 
+    // Zero is allowed for all three: no floor, no caching at all, and no caching of "no such host"
+    fn parse_dns_cache_config(finder: &ParameterFinder) -> DnsCacheConfig {
+        let mut dns_cache_config = DnsCacheConfig::new();
+        let parse_secs = |parameter_tag: &str, usage: &str| {
+            finder
+                .find_value_for(parameter_tag, usage)
+                .map(|secs_string| match secs_string.parse::<u64>() {
+                    Ok(secs) => Duration::from_secs(secs),
+                    Err(_) => panic!(
+                        "{} needs a number of seconds, not '{}'",
+                        parameter_tag, secs_string
+                    ),
+                })
+        };
+        if let Some(min_ttl) = parse_secs("--dns_min_ttl", "--dns_min_ttl <seconds>") {
+            dns_cache_config.min_ttl = min_ttl;
+        }
+        if let Some(max_ttl) = parse_secs("--dns_max_ttl", "--dns_max_ttl <seconds>") {
+            dns_cache_config.max_ttl = max_ttl;
+        }
+        if let Some(negative_ttl) = parse_secs("--dns_negative_ttl", "--dns_negative_ttl <seconds>")
+        {
+            dns_cache_config.negative_ttl = negative_ttl;
+        }
+        if dns_cache_config.min_ttl > dns_cache_config.max_ttl {
+            panic!(
+                "--dns_min_ttl ({} seconds) can't be more than --dns_max_ttl ({} seconds)",
+                dns_cache_config.min_ttl.as_secs(),
+                dns_cache_config.max_ttl.as_secs()
+            )
+        }
+        dns_cache_config
+    }
+
     fn parse_node_type(finder: &ParameterFinder) -> bool {
         let usage = "--node_type standard|bootstrap";
         match finder.find_value_for("--node_type", usage) {
@@ -223,6 +311,322 @@ impl Bootstrapper {
         }
     }
 
+    fn parse_exit_policy(finder: &ParameterFinder) -> ExitPolicy {
+        let allowed_ports = match finder.find_value_for("--exit_ports", "--exit_ports <port>,...") {
+            None => vec![],
+            Some(ports_string) => ports_string
+                .split(",")
+                .map(|port_string| match port_string.parse::<u16>() {
+                    Ok(port) if port > 0 => port,
+                    _ => panic!(
+                        "--exit_ports needs a comma-separated list of port numbers, not '{}'",
+                        ports_string
+                    ),
+                })
+                .collect(),
+        };
+        let allowed_hostnames =
+            match finder.find_value_for("--exit_hosts", "--exit_hosts <hostname pattern>,...") {
+                None => vec![],
+                Some(hosts_string) => hosts_string
+                    .split(",")
+                    .map(|host_string| {
+                        if !Bootstrapper::is_hostname_pattern(host_string) {
+                            panic!(
+                                "--exit_hosts needs a comma-separated list of hostnames, each optionally starting with '*.', not '{}'",
+                                hosts_string
+                            )
+                        }
+                        String::from(host_string)
+                    })
+                    .collect(),
+            };
+        ExitPolicy {
+            allowed_ports,
+            allowed_hostnames,
+        }
+    }
+
+    // Whatever is listed is blocked in addition to the defaults, not instead of them
+    fn parse_exit_filter(finder: &ParameterFinder, exit_policy: &ExitPolicy) -> ExitFilter {
+        let mut exit_filter = ExitFilter::new(exit_policy.clone());
+        if let Some(ports_string) =
+            finder.find_value_for("--exit_blocked_ports", "--exit_blocked_ports <port>,...")
+        {
+            ports_string.split(",").for_each(|port_string| {
+                match port_string.parse::<u16>() {
+                    Ok(port) if port > 0 => exit_filter.blocked_ports.push(port),
+                    _ => panic!(
+                        "--exit_blocked_ports needs a comma-separated list of port numbers, not '{}'",
+                        ports_string
+                    ),
+                }
+            })
+        }
+        if let Some(hosts_string) = finder.find_value_for(
+            "--exit_blocked_hosts",
+            "--exit_blocked_hosts <hostname pattern>,...",
+        ) {
+            hosts_string.split(",").for_each(|host_string| {
+                if !Bootstrapper::is_hostname_pattern(host_string) {
+                    panic!(
+                        "--exit_blocked_hosts needs a comma-separated list of hostnames, each optionally starting with '*.', not '{}'",
+                        hosts_string
+                    )
+                }
+                exit_filter.blocked_hostnames.push(String::from(host_string))
+            })
+        }
+        if let Some(ranges_string) = finder.find_value_for(
+            "--exit_blocked_ranges",
+            "--exit_blocked_ranges <IP address>/<prefix length>,...",
+        ) {
+            ranges_string.split(",").for_each(|range_string| {
+                match IpRange::from_str(range_string) {
+                    Ok(range) => exit_filter.blocked_ranges.push(range),
+                    Err(msg) => panic!("--exit_blocked_ranges: {}", msg),
+                }
+            })
+        }
+        exit_filter
+    }
+
+    // A zero delay starts every attempt at once; a zero timeout would give up before any attempt could succeed
+    fn parse_connection_attempt_config(finder: &ParameterFinder) -> ConnectionAttemptConfig {
+        let mut connection_attempt_config = ConnectionAttemptConfig::new();
+        let parse_millis = |parameter_tag: &str, usage: &str| {
+            finder
+                .find_value_for(parameter_tag, usage)
+                .map(|millis_string| match millis_string.parse::<u64>() {
+                    Ok(millis) => Duration::from_millis(millis),
+                    Err(_) => panic!(
+                        "{} needs a number of milliseconds, not '{}'",
+                        parameter_tag, millis_string
+                    ),
+                })
+        };
+        if let Some(attempt_delay) = parse_millis(
+            "--exit_connect_delay",
+            "--exit_connect_delay <milliseconds>",
+        ) {
+            connection_attempt_config.attempt_delay = attempt_delay;
+        }
+        if let Some(attempt_timeout) = parse_millis(
+            "--exit_connect_timeout",
+            "--exit_connect_timeout <milliseconds>",
+        ) {
+            if attempt_timeout == Duration::from_millis(0) {
+                panic!("--exit_connect_timeout can't be zero")
+            }
+            connection_attempt_config.attempt_timeout = attempt_timeout;
+        }
+        connection_attempt_config
+    }
+
+    fn is_hostname_pattern(pattern: &str) -> bool {
+        let name = if pattern.starts_with("*.") {
+            &pattern[2..]
+        } else {
+            pattern
+        };
+        !name.is_empty() && !name.contains('*')
+    }
+
+    // The policy without a hostname pattern is the Node's own; the others are kept in order
+    fn parse_route_policies(finder: &ParameterFinder) -> RoutePolicies {
+        let usage = "--route_policy [<hostname pattern>=]<hops over>[/<hops back>]";
+        let mut default_policy_opt: Option<RoutePolicy> = None;
+        let mut hostname_policies: Vec<(String, RoutePolicy)> = vec![];
+        finder
+            .find_values_for("--route_policy", usage)
+            .into_iter()
+            .for_each(|policy_string| {
+                let (pattern_opt, policy) = match Bootstrapper::parse_route_policy(&policy_string) {
+                    Some(pattern_and_policy) => pattern_and_policy,
+                    None => panic!(
+                        "--route_policy needs [<hostname pattern>=]<hops over>[/<hops back>], where hops are <count>, <minimum>-<maximum>, or <minimum>-, not '{}'",
+                        policy_string
+                    ),
+                };
+                if (policy.over.minimum == 0) != (policy.back.minimum == 0) {
+                    panic!(
+                        "--route_policy can't allow zero hops one way but not the other: '{}'",
+                        policy_string
+                    )
+                }
+                match pattern_opt {
+                    Some(pattern) => hostname_policies.push((pattern, policy)),
+                    None => {
+                        if default_policy_opt.is_some() {
+                            panic!(
+                                "--route_policy can be given without a hostname pattern only once, not again as '{}'",
+                                policy_string
+                            )
+                        }
+                        default_policy_opt = Some(policy);
+                    }
+                }
+            });
+        RoutePolicies {
+            default_policy_opt,
+            hostname_policies,
+        }
+    }
+
+    fn parse_route_policy(policy_string: &str) -> Option<(Option<String>, RoutePolicy)> {
+        let (pattern_opt, hops_string) = match policy_string.find('=') {
+            Some(index) => (Some(&policy_string[..index]), &policy_string[(index + 1)..]),
+            None => (None, policy_string),
+        };
+        if let Some(pattern) = pattern_opt {
+            if !Bootstrapper::is_hostname_pattern(pattern) {
+                return None;
+            }
+        }
+        let mut segments = hops_string.splitn(2, '/');
+        let over = Bootstrapper::parse_hop_range(segments.next()?)?;
+        let back = match segments.next() {
+            Some(back_string) => Bootstrapper::parse_hop_range(back_string)?,
+            None => over,
+        };
+        Some((pattern_opt.map(String::from), RoutePolicy { over, back }))
+    }
+
+    fn parse_hop_range(hops_string: &str) -> Option<HopRange> {
+        let mut bounds = hops_string.splitn(2, '-');
+        let minimum = bounds.next()?.parse::<usize>().ok()?;
+        match bounds.next() {
+            None => Some(HopRange {
+                minimum,
+                maximum_opt: Some(minimum),
+            }),
+            Some("") => Some(HopRange::at_least(minimum)),
+            Some(maximum_string) => match maximum_string.parse::<usize>() {
+                Ok(maximum) if maximum >= minimum => Some(HopRange {
+                    minimum,
+                    maximum_opt: Some(maximum),
+                }),
+                _ => None,
+            },
+        }
+    }
+
+    fn parse_keepalive_config(finder: &ParameterFinder) -> KeepaliveConfig {
+        let mut keepalive_config = KeepaliveConfig::new();
+        if let Some(interval_string) =
+            finder.find_value_for("--keepalive_interval", "--keepalive_interval <seconds>")
+        {
+            keepalive_config.interval = match interval_string.parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => panic!(
+                    "--keepalive_interval needs a number of seconds greater than zero, not '{}'",
+                    interval_string
+                ),
+            };
+        }
+        if let Some(misses_string) =
+            finder.find_value_for("--keepalive_misses", "--keepalive_misses <count>")
+        {
+            keepalive_config.max_missed = match misses_string.parse::<u32>() {
+                Ok(misses) if misses > 0 => misses,
+                _ => panic!(
+                    "--keepalive_misses needs a number greater than zero, not '{}'",
+                    misses_string
+                ),
+            };
+        }
+        keepalive_config
+    }
+
+    fn parse_admission_limits(finder: &ParameterFinder) -> AdmissionLimits {
+        let mut admission_limits = AdmissionLimits::new();
+        if let Some(count) = Bootstrapper::parse_positive_number::<u32>(
+            finder,
+            "--new_nodes_per_source",
+            "--new_nodes_per_source <count>",
+        ) {
+            admission_limits.new_nodes_per_source = count;
+        }
+        if let Some(secs) = Bootstrapper::parse_positive_number::<u64>(
+            finder,
+            "--new_node_window",
+            "--new_node_window <seconds>",
+        ) {
+            admission_limits.new_node_window = Duration::from_secs(secs);
+        }
+        if let Some(count) = Bootstrapper::parse_positive_number::<u32>(
+            finder,
+            "--nodes_per_subnet",
+            "--nodes_per_subnet <count>",
+        ) {
+            admission_limits.nodes_per_subnet = count;
+        }
+        if let Some(count) = Bootstrapper::parse_positive_number::<usize>(
+            finder,
+            "--max_database_size",
+            "--max_database_size <count>",
+        ) {
+            admission_limits.max_database_size = count;
+        }
+        if let Some(count) = Bootstrapper::parse_positive_number::<usize>(
+            finder,
+            "--max_hops_per_source",
+            "--max_hops_per_source <count>",
+        ) {
+            admission_limits.max_hops_per_source = count;
+        }
+        admission_limits
+    }
+
+    fn parse_introspection_port(finder: &ParameterFinder) -> Option<u16> {
+        let usage = "--introspection_port <port on 127.0.0.1 for inspecting the Neighborhood, default = none>";
+        match finder.find_value_for("--introspection_port", usage) {
+            None => None,
+            Some(ref port_str) => match port_str.parse::<u16>() {
+                Ok(port) if port > 0 => Some(port),
+                _ => panic!(
+                    "--introspection_port <port> needs a port number, not '{}'",
+                    port_str
+                ),
+            },
+        }
+    }
+
+    fn parse_positive_number<T: FromStr + PartialOrd + Default>(
+        finder: &ParameterFinder,
+        parameter_tag: &str,
+        usage: &str,
+    ) -> Option<T> {
+        finder
+            .find_value_for(parameter_tag, usage)
+            .map(|number_string| {
+                match number_string
+                    .parse::<T>()
+                    .ok()
+                    .filter(|number| *number > T::default())
+                {
+                    Some(number) => number,
+                    None => panic!(
+                        "{} needs a number greater than zero, not '{}'",
+                        parameter_tag, number_string
+                    ),
+                }
+            })
+    }
+
+    fn parse_cryptde_type(finder: &ParameterFinder) -> bool {
+        let usage = "--cryptde real|null";
+        match finder.find_value_for("--cryptde", usage) {
+            None => false,
+            Some(ref cryptde_type) if cryptde_type == "real" => false,
+            Some(ref cryptde_type) if cryptde_type == "null" => true,
+            Some(ref cryptde_type) => panic!(
+                "--cryptde must be either real or null, not {}",
+                cryptde_type
+            ),
+        }
+    }
+
     fn parse_neighbor_configs(
         finder: &ParameterFinder,
         parameter_tag: &str,
@@ -284,16 +688,77 @@ impl Bootstrapper {
         config.neighborhood_config.clandestine_port_list = clandestine_ports;
     }
 
-    fn initialize_cryptde() -> &'static CryptDE {
-        let mut exemplar = CryptDENull::new();
-        exemplar.generate_key_pair();
-        let cryptde: &'static CryptDENull = unsafe {
+    fn initialize_cryptde(config: &BootstrapperConfig) -> &'static CryptDE {
+        let exemplar = match config.data_directory_opt {
+            None => Bootstrapper::make_cryptde(config.cryptde_null),
+            Some(ref data_directory) => Bootstrapper::load_cryptde(
+                config.cryptde_null,
+                &Keystore::new(data_directory),
+                &Bootstrapper::find_passphrase(),
+            ),
+        };
+        let cryptde: &'static CryptDE = unsafe {
             CRYPT_DE_OPT = Some(exemplar);
-            CRYPT_DE_OPT.as_ref().expect("Internal error")
+            CRYPT_DE_OPT.as_ref().expect("Internal error").as_ref()
+        };
+        cryptde
+    }
+
+    fn make_cryptde(cryptde_null: bool) -> Box<CryptDE> {
+        let mut cryptde: Box<CryptDE> = if cryptde_null {
+            Box::new(CryptDENull::new())
+        } else {
+            Box::new(CryptDEReal::new())
         };
+        cryptde.generate_key_pair();
         cryptde
     }
 
+    fn load_cryptde(cryptde_null: bool, keystore: &Keystore, passphrase: &str) -> Box<CryptDE> {
+        if !keystore.exists() {
+            let cryptde = Bootstrapper::make_cryptde(cryptde_null);
+            if let Err(e) = keystore.save(&cryptde.public_key(), &cryptde.private_key(), passphrase)
+            {
+                panic!(
+                    "Could not save node identity to {:?}: {:?}",
+                    keystore.file_path(),
+                    e
+                )
+            }
+            return cryptde;
+        }
+        let (public_key, private_key) = match keystore.load(passphrase) {
+            Ok(key_pair) => key_pair,
+            Err(e) => panic!(
+                "Could not load node identity from {:?}: {:?}",
+                keystore.file_path(),
+                e
+            ),
+        };
+        if cryptde_null {
+            Box::new(CryptDENull::from(&public_key))
+        } else {
+            match CryptDEReal::from_key_pair(&public_key, &private_key) {
+                Ok(cryptde) => Box::new(cryptde),
+                Err(e) => panic!(
+                    "Node identity in {:?} is not usable with --cryptde real: {:?}",
+                    keystore.file_path(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn find_passphrase() -> String {
+        match env::var(PASSPHRASE_ENV_VAR) {
+            Ok(passphrase) => passphrase,
+            Err(_) => panic!(
+                "--data_directory requires the keystore passphrase in the {} environment variable",
+                PASSPHRASE_ENV_VAR
+            ),
+        }
+    }
+
     fn report_local_descriptor(
         cryptde: &CryptDE,
         ip_addr: IpAddr,
@@ -349,6 +814,9 @@ mod tests {
     use stream_handler_pool::StreamHandlerPoolSubs;
     use stream_messages::AddStreamMsg;
     use sub_lib::cryptde::PlainData;
+    use sub_lib::cryptde_real::PUBLIC_KEY_LEN;
+    use sub_lib::dns_server::DnsTransport;
+    use sub_lib::proxy_server::DEFAULT_PROXIED_PORTS;
     use sub_lib::stream_connector::ConnectionInfo;
     use test_utils::logging::init_test_logging;
     use test_utils::logging::TestLog;
@@ -357,6 +825,7 @@ mod tests {
     use test_utils::recorder::RecordAwaiter;
     use test_utils::recorder::Recording;
     use test_utils::test_utils::assert_contains;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
     use test_utils::test_utils::FakeStreamHolder;
     use test_utils::tokio_wrapper_mocks::ReadHalfWrapperMock;
     use test_utils::tokio_wrapper_mocks::WriteHalfWrapperMock;
@@ -521,17 +990,60 @@ mod tests {
             .collect(),
         );
 
-        let socket_addrs = Bootstrapper::parse_dns_servers(&finder);
+        let dns_servers = Bootstrapper::parse_dns_servers(&finder);
+
+        assert_eq!(
+            dns_servers,
+            vec!(
+                DnsServer::udp(IpAddr::from_str("1.2.3.4").unwrap()),
+                DnsServer::udp(IpAddr::from_str("2.3.4.5").unwrap())
+            )
+        )
+    }
+
+    #[test]
+    fn parse_dns_servers_accepts_encrypted_upstreams() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--dns_servers",
+                "tls://1.1.1.1@cloudflare-dns.com,https://[2606:4700::1111]:8443@cloudflare-dns.com,8.8.8.8",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let dns_servers = Bootstrapper::parse_dns_servers(&finder);
 
         assert_eq!(
-            socket_addrs,
+            dns_servers,
             vec!(
-                SocketAddr::from_str("1.2.3.4:53").unwrap(),
-                SocketAddr::from_str("2.3.4.5:53").unwrap()
+                DnsServer {
+                    socket_addr: SocketAddr::from_str("1.1.1.1:853").unwrap(),
+                    transport: DnsTransport::Tls(String::from("cloudflare-dns.com")),
+                },
+                DnsServer {
+                    socket_addr: SocketAddr::from_str("[2606:4700::1111]:8443").unwrap(),
+                    transport: DnsTransport::Https(String::from("cloudflare-dns.com")),
+                },
+                DnsServer::udp(IpAddr::from_str("8.8.8.8").unwrap())
             )
         )
     }
 
+    #[test]
+    #[should_panic(
+        expected = "tls DNS servers should be expressed as 'tls://<IP address>[:<port>]@<hostname>', not 'tls://1.1.1.1'"
+    )]
+    fn parse_dns_servers_requires_a_hostname_for_encrypted_upstreams() {
+        let finder = ParameterFinder::new(vec![
+            String::from("--dns_servers"),
+            String::from("tls://1.1.1.1"),
+        ]);
+
+        Bootstrapper::parse_dns_servers(&finder);
+    }
+
     #[test]
     #[should_panic(expected = "--neighbor <public key>:<IP address>:<port>,<port>,...")]
     fn parse_neighbor_configs_requires_two_pieces_to_a_configuration() {
@@ -679,115 +1191,725 @@ mod tests {
     }
 
     #[test]
-    fn parse_ip_defaults() {
+    fn parse_cryptde_type_handles_real() {
         let finder = ParameterFinder::new(
-            vec!["--irrelevant", "parameter"]
+            vec!["--cryptde", "real"]
                 .into_iter()
                 .map(String::from)
                 .collect(),
         );
 
-        let result = Bootstrapper::parse_ip(&finder);
+        let result = Bootstrapper::parse_cryptde_type(&finder);
 
-        assert_eq!(result, sentinel_ip_addr())
+        assert_eq!(result, false);
     }
 
     #[test]
-    #[should_panic(expected = "Invalid IP address for --ip <public IP address>: 'booga'")]
-    fn parse_complains_about_bad_ip_address() {
+    fn parse_cryptde_type_handles_null() {
         let finder = ParameterFinder::new(
-            vec!["--ip", "booga"]
+            vec!["--cryptde", "null"]
                 .into_iter()
                 .map(String::from)
                 .collect(),
         );
 
-        Bootstrapper::parse_ip(&finder);
+        let result = Bootstrapper::parse_cryptde_type(&finder);
+
+        assert_eq!(result, true);
     }
 
     #[test]
-    fn parse_args_creates_configurations() {
-        let args: Vec<String> = vec![
-            "--irrelevant",
-            "irrelevant",
-            "--dns_servers",
-            "12.34.56.78,23.45.67.89",
-            "--irrelevant",
-            "irrelevant",
-            "--neighbor",
-            "QmlsbA:1.2.3.4:1234,2345",
-            "--ip",
-            "34.56.78.90",
-            "--port_count",
-            "2",
-            "--neighbor",
-            "VGVk:2.3.4.5:3456,4567",
-            "--node_type",
-            "bootstrap",
-            "--bootstrap_from",
-            "R29vZEtleQ:3.4.5.6:5678",
-            "--irrelevant",
-            "irrelevant",
-        ]
-        .into_iter()
-        .map(String::from)
-        .collect();
-        let mut configuration = Configuration::new();
+    fn parse_cryptde_type_defaults_to_real() {
+        let finder = ParameterFinder::new(
+            vec!["--irrelevant", "parameter"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
 
-        configuration.establish(&args);
-        let mut config = BootstrapperConfig::new();
-        Bootstrapper::parse_args(&args, &mut config);
+        let result = Bootstrapper::parse_cryptde_type(&finder);
 
-        assert_eq!(
-            config.dns_servers,
-            vec!(
-                SocketAddr::from_str("12.34.56.78:53").unwrap(),
-                SocketAddr::from_str("23.45.67.89:53").unwrap()
-            )
-        );
-        assert_eq!(
-            config.neighborhood_config.neighbor_configs,
-            vec!(
-                (
-                    Key::new(b"Bill"),
-                    NodeAddr::new(&IpAddr::from_str("1.2.3.4").unwrap(), &vec!(1234, 2345))
-                ),
-                (
-                    Key::new(b"Ted"),
-                    NodeAddr::new(&IpAddr::from_str("2.3.4.5").unwrap(), &vec!(3456, 4567))
-                ),
-            )
-        );
-        assert_eq!(
-            config.neighborhood_config.bootstrap_configs,
-            vec!((
-                Key::new(b"GoodKey"),
-                NodeAddr::new(&IpAddr::from_str("3.4.5.6").unwrap(), &vec!(5678))
-            ),)
-        );
-        assert_eq!(config.neighborhood_config.is_bootstrap_node, true);
-        assert_eq!(
-            config.neighborhood_config.local_ip_addr,
-            IpAddr::V4(Ipv4Addr::new(34, 56, 78, 90))
+        assert_eq!(result, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "--cryptde must be either real or null, not booga")]
+    fn parse_cryptde_type_complains_about_bad_cryptde_type() {
+        let finder = ParameterFinder::new(
+            vec!["--cryptde", "booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
         );
+
+        Bootstrapper::parse_cryptde_type(&finder);
     }
 
     #[test]
-    fn parse_args_works_with_node_type_standard() {
-        let args: Vec<String> = vec!["--dns_servers", "12.34.56.78", "--node_type", "standard"]
+    fn parse_exit_policy_defaults_to_allowing_everything() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_exit_policy(&finder);
+
+        assert_eq!(result, ExitPolicy::allow_all());
+    }
+
+    #[test]
+    fn parse_exit_policy_handles_ports_and_hostname_patterns() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--exit_ports",
+                "80,443",
+                "--exit_hosts",
+                "booga.com,*.wooga.com",
+            ]
             .into_iter()
             .map(String::from)
-            .collect();
-        let mut config = BootstrapperConfig::new();
+            .collect(),
+        );
 
-        Bootstrapper::parse_args(&args, &mut config);
+        let result = Bootstrapper::parse_exit_policy(&finder);
 
-        assert_eq!(config.neighborhood_config.is_bootstrap_node, false);
+        assert_eq!(
+            result,
+            ExitPolicy {
+                allowed_ports: vec![80, 443],
+                allowed_hostnames: vec![String::from("booga.com"), String::from("*.wooga.com")],
+            }
+        );
     }
 
     #[test]
-    fn initialize_as_root_with_no_args_binds_port_80_and_443() {
-        let (first_handler, first_handler_log) =
+    #[should_panic(
+        expected = "--exit_ports needs a comma-separated list of port numbers, not '80,booga'"
+    )]
+    fn parse_exit_policy_complains_about_bad_port() {
+        let finder = ParameterFinder::new(
+            vec!["--exit_ports", "80,booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_exit_policy(&finder);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--exit_hosts needs a comma-separated list of hostnames, each optionally starting with '*.', not 'booga.com,w*oga.com'"
+    )]
+    fn parse_exit_policy_complains_about_misplaced_wildcard() {
+        let finder = ParameterFinder::new(
+            vec!["--exit_hosts", "booga.com,w*oga.com"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_exit_policy(&finder);
+    }
+
+    #[test]
+    fn parse_keepalive_config_defaults() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_keepalive_config(&finder);
+
+        assert_eq!(result, KeepaliveConfig::new());
+    }
+
+    #[test]
+    fn parse_keepalive_config_handles_interval_and_misses() {
+        let finder = ParameterFinder::new(
+            vec!["--keepalive_interval", "10", "--keepalive_misses", "5"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        let result = Bootstrapper::parse_keepalive_config(&finder);
+
+        assert_eq!(
+            result,
+            KeepaliveConfig {
+                interval: Duration::from_secs(10),
+                max_missed: 5,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--keepalive_interval needs a number of seconds greater than zero, not '0'"
+    )]
+    fn parse_keepalive_config_complains_about_zero_interval() {
+        let finder = ParameterFinder::new(
+            vec!["--keepalive_interval", "0"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_keepalive_config(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--keepalive_misses needs a number greater than zero, not 'booga'")]
+    fn parse_keepalive_config_complains_about_bad_misses() {
+        let finder = ParameterFinder::new(
+            vec!["--keepalive_misses", "booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_keepalive_config(&finder);
+    }
+
+    #[test]
+    fn parse_introspection_port_handles_absence_and_presence() {
+        let absent_finder = ParameterFinder::new(vec![]);
+        let present_finder = ParameterFinder::new(
+            vec!["--introspection_port", "5333"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        let absent_result = Bootstrapper::parse_introspection_port(&absent_finder);
+        let present_result = Bootstrapper::parse_introspection_port(&present_finder);
+
+        assert_eq!(absent_result, None);
+        assert_eq!(present_result, Some(5333));
+    }
+
+    #[test]
+    #[should_panic(expected = "--introspection_port <port> needs a port number, not '0'")]
+    fn parse_introspection_port_rejects_zero() {
+        let finder = ParameterFinder::new(
+            vec!["--introspection_port", "0"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_introspection_port(&finder);
+    }
+
+    #[test]
+    fn parse_dns_cache_config_defaults() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_dns_cache_config(&finder);
+
+        assert_eq!(result, DnsCacheConfig::new());
+    }
+
+    #[test]
+    fn parse_dns_cache_config_handles_ttls() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--dns_min_ttl",
+                "0",
+                "--dns_max_ttl",
+                "300",
+                "--dns_negative_ttl",
+                "10",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let result = Bootstrapper::parse_dns_cache_config(&finder);
+
+        assert_eq!(
+            result,
+            DnsCacheConfig {
+                min_ttl: Duration::from_secs(0),
+                max_ttl: Duration::from_secs(300),
+                negative_ttl: Duration::from_secs(10),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--dns_negative_ttl needs a number of seconds, not 'booga'")]
+    fn parse_dns_cache_config_complains_about_bad_ttl() {
+        let finder = ParameterFinder::new(
+            vec!["--dns_negative_ttl", "booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_dns_cache_config(&finder);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--dns_min_ttl (600 seconds) can't be more than --dns_max_ttl (300 seconds)"
+    )]
+    fn parse_dns_cache_config_complains_about_minimum_above_maximum() {
+        let finder = ParameterFinder::new(
+            vec!["--dns_min_ttl", "600", "--dns_max_ttl", "300"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_dns_cache_config(&finder);
+    }
+
+    #[test]
+    fn parse_exit_filter_defaults_to_blocking_smtp_and_private_addresses() {
+        let finder = ParameterFinder::new(vec![]);
+        let exit_policy = ExitPolicy {
+            allowed_ports: vec![80],
+            allowed_hostnames: vec![],
+        };
+
+        let result = Bootstrapper::parse_exit_filter(&finder, &exit_policy);
+
+        assert_eq!(result, ExitFilter::new(exit_policy));
+    }
+
+    #[test]
+    fn parse_exit_filter_adds_blocked_ports_hostnames_and_ranges_to_the_defaults() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--exit_blocked_ports",
+                "22,3389",
+                "--exit_blocked_hosts",
+                "*.corp.example.com,tracker.com",
+                "--exit_blocked_ranges",
+                "203.0.113.0/24,2001:db8::/32",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let result = Bootstrapper::parse_exit_filter(&finder, &ExitPolicy::allow_all());
+
+        assert_eq!(result.blocked_ports, vec![25, 22, 3389]);
+        assert_eq!(
+            result.blocked_hostnames,
+            vec![
+                String::from("*.corp.example.com"),
+                String::from("tracker.com")
+            ]
+        );
+        let default_filter = ExitFilter::new(ExitPolicy::allow_all());
+        assert_eq!(
+            result.blocked_ranges[..default_filter.blocked_ranges.len()],
+            default_filter.blocked_ranges[..]
+        );
+        assert_eq!(
+            result.blocked_ranges[default_filter.blocked_ranges.len()..],
+            [
+                IpRange::from_str("203.0.113.0/24").unwrap(),
+                IpRange::from_str("2001:db8::/32").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--exit_blocked_ranges: IP range prefix length must be between 0 and 32, not '40'"
+    )]
+    fn parse_exit_filter_complains_about_bad_range() {
+        let finder = ParameterFinder::new(
+            vec!["--exit_blocked_ranges", "203.0.113.0/40"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_exit_filter(&finder, &ExitPolicy::allow_all());
+    }
+
+    #[test]
+    fn parse_connection_attempt_config_defaults() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_connection_attempt_config(&finder);
+
+        assert_eq!(result, ConnectionAttemptConfig::new());
+    }
+
+    #[test]
+    fn parse_connection_attempt_config_handles_delay_and_timeout() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--exit_connect_delay",
+                "0",
+                "--exit_connect_timeout",
+                "2500",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let result = Bootstrapper::parse_connection_attempt_config(&finder);
+
+        assert_eq!(
+            result,
+            ConnectionAttemptConfig {
+                attempt_delay: Duration::from_millis(0),
+                attempt_timeout: Duration::from_millis(2500),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--exit_connect_delay needs a number of milliseconds, not '1s'")]
+    fn parse_connection_attempt_config_complains_about_bad_delay() {
+        let finder = ParameterFinder::new(
+            vec!["--exit_connect_delay", "1s"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_connection_attempt_config(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--exit_connect_timeout can't be zero")]
+    fn parse_connection_attempt_config_complains_about_zero_timeout() {
+        let finder = ParameterFinder::new(
+            vec!["--exit_connect_timeout", "0"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_connection_attempt_config(&finder);
+    }
+
+    #[test]
+    fn parse_route_policies_configures_nothing_without_route_policy_parameters() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_route_policies(&finder);
+
+        assert_eq!(result, RoutePolicies::unconfigured());
+    }
+
+    #[test]
+    fn parse_route_policies_handles_default_and_hostname_policies() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--route_policy",
+                "*.bank.com=3-5/3-",
+                "--route_policy",
+                "3",
+                "--route_policy",
+                "local.net=0",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let result = Bootstrapper::parse_route_policies(&finder);
+
+        assert_eq!(
+            result,
+            RoutePolicies {
+                default_policy_opt: Some(RoutePolicy {
+                    over: HopRange {
+                        minimum: 3,
+                        maximum_opt: Some(3),
+                    },
+                    back: HopRange {
+                        minimum: 3,
+                        maximum_opt: Some(3),
+                    },
+                }),
+                hostname_policies: vec![
+                    (
+                        String::from("*.bank.com"),
+                        RoutePolicy {
+                            over: HopRange {
+                                minimum: 3,
+                                maximum_opt: Some(5),
+                            },
+                            back: HopRange::at_least(3),
+                        }
+                    ),
+                    (
+                        String::from("local.net"),
+                        RoutePolicy {
+                            over: HopRange {
+                                minimum: 0,
+                                maximum_opt: Some(0),
+                            },
+                            back: HopRange {
+                                minimum: 0,
+                                maximum_opt: Some(0),
+                            },
+                        }
+                    ),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--route_policy can be given without a hostname pattern only once, not again as '4-'"
+    )]
+    fn parse_route_policies_complains_about_a_second_default_policy() {
+        let finder = ParameterFinder::new(
+            vec!["--route_policy", "3", "--route_policy", "4-"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_route_policies(&finder);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--route_policy needs [<hostname pattern>=]<hops over>[/<hops back>], where hops are <count>, <minimum>-<maximum>, or <minimum>-, not 'booga.com=4-2'"
+    )]
+    fn parse_route_policies_complains_about_maximum_below_minimum() {
+        let finder = ParameterFinder::new(
+            vec!["--route_policy", "booga.com=4-2"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_route_policies(&finder);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--route_policy needs [<hostname pattern>=]<hops over>[/<hops back>], where hops are <count>, <minimum>-<maximum>, or <minimum>-, not 'w*oga.com=2'"
+    )]
+    fn parse_route_policies_complains_about_misplaced_wildcard() {
+        let finder = ParameterFinder::new(
+            vec!["--route_policy", "w*oga.com=2"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_route_policies(&finder);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "--route_policy can't allow zero hops one way but not the other: '0-3/2'"
+    )]
+    fn parse_route_policies_complains_about_zero_hops_one_way_only() {
+        let finder = ParameterFinder::new(
+            vec!["--route_policy", "0-3/2"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_route_policies(&finder);
+    }
+
+    #[test]
+    fn parse_admission_limits_defaults() {
+        let finder = ParameterFinder::new(vec![]);
+
+        let result = Bootstrapper::parse_admission_limits(&finder);
+
+        assert_eq!(result, AdmissionLimits::new());
+    }
+
+    #[test]
+    fn parse_admission_limits_handles_every_limit() {
+        let finder = ParameterFinder::new(
+            vec![
+                "--new_nodes_per_source",
+                "10",
+                "--new_node_window",
+                "20",
+                "--nodes_per_subnet",
+                "3",
+                "--max_database_size",
+                "400",
+                "--max_hops_per_source",
+                "1",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+
+        let result = Bootstrapper::parse_admission_limits(&finder);
+
+        assert_eq!(
+            result,
+            AdmissionLimits {
+                new_nodes_per_source: 10,
+                new_node_window: Duration::from_secs(20),
+                nodes_per_subnet: 3,
+                max_database_size: 400,
+                max_hops_per_source: 1,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--nodes_per_subnet needs a number greater than zero, not '0'")]
+    fn parse_admission_limits_complains_about_zero_limit() {
+        let finder = ParameterFinder::new(
+            vec!["--nodes_per_subnet", "0"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_admission_limits(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--max_database_size needs a number greater than zero, not 'booga'")]
+    fn parse_admission_limits_complains_about_bad_limit() {
+        let finder = ParameterFinder::new(
+            vec!["--max_database_size", "booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_admission_limits(&finder);
+    }
+
+    #[test]
+    fn parse_ip_defaults() {
+        let finder = ParameterFinder::new(
+            vec!["--irrelevant", "parameter"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        let result = Bootstrapper::parse_ip(&finder);
+
+        assert_eq!(result, sentinel_ip_addr())
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid IP address for --ip <public IP address>: 'booga'")]
+    fn parse_complains_about_bad_ip_address() {
+        let finder = ParameterFinder::new(
+            vec!["--ip", "booga"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+
+        Bootstrapper::parse_ip(&finder);
+    }
+
+    #[test]
+    fn parse_args_creates_configurations() {
+        let args: Vec<String> = vec![
+            "--irrelevant",
+            "irrelevant",
+            "--dns_servers",
+            "12.34.56.78,23.45.67.89",
+            "--irrelevant",
+            "irrelevant",
+            "--neighbor",
+            "QmlsbA:1.2.3.4:1234,2345",
+            "--ip",
+            "34.56.78.90",
+            "--port_count",
+            "2",
+            "--neighbor",
+            "VGVk:2.3.4.5:3456,4567",
+            "--node_type",
+            "bootstrap",
+            "--bootstrap_from",
+            "R29vZEtleQ:3.4.5.6:5678",
+            "--cryptde",
+            "null",
+            "--data_directory",
+            "/var/lib/substratum",
+            "--irrelevant",
+            "irrelevant",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let mut configuration = Configuration::new();
+
+        configuration.establish(&args);
+        let mut config = BootstrapperConfig::new();
+        Bootstrapper::parse_args(&args, &mut config);
+
+        assert_eq!(
+            config.dns_servers,
+            vec!(
+                DnsServer::udp(IpAddr::from_str("12.34.56.78").unwrap()),
+                DnsServer::udp(IpAddr::from_str("23.45.67.89").unwrap())
+            )
+        );
+        assert_eq!(
+            config.neighborhood_config.neighbor_configs,
+            vec!(
+                (
+                    Key::new(b"Bill"),
+                    NodeAddr::new(&IpAddr::from_str("1.2.3.4").unwrap(), &vec!(1234, 2345))
+                ),
+                (
+                    Key::new(b"Ted"),
+                    NodeAddr::new(&IpAddr::from_str("2.3.4.5").unwrap(), &vec!(3456, 4567))
+                ),
+            )
+        );
+        assert_eq!(
+            config.neighborhood_config.bootstrap_configs,
+            vec!((
+                Key::new(b"GoodKey"),
+                NodeAddr::new(&IpAddr::from_str("3.4.5.6").unwrap(), &vec!(5678))
+            ),)
+        );
+        assert_eq!(config.neighborhood_config.is_bootstrap_node, true);
+        assert_eq!(
+            config.neighborhood_config.local_ip_addr,
+            IpAddr::V4(Ipv4Addr::new(34, 56, 78, 90))
+        );
+        assert_eq!(config.cryptde_null, true);
+        assert_eq!(
+            config.data_directory_opt,
+            Some(PathBuf::from("/var/lib/substratum"))
+        );
+        assert_eq!(
+            config.neighborhood_config.data_directory_opt,
+            Some(PathBuf::from("/var/lib/substratum"))
+        );
+    }
+
+    #[test]
+    fn parse_args_works_with_node_type_standard() {
+        let args: Vec<String> = vec!["--dns_servers", "12.34.56.78", "--node_type", "standard"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut config = BootstrapperConfig::new();
+
+        Bootstrapper::parse_args(&args, &mut config);
+
+        assert_eq!(config.neighborhood_config.is_bootstrap_node, false);
+    }
+
+    #[test]
+    fn initialize_as_root_with_no_args_binds_port_80_and_443() {
+        let (first_handler, first_handler_log) =
             extract_log(ListenerHandlerNull::new(vec![]).bind_port_result(Ok(())));
         let (second_handler, second_handler_log) =
             extract_log(ListenerHandlerNull::new(vec![]).bind_port_result(Ok(())));
@@ -919,12 +2041,41 @@ mod tests {
         assert_eq!(
             dns_servers_guard.as_ref().unwrap(),
             &vec!(
-                SocketAddr::from_str("1.2.3.4:53").unwrap(),
-                SocketAddr::from_str("2.3.4.5:53").unwrap()
+                DnsServer::udp(IpAddr::from_str("1.2.3.4").unwrap()),
+                DnsServer::udp(IpAddr::from_str("2.3.4.5").unwrap())
             )
         )
     }
 
+    #[test]
+    fn initialize_as_root_stores_proxied_ports_and_socks_port_for_proxy_server() {
+        let mut subject = BootstrapperBuilder::new()
+            .add_listener_handler(Box::new(
+                ListenerHandlerNull::new(vec![]).bind_port_result(Ok(())),
+            ))
+            .add_listener_handler(Box::new(
+                ListenerHandlerNull::new(vec![]).bind_port_result(Ok(())),
+            ))
+            .add_listener_handler(Box::new(
+                ListenerHandlerNull::new(vec![]).bind_port_result(Ok(())),
+            ))
+            .build();
+
+        subject.initialize_as_privileged(
+            &vec![
+                String::from("--dns_servers"),
+                String::from("1.2.3.4"),
+                String::from("--socks_port"),
+                String::from("1080"),
+            ],
+            &mut FakeStreamHolder::new().streams(),
+        );
+
+        let config = subject.config.unwrap();
+        assert_eq!(config.socks_port_opt, Some(1080));
+        assert_eq!(config.proxied_ports, DEFAULT_PROXIED_PORTS.to_vec());
+    }
+
     #[test]
     #[should_panic(expected = "Invalid IP address for --dns_servers <servers>: 'booga'")]
     fn initialize_as_root_complains_about_dns_servers_syntax_errors() {
@@ -980,12 +2131,12 @@ mod tests {
         let cryptde_ref = {
             let mut streams = holder.streams();
 
-            let cryptde_ref = Bootstrapper::initialize_cryptde();
+            let cryptde_ref = Bootstrapper::initialize_cryptde(&BootstrapperConfig::new());
             Bootstrapper::report_local_descriptor(cryptde_ref, ip_addr, ports, &mut streams);
 
             cryptde_ref
         };
-        assert_ne!(cryptde_ref.private_key().data, b"uninitialized".to_vec());
+        assert_eq!(cryptde_ref.public_key().data.len(), PUBLIC_KEY_LEN);
         let stdout_dump = holder.stdout.get_string();
         let expected_descriptor = format!(
             "{}:2.3.4.5:3456,4567",
@@ -1008,6 +2159,77 @@ mod tests {
         assert_eq!(decrypted_data, expected_data)
     }
 
+    #[test]
+    fn load_cryptde_creates_keystore_and_reuses_it_on_the_next_start() {
+        let home_dir = ensure_node_home_directory_exists("bootstrapper", "reuses_keystore");
+        let keystore = Keystore::new(&home_dir);
+
+        let first = Bootstrapper::load_cryptde(false, &keystore, "passphrase");
+        let second = Bootstrapper::load_cryptde(false, &keystore, "passphrase");
+
+        assert_eq!(keystore.exists(), true);
+        assert_eq!(second.public_key(), first.public_key());
+        assert_eq!(second.private_key(), first.private_key());
+        let expected_data = PlainData::new(b"ho'q ;iaerh;frjhvs;lkjerre");
+        let crypt_data = first.encode(&first.public_key(), &expected_data).unwrap();
+        assert_eq!(second.decode(&crypt_data).unwrap(), expected_data);
+    }
+
+    #[test]
+    fn load_cryptde_reports_the_same_descriptor_across_restarts() {
+        let home_dir = ensure_node_home_directory_exists("bootstrapper", "same_descriptor");
+        let keystore = Keystore::new(&home_dir);
+        let ip_addr = IpAddr::from_str("2.3.4.5").unwrap();
+        let mut first_holder = FakeStreamHolder::new();
+        let mut second_holder = FakeStreamHolder::new();
+
+        let first = Bootstrapper::load_cryptde(false, &keystore, "passphrase");
+        Bootstrapper::report_local_descriptor(
+            first.as_ref(),
+            ip_addr,
+            vec![3456],
+            &mut first_holder.streams(),
+        );
+        let second = Bootstrapper::load_cryptde(false, &keystore, "passphrase");
+        Bootstrapper::report_local_descriptor(
+            second.as_ref(),
+            ip_addr,
+            vec![3456],
+            &mut second_holder.streams(),
+        );
+
+        assert_eq!(
+            second_holder.stdout.get_string(),
+            first_holder.stdout.get_string()
+        );
+    }
+
+    #[test]
+    fn load_cryptde_works_with_cryptde_null() {
+        let home_dir = ensure_node_home_directory_exists("bootstrapper", "cryptde_null_keystore");
+        let keystore = Keystore::new(&home_dir);
+
+        let first = Bootstrapper::load_cryptde(true, &keystore, "passphrase");
+        let second = Bootstrapper::load_cryptde(true, &keystore, "passphrase");
+
+        assert_eq!(second.public_key(), first.public_key());
+        assert_eq!(second.private_key(), first.private_key());
+        assert_eq!(
+            first.public_key(),
+            CryptDENull::other_key(&first.private_key())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPassphrase")]
+    fn load_cryptde_complains_about_wrong_passphrase() {
+        let home_dir = ensure_node_home_directory_exists("bootstrapper", "wrong_passphrase");
+        let keystore = Keystore::new(&home_dir);
+        Bootstrapper::load_cryptde(false, &keystore, "passphrase");
+
+        Bootstrapper::load_cryptde(false, &keystore, "booga");
+    }
+
     #[test]
     fn initialize_as_unprivileged_moves_streams_from_listener_handlers_to_stream_handler_pool() {
         init_test_logging();
@@ -1166,7 +2388,7 @@ mod tests {
 
     struct ActorSystemFactoryMock {
         stream_handler_pool_cluster: StreamHandlerPoolCluster,
-        dnss: Arc<Mutex<Option<Vec<SocketAddr>>>>,
+        dnss: Arc<Mutex<Option<Vec<DnsServer>>>>,
     }
 
     impl ActorSystemFactory for ActorSystemFactoryMock {
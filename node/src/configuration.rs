@@ -2,40 +2,62 @@
 use discriminator::DiscriminatorFactory;
 use http_request_start_finder::HttpRequestDiscriminatorFactory;
 use json_discriminator_factory::JsonDiscriminatorFactory;
+use pass_through_discriminator_factory::PassThroughDiscriminatorFactory;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::net::UdpSocket;
 use sub_lib::parameter_finder::ParameterFinder;
+use sub_lib::proxy_server::ProxyProtocol;
+use sub_lib::proxy_server::DEFAULT_PROXIED_PORTS;
 use tls_discriminator_factory::TlsDiscriminatorFactory;
 
 // TODO: This should be subsumed into BootstrapperConfig
 pub struct Configuration {
     pub port_configurations: HashMap<u16, PortConfiguration>,
+    pub proxied_ports: Vec<(u16, ProxyProtocol)>,
+    pub socks_port_opt: Option<u16>,
 }
 
 impl Configuration {
     pub fn new() -> Configuration {
         Configuration {
             port_configurations: HashMap::new(),
+            proxied_ports: vec![],
+            socks_port_opt: None,
         }
     }
 
     pub fn establish(&mut self, args: &Vec<String>) {
-        self.port_configurations.insert(
-            80,
-            PortConfiguration::new(
-                vec![Box::new(HttpRequestDiscriminatorFactory::new())],
-                false,
-            ),
-        );
-        self.port_configurations.insert(
-            443,
-            PortConfiguration::new(vec![Box::new(TlsDiscriminatorFactory::new())], false),
-        );
+        let finder = ParameterFinder::new(args.clone());
+        self.proxied_ports = Configuration::parse_proxied_ports(&finder);
+        for &(port, protocol) in &self.proxied_ports {
+            let discriminator_factory: Box<DiscriminatorFactory> = match protocol {
+                ProxyProtocol::TLS => Box::new(TlsDiscriminatorFactory::new()),
+                _ => Box::new(HttpRequestDiscriminatorFactory::new()),
+            };
+            self.port_configurations.insert(
+                port,
+                PortConfiguration::new(vec![discriminator_factory], false),
+            );
+        }
+
+        self.socks_port_opt = Configuration::parse_socks_port(&finder);
+        if let Some(socks_port) = self.socks_port_opt {
+            if self.port_configurations.contains_key(&socks_port) {
+                panic!("--socks_port {} is already a proxied port", socks_port);
+            }
+            self.port_configurations.insert(
+                socks_port,
+                PortConfiguration::new(
+                    vec![Box::new(PassThroughDiscriminatorFactory::new())],
+                    false,
+                ),
+            );
+        }
 
-        let port_count = Configuration::parse_port_count(&ParameterFinder::new(args.clone()));
+        let port_count = Configuration::parse_port_count(&finder);
         for _ in 0..port_count {
             let port = Configuration::find_free_port();
             self.port_configurations.insert(
@@ -53,9 +75,10 @@ impl Configuration {
     }
 
     pub fn clandestine_ports(&self) -> Vec<u16> {
-        self.all_ports()
-            .into_iter()
-            .filter(|port| (*port != 80) && (*port != 443))
+        self.port_configurations
+            .iter()
+            .filter(|(_, port_configuration)| port_configuration.is_clandestine)
+            .map(|(port, _)| *port)
             .collect()
     }
 
@@ -65,6 +88,49 @@ impl Configuration {
         socket.local_addr().expect("Bind failed").port()
     }
 
+    fn parse_proxied_ports(finder: &ParameterFinder) -> Vec<(u16, ProxyProtocol)> {
+        let usage = "--proxied_ports <port>:<http|tls>,... default = 80:http,443:tls";
+        let proxied_ports_str = match finder.find_value_for("--proxied_ports", usage) {
+            None => return DEFAULT_PROXIED_PORTS.to_vec(),
+            Some(proxied_ports_str) => proxied_ports_str,
+        };
+        let mut proxied_ports: Vec<(u16, ProxyProtocol)> = vec![];
+        for pair in proxied_ports_str.split(',') {
+            let pieces: Vec<&str> = pair.split(':').collect();
+            let port = match pieces[0].parse::<u16>() {
+                Ok(port) if (pieces.len() == 2) && (port > 0) => port,
+                _ => panic!("--proxied_ports needs <port>:<http|tls>, not '{}'", pair),
+            };
+            let protocol = match pieces[1] {
+                "http" => ProxyProtocol::HTTP,
+                "tls" => ProxyProtocol::TLS,
+                _ => panic!(
+                    "--proxied_ports protocol must be http or tls, not '{}'",
+                    pieces[1]
+                ),
+            };
+            if proxied_ports.iter().any(|&(existing, _)| existing == port) {
+                panic!("--proxied_ports lists port {} more than once", port);
+            }
+            proxied_ports.push((port, protocol));
+        }
+        proxied_ports
+    }
+
+    fn parse_socks_port(finder: &ParameterFinder) -> Option<u16> {
+        let usage = "--socks_port <port on which to accept SOCKS5 connections, default = none>";
+        match finder.find_value_for("--socks_port", usage) {
+            None => None,
+            Some(ref socks_port_str) => match socks_port_str.parse::<u16>() {
+                Ok(socks_port) if socks_port > 0 => Some(socks_port),
+                _ => panic!(
+                    "--socks_port <port> needs a port number, not '{}'",
+                    socks_port_str
+                ),
+            },
+        }
+    }
+
     fn parse_port_count(finder: &ParameterFinder) -> usize {
         let usage = "--port_count <number of clandestine ports to open, default = 0>";
         match finder.find_value_for("--port_count", usage) {
@@ -231,6 +297,156 @@ mod tests {
         Configuration::parse_port_count(&finder);
     }
 
+    #[test]
+    fn proxied_ports_replace_the_default_ports() {
+        let args = vec![
+            String::from("command"),
+            String::from("--proxied_ports"),
+            String::from("8080:http,8443:tls"),
+        ];
+        let mut subject = Configuration::new();
+
+        subject.establish(&args);
+
+        assert_eq!(
+            subject.proxied_ports,
+            vec![(8080, ProxyProtocol::HTTP), (8443, ProxyProtocol::TLS)]
+        );
+        assert_eq!(subject.port_configurations.contains_key(&80), false);
+        assert_eq!(subject.port_configurations.contains_key(&443), false);
+        let mut http_configuration = subject.port_configurations.remove(&8080).unwrap();
+        assert!(!http_configuration.is_clandestine);
+        let mut http_discriminator = http_configuration.discriminator_factories.remove(0).make();
+        http_discriminator.add_data(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            http_discriminator.take_chunk(),
+            Some(UnmaskedChunk::new(
+                b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+                true,
+                true
+            ))
+        );
+        let mut tls_configuration = subject.port_configurations.remove(&8443).unwrap();
+        assert!(!tls_configuration.is_clandestine);
+        let mut tls_discriminator = tls_configuration.discriminator_factories.remove(0).make();
+        tls_discriminator.add_data(&[0x16, 0x03, 0x01, 0x00, 0x01, 0x01]);
+        assert_eq!(
+            tls_discriminator.take_chunk(),
+            Some(UnmaskedChunk::new(
+                vec![0x16, 0x03, 0x01, 0x00, 0x01, 0x01],
+                true,
+                true
+            ))
+        );
+    }
+
+    #[test]
+    fn no_proxied_ports_produces_the_default_ports() {
+        let args = vec![String::from("command")];
+        let mut subject = Configuration::new();
+
+        subject.establish(&args);
+
+        assert_eq!(
+            subject.proxied_ports,
+            vec![(80, ProxyProtocol::HTTP), (443, ProxyProtocol::TLS)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "--proxied_ports protocol must be http or tls, not 'ftp'")]
+    fn parse_proxied_ports_rejects_unknown_protocol() {
+        let finder = ParameterFinder::new(vec![
+            String::from("--proxied_ports"),
+            String::from("8080:http,21:ftp"),
+        ]);
+
+        Configuration::parse_proxied_ports(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--proxied_ports needs <port>:<http|tls>, not '8080'")]
+    fn parse_proxied_ports_rejects_missing_protocol() {
+        let finder =
+            ParameterFinder::new(vec![String::from("--proxied_ports"), String::from("8080")]);
+
+        Configuration::parse_proxied_ports(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--proxied_ports lists port 8080 more than once")]
+    fn parse_proxied_ports_rejects_duplicate_port() {
+        let finder = ParameterFinder::new(vec![
+            String::from("--proxied_ports"),
+            String::from("8080:http,8080:tls"),
+        ]);
+
+        Configuration::parse_proxied_ports(&finder);
+    }
+
+    #[test]
+    #[should_panic(expected = "--socks_port 443 is already a proxied port")]
+    fn socks_port_may_not_be_a_proxied_port() {
+        let args = vec![
+            String::from("command"),
+            String::from("--socks_port"),
+            String::from("443"),
+        ];
+        let mut subject = Configuration::new();
+
+        subject.establish(&args);
+    }
+
+    #[test]
+    fn socks_port_produces_non_clandestine_pass_through_configuration() {
+        let args = vec![
+            String::from("command"),
+            String::from("--socks_port"),
+            String::from("1080"),
+        ];
+        let mut subject = Configuration::new();
+
+        subject.establish(&args);
+
+        assert_eq!(subject.socks_port_opt, Some(1080));
+        assert_eq!(subject.clandestine_ports().len(), 0);
+        let mut socks_configuration = subject.port_configurations.remove(&1080).unwrap();
+        assert_eq!(socks_configuration.discriminator_factories.len(), 1);
+        assert!(!socks_configuration.is_clandestine);
+        let socks_factory = socks_configuration.discriminator_factories.remove(0);
+        let mut socks_discriminator = socks_factory.make();
+        socks_discriminator.add_data(&[0x05, 0x01, 0x00]);
+        let socks_chunk = socks_discriminator.take_chunk().unwrap();
+        assert_eq!(
+            socks_chunk,
+            UnmaskedChunk::new(vec![0x05, 0x01, 0x00], true, true)
+        );
+    }
+
+    #[test]
+    fn no_socks_port_produces_no_socks_configuration() {
+        let args = vec![String::from("command")];
+        let mut subject = Configuration::new();
+
+        subject.establish(&args);
+
+        assert_eq!(subject.socks_port_opt, None);
+        assert_eq!(subject.all_ports().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "--socks_port <port> needs a port number, not '65536'")]
+    fn parse_socks_port_rejects_invalid_port() {
+        let args = vec![
+            String::from("command"),
+            String::from("--socks_port"),
+            String::from("65536"),
+        ];
+        let finder = ParameterFinder::new(args);
+
+        Configuration::parse_socks_port(&finder);
+    }
+
     #[test]
     fn all_ports_returns_list_of_all_ports() {
         let mut subject = Configuration::new();
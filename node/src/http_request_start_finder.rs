@@ -242,4 +242,33 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn discriminator_passes_everything_after_a_connect_request_through_unframed() {
+        let request = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n";
+        let mut data = request.to_vec();
+        data.extend_from_slice(&[0x16, 0x03, 0x01]);
+        let subject = HttpRequestDiscriminatorFactory::new();
+        let mut http_discriminator = subject.make();
+
+        http_discriminator.add_data(&data[..]);
+        let connect_chunk = http_discriminator.take_chunk().unwrap();
+        let first_tunnel_chunk = http_discriminator.take_chunk().unwrap();
+        http_discriminator.add_data(b"GET http://url.com HTTP/1.1\r\n\r\n");
+        let second_tunnel_chunk = http_discriminator.take_chunk().unwrap();
+
+        assert_eq!(
+            connect_chunk,
+            UnmaskedChunk::new(request.to_vec(), true, true)
+        );
+        assert_eq!(
+            first_tunnel_chunk,
+            UnmaskedChunk::new(vec![0x16, 0x03, 0x01], true, true)
+        );
+        assert_eq!(
+            second_tunnel_chunk,
+            UnmaskedChunk::new(b"GET http://url.com HTTP/1.1\r\n\r\n".to_vec(), true, true)
+        );
+        assert_eq!(http_discriminator.take_chunk(), None);
+    }
 }
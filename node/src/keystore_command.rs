@@ -0,0 +1,295 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use base64;
+use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde_null::CryptDENull;
+use sub_lib::cryptde_real::CryptDEReal;
+use sub_lib::keystore::Keystore;
+use sub_lib::keystore::PASSPHRASE_ENV_VAR;
+use sub_lib::main_tools::Command;
+use sub_lib::main_tools::StdStreams;
+use sub_lib::parameter_finder::ParameterFinder;
+
+const USAGE: &str = "Usage: SubstratumNode keystore [ export <file> | import <file> | rotate ] --data_directory <directory>";
+
+pub struct KeystoreCommand {
+    passphrase_opt: Option<String>,
+}
+
+enum Action {
+    Export(PathBuf),
+    Import(PathBuf),
+    Rotate,
+}
+
+impl Command for KeystoreCommand {
+    fn go(&mut self, streams: &mut StdStreams, args: &Vec<String>) -> u8 {
+        let action = match KeystoreCommand::parse_action(args) {
+            Some(action) => action,
+            None => return KeystoreCommand::usage(streams),
+        };
+        let finder = ParameterFinder::new(args.clone());
+        let data_directory = match finder.find_value_for("--data_directory", USAGE) {
+            Some(data_directory) => PathBuf::from(data_directory),
+            None => return KeystoreCommand::usage(streams),
+        };
+        let passphrase = match self.passphrase_opt {
+            Some(ref passphrase) => passphrase.clone(),
+            None => {
+                writeln!(
+                    streams.stderr,
+                    "The {} environment variable must contain the keystore passphrase",
+                    PASSPHRASE_ENV_VAR
+                )
+                .expect("Internal error");
+                return 1;
+            }
+        };
+        let keystore = Keystore::new(&data_directory);
+        let (result, name) = match action {
+            Action::Export(ref file) => {
+                (keystore.export(file).map(|_| None), "export node identity")
+            }
+            Action::Import(ref file) => (
+                keystore.import(file, &passphrase).map(Some),
+                "import node identity",
+            ),
+            Action::Rotate => (
+                keystore
+                    .rotate(KeystoreCommand::make_cryptde(&finder).as_mut(), &passphrase)
+                    .map(Some),
+                "rotate node identity",
+            ),
+        };
+        match result {
+            Ok(None) => 0,
+            Ok(Some(public_key)) => {
+                writeln!(
+                    streams.stdout,
+                    "SubstratumNode public key: {}",
+                    base64::encode_config(&public_key.data, base64::STANDARD_NO_PAD)
+                )
+                .expect("Internal error");
+                0
+            }
+            Err(e) => {
+                writeln!(streams.stderr, "Cannot {}: {:?}", name, e).expect("Internal error");
+                1
+            }
+        }
+    }
+}
+
+impl KeystoreCommand {
+    pub fn new() -> KeystoreCommand {
+        KeystoreCommand {
+            passphrase_opt: env::var(PASSPHRASE_ENV_VAR).ok(),
+        }
+    }
+
+    pub fn is_invoked(args: &Vec<String>) -> bool {
+        args.len() > 1 && args[1] == "keystore"
+    }
+
+    fn parse_action(args: &Vec<String>) -> Option<Action> {
+        match args.get(2).map(|s| s.as_str()) {
+            Some("export") => args
+                .get(3)
+                .map(|file| Action::Export(Path::new(file).to_path_buf())),
+            Some("import") => args
+                .get(3)
+                .map(|file| Action::Import(Path::new(file).to_path_buf())),
+            Some("rotate") => Some(Action::Rotate),
+            _ => None,
+        }
+    }
+
+    fn make_cryptde(finder: &ParameterFinder) -> Box<CryptDE> {
+        match finder.find_value_for("--cryptde", "--cryptde real|null") {
+            Some(ref cryptde_type) if cryptde_type == "null" => Box::new(CryptDENull::new()),
+            _ => Box::new(CryptDEReal::new()),
+        }
+    }
+
+    fn usage(streams: &mut StdStreams) -> u8 {
+        writeln!(streams.stderr, "{}", USAGE).expect("Internal error");
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sub_lib::keystore::KEYSTORE_FILENAME;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
+    use test_utils::test_utils::FakeStreamHolder;
+
+    fn make_subject(passphrase: &str) -> KeystoreCommand {
+        KeystoreCommand {
+            passphrase_opt: Some(String::from(passphrase)),
+        }
+    }
+
+    fn make_args(args: Vec<&str>) -> Vec<String> {
+        vec!["SubstratumNode", "keystore"]
+            .into_iter()
+            .chain(args.into_iter())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn is_invoked_recognizes_keystore_command() {
+        assert_eq!(
+            KeystoreCommand::is_invoked(&make_args(vec!["rotate"])),
+            true
+        );
+        assert_eq!(
+            KeystoreCommand::is_invoked(&vec![
+                String::from("SubstratumNode"),
+                String::from("--dns_servers"),
+                String::from("1.1.1.1"),
+            ]),
+            false
+        );
+        assert_eq!(
+            KeystoreCommand::is_invoked(&vec![String::from("SubstratumNode")]),
+            false
+        );
+    }
+
+    #[test]
+    fn go_with_unknown_action_prints_usage_to_stderr() {
+        let mut holder = FakeStreamHolder::new();
+        let mut subject = make_subject("passphrase");
+
+        let result = subject.go(
+            &mut holder.streams(),
+            &make_args(vec!["booga", "--data_directory", "/tmp"]),
+        );
+
+        assert_eq!(result, 1);
+        assert_eq!(holder.stderr.get_string(), format!("{}\n", USAGE));
+    }
+
+    #[test]
+    fn go_without_data_directory_prints_usage_to_stderr() {
+        let mut holder = FakeStreamHolder::new();
+        let mut subject = make_subject("passphrase");
+
+        let result = subject.go(&mut holder.streams(), &make_args(vec!["rotate"]));
+
+        assert_eq!(result, 1);
+        assert_eq!(holder.stderr.get_string(), format!("{}\n", USAGE));
+    }
+
+    #[test]
+    fn go_without_passphrase_complains() {
+        let mut holder = FakeStreamHolder::new();
+        let mut subject = KeystoreCommand {
+            passphrase_opt: None,
+        };
+
+        let result = subject.go(
+            &mut holder.streams(),
+            &make_args(vec!["rotate", "--data_directory", "/tmp"]),
+        );
+
+        assert_eq!(result, 1);
+        assert_eq!(
+            holder.stderr.get_string(),
+            String::from(
+                "The SUBSTRATUM_PASSPHRASE environment variable must contain the keystore passphrase\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rotate_export_and_import_work_together() {
+        let source_dir = ensure_node_home_directory_exists("keystore_command", "source");
+        let target_dir = ensure_node_home_directory_exists("keystore_command", "target");
+        let export_file = source_dir.join("exported.cbor");
+        let mut subject = make_subject("passphrase");
+        let mut rotate_holder = FakeStreamHolder::new();
+        let mut export_holder = FakeStreamHolder::new();
+        let mut import_holder = FakeStreamHolder::new();
+
+        let rotate_result = subject.go(
+            &mut rotate_holder.streams(),
+            &make_args(vec![
+                "rotate",
+                "--data_directory",
+                source_dir.to_str().unwrap(),
+            ]),
+        );
+        let export_result = subject.go(
+            &mut export_holder.streams(),
+            &make_args(vec![
+                "export",
+                export_file.to_str().unwrap(),
+                "--data_directory",
+                source_dir.to_str().unwrap(),
+            ]),
+        );
+        let import_result = subject.go(
+            &mut import_holder.streams(),
+            &make_args(vec![
+                "import",
+                export_file.to_str().unwrap(),
+                "--data_directory",
+                target_dir.to_str().unwrap(),
+            ]),
+        );
+
+        assert_eq!(rotate_result, 0);
+        assert_eq!(export_result, 0);
+        assert_eq!(import_result, 0);
+        assert_eq!(
+            rotate_holder
+                .stdout
+                .get_string()
+                .starts_with("SubstratumNode public key: "),
+            true
+        );
+        assert_eq!(export_holder.stdout.get_string(), String::new());
+        assert_eq!(
+            import_holder.stdout.get_string(),
+            rotate_holder.stdout.get_string()
+        );
+        assert_eq!(target_dir.join(KEYSTORE_FILENAME).is_file(), true);
+    }
+
+    #[test]
+    fn import_with_wrong_passphrase_reports_failure() {
+        let source_dir = ensure_node_home_directory_exists("keystore_command", "bad_source");
+        let target_dir = ensure_node_home_directory_exists("keystore_command", "bad_target");
+        let mut holder = FakeStreamHolder::new();
+        make_subject("passphrase").go(
+            &mut FakeStreamHolder::new().streams(),
+            &make_args(vec![
+                "rotate",
+                "--data_directory",
+                source_dir.to_str().unwrap(),
+            ]),
+        );
+        let mut subject = make_subject("booga");
+
+        let result = subject.go(
+            &mut holder.streams(),
+            &make_args(vec![
+                "import",
+                source_dir.join(KEYSTORE_FILENAME).to_str().unwrap(),
+                "--data_directory",
+                target_dir.to_str().unwrap(),
+            ]),
+        );
+
+        assert_eq!(result, 1);
+        assert_eq!(
+            holder.stderr.get_string(),
+            String::from("Cannot import node identity: WrongPassphrase\n")
+        );
+    }
+}
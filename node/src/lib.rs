@@ -36,9 +36,11 @@ pub mod http_request_start_finder;
 pub mod json_discriminator_factory;
 pub mod json_framer;
 pub mod json_masquerader;
+pub mod keystore_command;
 mod listener_handler;
 pub mod masquerader;
 mod null_masquerader;
+pub mod pass_through_discriminator_factory;
 mod privilege_drop;
 pub mod server_initializer;
 mod stream_handler_pool;
@@ -0,0 +1,57 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use discriminator::Discriminator;
+use discriminator::DiscriminatorFactory;
+use null_masquerader::NullMasquerader;
+use sub_lib::pass_through_framer::PassThroughFramer;
+
+// For ports like the SOCKS port, where the ProxyServer makes sense of the data itself
+pub struct PassThroughDiscriminatorFactory {}
+
+impl DiscriminatorFactory for PassThroughDiscriminatorFactory {
+    fn make(&self) -> Discriminator {
+        Discriminator::new(
+            Box::new(PassThroughFramer::new()),
+            vec![Box::new(NullMasquerader::new())],
+        )
+    }
+
+    fn duplicate(&self) -> Box<DiscriminatorFactory> {
+        Box::new(PassThroughDiscriminatorFactory {})
+    }
+}
+
+impl PassThroughDiscriminatorFactory {
+    pub fn new() -> PassThroughDiscriminatorFactory {
+        PassThroughDiscriminatorFactory {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discriminator::UnmaskedChunk;
+
+    #[test]
+    fn discriminator_factory_duplicate_works() {
+        let subject = PassThroughDiscriminatorFactory::new();
+
+        subject.duplicate();
+
+        // no panic; test passes
+    }
+
+    #[test]
+    fn factory_makes_discriminator_that_passes_everything_through_to_proxy_server() {
+        let data: &[u8] = &[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01];
+        let subject = PassThroughDiscriminatorFactory::new();
+
+        let mut result = subject.make();
+
+        result.add_data(data);
+        assert_eq!(
+            result.take_chunk(),
+            Some(UnmaskedChunk::new(Vec::from(data), true, true))
+        );
+        assert_eq!(result.take_chunk(), None);
+    }
+}
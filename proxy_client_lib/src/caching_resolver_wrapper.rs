@@ -0,0 +1,309 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use futures::future;
+use futures::sync::oneshot;
+use resolver_wrapper::ResolverWrapper;
+use resolver_wrapper::WrappedLookupIpFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use sub_lib::proxy_client::DnsCacheConfig;
+use tokio::prelude::Future;
+use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::lookup_ip::LookupIp;
+
+// Past this many hostnames, expired answers are swept out before another is remembered
+pub const DNS_CACHE_CAPACITY: usize = 10000;
+
+type LookupResult = Result<LookupIp, ResolveError>;
+
+enum CacheEntry {
+    Answered(LookupResult, Instant),
+    // A lookup is in flight; these are waiting for its answer
+    Pending(Vec<oneshot::Sender<LookupResult>>),
+}
+
+// Sits in front of another ResolverWrapper, remembering its answers per hostname for as long as the DnsCacheConfig
+// allows, and sending only one lookup for a hostname no matter how many streams ask for it at once.
+pub struct CachingResolverWrapper {
+    delegate: Box<ResolverWrapper>,
+    config: DnsCacheConfig,
+    entries: Arc<Mutex<HashMap<String, CacheEntry>>>,
+}
+
+impl ResolverWrapper for CachingResolverWrapper {
+    fn lookup_ip(&self, host: &str) -> Box<WrappedLookupIpFuture> {
+        let key = host.trim_right_matches('.').to_lowercase();
+        {
+            let mut entries = self.entries.lock().expect("DNS cache is poisoned");
+            match entries.get_mut(&key) {
+                Some(CacheEntry::Answered(ref result, expires_at))
+                    if *expires_at > Instant::now() =>
+                {
+                    return Box::new(future::result(result.clone()))
+                }
+                Some(CacheEntry::Pending(ref mut waiters)) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    return Box::new(rx.then(|received| match received {
+                        Ok(result) => result,
+                        Err(_) => Err(ResolveError::from(ResolveErrorKind::Message(
+                            "DNS lookup was abandoned",
+                        ))),
+                    }));
+                }
+                _ => (),
+            }
+            entries.insert(key.clone(), CacheEntry::Pending(vec![]));
+        }
+        let mut pending = PendingEntryGuard {
+            key_opt: Some(key),
+            entries: self.entries.clone(),
+        };
+        let config = self.config;
+        Box::new(self.delegate.lookup_ip(host).then(move |result| {
+            let key = pending
+                .key_opt
+                .take()
+                .expect("Pending entry already cleared");
+            let mut entries = pending.entries.lock().expect("DNS cache is poisoned");
+            let waiters = match entries.remove(&key) {
+                Some(CacheEntry::Pending(waiters)) => waiters,
+                _ => vec![],
+            };
+            let now = Instant::now();
+            if let Some(ttl) = CachingResolverWrapper::ttl_for(&result, &config, now) {
+                if entries.len() >= DNS_CACHE_CAPACITY {
+                    entries.retain(|_, entry| match entry {
+                        CacheEntry::Answered(_, expires_at) => *expires_at > now,
+                        CacheEntry::Pending(_) => true,
+                    });
+                }
+                if entries.len() < DNS_CACHE_CAPACITY {
+                    entries.insert(key, CacheEntry::Answered(result.clone(), now + ttl));
+                }
+            }
+            waiters.into_iter().for_each(|waiter| {
+                // The stream that was waiting may have given up already
+                let _ = waiter.send(result.clone());
+            });
+            result
+        }))
+    }
+}
+
+// Owned by the lookup that's actually in flight. If that lookup is dropped or panics before it's answered, its
+// hostname mustn't stay Pending, or every later stream asking for it would wait forever.
+struct PendingEntryGuard {
+    key_opt: Option<String>,
+    entries: Arc<Mutex<HashMap<String, CacheEntry>>>,
+}
+
+impl Drop for PendingEntryGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key_opt.take() {
+            if let Ok(mut entries) = self.entries.lock() {
+                // Dropping the waiters' senders tells them the lookup was abandoned
+                entries.remove(&key);
+            }
+        }
+    }
+}
+
+impl CachingResolverWrapper {
+    pub fn new(delegate: Box<ResolverWrapper>, config: DnsCacheConfig) -> CachingResolverWrapper {
+        CachingResolverWrapper {
+            delegate,
+            config,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Failures other than "no such host" are probably temporary, so they aren't remembered at all
+    fn ttl_for(result: &LookupResult, config: &DnsCacheConfig, now: Instant) -> Option<Duration> {
+        match result {
+            Ok(lookup_ip) => {
+                let valid_until = lookup_ip.valid_until();
+                let ttl = if valid_until > now {
+                    valid_until - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                Some(config.clamp(ttl))
+            }
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => Some(config.negative_ttl),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use local_test_utils::ResolverWrapperMock;
+    use std::net::IpAddr;
+    use std::ops::Deref;
+    use std::str::FromStr;
+    use trust_dns_proto::op::Query;
+    use trust_dns_proto::rr::RData;
+    use trust_dns_resolver::lookup::Lookup;
+
+    fn config(min_secs: u64, max_secs: u64, negative_secs: u64) -> DnsCacheConfig {
+        DnsCacheConfig {
+            min_ttl: Duration::from_secs(min_secs),
+            max_ttl: Duration::from_secs(max_secs),
+            negative_ttl: Duration::from_secs(negative_secs),
+        }
+    }
+
+    fn ips(result: LookupResult) -> Vec<IpAddr> {
+        result.unwrap().iter().collect()
+    }
+
+    #[test]
+    fn answers_are_remembered_per_hostname() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_success(vec![IpAddr::from_str("1.2.3.4").unwrap()])
+            .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(30, 3600, 60));
+
+        let first = subject.lookup_ip("booga.com.").wait();
+        let second = subject.lookup_ip("BOOGA.com").wait();
+        let third = subject.lookup_ip("wooga.com.").wait();
+
+        assert_eq!(ips(first), vec![IpAddr::from_str("1.2.3.4").unwrap()]);
+        assert_eq!(ips(second), vec![IpAddr::from_str("1.2.3.4").unwrap()]);
+        assert_eq!(ips(third), vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        assert_eq!(
+            lookup_ip_parameters.lock().unwrap().deref(),
+            &vec![String::from("booga.com."), String::from("wooga.com.")]
+        );
+    }
+
+    #[test]
+    fn answers_are_forgotten_after_the_maximum_ttl() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_success(vec![IpAddr::from_str("1.2.3.4").unwrap()])
+            .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(0, 0, 60));
+
+        subject.lookup_ip("booga.com.").wait().unwrap();
+        let second = subject.lookup_ip("booga.com.").wait();
+
+        assert_eq!(ips(second), vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        assert_eq!(lookup_ip_parameters.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn answers_are_kept_for_at_least_the_minimum_ttl() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let expired_lookup: LookupIp = Lookup::new_with_deadline(
+            Arc::new(vec![RData::A(FromStr::from_str("1.2.3.4").unwrap())]),
+            Instant::now(),
+        )
+        .into();
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_result(Box::new(future::ok(expired_lookup)));
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(30, 3600, 60));
+
+        subject.lookup_ip("booga.com.").wait().unwrap();
+        let second = subject.lookup_ip("booga.com.").wait();
+
+        assert_eq!(ips(second), vec![IpAddr::from_str("1.2.3.4").unwrap()]);
+        assert_eq!(lookup_ip_parameters.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn no_such_host_is_remembered_but_other_failures_are_not() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_failure(ResolveError::from(ResolveErrorKind::NoRecordsFound {
+                query: Query::new(),
+                valid_until: None,
+            }))
+            .lookup_ip_failure(ResolveError::from(ResolveErrorKind::Io))
+            .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(30, 3600, 60));
+
+        subject.lookup_ip("nowhere.com.").wait().err().unwrap();
+        let remembered = subject.lookup_ip("nowhere.com.").wait();
+        subject.lookup_ip("flaky.com.").wait().err().unwrap();
+        let retried = subject.lookup_ip("flaky.com.").wait();
+
+        match remembered.err().unwrap().kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => (),
+            kind => panic!("Expected NoRecordsFound, got {:?}", kind),
+        }
+        assert_eq!(ips(retried), vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        assert_eq!(
+            lookup_ip_parameters.lock().unwrap().deref(),
+            &vec![
+                String::from("nowhere.com."),
+                String::from("flaky.com."),
+                String::from("flaky.com.")
+            ]
+        );
+    }
+
+    #[test]
+    fn concurrent_lookups_for_the_same_hostname_share_one_answer() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let (tx, rx) = oneshot::channel::<LookupResult>();
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_result(Box::new(rx.then(|received| received.unwrap())));
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(30, 3600, 60));
+
+        let first = subject.lookup_ip("booga.com.");
+        let second = subject.lookup_ip("booga.com.");
+        tx.send(Ok(Lookup::new_with_max_ttl(Arc::new(vec![RData::A(
+            FromStr::from_str("1.2.3.4").unwrap(),
+        )]))
+        .into()))
+            .unwrap();
+
+        assert_eq!(
+            ips(first.wait()),
+            vec![IpAddr::from_str("1.2.3.4").unwrap()]
+        );
+        assert_eq!(
+            ips(second.wait()),
+            vec![IpAddr::from_str("1.2.3.4").unwrap()]
+        );
+        assert_eq!(lookup_ip_parameters.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn abandoned_lookup_releases_its_waiters_and_its_hostname() {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let (_tx, rx) = oneshot::channel::<LookupResult>();
+        let delegate = ResolverWrapperMock::new()
+            .lookup_ip_parameters(&lookup_ip_parameters)
+            .lookup_ip_result(Box::new(rx.then(|received| received.unwrap())))
+            .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        let subject = CachingResolverWrapper::new(Box::new(delegate), config(30, 3600, 60));
+
+        let abandoned = subject.lookup_ip("booga.com.");
+        let waiting = subject.lookup_ip("booga.com.");
+        drop(abandoned);
+        let waiting_result = waiting.wait();
+        let retried = subject.lookup_ip("booga.com.").wait();
+
+        assert_eq!(
+            waiting_result.err().unwrap().to_string(),
+            "DNS lookup was abandoned"
+        );
+        assert_eq!(ips(retried), vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+        assert_eq!(lookup_ip_parameters.lock().unwrap().len(), 2);
+    }
+}
@@ -13,6 +13,7 @@ extern crate trust_dns_resolver;
 extern crate test_utils;
 extern crate tokio;
 
+mod caching_resolver_wrapper;
 #[cfg(test)]
 mod local_test_utils;
 pub mod proxy_client;
@@ -54,6 +54,11 @@ impl ResolverWrapperMock {
         self
     }
 
+    pub fn lookup_ip_result(self, result: Box<WrappedLookupIpFuture>) -> ResolverWrapperMock {
+        self.lookup_ip_results.borrow_mut().push(result);
+        self
+    }
+
     pub fn lookup_ip_failure(self, error: ResolveError) -> ResolverWrapperMock {
         self.lookup_ip_results
             .borrow_mut()
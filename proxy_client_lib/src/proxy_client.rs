@@ -5,19 +5,24 @@ use actix::Context;
 use actix::Handler;
 use actix::Recipient;
 use actix::Syn;
+use caching_resolver_wrapper::CachingResolverWrapper;
 use resolver_wrapper::ResolverWrapperFactory;
 use resolver_wrapper::ResolverWrapperFactoryReal;
-use std::net::SocketAddr;
 use stream_handler_pool::StreamHandlerPool;
 use stream_handler_pool::StreamHandlerPoolFactory;
 use stream_handler_pool::StreamHandlerPoolFactoryReal;
 use sub_lib::cryptde::CryptDE;
+use sub_lib::dns_server::DnsServer;
+use sub_lib::dns_server::DnsTransport;
 use sub_lib::hopper::ExpiredCoresPackage;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
 use sub_lib::peer_actors::BindMessage;
+use sub_lib::proxy_client::DnsCacheConfig;
+use sub_lib::proxy_client::ExitFilter;
 use sub_lib::proxy_client::ProxyClientSubs;
 use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
 use trust_dns_resolver::config::NameServerConfig;
 use trust_dns_resolver::config::Protocol;
@@ -25,7 +30,10 @@ use trust_dns_resolver::config::ResolverConfig;
 use trust_dns_resolver::config::ResolverOpts;
 
 pub struct ProxyClient {
-    dns_servers: Vec<SocketAddr>,
+    dns_servers: Vec<DnsServer>,
+    exit_filter: ExitFilter,
+    dns_cache_config: DnsCacheConfig,
+    connection_attempt_config: ConnectionAttemptConfig,
     resolver_wrapper_factory: Box<ResolverWrapperFactory>,
     stream_handler_pool_factory: Box<StreamHandlerPoolFactory>,
     _cryptde: &'static CryptDE, // This is not used now, but a version of it may be used in the future when ser/de and en/decrypt are combined.
@@ -48,19 +56,28 @@ impl Handler<BindMessage> for ProxyClient {
         let mut config = ResolverConfig::new();
         for dns_server_ref in &self.dns_servers {
             self.logger
-                .info(format!("Adding DNS server: {}", dns_server_ref.ip()));
+                .info(format!("Adding DNS server: {}", dns_server_ref));
+            let (protocol, tls_dns_name) = match dns_server_ref.transport {
+                DnsTransport::Udp => (Protocol::Udp, None),
+                DnsTransport::Tls(ref hostname) => (Protocol::Tls, Some(hostname.clone())),
+                DnsTransport::Https(ref hostname) => (Protocol::Https, Some(hostname.clone())),
+            };
             config.add_name_server(NameServerConfig {
-                socket_addr: *dns_server_ref,
-                protocol: Protocol::Udp,
-                tls_dns_name: None,
+                socket_addr: dns_server_ref.socket_addr,
+                protocol,
+                tls_dns_name,
             })
         }
-        let opts = ResolverOpts::default();
+        let mut opts = ResolverOpts::default();
+        // The CachingResolverWrapper does the caching, so that its TTL limits aren't undercut by a cache behind it
+        opts.cache_size = 0;
         let resolver = self.resolver_wrapper_factory.make(config, opts);
         self.pool = Some(self.stream_handler_pool_factory.make(
-            resolver,
+            Box::new(CachingResolverWrapper::new(resolver, self.dns_cache_config)),
             self._cryptde,
             msg.peer_actors.hopper.from_hopper_client,
+            self.exit_filter.clone(),
+            self.connection_attempt_config,
         ));
         ()
     }
@@ -90,12 +107,21 @@ impl Handler<ExpiredCoresPackage> for ProxyClient {
 }
 
 impl ProxyClient {
-    pub fn new(cryptde: &'static CryptDE, dns_servers: Vec<SocketAddr>) -> ProxyClient {
+    pub fn new(
+        cryptde: &'static CryptDE,
+        dns_servers: Vec<DnsServer>,
+        exit_filter: ExitFilter,
+        dns_cache_config: DnsCacheConfig,
+        connection_attempt_config: ConnectionAttemptConfig,
+    ) -> ProxyClient {
         if dns_servers.is_empty() {
             panic! ("Proxy Client requires at least one DNS server IP address after the --dns_servers parameter")
         }
         ProxyClient {
             dns_servers,
+            exit_filter,
+            dns_cache_config,
+            connection_attempt_config,
             resolver_wrapper_factory: Box::new(ResolverWrapperFactoryReal {}),
             stream_handler_pool_factory: Box::new(StreamHandlerPoolFactoryReal {}),
             _cryptde: cryptde,
@@ -130,10 +156,12 @@ mod tests {
     use std::str::FromStr;
     use std::sync::Arc;
     use std::sync::Mutex;
+    use std::time::Duration;
     use stream_handler_pool::StreamHandlerPool;
     use stream_handler_pool::StreamHandlerPoolFactory;
     use sub_lib::cryptde::Key;
     use sub_lib::cryptde::PlainData;
+    use sub_lib::neighborhood::ExitPolicy;
     use sub_lib::proxy_server::ClientRequestPayload;
     use sub_lib::proxy_server::ProxyProtocol;
     use sub_lib::route::Route;
@@ -147,8 +175,8 @@ mod tests {
     use test_utils::test_utils::cryptde;
     use test_utils::test_utils::make_meaningless_stream_key;
 
-    fn dnss() -> Vec<SocketAddr> {
-        vec![SocketAddr::from_str("8.8.8.8:53").unwrap()]
+    fn dnss() -> Vec<DnsServer> {
+        vec![DnsServer::udp(IpAddr::from_str("8.8.8.8").unwrap())]
     }
 
     pub struct StreamHandlerPoolMock {
@@ -187,6 +215,8 @@ mod tests {
                     Box<ResolverWrapper>,
                     &'static CryptDE,
                     Recipient<Syn, IncipientCoresPackage>,
+                    ExitFilter,
+                    ConnectionAttemptConfig,
                 )>,
             >,
         >,
@@ -199,11 +229,16 @@ mod tests {
             resolver: Box<ResolverWrapper>,
             cryptde: &'static CryptDE,
             hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+            exit_filter: ExitFilter,
+            connection_attempt_config: ConnectionAttemptConfig,
         ) -> Box<StreamHandlerPool> {
-            self.make_parameters
-                .lock()
-                .unwrap()
-                .push((resolver, cryptde, hopper_sub));
+            self.make_parameters.lock().unwrap().push((
+                resolver,
+                cryptde,
+                hopper_sub,
+                exit_filter,
+                connection_attempt_config,
+            ));
             self.make_results.borrow_mut().remove(0)
         }
     }
@@ -224,6 +259,8 @@ mod tests {
                         Box<ResolverWrapper>,
                         &'static CryptDE,
                         Recipient<Syn, IncipientCoresPackage>,
+                        ExitFilter,
+                        ConnectionAttemptConfig,
                     )>,
                 >,
             >,
@@ -243,7 +280,13 @@ mod tests {
         expected = "Proxy Client requires at least one DNS server IP address after the --dns_servers parameter"
     )]
     fn at_least_one_dns_server_must_be_provided() {
-        ProxyClient::new(cryptde(), vec![]);
+        ProxyClient::new(
+            cryptde(),
+            vec![],
+            ExitFilter::allow_all(),
+            DnsCacheConfig::new(),
+            ConnectionAttemptConfig::new(),
+        );
     }
 
     #[test]
@@ -264,9 +307,22 @@ mod tests {
         let mut subject = ProxyClient::new(
             cryptde(),
             vec![
-                SocketAddr::from_str("4.3.2.1:4321").unwrap(),
-                SocketAddr::from_str("5.4.3.2:5432").unwrap(),
+                DnsServer {
+                    socket_addr: SocketAddr::from_str("4.3.2.1:4321").unwrap(),
+                    transport: DnsTransport::Udp,
+                },
+                DnsServer::from_str("tls://1.1.1.1@cloudflare-dns.com").unwrap(),
+                DnsServer::from_str("https://[2606:4700::1111]@cloudflare-dns.com").unwrap(),
             ],
+            ExitFilter::new(ExitPolicy {
+                allowed_ports: vec![443],
+                allowed_hostnames: vec![],
+            }),
+            DnsCacheConfig::new(),
+            ConnectionAttemptConfig {
+                attempt_delay: Duration::from_millis(100),
+                attempt_timeout: Duration::from_millis(2000),
+            },
         );
         subject.resolver_wrapper_factory = Box::new(resolver_wrapper_factory);
         subject.stream_handler_pool_factory = Box::new(pool_factory);
@@ -290,14 +346,37 @@ mod tests {
                     tls_dns_name: None
                 },
                 NameServerConfig {
-                    socket_addr: SocketAddr::from_str("5.4.3.2:5432").unwrap(),
-                    protocol: Protocol::Udp,
-                    tls_dns_name: None
+                    socket_addr: SocketAddr::from_str("1.1.1.1:853").unwrap(),
+                    protocol: Protocol::Tls,
+                    tls_dns_name: Some(String::from("cloudflare-dns.com"))
+                },
+                NameServerConfig {
+                    socket_addr: SocketAddr::from_str("[2606:4700::1111]:443").unwrap(),
+                    protocol: Protocol::Https,
+                    tls_dns_name: Some(String::from("cloudflare-dns.com"))
                 },
             ]
         );
-        assert_eq!(opts, ResolverOpts::default());
+        let mut expected_opts = ResolverOpts::default();
+        expected_opts.cache_size = 0;
+        assert_eq!(opts, expected_opts);
         assert_eq!(new_parameters_guard.is_empty(), true);
+        let (_, _, _, exit_filter, connection_attempt_config) =
+            pool_factory_make_parameters.lock().unwrap().remove(0);
+        assert_eq!(
+            exit_filter,
+            ExitFilter::new(ExitPolicy {
+                allowed_ports: vec![443],
+                allowed_hostnames: vec![],
+            })
+        );
+        assert_eq!(
+            connection_attempt_config,
+            ConnectionAttemptConfig {
+                attempt_delay: Duration::from_millis(100),
+                attempt_timeout: Duration::from_millis(2000),
+            }
+        );
     }
 
     #[test]
@@ -321,7 +400,13 @@ mod tests {
             PlainData::new(&serde_cbor::ser::to_vec(&request.clone()).unwrap()[..]),
         );
         let system = System::new("panics_if_hopper_is_unbound");
-        let subject = ProxyClient::new(cryptde, dnss());
+        let subject = ProxyClient::new(
+            cryptde,
+            dnss(),
+            ExitFilter::allow_all(),
+            DnsCacheConfig::new(),
+            ConnectionAttemptConfig::new(),
+        );
         let subject_addr: Addr<Syn, ProxyClient> = subject.start();
 
         subject_addr.try_send(package).unwrap();
@@ -338,7 +423,13 @@ mod tests {
             PlainData::new(&b"invalid"[..]),
         );
         let system = System::new("invalid_package_is_logged_and_discarded");
-        let subject = ProxyClient::new(cryptde(), dnss());
+        let subject = ProxyClient::new(
+            cryptde(),
+            dnss(),
+            ExitFilter::allow_all(),
+            DnsCacheConfig::new(),
+            ConnectionAttemptConfig::new(),
+        );
         let addr: Addr<Syn, ProxyClient> = subject.start();
         let peer_actors = make_peer_actors_from(None, None, None, None, None);
         addr.try_send(BindMessage { peer_actors }).unwrap();
@@ -381,7 +472,13 @@ mod tests {
         let resolver = ResolverWrapperMock::new()
             .lookup_ip_success(vec![IpAddr::from_str("4.3.2.1").unwrap()]);
         let resolver_factory = ResolverWrapperFactoryMock::new().new_result(Box::new(resolver));
-        let mut subject = ProxyClient::new(cryptde(), dnss());
+        let mut subject = ProxyClient::new(
+            cryptde(),
+            dnss(),
+            ExitFilter::allow_all(),
+            DnsCacheConfig::new(),
+            ConnectionAttemptConfig::new(),
+        );
         subject.resolver_wrapper_factory = Box::new(resolver_factory);
         subject.stream_handler_pool_factory = Box::new(pool_factory);
         let subject_addr: Addr<Syn, ProxyClient> = subject.start();
@@ -1,10 +1,11 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use actix::Arbiter;
 use tokio::prelude::Future;
 use trust_dns_resolver::config::ResolverConfig;
 use trust_dns_resolver::config::ResolverOpts;
 use trust_dns_resolver::error::ResolveError;
 use trust_dns_resolver::lookup_ip::LookupIp;
-use trust_dns_resolver::ResolverFuture;
+use trust_dns_resolver::AsyncResolver;
 
 pub type WrappedLookupIpFuture = Future<Item = LookupIp, Error = ResolveError> + Send;
 
@@ -17,7 +18,7 @@ pub trait ResolverWrapperFactory {
 }
 
 pub struct ResolverWrapperReal {
-    delegate: Box<ResolverFuture>,
+    delegate: AsyncResolver,
 }
 
 impl ResolverWrapper for ResolverWrapperReal {
@@ -29,12 +30,10 @@ impl ResolverWrapper for ResolverWrapperReal {
 pub struct ResolverWrapperFactoryReal;
 impl ResolverWrapperFactory for ResolverWrapperFactoryReal {
     fn make(&self, config: ResolverConfig, options: ResolverOpts) -> Box<ResolverWrapper> {
-        // THIS HAPPENS ONLY ONCE AT STARTUP during ProxyClient bind. So don't worry about the `wait()`.
-        let delegate = Box::new(
-            ResolverFuture::new(config, options)
-                .wait()
-                .expect("couldn't create resolver future"),
-        );
+        // The resolver does its work, including any TLS or HTTPS connections to its name servers, in a background
+        // future that has to run on the ProxyClient's Arbiter for as long as the resolver is in use.
+        let (delegate, background) = AsyncResolver::new(config, options);
+        Arbiter::handle().spawn(background);
 
         Box::new(ResolverWrapperReal { delegate })
     }
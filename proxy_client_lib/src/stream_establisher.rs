@@ -2,8 +2,10 @@
 
 use actix::Recipient;
 use actix::Syn;
+use futures::future::err;
 use std::io;
 use std::io::Error;
+use std::io::ErrorKind;
 use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::sync::mpsc::Sender;
@@ -15,9 +17,14 @@ use sub_lib::channel_wrappers::FuturesChannelFactoryReal;
 use sub_lib::channel_wrappers::SenderWrapper;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
+use sub_lib::proxy_client::ExitFilter;
+use sub_lib::proxy_client::ExitRefusal;
 use sub_lib::proxy_server::ClientRequestPayload;
 use sub_lib::route::Route;
 use sub_lib::sequence_buffer::SequencedPacket;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
+use sub_lib::stream_connector::ConnectionInfo;
+use sub_lib::stream_connector::ConnectionInfoFuture;
 use sub_lib::stream_connector::StreamConnector;
 use sub_lib::stream_connector::StreamConnectorReal;
 use sub_lib::stream_key::StreamKey;
@@ -33,15 +40,18 @@ pub struct StreamEstablisher {
     pub hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     pub logger: Logger,
     pub channel_factory: Box<FuturesChannelFactory<SequencedPacket>>,
+    pub connection_attempt_config: ConnectionAttemptConfig,
 }
 
 impl StreamEstablisher {
-    pub fn establish_stream(
-        &mut self,
+    // Addresses the ExitFilter doesn't allow are skipped; if none are left, the error is a PermissionDenied wrapping
+    // the ExitRefusal.
+    pub fn connect_stream(
+        &self,
         payload: &ClientRequestPayload,
-        return_route: &Route,
         lookup_result: Result<LookupIp, ResolveError>,
-    ) -> io::Result<Box<SenderWrapper<SequencedPacket>>> {
+        exit_filter: &ExitFilter,
+    ) -> ConnectionInfoFuture {
         let target_hostname = payload
             .target_hostname
             .clone()
@@ -52,7 +62,7 @@ impl StreamEstablisher {
                     "Could not find IP address for host {}: {}",
                     target_hostname, e
                 ));
-                return Err(Error::from(e));
+                return Box::new(err(Error::from(e)));
             }
             Ok(lookup_ip) => lookup_ip.iter().map(|x| x).collect(),
         };
@@ -60,14 +70,32 @@ impl StreamEstablisher {
             "Found IP addresses for {}: {:?}",
             target_hostname, &ip_addrs
         ));
-
-        let connection_info = self.stream_connector.connect_one(
+        let (ip_addrs, blocked_ip_addrs): (Vec<IpAddr>, Vec<IpAddr>) = ip_addrs
+            .into_iter()
+            .partition(|ip_addr| exit_filter.allows_ip(*ip_addr));
+        if ip_addrs.is_empty() && !blocked_ip_addrs.is_empty() {
+            let refusal = ExitRefusal::AddressBlocked(blocked_ip_addrs[0]);
+            self.logger.warning(format!(
+                "Refusing to connect to {}: {}",
+                target_hostname, refusal
+            ));
+            return Box::new(err(Error::new(ErrorKind::PermissionDenied, refusal)));
+        }
+        self.stream_connector.connect_one(
             ip_addrs,
             &target_hostname,
             payload.target_port,
+            self.connection_attempt_config,
             &self.logger,
-        )?;
+        )
+    }
 
+    pub fn establish_stream(
+        &mut self,
+        payload: &ClientRequestPayload,
+        return_route: &Route,
+        connection_info: ConnectionInfo,
+    ) -> io::Result<Box<SenderWrapper<SequencedPacket>>> {
         self.spawn_stream_reader(
             return_route,
             &payload.clone(),
@@ -125,6 +153,7 @@ pub struct StreamEstablisherFactoryReal {
     pub stream_killer_tx: Sender<StreamKey>,
     pub hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     pub logger: Logger,
+    pub connection_attempt_config: ConnectionAttemptConfig,
 }
 
 impl StreamEstablisherFactory for StreamEstablisherFactoryReal {
@@ -136,6 +165,7 @@ impl StreamEstablisherFactory for StreamEstablisherFactoryReal {
             hopper_sub: self.hopper_sub.clone(),
             logger: self.logger.clone(),
             channel_factory: Box::new(FuturesChannelFactoryReal {}),
+            connection_attempt_config: self.connection_attempt_config,
         }
     }
 }
@@ -146,7 +176,6 @@ mod tests {
     use actix::System;
     use futures::future::lazy;
     use serde_cbor;
-    use std::io::ErrorKind;
     use std::net::SocketAddr;
     use std::str::FromStr;
     use std::sync::mpsc;
@@ -195,6 +224,7 @@ mod tests {
                 hopper_sub,
                 logger: Logger::new("Proxy Client"),
                 channel_factory: Box::new(FuturesChannelFactoryReal {}),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
             };
             subject
                 .spawn_stream_reader(
@@ -271,6 +301,7 @@ mod tests {
                 hopper_sub,
                 logger: Logger::new("Proxy Client"),
                 channel_factory: Box::new(FuturesChannelFactoryReal {}),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
             };
 
             subject
@@ -6,13 +6,21 @@ use actix::Syn;
 use futures::future::Executor;
 use futures::future::Future;
 use resolver_wrapper::ResolverWrapper;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::io;
 use std::io::Error;
 use std::io::ErrorKind;
+use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use stream_establisher::StreamEstablisher;
 use stream_establisher::StreamEstablisherFactory;
 use stream_establisher::StreamEstablisherFactoryReal;
 use sub_lib::channel_wrappers::SenderWrapper;
@@ -21,26 +29,77 @@ use sub_lib::framer::Framer;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::http_packet_framer::HttpPacketFramer;
 use sub_lib::http_response_start_finder::HttpResponseStartFinder;
+use sub_lib::http_server_impersonator;
 use sub_lib::logger::Logger;
+use sub_lib::pass_through_framer::PassThroughFramer;
 use sub_lib::proxy_client::ClientResponsePayload;
+use sub_lib::proxy_client::ExitFilter;
+use sub_lib::proxy_client::ExitRefusal;
 use sub_lib::proxy_server::ClientRequestPayload;
 use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::route::Route;
 use sub_lib::sequence_buffer::SequencedPacket;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
 use sub_lib::stream_key::StreamKey;
 use sub_lib::tls_framer::TlsFramer;
 
+// How many packets may pile up for a stream whose host is still being resolved and connected to
+pub const PENDING_PACKET_LIMIT: usize = 100;
+// How long a stream may take to be resolved and connected to before the packets waiting for it are given up on
+pub const PENDING_STREAM_TIMEOUT_SECS: u64 = 30;
+
 pub trait StreamHandlerPool {
     fn process_package(&mut self, payload: ClientRequestPayload, route: Route);
 }
 
+// Packets for a stream that doesn't have a StreamWriter yet, waiting to be written in order once it does. An
+// abandoned stream has been terminated already; whatever connection is finally made for it is closed immediately.
+struct PendingStream {
+    request: ClientRequestPayload,
+    return_route: Route,
+    packets: BTreeMap<u64, SequencedPacket>,
+    started: Instant,
+    abandoned: bool,
+}
+
+impl PendingStream {
+    fn new(request: &ClientRequestPayload, return_route: &Route) -> PendingStream {
+        let mut packets = BTreeMap::new();
+        packets.insert(
+            request.sequenced_packet.sequence_number,
+            request.sequenced_packet.clone(),
+        );
+        PendingStream {
+            request: request.clone(),
+            return_route: return_route.clone(),
+            packets,
+            started: Instant::now(),
+            abandoned: false,
+        }
+    }
+
+    fn abandon(&mut self, hopper_sub: &Recipient<Syn, IncipientCoresPackage>) {
+        self.abandoned = true;
+        self.packets.clear();
+        StreamHandlerPoolReal::send_terminating_package(
+            self.return_route.clone(),
+            &self.request,
+            hopper_sub,
+        );
+    }
+}
+
 pub struct StreamHandlerPoolReal {
     hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     stream_writer_channels: HashMap<StreamKey, Box<SenderWrapper<SequencedPacket>>>,
+    pending_streams: Arc<Mutex<HashMap<StreamKey, PendingStream>>>,
+    pending_packet_limit: usize,
+    pending_stream_timeout: Duration,
     stream_adder_rx: Receiver<(StreamKey, Box<SenderWrapper<SequencedPacket>>)>,
     stream_killer_rx: Receiver<StreamKey>,
     resolver: Box<ResolverWrapper>,
     _cryptde: &'static CryptDE, // This is not used now, but a version of it may be used in the future when ser/de and en/decrypt are combined.
+    exit_filter: ExitFilter,
     logger: Logger,
     establisher_factory: Box<StreamEstablisherFactory>,
 }
@@ -52,6 +111,9 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
             payload.sequenced_packet.data.len()
         ));
         self.do_housekeeping();
+        if self.queue_if_pending(&payload) {
+            return;
+        }
 
         let mut to_remove: Option<(StreamKey, SocketAddr)> = None;
         match self.stream_writer_channels.get_mut(&payload.stream_key) {
@@ -83,10 +145,6 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
                 }
             }
             None => {
-                // TODO: Figure out what to do if a flurry of requests for a particular stream key
-                // come flooding in so densely that several of them arrive in the time it takes to
-                // resolve the first one and add it to the stream_writers map.
-
                 if payload.sequenced_packet.last_data && payload.sequenced_packet.data.len() == 0 {
                     self.logger.debug(format!(
                         "Empty last_data message received for nonexistent stream {:?}. Returning.",
@@ -114,9 +172,32 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
                     }
                     &Some(ref s) => s.clone(),
                 };
-                fqdn.push('.');
+                let exit_filter = self.exit_filter.clone();
+                if let Err(refusal) = exit_filter.check_destination(&fqdn, payload.target_port) {
+                    self.logger
+                        .warning(format!("Refusing to connect to {}: {}", fqdn, refusal));
+                    StreamHandlerPoolReal::send_refusal_package(
+                        return_route,
+                        &payload,
+                        &refusal,
+                        &self.hopper_sub,
+                    );
+                    return;
+                }
+                // SOCKS5 and CONNECT clients may name their targets by IP address, which needs no resolution
+                if IpAddr::from_str(&fqdn).is_err() {
+                    fqdn.push('.');
+                }
+                // Anything else for this stream waits here until the first packet's connection is made
+                self.pending_streams
+                    .lock()
+                    .expect("Pending streams are poisoned")
+                    .insert(
+                        payload.stream_key,
+                        PendingStream::new(&payload, &return_route),
+                    );
                 let mut establisher = self.establisher_factory.make();
-                let payload_clone = payload.clone();
+                let pending_streams = self.pending_streams.clone();
                 let future = self
                     .resolver
                     .lookup_ip(&fqdn[..])
@@ -124,21 +205,62 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
                         establisher
                             .logger
                             .debug(format!("Resolution closure beginning"));
-                        let remaining_route = return_route.clone();
                         establisher
-                            .establish_stream(&payload_clone, &return_route, lookup_result)
-                            .and_then(|mut stream_writer| {
-                                StreamHandlerPoolReal::perform_write(
-                                    payload.sequenced_packet,
-                                    &mut stream_writer,
-                                )
-                            })
-                            .map_err(|_| {
-                                StreamHandlerPoolReal::send_terminating_package(
-                                    remaining_route,
-                                    &payload_clone,
-                                    &establisher.hopper_sub,
-                                );
+                            .connect_stream(&payload, lookup_result, &exit_filter)
+                            .then(move |connection_result| {
+                                let establish_result =
+                                    connection_result.and_then(|connection_info| {
+                                        establisher.establish_stream(
+                                            &payload,
+                                            &return_route,
+                                            connection_info,
+                                        )
+                                    });
+                                let pending_stream = pending_streams
+                                    .lock()
+                                    .expect("Pending streams are poisoned")
+                                    .remove(&payload.stream_key)
+                                    .expect("Internal error: pending stream disappeared");
+                                match (establish_result, pending_stream.abandoned) {
+                                    (Ok(_), true) => {
+                                        establisher.logger.debug(format!(
+                                            "Closing newly-made stream to {:?}: it was abandoned while being established",
+                                            payload.target_hostname
+                                        ));
+                                        establisher
+                                            .stream_killer_tx
+                                            .send(payload.stream_key)
+                                            .expect("StreamHandlerPool died");
+                                        Ok(())
+                                    }
+                                    (Ok(mut stream_writer), false) => {
+                                        StreamHandlerPoolReal::flush_pending_stream(
+                                            pending_stream,
+                                            &mut stream_writer,
+                                            &establisher,
+                                        )
+                                    }
+                                    (Err(_), true) => Err(()),
+                                    (Err(e), false) => {
+                                        match e
+                                            .get_ref()
+                                            .and_then(|inner| inner.downcast_ref::<ExitRefusal>())
+                                        {
+                                            Some(refusal) => StreamHandlerPoolReal::send_refusal_package(
+                                                return_route,
+                                                &payload,
+                                                refusal,
+                                                &establisher.hopper_sub,
+                                            ),
+                                            None => StreamHandlerPoolReal::send_terminating_package(
+                                                return_route,
+                                                &payload,
+                                                &establisher.hopper_sub,
+                                            ),
+                                        };
+                                        Err(())
+                                    }
+                                }
                             })
                     });
                 self.logger.debug(format!("Host resolution scheduled"));
@@ -161,6 +283,8 @@ impl StreamHandlerPoolReal {
         resolver: Box<ResolverWrapper>,
         cryptde: &'static CryptDE,
         hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+        exit_filter: ExitFilter,
+        connection_attempt_config: ConnectionAttemptConfig,
     ) -> StreamHandlerPoolReal {
         let (stream_killer_tx, stream_killer_rx) = mpsc::channel();
         let (stream_adder_tx, stream_adder_rx) = mpsc::channel();
@@ -170,20 +294,112 @@ impl StreamHandlerPoolReal {
                 stream_killer_tx,
                 hopper_sub: hopper_sub.clone(),
                 logger: Logger::new("Proxy Client"),
+                connection_attempt_config,
             }),
             hopper_sub,
             stream_writer_channels: HashMap::new(),
+            pending_streams: Arc::new(Mutex::new(HashMap::new())),
+            pending_packet_limit: PENDING_PACKET_LIMIT,
+            pending_stream_timeout: Duration::from_secs(PENDING_STREAM_TIMEOUT_SECS),
             stream_adder_rx,
             stream_killer_rx,
             resolver,
             _cryptde: cryptde,
+            exit_filter,
             logger: Logger::new("Proxy Client"),
         }
     }
 
+    // New streams are added before dead ones are cleaned up, so that a stream that comes and goes between two
+    // packages doesn't linger
     fn do_housekeeping(&mut self) {
-        self.clean_up_dead_streams();
         self.add_new_streams();
+        self.clean_up_dead_streams();
+        self.abandon_stale_pending_streams();
+    }
+
+    fn abandon_stale_pending_streams(&self) {
+        let mut pending_streams = self
+            .pending_streams
+            .lock()
+            .expect("Pending streams are poisoned");
+        for (stream_key, pending_stream) in pending_streams.iter_mut() {
+            if !pending_stream.abandoned
+                && (pending_stream.started.elapsed() >= self.pending_stream_timeout)
+            {
+                self.logger.warning(format!(
+                    "Stream {:?} to {:?} could not be established in {:?}; abandoning it",
+                    stream_key, pending_stream.request.target_hostname, self.pending_stream_timeout
+                ));
+                pending_stream.abandon(&self.hopper_sub);
+            }
+        }
+    }
+
+    // Returns true if the payload belongs to a stream that is still being established; then it has been queued,
+    // or dropped if that stream has been abandoned.
+    fn queue_if_pending(&self, payload: &ClientRequestPayload) -> bool {
+        let mut pending_streams = self
+            .pending_streams
+            .lock()
+            .expect("Pending streams are poisoned");
+        let pending_stream = match pending_streams.get_mut(&payload.stream_key) {
+            Some(pending_stream) => pending_stream,
+            None => return false,
+        };
+        if pending_stream.abandoned {
+            self.logger.debug(format!(
+                "Dropping {}-byte packet for abandoned stream {:?}",
+                payload.sequenced_packet.data.len(),
+                payload.stream_key
+            ));
+        } else if pending_stream.packets.len() >= self.pending_packet_limit {
+            self.logger.warning(format!(
+                "More than {} packets arrived for stream {:?} before it could be established; abandoning it",
+                self.pending_packet_limit, payload.stream_key
+            ));
+            pending_stream.abandon(&self.hopper_sub);
+        } else {
+            self.logger.debug(format!(
+                "Queueing {}-byte packet #{} until stream {:?} is established",
+                payload.sequenced_packet.data.len(),
+                payload.sequenced_packet.sequence_number,
+                payload.stream_key
+            ));
+            pending_stream.packets.insert(
+                payload.sequenced_packet.sequence_number,
+                payload.sequenced_packet.clone(),
+            );
+        }
+        true
+    }
+
+    // If the last packet for the stream was among those flushed, the StreamWriter isn't wanted any longer
+    fn flush_pending_stream(
+        pending_stream: PendingStream,
+        stream_writer: &mut Box<SenderWrapper<SequencedPacket>>,
+        establisher: &StreamEstablisher,
+    ) -> Result<(), ()> {
+        let stream_key = pending_stream.request.stream_key;
+        let mut last_data = false;
+        for (_, sequenced_packet) in pending_stream.packets {
+            last_data = last_data || sequenced_packet.last_data;
+            if let Err(_) = StreamHandlerPoolReal::perform_write(sequenced_packet, stream_writer) {
+                StreamHandlerPoolReal::send_terminating_package(
+                    pending_stream.return_route,
+                    &pending_stream.request,
+                    &establisher.hopper_sub,
+                );
+                return Err(());
+            }
+        }
+        if last_data {
+            establisher
+                .stream_killer_tx
+                .send(stream_key)
+                .expect("StreamHandlerPool died");
+        }
+        Ok(())
     }
 
     fn clean_up_dead_streams(&mut self) {
@@ -236,6 +452,7 @@ impl StreamHandlerPoolReal {
                 Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {})))
             }
             ProxyProtocol::TLS => Box::new(TlsFramer::new()),
+            ProxyProtocol::SOCKS5 | ProxyProtocol::TUNNEL => Box::new(PassThroughFramer::new()),
         }
     }
 
@@ -249,6 +466,43 @@ impl StreamHandlerPoolReal {
             IncipientCoresPackage::new(return_route, response, &request.originator_public_key);
         hopper_sub.try_send(package).expect("Hopper died");
     }
+
+    // HTTP clients get an error page explaining the refusal; anything else just sees its stream closed
+    fn send_refusal_package(
+        return_route: Route,
+        request: &ClientRequestPayload,
+        refusal: &ExitRefusal,
+        hopper_sub: &Recipient<Syn, IncipientCoresPackage>,
+    ) {
+        let data = match request.protocol {
+            ProxyProtocol::HTTP => http_server_impersonator::make_error_response(
+                403,
+                "Exit Refused",
+                &format!(
+                    "The exit Node won't connect to {}",
+                    request.target_hostname.as_ref().map(|s| &s[..]).unwrap_or("")
+                ),
+                &format!(
+                    "The Node chosen to carry your request out of the Substratum Network refused \
+                     to make the connection because {}. Another exit Node may be more accommodating; \
+                     try reloading the page.",
+                    refusal
+                ),
+            ),
+            _ => vec![],
+        };
+        let response = ClientResponsePayload {
+            stream_key: request.stream_key,
+            sequenced_packet: SequencedPacket {
+                data,
+                sequence_number: 0,
+                last_data: true,
+            },
+        };
+        let package =
+            IncipientCoresPackage::new(return_route, response, &request.originator_public_key);
+        hopper_sub.try_send(package).expect("Hopper died");
+    }
 }
 
 pub trait StreamHandlerPoolFactory {
@@ -257,6 +511,8 @@ pub trait StreamHandlerPoolFactory {
         resolver: Box<ResolverWrapper>,
         cryptde: &'static CryptDE,
         hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+        exit_filter: ExitFilter,
+        connection_attempt_config: ConnectionAttemptConfig,
     ) -> Box<StreamHandlerPool>;
 }
 
@@ -268,8 +524,16 @@ impl StreamHandlerPoolFactory for StreamHandlerPoolFactoryReal {
         resolver: Box<ResolverWrapper>,
         cryptde: &'static CryptDE,
         hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+        exit_filter: ExitFilter,
+        connection_attempt_config: ConnectionAttemptConfig,
     ) -> Box<StreamHandlerPool> {
-        Box::new(StreamHandlerPoolReal::new(resolver, cryptde, hopper_sub))
+        Box::new(StreamHandlerPoolReal::new(
+            resolver,
+            cryptde,
+            hopper_sub,
+            exit_filter,
+            connection_attempt_config,
+        ))
     }
 }
 
@@ -296,12 +560,12 @@ mod tests {
     use std::sync::Mutex;
     use std::thread;
     use std::time::Duration;
-    use stream_establisher::StreamEstablisher;
     use sub_lib::channel_wrappers::FuturesChannelFactoryReal;
     use sub_lib::channel_wrappers::SenderWrapperReal;
     use sub_lib::cryptde::Key;
     use sub_lib::cryptde::PlainData;
     use sub_lib::hopper::ExpiredCoresPackage;
+    use sub_lib::neighborhood::ExitPolicy;
     use sub_lib::proxy_server::ProxyProtocol;
     use test_utils::channel_wrapper_mocks::FuturesChannelFactoryMock;
     use test_utils::channel_wrapper_mocks::ReceiverWrapperMock;
@@ -398,6 +662,8 @@ mod tests {
                 Box::new(ResolverWrapperMock::new()),
                 cryptde(),
                 hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
             );
             subject
                 .stream_writer_channels
@@ -455,7 +721,13 @@ mod tests {
                 client_request_payload.sequenced_packet.clone(),
             )];
 
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             subject
                 .stream_writer_channels
                 .insert(client_request_payload.stream_key, Box::new(tx_to_write));
@@ -503,7 +775,13 @@ mod tests {
             );
             let resolver = ResolverWrapperMock::new()
                 .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
-            let subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
 
             let test_actor = TestActor { subject };
             let addr: Addr<Syn, TestActor> = test_actor.start();
@@ -575,7 +853,13 @@ mod tests {
                 poll_write_results: vec![Ok(Async::Ready(123))],
                 shutdown_results: Arc::new(Mutex::new(vec![])),
             };
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             let (stream_killer_tx, stream_killer_rx) = mpsc::channel();
             subject.stream_killer_rx = stream_killer_rx;
             let (stream_adder_tx, _stream_adder_rx) = mpsc::channel();
@@ -591,6 +875,7 @@ mod tests {
                 hopper_sub: subject.hopper_sub.clone(),
                 logger: subject.logger.clone(),
                 channel_factory: Box::new(FuturesChannelFactoryReal {}),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
             };
 
             subject.establisher_factory = Box::new(StreamEstablisherFactoryMock {
@@ -666,7 +951,13 @@ mod tests {
                     IpAddr::from_str("2.3.4.5").unwrap(),
                     IpAddr::from_str("3.4.5.6").unwrap(),
                 ]);
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             let (stream_killer_tx, stream_killer_rx) = mpsc::channel();
             subject.stream_killer_rx = stream_killer_rx;
             let (stream_adder_tx, _stream_adder_rx) = mpsc::channel();
@@ -680,6 +971,7 @@ mod tests {
                 hopper_sub: subject.hopper_sub.clone(),
                 logger: subject.logger.clone(),
                 channel_factory: Box::new(FuturesChannelFactoryReal {}),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
             };
 
             subject.establisher_factory = Box::new(StreamEstablisherFactoryMock {
@@ -753,7 +1045,13 @@ mod tests {
                 poll_write_results: vec![Ok(Async::NotReady)],
                 shutdown_results: Arc::new(Mutex::new(vec![])),
             };
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             let disconnected_sender = Box::new(SenderWrapperMock {
                 peer_addr,
                 unbounded_send_params: Arc::new(Mutex::new(vec![])),
@@ -781,6 +1079,7 @@ mod tests {
                         }),
                     )],
                 }),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
             };
 
             subject.establisher_factory = Box::new(StreamEstablisherFactoryMock {
@@ -839,7 +1138,13 @@ mod tests {
             let resolver = ResolverWrapperMock::new()
                 .lookup_ip_parameters(&mut lookup_ip_parameters)
                 .lookup_ip_failure(ResolveError::from(ResolveErrorKind::Io));
-            let subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
 
             let test_actor = TestActor { subject };
             let addr: Addr<Syn, TestActor> = test_actor.start();
@@ -864,6 +1169,262 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ip_address_target_is_looked_up_without_a_trailing_dot() {
+        let stream_key = make_meaningless_stream_key();
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let expected_lookup_ip_parameters = lookup_ip_parameters.clone();
+        thread::spawn(move || {
+            let client_request_payload = ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"These are the times".to_vec(),
+                    sequence_number: 0,
+                    last_data: true,
+                },
+                target_hostname: Some(String::from("1.2.3.4")),
+                target_port: 80,
+                protocol: ProxyProtocol::SOCKS5,
+                originator_public_key: Key::new(&b"men's souls"[..]),
+            };
+            let package = ExpiredCoresPackage::new(
+                test_utils::make_meaningless_route(),
+                PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]),
+            );
+            let system = System::new("test");
+            let hopper_sub = recorder::make_peer_actors_from(None, None, Some(hopper), None, None)
+                .hopper
+                .from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_parameters(&lookup_ip_parameters)
+                .lookup_ip_failure(ResolveError::from(ResolveErrorKind::Io));
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
+
+            let test_actor = TestActor { subject };
+            let addr: Addr<Syn, TestActor> = test_actor.start();
+            let test_trigger: Recipient<Syn, TriggerSubject> =
+                addr.clone().recipient::<TriggerSubject>();
+            test_trigger.try_send(TriggerSubject { package }).is_ok();
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count(1);
+        assert_eq!(
+            expected_lookup_ip_parameters.lock().unwrap().deref(),
+            &vec!(String::from("1.2.3.4"))
+        );
+    }
+
+    #[test]
+    fn blocked_destination_is_refused_before_resolution_with_an_error_page() {
+        init_test_logging();
+        let stream_key = make_meaningless_stream_key();
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let recording_arc = hopper.get_recording();
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let expected_lookup_ip_parameters = lookup_ip_parameters.clone();
+        thread::spawn(move || {
+            let client_request_payload = ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"These are the times".to_vec(),
+                    sequence_number: 0,
+                    last_data: false,
+                },
+                target_hostname: Some(String::from("db.internal.com")),
+                target_port: 80,
+                protocol: ProxyProtocol::HTTP,
+                originator_public_key: Key::new(&b"men's souls"[..]),
+            };
+            let package = ExpiredCoresPackage::new(
+                test_utils::make_meaningless_route(),
+                PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]),
+            );
+            let system = System::new("test");
+            let hopper_sub = recorder::make_peer_actors_from(None, None, Some(hopper), None, None)
+                .hopper
+                .from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_parameters(&lookup_ip_parameters)
+                .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+            let mut exit_filter = ExitFilter::new(ExitPolicy::allow_all());
+            exit_filter.blocked_hostnames = vec![String::from("*.internal.com")];
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                exit_filter,
+                ConnectionAttemptConfig::new(),
+            );
+
+            let test_actor = TestActor { subject };
+            let addr: Addr<Syn, TestActor> = test_actor.start();
+            let test_trigger: Recipient<Syn, TriggerSubject> =
+                addr.clone().recipient::<TriggerSubject>();
+            test_trigger.try_send(TriggerSubject { package }).is_ok();
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count(1);
+        TestLogHandler::new().exists_log_containing(
+            "WARN: Proxy Client: Refusing to connect to db.internal.com: db.internal.com is blocked",
+        );
+        let recording = recording_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        let client_response_payload =
+            serde_cbor::de::from_slice::<ClientResponsePayload>(&record.payload.data[..]).unwrap();
+        assert_eq!(
+            client_response_payload,
+            ClientResponsePayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: http_server_impersonator::make_error_response(
+                        403,
+                        "Exit Refused",
+                        "The exit Node won't connect to db.internal.com",
+                        "The Node chosen to carry your request out of the Substratum Network refused \
+                         to make the connection because db.internal.com is blocked. Another exit Node \
+                         may be more accommodating; try reloading the page.",
+                    ),
+                    sequence_number: 0,
+                    last_data: true,
+                },
+            }
+        );
+        assert_eq!(
+            expected_lookup_ip_parameters.lock().unwrap().is_empty(),
+            true
+        );
+    }
+
+    #[test]
+    fn hostname_that_resolves_only_to_blocked_addresses_is_refused() {
+        init_test_logging();
+        let stream_key = make_meaningless_stream_key();
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let recording_arc = hopper.get_recording();
+        thread::spawn(move || {
+            let client_request_payload = ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"These are the times".to_vec(),
+                    sequence_number: 0,
+                    last_data: false,
+                },
+                target_hostname: Some(String::from("rebound.com")),
+                target_port: 443,
+                protocol: ProxyProtocol::TLS,
+                originator_public_key: Key::new(&b"men's souls"[..]),
+            };
+            let package = ExpiredCoresPackage::new(
+                test_utils::make_meaningless_route(),
+                PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]),
+            );
+            let system = System::new("test");
+            let hopper_sub = recorder::make_peer_actors_from(None, None, Some(hopper), None, None)
+                .hopper
+                .from_hopper_client;
+            let resolver = ResolverWrapperMock::new().lookup_ip_success(vec![
+                IpAddr::from_str("10.0.0.1").unwrap(),
+                IpAddr::from_str("::1").unwrap(),
+            ]);
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::new(ExitPolicy::allow_all()),
+                ConnectionAttemptConfig::new(),
+            );
+
+            let test_actor = TestActor { subject };
+            let addr: Addr<Syn, TestActor> = test_actor.start();
+            let test_trigger: Recipient<Syn, TriggerSubject> =
+                addr.clone().recipient::<TriggerSubject>();
+            test_trigger.try_send(TriggerSubject { package }).is_ok();
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count(1);
+        TestLogHandler::new().exists_log_containing(
+            "WARN: Proxy Client: Refusing to connect to rebound.com: 10.0.0.1 is in a blocked address range",
+        );
+        let recording = recording_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        let client_response_payload =
+            serde_cbor::de::from_slice::<ClientResponsePayload>(&record.payload.data[..]).unwrap();
+        assert_eq!(
+            client_response_payload,
+            ClientResponsePayload::make_terminating_payload(stream_key)
+        );
+    }
+
+    #[test]
+    fn originator_key_claiming_to_be_this_node_does_not_get_past_the_exit_filter() {
+        init_test_logging();
+        let stream_key = make_meaningless_stream_key();
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter();
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let expected_lookup_ip_parameters = lookup_ip_parameters.clone();
+        thread::spawn(move || {
+            let client_request_payload = ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"These are the times".to_vec(),
+                    sequence_number: 0,
+                    last_data: false,
+                },
+                target_hostname: Some(String::from("127.0.0.1")),
+                target_port: 25,
+                protocol: ProxyProtocol::SOCKS5,
+                originator_public_key: cryptde().public_key(),
+            };
+            let package = ExpiredCoresPackage::new(
+                test_utils::make_meaningless_route(),
+                PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]),
+            );
+            let system = System::new("test");
+            let hopper_sub = recorder::make_peer_actors_from(None, None, Some(hopper), None, None)
+                .hopper
+                .from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_parameters(&lookup_ip_parameters)
+                .lookup_ip_success(vec![IpAddr::from_str("127.0.0.1").unwrap()]);
+            let subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::new(ExitPolicy::allow_all()),
+                ConnectionAttemptConfig::new(),
+            );
+
+            let test_actor = TestActor { subject };
+            let addr: Addr<Syn, TestActor> = test_actor.start();
+            let test_trigger: Recipient<Syn, TriggerSubject> =
+                addr.clone().recipient::<TriggerSubject>();
+            test_trigger.try_send(TriggerSubject { package }).is_ok();
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count(1);
+        TestLogHandler::new()
+            .exists_log_containing("WARN: Proxy Client: Refusing to connect to 127.0.0.1: ");
+        assert_eq!(
+            expected_lookup_ip_parameters.lock().unwrap().is_empty(),
+            true
+        );
+    }
+
     #[test]
     fn after_writing_last_data_the_stream_should_close() {
         init_test_logging();
@@ -894,7 +1455,13 @@ mod tests {
                 .hopper
                 .from_hopper_client;
             let resolver = ResolverWrapperMock::new();
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             subject.stream_writer_channels.insert(
                 stream_key,
                 Box::new(SenderWrapperReal::new(
@@ -966,7 +1533,13 @@ mod tests {
                 .from_hopper_client;
             let resolver = ResolverWrapperMock::new();
 
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
             subject
                 .stream_writer_channels
                 .insert(stream_key, Box::new(sender_wrapper));
@@ -1014,7 +1587,13 @@ mod tests {
                 PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]),
             );
             let resolver = ResolverWrapperMock::new();
-            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver), cryptde(), hopper_sub);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
 
             subject.establisher_factory = Box::new(StreamEstablisherFactoryMock {
                 make_results: RefCell::new(vec![]),
@@ -1041,4 +1620,209 @@ mod tests {
         let hopper_recording = hopper_recording_arc.lock().unwrap();
         assert_eq!(hopper_recording.len(), 0);
     }
+
+    fn make_pending_stream_payload(sequence_number: u64, last_data: bool) -> ClientRequestPayload {
+        ClientRequestPayload {
+            stream_key: make_meaningless_stream_key(),
+            sequenced_packet: SequencedPacket {
+                data: format!("packet {}", sequence_number).into_bytes(),
+                sequence_number,
+                last_data,
+            },
+            target_hostname: Some(String::from("that.try")),
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: Key::new(&b"men's souls"[..]),
+        }
+    }
+
+    // Runs the payloads through a subject whose single lookup and connection only happen once they've all arrived
+    fn run_pending_stream_subject(
+        payloads: Vec<ClientRequestPayload>,
+        pending_packet_limit: usize,
+        pending_stream_timeout: Duration,
+        hopper: Recorder,
+    ) -> (
+        Arc<Mutex<Vec<String>>>,
+        Arc<Mutex<Vec<SequencedPacket>>>,
+        mpsc::Receiver<StreamKey>,
+    ) {
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec![]));
+        let lookup_ip_parameters_inner = lookup_ip_parameters.clone();
+        let peer_addr = SocketAddr::from_str("2.3.4.5:80").unwrap();
+        let tx_to_write: SenderWrapperMock<SequencedPacket> = SenderWrapperMock::new(peer_addr);
+        let write_parameters = tx_to_write.unbounded_send_params.clone();
+        let (stream_killer_tx, stream_killer_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let system = System::new("test");
+            let hopper_sub = recorder::make_peer_actors_from(None, None, Some(hopper), None, None)
+                .hopper
+                .from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_parameters(&lookup_ip_parameters_inner)
+                .lookup_ip_success(vec![IpAddr::from_str("2.3.4.5").unwrap()]);
+            let mut subject = StreamHandlerPoolReal::new(
+                Box::new(resolver),
+                cryptde(),
+                hopper_sub,
+                ExitFilter::allow_all(),
+                ConnectionAttemptConfig::new(),
+            );
+            subject.pending_packet_limit = pending_packet_limit;
+            subject.pending_stream_timeout = pending_stream_timeout;
+            let (stream_adder_tx, _stream_adder_rx) = mpsc::channel();
+            let establisher = StreamEstablisher {
+                stream_adder_tx,
+                stream_killer_tx,
+                stream_connector: Box::new(StreamConnectorMock::new().with_connection(
+                    peer_addr.clone(),
+                    peer_addr.clone(),
+                    ReadHalfWrapperMock {
+                        poll_read_results: vec![(vec![], Ok(Async::NotReady))],
+                    },
+                    WriteHalfWrapperMock {
+                        poll_write_params: Arc::new(Mutex::new(vec![])),
+                        poll_write_results: vec![],
+                        shutdown_results: Arc::new(Mutex::new(vec![])),
+                    },
+                )),
+                hopper_sub: subject.hopper_sub.clone(),
+                logger: subject.logger.clone(),
+                channel_factory: Box::new(FuturesChannelFactoryMock {
+                    results: vec![(
+                        Box::new(tx_to_write),
+                        Box::new(ReceiverWrapperMock {
+                            poll_results: vec![Ok(Async::NotReady)],
+                        }),
+                    )],
+                }),
+                connection_attempt_config: ConnectionAttemptConfig::new(),
+            };
+            subject.establisher_factory = Box::new(StreamEstablisherFactoryMock {
+                make_results: RefCell::new(vec![establisher]),
+            });
+
+            // The resolution future doesn't run until the System does
+            payloads.into_iter().for_each(|payload| {
+                subject.process_package(payload, test_utils::make_meaningless_route())
+            });
+
+            system.run();
+        });
+        (lookup_ip_parameters, write_parameters, stream_killer_rx)
+    }
+
+    #[test]
+    fn packets_that_arrive_while_a_stream_is_being_established_are_written_to_it_in_order() {
+        let (lookup_ip_parameters, write_parameters, stream_killer_rx) = run_pending_stream_subject(
+            vec![
+                make_pending_stream_payload(0, false),
+                make_pending_stream_payload(2, true),
+                make_pending_stream_payload(1, false),
+            ],
+            PENDING_PACKET_LIMIT,
+            Duration::from_secs(PENDING_STREAM_TIMEOUT_SECS),
+            Recorder::new(),
+        );
+
+        await_messages(3, &write_parameters);
+        assert_eq!(
+            write_parameters.lock().unwrap().deref(),
+            &vec![
+                make_pending_stream_payload(0, false).sequenced_packet,
+                make_pending_stream_payload(1, false).sequenced_packet,
+                make_pending_stream_payload(2, true).sequenced_packet,
+            ]
+        );
+        assert_eq!(
+            lookup_ip_parameters.lock().unwrap().deref(),
+            &vec![String::from("that.try.")]
+        );
+        assert_eq!(
+            stream_killer_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(make_meaningless_stream_key())
+        );
+    }
+
+    #[test]
+    fn stream_is_abandoned_when_too_many_packets_arrive_while_it_is_being_established() {
+        init_test_logging();
+        let (hopper, hopper_awaiter, hopper_recording_arc) = make_recorder();
+
+        let (lookup_ip_parameters, write_parameters, stream_killer_rx) = run_pending_stream_subject(
+            vec![
+                make_pending_stream_payload(0, false),
+                make_pending_stream_payload(1, false),
+                make_pending_stream_payload(2, false),
+                make_pending_stream_payload(3, false),
+            ],
+            2,
+            Duration::from_secs(PENDING_STREAM_TIMEOUT_SECS),
+            hopper,
+        );
+
+        hopper_awaiter.await_message_count(1);
+        let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload>(
+            &hopper_recording_arc
+                .lock()
+                .unwrap()
+                .get_record::<IncipientCoresPackage>(0)
+                .payload
+                .data[..],
+        )
+        .unwrap();
+        assert_eq!(
+            client_response_payload,
+            ClientResponsePayload::make_terminating_payload(make_meaningless_stream_key())
+        );
+        // The connection made for the abandoned stream is closed without anything written to it
+        assert_eq!(
+            stream_killer_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(make_meaningless_stream_key())
+        );
+        assert_eq!(write_parameters.lock().unwrap().is_empty(), true);
+        assert_eq!(lookup_ip_parameters.lock().unwrap().len(), 1);
+        TestLogHandler::new().exists_log_containing(&format!(
+            "More than 2 packets arrived for stream {:?} before it could be established; abandoning it",
+            make_meaningless_stream_key()
+        ));
+    }
+
+    #[test]
+    fn stream_is_abandoned_when_it_takes_too_long_to_establish() {
+        init_test_logging();
+        let (hopper, hopper_awaiter, hopper_recording_arc) = make_recorder();
+
+        let (_, write_parameters, stream_killer_rx) = run_pending_stream_subject(
+            vec![
+                make_pending_stream_payload(0, false),
+                make_pending_stream_payload(1, false),
+            ],
+            PENDING_PACKET_LIMIT,
+            Duration::from_secs(0),
+            hopper,
+        );
+
+        hopper_awaiter.await_message_count(1);
+        let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload>(
+            &hopper_recording_arc
+                .lock()
+                .unwrap()
+                .get_record::<IncipientCoresPackage>(0)
+                .payload
+                .data[..],
+        )
+        .unwrap();
+        assert_eq!(
+            client_response_payload,
+            ClientResponsePayload::make_terminating_payload(make_meaningless_stream_key())
+        );
+        assert_eq!(
+            stream_killer_rx.recv_timeout(Duration::from_secs(1)),
+            Ok(make_meaningless_stream_key())
+        );
+        assert_eq!(write_parameters.lock().unwrap().is_empty(), true);
+        TestLogHandler::new()
+            .exists_log_containing("could not be established in 0ns; abandoning it");
+    }
 }
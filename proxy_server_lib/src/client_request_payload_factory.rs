@@ -7,6 +7,7 @@ use sub_lib::cryptde::PlainData;
 use sub_lib::dispatcher::InboundClientData;
 use sub_lib::logger::Logger;
 use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::sequence_buffer::SequencedPacket;
 use sub_lib::stream_key::StreamKey;
 use tls_protocol_pack::TlsProtocolPack;
@@ -16,10 +17,18 @@ pub struct ClientRequestPayloadFactory {
 }
 
 impl ClientRequestPayloadFactory {
-    pub fn new() -> ClientRequestPayloadFactory {
-        let mut protocol_packs: HashMap<u16, Box<ProtocolPack>> = HashMap::new();
-        protocol_packs.insert(80, Box::new(HttpProtocolPack {}));
-        protocol_packs.insert(443, Box::new(TlsProtocolPack {}));
+    pub fn new(proxied_ports: &[(u16, ProxyProtocol)]) -> ClientRequestPayloadFactory {
+        let protocol_packs: HashMap<u16, Box<ProtocolPack>> = proxied_ports
+            .iter()
+            .map(|&(port, protocol)| {
+                let protocol_pack: Box<ProtocolPack> = match protocol {
+                    ProxyProtocol::HTTP => Box::new(HttpProtocolPack {}),
+                    ProxyProtocol::TLS => Box::new(TlsProtocolPack {}),
+                    _ => panic!("No protocol pack for {:?} on port {}", protocol, port),
+                };
+                (port, protocol_pack)
+            })
+            .collect();
         ClientRequestPayloadFactory { protocol_packs }
     }
 
@@ -85,7 +94,7 @@ mod tests {
     use std::net::SocketAddr;
     use std::str::FromStr;
     use sub_lib::cryptde_null::CryptDENull;
-    use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::proxy_server::DEFAULT_PROXIED_PORTS;
     use test_utils::logging::init_test_logging;
     use test_utils::logging::TestLogHandler;
     use test_utils::test_utils::make_meaningless_stream_key;
@@ -103,7 +112,7 @@ mod tests {
         };
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -157,7 +166,7 @@ mod tests {
         };
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -204,7 +213,7 @@ mod tests {
         };
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -238,7 +247,7 @@ mod tests {
         };
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -261,7 +270,7 @@ mod tests {
         };
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -282,7 +291,7 @@ mod tests {
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
 
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject
             .make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger)
@@ -305,7 +314,7 @@ mod tests {
         let cryptde = CryptDENull::new();
         let logger = Logger::new("test");
 
-        let subject = ClientRequestPayloadFactory::new();
+        let subject = ClientRequestPayloadFactory::new(DEFAULT_PROXIED_PORTS);
 
         let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
 
@@ -315,4 +324,45 @@ mod tests {
             "ERROR: test: internal error: got IBCD with no sequence number and 4 bytes",
         );
     }
+
+    #[test]
+    fn handles_http_on_another_proxied_port_and_keeps_that_port_as_the_target() {
+        let data =
+            PlainData::new(&b"GET /index.html HTTP/1.1\r\nHost: borkoed.com:8080\r\n\r\n"[..]);
+        let ibcd = InboundClientData {
+            peer_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            reception_port: Some(8080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: data.data.clone(),
+        };
+        let cryptde = CryptDENull::new();
+        let logger = Logger::new("test");
+        let subject = ClientRequestPayloadFactory::new(&[(8080, ProxyProtocol::HTTP)]);
+
+        let result = subject.make(&ibcd, make_meaningless_stream_key(), &cryptde, &logger);
+
+        assert_eq!(
+            result,
+            Some(ClientRequestPayload {
+                stream_key: make_meaningless_stream_key(),
+                sequenced_packet: SequencedPacket {
+                    data: data.data,
+                    sequence_number: 0,
+                    last_data: false
+                },
+                target_hostname: Some(String::from("borkoed.com")),
+                target_port: 8080,
+                protocol: ProxyProtocol::HTTP,
+                originator_public_key: cryptde.public_key(),
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No protocol pack for SOCKS5 on port 1080")]
+    fn complains_about_proxied_port_with_no_protocol_pack() {
+        ClientRequestPayloadFactory::new(&[(1080, ProxyProtocol::SOCKS5)]);
+    }
 }
@@ -12,6 +12,10 @@ impl ProtocolPack for HttpProtocolPack {
     }
 
     fn find_host_name(&self, data: &PlainData) -> Option<String> {
+        match HttpProtocolPack::find_connect_target(&data.data[..]) {
+            Some((hostname, _)) => return Some(hostname),
+            None => (),
+        }
         match HttpProtocolPack::find_header_host_name(&data.data[..]) {
             Some(string) => return Some(string),
             None => (),
@@ -21,6 +25,37 @@ impl ProtocolPack for HttpProtocolPack {
 }
 
 impl HttpProtocolPack {
+    // A client configured to use us as an explicit proxy asks for a tunnel with "CONNECT host:port HTTP/1.1"
+    pub fn find_connect_target(data: &[u8]) -> Option<(String, u16)> {
+        let prefix = b"CONNECT ";
+        if !data.starts_with(&prefix[..]) {
+            return None;
+        }
+        let idx = index_of(data, &b"\r\n"[..])?;
+        let first_line = &data[prefix.len()..idx];
+        let end = index_of(first_line, &b" "[..])?;
+        let authority = match String::from_utf8(Vec::from(&first_line[0..end])) {
+            Ok(authority) => authority,
+            Err(_) => return None,
+        };
+        let colon = authority.rfind(':')?;
+        let port = match authority[(colon + 1)..].parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => return None,
+        };
+        let host = &authority[0..colon];
+        let host = if host.starts_with('[') && host.ends_with(']') {
+            &host[1..(host.len() - 1)]
+        } else {
+            host
+        };
+        if host.is_empty() {
+            None
+        } else {
+            Some((String::from(host), port))
+        }
+    }
+
     fn find_header_host_name(data: &[u8]) -> Option<String> {
         let idx = index_of(data, &b"\r\n\r\n"[..])?;
         let headers = &data[0..idx + 2];
@@ -29,11 +64,19 @@ impl HttpProtocolPack {
         let end = index_of(&headers[begin..], &b"\r\n"[..])? + begin;
         let hostname_u8s = &headers[begin..end];
         match String::from_utf8(Vec::from(hostname_u8s)) {
-            Ok(result) => Some(result),
+            Ok(result) => Some(HttpProtocolPack::strip_port(result)),
             Err(_) => None,
         }
     }
 
+    // The target port is the one the client connected to, so a port in the Host header is redundant
+    fn strip_port(host: String) -> String {
+        match host.rfind(':') {
+            Some(colon) if !host[colon..].contains(']') => String::from(&host[0..colon]),
+            _ => host,
+        }
+    }
+
     fn find_url_host_name(data: &[u8]) -> Option<String> {
         let idx = index_of(data, &b"\r\n"[..])?;
         let first_line = &data[0..idx];
@@ -164,6 +207,16 @@ mod tests {
         assert_eq!(result, Some(String::from("top.host.com")));
     }
 
+    #[test]
+    fn returns_host_name_from_header_without_port() {
+        let data =
+            PlainData::new(b"GET /index.html HTTP/1.1\r\nHost: header.host.com:8080\r\n\r\n");
+
+        let result = HttpProtocolPack {}.find_host_name(&data);
+
+        assert_eq!(result, Some(String::from("header.host.com")));
+    }
+
     #[test]
     fn from_integration_test() {
         let data = PlainData::new(b"GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n");
@@ -172,4 +225,47 @@ mod tests {
 
         assert_eq!(result, Some(String::from("www.example.com")));
     }
+
+    #[test]
+    fn returns_host_name_from_connect_request_without_port() {
+        let data = PlainData::new(
+            b"CONNECT tunnel.host.com:443 HTTP/1.1\r\nHost: tunnel.host.com:443\r\n\r\n",
+        );
+
+        let result = HttpProtocolPack {}.find_host_name(&data);
+
+        assert_eq!(result, Some(String::from("tunnel.host.com")));
+    }
+
+    #[test]
+    fn finds_connect_target_host_and_port() {
+        let result = HttpProtocolPack::find_connect_target(
+            b"CONNECT tunnel.host.com:8443 HTTP/1.1\r\nHost: tunnel.host.com:8443\r\n\r\n",
+        );
+
+        assert_eq!(result, Some((String::from("tunnel.host.com"), 8443)));
+    }
+
+    #[test]
+    fn finds_connect_target_with_ipv6_address() {
+        let result =
+            HttpProtocolPack::find_connect_target(b"CONNECT [2001:db8::1]:443 HTTP/1.1\r\n\r\n");
+
+        assert_eq!(result, Some((String::from("2001:db8::1"), 443)));
+    }
+
+    #[test]
+    fn finds_no_connect_target_in_other_requests_or_without_a_port() {
+        let results = vec![
+            HttpProtocolPack::find_connect_target(b"GET http://top.host.com:443/ HTTP/1.1\r\n\r\n"),
+            HttpProtocolPack::find_connect_target(b"CONNECT tunnel.host.com HTTP/1.1\r\n\r\n"),
+            HttpProtocolPack::find_connect_target(
+                b"CONNECT tunnel.host.com:https HTTP/1.1\r\n\r\n",
+            ),
+            HttpProtocolPack::find_connect_target(b"CONNECT :443 HTTP/1.1\r\n\r\n"),
+            HttpProtocolPack::find_connect_target(b"CONNECT tunnel.host.com:443"),
+        ];
+
+        assert_eq!(results, vec![None, None, None, None, None]);
+    }
 }
@@ -13,4 +13,5 @@ pub mod client_request_payload_factory;
 pub mod http_protocol_pack;
 pub mod protocol_pack;
 pub mod proxy_server;
+pub mod socks5;
 pub mod tls_protocol_pack;
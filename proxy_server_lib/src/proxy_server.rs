@@ -1,12 +1,19 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use actix::Actor;
 use actix::Addr;
+use actix::AsyncContext;
 use actix::Context;
 use actix::Handler;
-use actix::MailboxError;
+use actix::Message;
 use actix::Recipient;
 use actix::Syn;
 use client_request_payload_factory::ClientRequestPayloadFactory;
+use futures::future::Shared;
+use http_protocol_pack::HttpProtocolPack;
+use socks5;
+use socks5::Socks5Negotiator;
+use socks5::Socks5Step;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use sub_lib::bidi_hashmap::BidiHashMap;
 use sub_lib::cryptde::CryptDE;
@@ -17,6 +24,9 @@ use sub_lib::hopper::ExpiredCoresPackage;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::http_server_impersonator;
 use sub_lib::logger::Logger;
+use sub_lib::neighborhood::RouteConstraint;
+use sub_lib::neighborhood::RoutePolicies;
+use sub_lib::neighborhood::RoutePolicy;
 use sub_lib::neighborhood::RouteQueryMessage;
 use sub_lib::neighborhood::RouteQueryResponse;
 use sub_lib::peer_actors::BindMessage;
@@ -24,12 +34,21 @@ use sub_lib::proxy_client::ClientResponsePayload;
 use sub_lib::proxy_server::ClientRequestPayload;
 use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::proxy_server::ProxyServerSubs;
+use sub_lib::sequence_buffer::SequencedPacket;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
 use sub_lib::stream_key::StreamKey;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
 use tokio;
 use tokio::prelude::Future;
 
+// The greeting and the CONNECT request each get a reply before any data from the target arrives
+const SOCKS_REPLY_COUNT: u64 = 2;
+// An HTTP CONNECT request gets one
+const TUNNEL_REPLY_COUNT: u64 = 1;
+const CONNECTION_ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+
+type RouteQuery = Box<Future<Item = Option<RouteQueryResponse>, Error = String> + Send>;
+
 pub struct ProxyServer {
     dispatcher: Option<Recipient<Syn, TransmitDataMsg>>,
     hopper: Option<Recipient<Syn, IncipientCoresPackage>>,
@@ -37,7 +56,12 @@ pub struct ProxyServer {
     client_request_payload_factory: ClientRequestPayloadFactory,
     stream_key_factory: Box<StreamKeyFactory>,
     keys_and_addrs: BidiHashMap<StreamKey, SocketAddr>,
-    is_decentralized: bool, // TODO: This should be replaced by something more general and configurable.
+    route_policies: RoutePolicies,
+    socks_port_opt: Option<u16>,
+    socks_streams: HashMap<SocketAddr, SocksStream>,
+    tunnels: HashMap<SocketAddr, (String, u16)>,
+    // Every packet of a stream has to take the same route, or the exit would get it in pieces
+    route_queries: HashMap<StreamKey, Shared<RouteQuery>>,
     cryptde: &'static CryptDE,
     logger: Logger,
 }
@@ -61,7 +85,7 @@ impl Handler<BindMessage> for ProxyServer {
 impl Handler<InboundClientData> for ProxyServer {
     type Result = ();
 
-    fn handle(&mut self, msg: InboundClientData, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: InboundClientData, ctx: &mut Self::Context) -> Self::Result {
         let route_source = self
             .route_source
             .as_ref()
@@ -78,28 +102,65 @@ impl Handler<InboundClientData> for ProxyServer {
             .expect("Dispatcher unbound in ProxyServer")
             .clone();
         let source_addr = msg.peer_addr;
-        let payload = match self.make_payload(msg) {
+        let payload_result = if self.is_socks(&msg) {
+            self.make_socks_payload(msg, &dispatcher)
+        } else if self.is_tunnel(&msg) {
+            self.make_tunnel_payload(msg)
+        } else {
+            self.make_payload(msg)
+        };
+        let payload = match payload_result {
             Ok(payload) => payload,
             Err(_) => return (),
         };
         let logger = self.logger.clone();
-        let minimum_hop_count = if self.is_decentralized { 2 } else { 0 };
-        tokio::spawn(
-            route_source
-                .send(RouteQueryMessage::data_indefinite_route_request(
-                    minimum_hop_count,
-                ))
-                .then(move |route_result| {
-                    ProxyServer::try_transmit_to_hopper(
-                        hopper,
-                        route_result,
-                        payload,
-                        logger,
-                        source_addr,
-                        dispatcher,
-                    )
-                }),
-        );
+        let subject_addr: Addr<Syn, ProxyServer> = ctx.address();
+        let route_failure_sub = subject_addr.recipient::<RouteFailureMessage>();
+        let route_query = self.route_query_for(&payload, &route_source);
+        if payload.sequenced_packet.last_data {
+            self.route_queries.remove(&payload.stream_key);
+        }
+        let configured_policy_opt = self
+            .route_policies
+            .configured_policy_for(payload.target_hostname.as_ref());
+        tokio::spawn(route_query.then(move |route_result| {
+            let route_result = match route_result {
+                Ok(response_opt) => Ok((*response_opt).clone()),
+                Err(e) => Err((*e).clone()),
+            };
+            ProxyServer::try_transmit_to_hopper(
+                hopper,
+                route_result,
+                payload,
+                configured_policy_opt,
+                logger,
+                source_addr,
+                dispatcher,
+                route_failure_sub,
+            )
+        }));
+        ()
+    }
+}
+
+// Sent by the ProxyServer to itself when no route could be found for a client's stream, so that it can forget
+// the stream
+struct RouteFailureMessage {
+    peer_addr: SocketAddr,
+    stream_key: StreamKey,
+}
+
+impl Message for RouteFailureMessage {
+    type Result = ();
+}
+
+impl Handler<RouteFailureMessage> for ProxyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: RouteFailureMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.route_queries.remove(&msg.stream_key);
+        self.socks_streams.remove(&msg.peer_addr);
+        self.tunnels.remove(&msg.peer_addr);
         ()
     }
 }
@@ -117,18 +178,23 @@ impl Handler<ExpiredCoresPackage> for ProxyServer {
                 match self.keys_and_addrs.a_to_b(&payload.stream_key) {
                     Some(socket_addr) => {
                         let last_data = payload.sequenced_packet.last_data;
+                        let sequence_number = payload.sequenced_packet.sequence_number
+                            + self.reply_count(&socket_addr);
                         self.dispatcher
                             .as_ref()
                             .expect("Dispatcher unbound in ProxyServer")
                             .try_send(TransmitDataMsg {
                                 endpoint: Endpoint::Socket(socket_addr),
                                 last_data,
-                                sequence_number: Some(payload.sequenced_packet.sequence_number),
+                                sequence_number: Some(sequence_number),
                                 data: payload.sequenced_packet.data.clone(),
                             })
                             .expect("Dispatcher is dead");
                         if last_data {
+                            self.route_queries.remove(&payload.stream_key);
                             self.keys_and_addrs.remove_b(&socket_addr);
+                            self.socks_streams.remove(&socket_addr);
+                            self.tunnels.remove(&socket_addr);
                         }
                     }
                     None => self.logger.error(format!(
@@ -151,15 +217,24 @@ impl Handler<ExpiredCoresPackage> for ProxyServer {
 }
 
 impl ProxyServer {
-    pub fn new(cryptde: &'static CryptDE, is_decentralized: bool) -> ProxyServer {
+    pub fn new(
+        cryptde: &'static CryptDE,
+        route_policies: RoutePolicies,
+        proxied_ports: &[(u16, ProxyProtocol)],
+        socks_port_opt: Option<u16>,
+    ) -> ProxyServer {
         ProxyServer {
             dispatcher: None,
             hopper: None,
             route_source: None,
-            client_request_payload_factory: ClientRequestPayloadFactory::new(),
+            client_request_payload_factory: ClientRequestPayloadFactory::new(proxied_ports),
             stream_key_factory: Box::new(StreamKeyFactoryReal {}),
             keys_and_addrs: BidiHashMap::new(),
-            is_decentralized,
+            route_policies,
+            socks_port_opt,
+            socks_streams: HashMap::new(),
+            tunnels: HashMap::new(),
+            route_queries: HashMap::new(),
             cryptde,
             logger: Logger::new("Proxy Server"),
         }
@@ -174,17 +249,7 @@ impl ProxyServer {
     }
 
     fn make_payload(&mut self, msg: InboundClientData) -> Result<ClientRequestPayload, ()> {
-        let stream_key = match self.keys_and_addrs.b_to_a(&msg.peer_addr) {
-            Some(stream_key) => stream_key,
-            None => {
-                let stream_key = self
-                    .stream_key_factory
-                    .make(&self.cryptde.public_key(), msg.peer_addr);
-                self.keys_and_addrs
-                    .insert(stream_key.clone(), msg.peer_addr);
-                stream_key
-            }
-        };
+        let stream_key = self.stream_key_for(msg.peer_addr);
         match self
             .client_request_payload_factory
             .make(&msg, stream_key, self.cryptde, &self.logger)
@@ -198,13 +263,234 @@ impl ProxyServer {
         }
     }
 
+    fn stream_key_for(&mut self, peer_addr: SocketAddr) -> StreamKey {
+        match self.keys_and_addrs.b_to_a(&peer_addr) {
+            Some(stream_key) => stream_key,
+            None => {
+                let stream_key = self
+                    .stream_key_factory
+                    .make(&self.cryptde.public_key(), peer_addr);
+                self.keys_and_addrs.insert(stream_key.clone(), peer_addr);
+                stream_key
+            }
+        }
+    }
+
+    // The first packet of a stream asks the Neighborhood for a route; the rest wait for the same answer
+    fn route_query_for(
+        &mut self,
+        payload: &ClientRequestPayload,
+        route_source: &Recipient<Syn, RouteQueryMessage>,
+    ) -> Shared<RouteQuery> {
+        if let Some(route_query) = self.route_queries.get(&payload.stream_key) {
+            return route_query.clone();
+        }
+        let route_policy = self
+            .route_policies
+            .policy_for(payload.target_hostname.as_ref());
+        let constraint =
+            RouteConstraint::for_exit_to(payload.target_hostname.clone(), payload.target_port);
+        let route_query: RouteQuery = Box::new(
+            route_source
+                .send(RouteQueryMessage::data_indefinite_route_request(
+                    route_policy,
+                    constraint,
+                ))
+                .map_err(|e| format!("{}", e)),
+        );
+        let route_query = route_query.shared();
+        self.route_queries
+            .insert(payload.stream_key.clone(), route_query.clone());
+        route_query
+    }
+
+    fn is_socks(&self, msg: &InboundClientData) -> bool {
+        msg.reception_port.is_some() && (msg.reception_port == self.socks_port_opt)
+    }
+
+    // Until the client's CONNECT request is complete, its data goes to the negotiator and nothing goes
+    // to the Hopper. After that, the client's data is numbered from 0 as if the stream started there.
+    fn make_socks_payload(
+        &mut self,
+        msg: InboundClientData,
+        dispatcher: &Recipient<Syn, TransmitDataMsg>,
+    ) -> Result<ClientRequestPayload, ()> {
+        let peer_addr = msg.peer_addr;
+        let stream = self
+            .socks_streams
+            .remove(&peer_addr)
+            .unwrap_or_else(|| SocksStream::Negotiating(Socks5Negotiator::new()));
+        let (sequenced_packet, target_hostname, target_port) = match stream {
+            SocksStream::Connected {
+                target_hostname,
+                target_port,
+                next_sequence_number,
+            } => {
+                if !msg.last_data {
+                    self.socks_streams.insert(
+                        peer_addr,
+                        SocksStream::Connected {
+                            target_hostname: target_hostname.clone(),
+                            target_port,
+                            next_sequence_number: next_sequence_number + 1,
+                        },
+                    );
+                }
+                (
+                    SequencedPacket::new(msg.data, next_sequence_number, msg.last_data),
+                    target_hostname,
+                    target_port,
+                )
+            }
+            SocksStream::Negotiating(mut negotiator) => {
+                negotiator.add_data(&msg.data);
+                loop {
+                    match negotiator.take_step() {
+                        Socks5Step::NeedMoreData => {
+                            if !msg.last_data {
+                                self.socks_streams
+                                    .insert(peer_addr, SocksStream::Negotiating(negotiator));
+                            }
+                            return Err(());
+                        }
+                        Socks5Step::Reply(data) => {
+                            ProxyServer::send_reply(dispatcher, peer_addr, data, 0, false)
+                        }
+                        Socks5Step::Fail(data) => {
+                            let sequence_number = if negotiator.is_greeted() { 1 } else { 0 };
+                            ProxyServer::send_reply(
+                                dispatcher,
+                                peer_addr,
+                                data,
+                                sequence_number,
+                                true,
+                            );
+                            self.logger
+                                .warning(format!("Rejected SOCKS5 negotiation from {}", peer_addr));
+                            return Err(());
+                        }
+                        Socks5Step::Connect {
+                            hostname,
+                            port,
+                            remainder,
+                        } => {
+                            self.logger.debug(format!(
+                                "SOCKS5 client {} wants to connect to {}:{}",
+                                peer_addr, hostname, port
+                            ));
+                            if !msg.last_data {
+                                self.socks_streams.insert(
+                                    peer_addr,
+                                    SocksStream::Connected {
+                                        target_hostname: hostname.clone(),
+                                        target_port: port,
+                                        next_sequence_number: 1,
+                                    },
+                                );
+                            }
+                            break (
+                                SequencedPacket::new(remainder, 0, msg.last_data),
+                                hostname,
+                                port,
+                            );
+                        }
+                    }
+                }
+            }
+        };
+        Ok(ClientRequestPayload {
+            stream_key: self.stream_key_for(peer_addr),
+            sequenced_packet,
+            target_hostname: Some(target_hostname),
+            target_port,
+            protocol: ProxyProtocol::SOCKS5,
+            originator_public_key: self.cryptde.public_key().clone(),
+        })
+    }
+
+    fn is_tunnel(&self, msg: &InboundClientData) -> bool {
+        self.tunnels.contains_key(&msg.peer_addr)
+            || ((msg.sequence_number == Some(0))
+                && HttpProtocolPack::find_connect_target(&msg.data).is_some())
+    }
+
+    // The CONNECT request is answered here rather than sent along, so the first payload of a tunnel is
+    // empty; it just opens the stream at the exit. Client data after it keeps its own sequence numbers.
+    fn make_tunnel_payload(&mut self, msg: InboundClientData) -> Result<ClientRequestPayload, ()> {
+        let sequence_number = match msg.sequence_number {
+            Some(sequence_number) => sequence_number,
+            None => {
+                self.logger.error(format!(
+                    "internal error: got IBCD with no sequence number and {} bytes",
+                    msg.data.len()
+                ));
+                return Err(());
+            }
+        };
+        let peer_addr = msg.peer_addr;
+        let (target_hostname, target_port, data) = match self.tunnels.remove(&peer_addr) {
+            Some((hostname, port)) => (hostname, port, msg.data),
+            None => {
+                let (hostname, port) = HttpProtocolPack::find_connect_target(&msg.data)
+                    .expect("CONNECT request disappeared");
+                self.logger.debug(format!(
+                    "HTTP client {} wants a tunnel to {}:{}",
+                    peer_addr, hostname, port
+                ));
+                (hostname, port, vec![])
+            }
+        };
+        if !msg.last_data {
+            self.tunnels
+                .insert(peer_addr, (target_hostname.clone(), target_port));
+        }
+        Ok(ClientRequestPayload {
+            stream_key: self.stream_key_for(peer_addr),
+            sequenced_packet: SequencedPacket::new(data, sequence_number, msg.last_data),
+            target_hostname: Some(target_hostname),
+            target_port,
+            protocol: ProxyProtocol::TUNNEL,
+            originator_public_key: self.cryptde.public_key().clone(),
+        })
+    }
+
+    // How many packets the ProxyServer itself sent the client before the target's data
+    fn reply_count(&self, socket_addr: &SocketAddr) -> u64 {
+        if self.socks_streams.contains_key(socket_addr) {
+            SOCKS_REPLY_COUNT
+        } else if self.tunnels.contains_key(socket_addr) {
+            TUNNEL_REPLY_COUNT
+        } else {
+            0
+        }
+    }
+
+    fn send_reply(
+        dispatcher: &Recipient<Syn, TransmitDataMsg>,
+        peer_addr: SocketAddr,
+        data: Vec<u8>,
+        sequence_number: u64,
+        last_data: bool,
+    ) {
+        dispatcher
+            .try_send(TransmitDataMsg {
+                endpoint: Endpoint::Socket(peer_addr),
+                last_data,
+                sequence_number: Some(sequence_number),
+                data,
+            })
+            .expect("Dispatcher is dead");
+    }
+
     fn try_transmit_to_hopper(
         hopper: Recipient<Syn, IncipientCoresPackage>,
-        route_result: Result<Option<RouteQueryResponse>, MailboxError>,
+        route_result: Result<Option<RouteQueryResponse>, String>,
         payload: ClientRequestPayload,
+        configured_policy_opt: Option<RoutePolicy>,
         logger: Logger,
         source_addr: SocketAddr,
         dispatcher: Recipient<Syn, TransmitDataMsg>,
+        route_failure_sub: Recipient<Syn, RouteFailureMessage>,
     ) -> Result<(), ()> {
         match route_result {
             Ok(Some(response)) => {
@@ -212,16 +498,41 @@ impl ProxyServer {
                     .segment_endpoints
                     .first()
                     .expect("no segment endpoints");
+                if ProxyServer::opens_negotiated_stream(&payload) {
+                    ProxyServer::send_stream_opened_reply(&payload, source_addr, &dispatcher);
+                }
                 let pkg =
                     IncipientCoresPackage::new(response.route, payload, &payload_destination_key);
                 hopper.try_send(pkg).expect("Hopper is dead");
             }
             Ok(None) => {
+                // Forget the stream before the client hears about it, in case it tries again right away
+                route_failure_sub
+                    .try_send(RouteFailureMessage {
+                        peer_addr: source_addr,
+                        stream_key: payload.stream_key.clone(),
+                    })
+                    .expect("ProxyServer is dead");
                 let target_hostname = ProxyServer::hostname(&payload);
-                ProxyServer::send_route_failure(payload, source_addr, dispatcher);
+                if !ProxyServer::is_negotiated(&payload)
+                    || ProxyServer::opens_negotiated_stream(&payload)
+                {
+                    ProxyServer::send_route_failure(
+                        payload,
+                        configured_policy_opt,
+                        source_addr,
+                        dispatcher,
+                    );
+                }
                 logger.error(format!("Failed to find route to {}", target_hostname));
             }
             Err(e) => {
+                route_failure_sub
+                    .try_send(RouteFailureMessage {
+                        peer_addr: source_addr,
+                        stream_key: payload.stream_key.clone(),
+                    })
+                    .expect("ProxyServer is dead");
                 let msg = format!("Neighborhood refused to answer route request: {}", e);
                 logger.error(msg);
             }
@@ -231,32 +542,75 @@ impl ProxyServer {
 
     fn send_route_failure(
         payload: ClientRequestPayload,
+        configured_policy_opt: Option<RoutePolicy>,
         source_addr: SocketAddr,
         dispatcher: Recipient<Syn, TransmitDataMsg>,
     ) {
         let data = match payload.protocol {
-            ProxyProtocol::HTTP => {
+            ProxyProtocol::HTTP | ProxyProtocol::TUNNEL => {
                 let target_hostname = ProxyServer::hostname(&payload);
-                http_server_impersonator::make_error_response (
-                    503,
-                   "Routing Problem",
-                    format! ("Can't find a route to {}", target_hostname).as_str (),
-                    format! ("Substratum can't find a route through the Network yet to a Node that knows \
-                    where to find {}. Maybe later enough will be known about the Network to \
-                    find that Node, but we can't guarantee it. We're sorry.", target_hostname).as_str ()
-                )
+                match configured_policy_opt {
+                    None => http_server_impersonator::make_error_response (
+                        503,
+                       "Routing Problem",
+                        format! ("Can't find a route to {}", target_hostname).as_str (),
+                        format! ("Substratum can't find a route through the Network yet to a Node that knows \
+                        where to find {}. Maybe later enough will be known about the Network to \
+                        find that Node, but we can't guarantee it. We're sorry.", target_hostname).as_str ()
+                    ),
+                    Some(route_policy) => http_server_impersonator::make_error_response (
+                        503,
+                       "Routing Problem",
+                        format! ("Can't find a route to {} that meets its route policy", target_hostname).as_str (),
+                        format! ("Substratum can't find a route through the Network to a Node that knows \
+                        where to find {} with {} hops on the way there and {} on the way back. A looser \
+                        --route_policy for {} might help, or maybe later enough will be known about the \
+                        Network to find such a route. We're sorry.", target_hostname, route_policy.over,
+                        route_policy.back, target_hostname).as_str ()
+                    ),
+                }
             }
             ProxyProtocol::TLS => vec![],
+            ProxyProtocol::SOCKS5 => Socks5Negotiator::reply(socks5::HOST_UNREACHABLE),
+        };
+        // A SOCKS5 client has already been answered once, when it sent its greeting
+        let sequence_number = match payload.protocol {
+            ProxyProtocol::SOCKS5 => 1,
+            _ => 0,
         };
         let msg = TransmitDataMsg {
             endpoint: Endpoint::Socket(source_addr),
             last_data: true,
-            sequence_number: Some(0),
+            sequence_number: Some(sequence_number),
             data,
         };
         dispatcher.try_send(msg).expect("Dispatcher is dead");
     }
 
+    fn send_stream_opened_reply(
+        payload: &ClientRequestPayload,
+        source_addr: SocketAddr,
+        dispatcher: &Recipient<Syn, TransmitDataMsg>,
+    ) {
+        let (data, sequence_number) = match payload.protocol {
+            ProxyProtocol::SOCKS5 => (Socks5Negotiator::reply(socks5::SUCCEEDED), 1),
+            _ => (CONNECTION_ESTABLISHED.to_vec(), 0),
+        };
+        ProxyServer::send_reply(dispatcher, source_addr, data, sequence_number, false);
+    }
+
+    // SOCKS5 and CONNECT streams are set up by talking to the ProxyServer before any data flows
+    fn is_negotiated(payload: &ClientRequestPayload) -> bool {
+        match payload.protocol {
+            ProxyProtocol::SOCKS5 | ProxyProtocol::TUNNEL => true,
+            _ => false,
+        }
+    }
+
+    fn opens_negotiated_stream(payload: &ClientRequestPayload) -> bool {
+        ProxyServer::is_negotiated(payload) && (payload.sequenced_packet.sequence_number == 0)
+    }
+
     fn hostname(payload: &ClientRequestPayload) -> String {
         match payload.target_hostname {
             Some(ref thn) => thn.clone(),
@@ -265,6 +619,15 @@ impl ProxyServer {
     }
 }
 
+enum SocksStream {
+    Negotiating(Socks5Negotiator),
+    Connected {
+        target_hostname: String,
+        target_port: u16,
+        next_sequence_number: u64,
+    },
+}
+
 trait StreamKeyFactory: Send {
     fn make(&self, public_key: &Key, peer_addr: SocketAddr) -> StreamKey;
 }
@@ -287,6 +650,7 @@ mod tests {
     use std::cell::RefCell;
     use std::net::SocketAddr;
     use std::str::FromStr;
+    use std::sync::mpsc;
     use std::sync::Arc;
     use std::sync::Mutex;
     use std::thread;
@@ -295,9 +659,11 @@ mod tests {
     use sub_lib::dispatcher::Component;
     use sub_lib::hopper::ExpiredCoresPackage;
     use sub_lib::http_server_impersonator;
+    use sub_lib::neighborhood::HopRange;
     use sub_lib::proxy_client::ClientResponsePayload;
     use sub_lib::proxy_server::ClientRequestPayload;
     use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::proxy_server::DEFAULT_PROXIED_PORTS;
     use sub_lib::route::Route;
     use sub_lib::route::RouteSegment;
     use sub_lib::sequence_buffer::SequencedPacket;
@@ -394,7 +760,12 @@ mod tests {
                 .make_parameters(&make_parameters_arc)
                 .make_result(stream_key);
             let system = System::new("proxy_server_receives_http_request_from_dispatcher_then_sends_cores_package_to_hopper");
-            let mut subject = ProxyServer::new(cryptde, false);
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
             subject.stream_key_factory = Box::new(stream_key_factory);
             let subject_addr: Addr<Syn, ProxyServer> = subject.start();
             let mut peer_actors =
@@ -418,7 +789,13 @@ mod tests {
         );
         let recording = neighborhood_recording_arc.lock().unwrap();
         let record = recording.get_record::<RouteQueryMessage>(0);
-        assert_eq!(record, &RouteQueryMessage::data_indefinite_route_request(0));
+        assert_eq!(
+            record,
+            &RouteQueryMessage::data_indefinite_route_request(
+                RoutePolicy::zero_hop(),
+                RouteConstraint::for_exit_to(Some(String::from("nowhere.com")), 80)
+            )
+        );
     }
 
     #[test]
@@ -462,7 +839,12 @@ mod tests {
         thread::spawn(move || {
             let stream_key_factory = StreamKeyFactoryMock::new(); // can't make any stream keys; shouldn't have to
             let system = System::new("proxy_server_receives_http_request_from_dispatcher_then_sends_cores_package_to_hopper");
-            let mut subject = ProxyServer::new(cryptde, false);
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
             subject.stream_key_factory = Box::new(stream_key_factory);
             subject.keys_and_addrs.insert(stream_key, socket_addr);
             let subject_addr: Addr<Syn, ProxyServer> = subject.start();
@@ -550,7 +932,12 @@ mod tests {
         thread::spawn(move || {
             let stream_key_factory = StreamKeyFactoryMock::new().make_result(stream_key);
             let system = System::new("proxy_server_receives_http_request_from_dispatcher_then_sends_cores_package_to_hopper");
-            let mut subject = ProxyServer::new(cryptde, true);
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::at_least(2)),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
             subject.stream_key_factory = Box::new(stream_key_factory);
             let subject_addr: Addr<Syn, ProxyServer> = subject.start();
             let mut peer_actors =
@@ -569,7 +956,80 @@ mod tests {
         assert_eq!(record, &expected_pkg);
         let recording = neighborhood_recording_arc.lock().unwrap();
         let record = recording.get_record::<RouteQueryMessage>(0);
-        assert_eq!(record, &RouteQueryMessage::data_indefinite_route_request(2));
+        assert_eq!(
+            record,
+            &RouteQueryMessage::data_indefinite_route_request(
+                RoutePolicy::at_least(2),
+                RouteConstraint::for_exit_to(Some(String::from("nowhere.com")), 80)
+            )
+        );
+    }
+
+    #[test]
+    fn proxy_server_sends_every_packet_of_a_stream_along_the_same_route() {
+        let cryptde = cryptde();
+        let key = cryptde.public_key();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let first_route = zero_hop_route_response(&key, cryptde);
+        let second_route = RouteQueryResponse {
+            route: route_to_proxy_server(&key, cryptde),
+            segment_endpoints: vec![key.clone()],
+        };
+        let (neighborhood_mock, _, neighborhood_recording_arc) = make_recorder();
+        let neighborhood_mock = neighborhood_mock
+            .route_query_response(Some(first_route.clone()))
+            .route_query_response(Some(second_route));
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        let make_msg = |sequence_number: u64, data: &[u8]| InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(sequence_number),
+            last_data: false,
+            is_clandestine: false,
+            data: data.to_vec(),
+        };
+        let first_msg = make_msg(
+            0,
+            b"POST /index.html HTTP/1.1\r\nHost: nowhere.com\r\nContent-Length: 4\r\n\r\n",
+        );
+        let second_msg = make_msg(1, b"data");
+        thread::spawn(move || {
+            let stream_key_factory = StreamKeyFactoryMock::new().make_result(stream_key);
+            let system =
+                System::new("proxy_server_sends_every_packet_of_a_stream_along_the_same_route");
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
+            subject.stream_key_factory = Box::new(stream_key_factory);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors =
+                make_peer_actors_from(None, None, Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(first_msg).unwrap();
+            subject_addr.try_send(second_msg).unwrap();
+
+            system.run();
+        });
+
+        hopper_awaiter.await_message_count(2);
+        let recording = hopper_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<IncipientCoresPackage>(0).route,
+            first_route.route
+        );
+        assert_eq!(
+            recording.get_record::<IncipientCoresPackage>(1).route,
+            first_route.route
+        );
+        assert_eq!(neighborhood_recording_arc.lock().unwrap().len(), 1);
     }
 
     #[test]
@@ -594,7 +1054,12 @@ mod tests {
         };
         thread::spawn(move || {
             let system = System::new("proxy_server_receives_http_request_from_dispatcher_but_neighborhood_cant_make_route");
-            let subject = ProxyServer::new(cryptde, true);
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::unconfigured(),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
             let subject_addr: Addr<Syn, ProxyServer> = subject.start();
             let mut peer_actors =
                 make_peer_actors_from(None, Some(dispatcher), None, None, Some(neighborhood_mock));
@@ -625,11 +1090,140 @@ mod tests {
         assert_eq!(record, &expected_msg);
         let recording = neighborhood_recording_arc.lock().unwrap();
         let record = recording.get_record::<RouteQueryMessage>(0);
-        assert_eq!(record, &RouteQueryMessage::data_indefinite_route_request(2));
+        assert_eq!(
+            record,
+            &RouteQueryMessage::data_indefinite_route_request(
+                RoutePolicy::at_least(2),
+                RouteConstraint::for_exit_to(Some(String::from("nowhere.com")), 80)
+            )
+        );
         TestLogHandler::new()
             .exists_log_containing("ERROR: Proxy Server: Failed to find route to nowhere.com");
     }
 
+    #[test]
+    fn proxy_server_asks_for_route_that_meets_hostname_policy_and_explains_when_there_is_none() {
+        let cryptde = cryptde();
+        let http_request = b"GET /index.html HTTP/1.1\r\nHost: www.nowhere.com\r\n\r\n";
+        let (neighborhood_mock, _, neighborhood_recording_arc) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(None);
+        let dispatcher = Recorder::new();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let dispatcher_recording_arc = dispatcher.get_recording();
+        let msg_from_dispatcher = InboundClientData {
+            peer_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            reception_port: Some(80),
+            sequence_number: Some(0),
+            last_data: true,
+            data: http_request.to_vec(),
+            is_clandestine: false,
+        };
+        let nowhere_policy = RoutePolicy {
+            over: HopRange {
+                minimum: 3,
+                maximum_opt: Some(4),
+            },
+            back: HopRange::at_least(3),
+        };
+        let mut route_policies = RoutePolicies::new(RoutePolicy::at_least(2));
+        route_policies
+            .hostname_policies
+            .push((String::from("*.nowhere.com"), nowhere_policy));
+        thread::spawn(move || {
+            let system = System::new("proxy_server_asks_for_route_that_meets_hostname_policy_and_explains_when_there_is_none");
+            let subject = ProxyServer::new(cryptde, route_policies, DEFAULT_PROXIED_PORTS, None);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors =
+                make_peer_actors_from(None, Some(dispatcher), None, None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(msg_from_dispatcher).unwrap();
+
+            system.run();
+        });
+
+        dispatcher_awaiter.await_message_count(1);
+        let recording = dispatcher_recording_arc.lock().unwrap();
+        let record = recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(
+            record.data,
+            http_server_impersonator::make_error_response(
+                503,
+                "Routing Problem",
+                "Can't find a route to www.nowhere.com that meets its route policy",
+                "Substratum can't find a route through the Network to a Node that knows \
+                 where to find www.nowhere.com with 3 to 4 hops on the way there and 3 or more on \
+                 the way back. A looser --route_policy for www.nowhere.com might help, or maybe \
+                 later enough will be known about the Network to find such a route. We're sorry.",
+            )
+        );
+        let recording = neighborhood_recording_arc.lock().unwrap();
+        let record = recording.get_record::<RouteQueryMessage>(0);
+        assert_eq!(
+            record,
+            &RouteQueryMessage::data_indefinite_route_request(
+                nowhere_policy,
+                RouteConstraint::for_exit_to(Some(String::from("www.nowhere.com")), 80)
+            )
+        );
+    }
+
+    #[test]
+    fn proxy_server_explains_configured_policy_even_when_it_matches_the_standard_one() {
+        let cryptde = cryptde();
+        let http_request = b"GET /index.html HTTP/1.1\r\nHost: www.nowhere.com\r\n\r\n";
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(None);
+        let dispatcher = Recorder::new();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let dispatcher_recording_arc = dispatcher.get_recording();
+        let msg_from_dispatcher = InboundClientData {
+            peer_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            reception_port: Some(80),
+            sequence_number: Some(0),
+            last_data: true,
+            data: http_request.to_vec(),
+            is_clandestine: false,
+        };
+        thread::spawn(move || {
+            let system = System::new(
+                "proxy_server_explains_configured_policy_even_when_it_matches_the_standard_one",
+            );
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::at_least(2)),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors =
+                make_peer_actors_from(None, Some(dispatcher), None, None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(msg_from_dispatcher).unwrap();
+
+            system.run();
+        });
+
+        dispatcher_awaiter.await_message_count(1);
+        let recording = dispatcher_recording_arc.lock().unwrap();
+        let record = recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(
+            record.data,
+            http_server_impersonator::make_error_response(
+                503,
+                "Routing Problem",
+                "Can't find a route to www.nowhere.com that meets its route policy",
+                "Substratum can't find a route through the Network to a Node that knows \
+                 where to find www.nowhere.com with 2 or more hops on the way there and 2 or more on \
+                 the way back. A looser --route_policy for www.nowhere.com might help, or maybe \
+                 later enough will be known about the Network to find such a route. We're sorry.",
+            )
+        );
+    }
+
     #[test]
     fn proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper() {
         let tls_request = &[
@@ -661,7 +1255,12 @@ mod tests {
             zero_hop_route_response(&cryptde.public_key(), cryptde),
         ));
         let stream_key = make_meaningless_stream_key();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         subject.stream_key_factory =
             Box::new(StreamKeyFactoryMock::new().make_result(stream_key.clone()));
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
@@ -726,7 +1325,12 @@ mod tests {
             zero_hop_route_response(&cryptde.public_key(), cryptde),
         ));
         let stream_key = make_meaningless_stream_key();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         subject.stream_key_factory =
             Box::new(StreamKeyFactoryMock::new().make_result(stream_key.clone()));
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
@@ -789,7 +1393,12 @@ mod tests {
             zero_hop_route_response(&cryptde.public_key(), cryptde),
         ));
         let stream_key = make_meaningless_stream_key();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         subject.stream_key_factory =
             Box::new(StreamKeyFactoryMock::new().make_result(stream_key.clone()));
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
@@ -878,7 +1487,12 @@ mod tests {
         };
         thread::spawn(move || {
             let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_but_neighborhood_cant_make_route");
-            let subject = ProxyServer::new(cryptde, false);
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
             let subject_addr: Addr<Syn, ProxyServer> = subject.start();
             let mut peer_actors =
                 make_peer_actors_from(None, Some(dispatcher), None, None, Some(neighborhood));
@@ -905,28 +1519,759 @@ mod tests {
     }
 
     #[test]
-    fn proxy_server_receives_terminal_response_from_hopper() {
-        init_test_logging();
-        let system = System::new("proxy_server_receives_response_from_hopper");
+    fn proxy_server_negotiates_socks5_connect_then_sends_cores_package_to_hopper() {
+        let cryptde = cryptde();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
         let dispatcher_mock = Recorder::new();
         let dispatcher_log_arc = dispatcher_mock.get_recording();
         let dispatcher_awaiter = dispatcher_mock.get_awaiter();
-        let cryptde = cryptde();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(Some(
+            zero_hop_route_response(&cryptde.public_key(), cryptde),
+        ));
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let stream_key = make_meaningless_stream_key();
-        subject
-            .keys_and_addrs
-            .insert(stream_key.clone(), socket_addr.clone());
+        let greeting = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: vec![0x05, 0x01, 0x00],
+        };
+        let mut request_data = vec![0x05, 0x01, 0x00, 0x03, 0x0B];
+        request_data.extend_from_slice(b"example.com");
+        request_data.extend_from_slice(&[0x01, 0xBB]);
+        request_data.extend_from_slice(b"client hello");
+        let request = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(1),
+            last_data: false,
+            is_clandestine: false,
+            data: request_data,
+        };
         let key = cryptde.public_key();
-        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
-        let remaining_route = route_to_proxy_server(&key, cryptde);
-        let client_response_payload = ClientResponsePayload {
+        let route = zero_hop_route_response(&key, cryptde).route;
+        let expected_payload = ClientRequestPayload {
             stream_key: stream_key.clone(),
             sequenced_packet: SequencedPacket {
-                data: b"16 bytes of data".to_vec(),
-                sequence_number: 12345678,
-                last_data: true,
+                data: b"client hello".to_vec(),
+                sequence_number: 0,
+                last_data: false,
+            },
+            target_hostname: Some(String::from("example.com")),
+            target_port: 443,
+            protocol: ProxyProtocol::SOCKS5,
+            originator_public_key: key.clone(),
+        };
+        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        thread::spawn(move || {
+            let stream_key_factory = StreamKeyFactoryMock::new().make_result(stream_key);
+            let system = System::new(
+                "proxy_server_negotiates_socks5_connect_then_sends_cores_package_to_hopper",
+            );
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                Some(1080),
+            );
+            subject.stream_key_factory = Box::new(stream_key_factory);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(
+                None,
+                Some(dispatcher_mock),
+                Some(hopper_mock),
+                None,
+                Some(neighborhood_mock),
+            );
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(greeting).unwrap();
+            subject_addr.try_send(request).unwrap();
+
+            system.run();
+        });
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+        dispatcher_awaiter.await_message_count(2);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(0),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: false,
+                sequence_number: Some(0),
+                data: vec![0x05, 0x00],
+            }
+        );
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(1),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: false,
+                sequence_number: Some(1),
+                data: vec![0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            }
+        );
+    }
+
+    #[test]
+    fn proxy_server_numbers_socks5_client_data_after_the_connect_request_from_zero() {
+        let cryptde = cryptde();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        let dispatcher = Recorder::new();
+        let system = System::new(
+            "proxy_server_numbers_socks5_client_data_after_the_connect_request_from_zero",
+        );
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            Some(1080),
+        );
+        subject
+            .keys_and_addrs
+            .insert(stream_key.clone(), socket_addr.clone());
+        subject.socks_streams.insert(
+            socket_addr,
+            SocksStream::Connected {
+                target_hostname: String::from("1.2.3.4"),
+                target_port: 80,
+                next_sequence_number: 3,
+            },
+        );
+        let dispatcher_addr: Addr<Syn, Recorder> = dispatcher.start();
+
+        let result = subject.make_socks_payload(
+            InboundClientData {
+                peer_addr: socket_addr.clone(),
+                reception_port: Some(1080),
+                sequence_number: Some(7),
+                last_data: true,
+                is_clandestine: false,
+                data: b"more".to_vec(),
+            },
+            &dispatcher_addr.recipient::<TransmitDataMsg>(),
+        );
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+        assert_eq!(
+            result,
+            Ok(ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"more".to_vec(),
+                    sequence_number: 3,
+                    last_data: true,
+                },
+                target_hostname: Some(String::from("1.2.3.4")),
+                target_port: 80,
+                protocol: ProxyProtocol::SOCKS5,
+                originator_public_key: cryptde.public_key(),
+            })
+        );
+    }
+
+    #[test]
+    fn proxy_server_rejects_socks5_commands_other_than_connect() {
+        init_test_logging();
+        let cryptde = cryptde();
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("2.3.4.5:6789").unwrap();
+        let msg_from_dispatcher = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: vec![
+                0x05, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50,
+            ],
+        };
+        thread::spawn(move || {
+            let system = System::new("proxy_server_rejects_socks5_commands_other_than_connect");
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                Some(1080),
+            );
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors =
+                make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(msg_from_dispatcher).unwrap();
+
+            system.run();
+        });
+
+        dispatcher_awaiter.await_message_count(2);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(1),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: true,
+                sequence_number: Some(1),
+                data: vec![0x05, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            }
+        );
+        TestLogHandler::new().exists_log_containing(
+            "WARN: Proxy Server: Rejected SOCKS5 negotiation from 2.3.4.5:6789",
+        );
+    }
+
+    #[test]
+    fn proxy_server_receives_socks5_connect_but_neighborhood_cant_make_route() {
+        let cryptde = cryptde();
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(None);
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("3.4.5.6:7890").unwrap();
+        let msg_from_dispatcher = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: vec![
+                0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50,
+            ],
+        };
+        thread::spawn(move || {
+            let system = System::new(
+                "proxy_server_receives_socks5_connect_but_neighborhood_cant_make_route",
+            );
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::at_least(2)),
+                DEFAULT_PROXIED_PORTS,
+                Some(1080),
+            );
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(
+                None,
+                Some(dispatcher_mock),
+                None,
+                None,
+                Some(neighborhood_mock),
+            );
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(msg_from_dispatcher).unwrap();
+
+            system.run();
+        });
+
+        dispatcher_awaiter.await_message_count(2);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(1),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: true,
+                sequence_number: Some(1),
+                data: vec![0x05, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            }
+        );
+    }
+
+    #[test]
+    fn proxy_server_forgets_socks5_stream_when_client_closes_it() {
+        let cryptde = cryptde();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let dispatcher = Recorder::new();
+        let system = System::new("proxy_server_forgets_socks5_stream_when_client_closes_it");
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            Some(1080),
+        );
+        subject
+            .keys_and_addrs
+            .insert(make_meaningless_stream_key(), socket_addr.clone());
+        subject.socks_streams.insert(
+            socket_addr,
+            SocksStream::Connected {
+                target_hostname: String::from("1.2.3.4"),
+                target_port: 80,
+                next_sequence_number: 3,
+            },
+        );
+        let dispatcher_addr: Addr<Syn, Recorder> = dispatcher.start();
+
+        let result = subject.make_socks_payload(
+            InboundClientData {
+                peer_addr: socket_addr.clone(),
+                reception_port: Some(1080),
+                sequence_number: Some(7),
+                last_data: true,
+                is_clandestine: false,
+                data: vec![],
+            },
+            &dispatcher_addr.recipient::<TransmitDataMsg>(),
+        );
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(subject.socks_streams.contains_key(&socket_addr), false);
+    }
+
+    #[test]
+    fn proxy_server_forgets_socks5_stream_when_neighborhood_cant_make_route() {
+        let cryptde = cryptde();
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(None);
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("3.4.5.6:7890").unwrap();
+        let connect_request = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: vec![
+                0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50,
+            ],
+        };
+        let second_greeting = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(1080),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: vec![0x05, 0x01, 0x00],
+        };
+        let (subject_addr_tx, subject_addr_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let system =
+                System::new("proxy_server_forgets_socks5_stream_when_neighborhood_cant_make_route");
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                Some(1080),
+            );
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(
+                None,
+                Some(dispatcher_mock),
+                None,
+                None,
+                Some(neighborhood_mock),
+            );
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+            subject_addr.try_send(connect_request).unwrap();
+            subject_addr_tx.send(subject_addr).unwrap();
+
+            system.run();
+        });
+        let subject_addr = subject_addr_rx.recv().unwrap();
+        dispatcher_awaiter.await_message_count(2);
+
+        subject_addr.try_send(second_greeting).unwrap();
+
+        dispatcher_awaiter.await_message_count(3);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(2),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: false,
+                sequence_number: Some(0),
+                data: vec![0x05, 0x00],
+            }
+        );
+    }
+
+    #[test]
+    fn proxy_server_offsets_responses_to_socks5_streams_past_the_negotiation_replies() {
+        let system = System::new(
+            "proxy_server_offsets_responses_to_socks5_streams_past_the_negotiation_replies",
+        );
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            Some(1080),
+        );
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        subject
+            .keys_and_addrs
+            .insert(stream_key.clone(), socket_addr.clone());
+        subject.socks_streams.insert(
+            socket_addr,
+            SocksStream::Connected {
+                target_hostname: String::from("example.com"),
+                target_port: 443,
+                next_sequence_number: 1,
+            },
+        );
+        let key = cryptde.public_key();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequenced_packet: SequencedPacket {
+                data: b"server hello".to_vec(),
+                sequence_number: 0,
+                last_data: false,
+            },
+        };
+        let incipient_cores_package =
+            IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+        let expired_cores_package =
+            ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload);
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+        subject_addr.try_send(expired_cores_package).unwrap();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(1);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        let record = recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(record.sequence_number, Some(2));
+        assert_eq!(record.data, b"server hello".to_vec());
+    }
+
+    #[test]
+    fn proxy_server_answers_http_connect_request_and_opens_tunnel_through_hopper() {
+        let cryptde = cryptde();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock.route_query_response(Some(
+            zero_hop_route_response(&cryptde.public_key(), cryptde),
+        ));
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        let msg_from_dispatcher = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: b"CONNECT example.com:8443 HTTP/1.1\r\nHost: example.com:8443\r\n\r\n".to_vec(),
+        };
+        let key = cryptde.public_key();
+        let route = zero_hop_route_response(&key, cryptde).route;
+        let expected_payload = ClientRequestPayload {
+            stream_key: stream_key.clone(),
+            sequenced_packet: SequencedPacket {
+                data: vec![],
+                sequence_number: 0,
+                last_data: false,
+            },
+            target_hostname: Some(String::from("example.com")),
+            target_port: 8443,
+            protocol: ProxyProtocol::TUNNEL,
+            originator_public_key: key.clone(),
+        };
+        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        thread::spawn(move || {
+            let stream_key_factory = StreamKeyFactoryMock::new().make_result(stream_key);
+            let system = System::new(
+                "proxy_server_answers_http_connect_request_and_opens_tunnel_through_hopper",
+            );
+            let mut subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
+            subject.stream_key_factory = Box::new(stream_key_factory);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(
+                None,
+                Some(dispatcher_mock),
+                Some(hopper_mock),
+                None,
+                Some(neighborhood_mock),
+            );
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+
+            subject_addr.try_send(msg_from_dispatcher).unwrap();
+
+            system.run();
+        });
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+        dispatcher_awaiter.await_message_count(1);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(
+            recording.get_record::<TransmitDataMsg>(0),
+            &TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: false,
+                sequence_number: Some(0),
+                data: b"HTTP/1.1 200 Connection Established\r\n\r\n".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn proxy_server_passes_tunnel_data_along_with_its_own_sequence_numbers() {
+        let cryptde = cryptde();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
+        subject
+            .keys_and_addrs
+            .insert(stream_key.clone(), socket_addr.clone());
+        subject
+            .tunnels
+            .insert(socket_addr, (String::from("example.com"), 443));
+        let msg = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(3),
+            last_data: false,
+            is_clandestine: false,
+            data: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+        };
+
+        let is_tunnel = subject.is_tunnel(&msg);
+        let result = subject.make_tunnel_payload(msg);
+
+        assert_eq!(is_tunnel, true);
+        assert_eq!(
+            result,
+            Ok(ClientRequestPayload {
+                stream_key,
+                sequenced_packet: SequencedPacket {
+                    data: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+                    sequence_number: 3,
+                    last_data: false,
+                },
+                target_hostname: Some(String::from("example.com")),
+                target_port: 443,
+                protocol: ProxyProtocol::TUNNEL,
+                originator_public_key: cryptde.public_key(),
+            })
+        );
+    }
+
+    #[test]
+    fn proxy_server_forgets_tunnel_when_client_closes_it() {
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let mut subject = ProxyServer::new(
+            cryptde(),
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
+        subject
+            .keys_and_addrs
+            .insert(make_meaningless_stream_key(), socket_addr.clone());
+        subject
+            .tunnels
+            .insert(socket_addr, (String::from("example.com"), 443));
+        let msg = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(3),
+            last_data: true,
+            is_clandestine: false,
+            data: vec![],
+        };
+
+        let result = subject.make_tunnel_payload(msg);
+
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(subject.tunnels.contains_key(&socket_addr), false);
+    }
+
+    #[test]
+    fn proxy_server_forgets_tunnel_when_neighborhood_cant_make_route() {
+        let cryptde = cryptde();
+        let (neighborhood_mock, _, _) = make_recorder();
+        let neighborhood_mock = neighborhood_mock
+            .route_query_response(None)
+            .route_query_response(None);
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("3.4.5.6:7890").unwrap();
+        let connect_request = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: b"CONNECT example.com:443 HTTP/1.1\r\n\r\n".to_vec(),
+        };
+        // Part of a tunnel, this would just be passed along; otherwise it gets an error page of its own
+        let http_request = InboundClientData {
+            peer_addr: socket_addr.clone(),
+            reception_port: Some(80),
+            sequence_number: Some(1),
+            last_data: false,
+            is_clandestine: false,
+            data: b"GET http://example.com/ HTTP/1.1\r\n\r\n".to_vec(),
+        };
+        let (subject_addr_tx, subject_addr_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let system =
+                System::new("proxy_server_forgets_tunnel_when_neighborhood_cant_make_route");
+            let subject = ProxyServer::new(
+                cryptde,
+                RoutePolicies::new(RoutePolicy::zero_hop()),
+                DEFAULT_PROXIED_PORTS,
+                None,
+            );
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(
+                None,
+                Some(dispatcher_mock),
+                None,
+                None,
+                Some(neighborhood_mock),
+            );
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap();
+            subject_addr.try_send(connect_request).unwrap();
+            subject_addr_tx.send(subject_addr).unwrap();
+
+            system.run();
+        });
+        let subject_addr = subject_addr_rx.recv().unwrap();
+        dispatcher_awaiter.await_message_count(1);
+
+        subject_addr.try_send(http_request).unwrap();
+
+        dispatcher_awaiter.await_message_count(2);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        let record = recording.get_record::<TransmitDataMsg>(1);
+        assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
+        assert_eq!(record.last_data, true);
+        assert_eq!(record.sequence_number, Some(0));
+    }
+
+    #[test]
+    fn connect_request_opens_a_tunnel_only_at_the_start_of_a_stream() {
+        let subject = ProxyServer::new(
+            cryptde(),
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
+        let mut msg = InboundClientData {
+            peer_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            reception_port: Some(80),
+            sequence_number: Some(0),
+            last_data: false,
+            is_clandestine: false,
+            data: b"CONNECT example.com:443 HTTP/1.1\r\n\r\n".to_vec(),
+        };
+
+        let at_start = subject.is_tunnel(&msg);
+        msg.sequence_number = Some(1);
+        let later = subject.is_tunnel(&msg);
+        msg.sequence_number = Some(0);
+        msg.data = b"GET http://example.com/ HTTP/1.1\r\n\r\n".to_vec();
+        let not_connect = subject.is_tunnel(&msg);
+
+        assert_eq!(at_start, true);
+        assert_eq!(later, false);
+        assert_eq!(not_connect, false);
+    }
+
+    #[test]
+    fn responses_to_tunnels_are_numbered_after_the_connection_established_reply() {
+        let mut subject = ProxyServer::new(
+            cryptde(),
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            Some(1080),
+        );
+        let tunnel_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let socks_addr = SocketAddr::from_str("2.3.4.5:6789").unwrap();
+        let plain_addr = SocketAddr::from_str("3.4.5.6:7890").unwrap();
+        subject
+            .tunnels
+            .insert(tunnel_addr, (String::from("example.com"), 443));
+        subject.socks_streams.insert(
+            socks_addr,
+            SocksStream::Negotiating(Socks5Negotiator::new()),
+        );
+
+        assert_eq!(subject.reply_count(&tunnel_addr), 1);
+        assert_eq!(subject.reply_count(&socks_addr), 2);
+        assert_eq!(subject.reply_count(&plain_addr), 0);
+    }
+
+    #[test]
+    fn proxy_server_receives_terminal_response_from_hopper() {
+        init_test_logging();
+        let system = System::new("proxy_server_receives_response_from_hopper");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = make_meaningless_stream_key();
+        subject
+            .keys_and_addrs
+            .insert(stream_key.clone(), socket_addr.clone());
+        let key = cryptde.public_key();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key: stream_key.clone(),
+            sequenced_packet: SequencedPacket {
+                data: b"16 bytes of data".to_vec(),
+                sequence_number: 12345678,
+                last_data: true,
             },
         };
         let incipient_cores_package =
@@ -961,7 +2306,12 @@ mod tests {
         let dispatcher_log_arc = dispatcher_mock.get_recording();
         let dispatcher_awaiter = dispatcher_mock.get_awaiter();
         let cryptde = cryptde();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let stream_key = make_meaningless_stream_key();
         subject
@@ -1013,7 +2363,12 @@ mod tests {
         let cryptde = cryptde();
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let stream_key = make_meaningless_stream_key();
-        let mut subject = ProxyServer::new(cryptde, false);
+        let mut subject = ProxyServer::new(
+            cryptde,
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         subject
             .keys_and_addrs
             .insert(stream_key.clone(), socket_addr.clone());
@@ -1044,7 +2399,12 @@ mod tests {
     fn panics_if_hopper_is_unbound() {
         let system = System::new("panics_if_hopper_is_unbound");
         let http_request = b"GET /index.html HTTP/1.1\r\nHost: nowhere.com\r\n\r\n";
-        let subject = ProxyServer::new(cryptde(), false);
+        let subject = ProxyServer::new(
+            cryptde(),
+            RoutePolicies::new(RoutePolicy::zero_hop()),
+            DEFAULT_PROXIED_PORTS,
+            None,
+        );
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let expected_data = http_request.to_vec();
         let msg_from_dispatcher = InboundClientData {
@@ -0,0 +1,312 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+// RFC 1928
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION_REQUIRED: u8 = 0x00;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAINNAME: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+pub const SUCCEEDED: u8 = 0x00;
+pub const GENERAL_FAILURE: u8 = 0x01;
+pub const HOST_UNREACHABLE: u8 = 0x04;
+pub const COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+#[derive(Debug, PartialEq)]
+pub enum Socks5Step {
+    // Wait for the client to send more
+    NeedMoreData,
+    // Send this to the client and keep negotiating
+    Reply(Vec<u8>),
+    // Negotiation is over; anything the client sent after its request belongs to the target
+    Connect {
+        hostname: String,
+        port: u16,
+        remainder: Vec<u8>,
+    },
+    // Send this to the client (if it's not empty) and close the connection
+    Fail(Vec<u8>),
+}
+
+// Handles the method-selection greeting and the CONNECT request that a SOCKS5 client sends before its
+// data. Only the "no authentication required" method and the CONNECT command are supported.
+pub struct Socks5Negotiator {
+    buffer: Vec<u8>,
+    greeted: bool,
+}
+
+impl Socks5Negotiator {
+    pub fn new() -> Socks5Negotiator {
+        Socks5Negotiator {
+            buffer: vec![],
+            greeted: false,
+        }
+    }
+
+    pub fn add_data(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    // Call repeatedly until it returns something other than Reply
+    pub fn take_step(&mut self) -> Socks5Step {
+        if self.greeted {
+            self.take_request()
+        } else {
+            self.take_greeting()
+        }
+    }
+
+    // True once the greeting has been answered; the reply to the request is the second thing sent
+    pub fn is_greeted(&self) -> bool {
+        self.greeted
+    }
+
+    fn take_greeting(&mut self) -> Socks5Step {
+        if self.buffer.len() < 2 {
+            return Socks5Step::NeedMoreData;
+        }
+        if self.buffer[0] != SOCKS_VERSION {
+            return Socks5Step::Fail(vec![]);
+        }
+        let greeting_length = 2 + self.buffer[1] as usize;
+        if self.buffer.len() < greeting_length {
+            return Socks5Step::NeedMoreData;
+        }
+        let greeting: Vec<u8> = self.buffer.drain(0..greeting_length).collect();
+        if greeting[2..].contains(&NO_AUTHENTICATION_REQUIRED) {
+            self.greeted = true;
+            Socks5Step::Reply(vec![SOCKS_VERSION, NO_AUTHENTICATION_REQUIRED])
+        } else {
+            Socks5Step::Fail(vec![SOCKS_VERSION, NO_ACCEPTABLE_METHODS])
+        }
+    }
+
+    fn take_request(&mut self) -> Socks5Step {
+        if self.buffer.len() < 5 {
+            return Socks5Step::NeedMoreData;
+        }
+        if self.buffer[0] != SOCKS_VERSION {
+            return Socks5Step::Fail(Socks5Negotiator::reply(GENERAL_FAILURE));
+        }
+        if self.buffer[1] != CONNECT {
+            return Socks5Step::Fail(Socks5Negotiator::reply(COMMAND_NOT_SUPPORTED));
+        }
+        let address_length = match self.buffer[3] {
+            ATYP_IPV4 => 4,
+            ATYP_DOMAINNAME => 1 + self.buffer[4] as usize,
+            ATYP_IPV6 => 16,
+            _ => return Socks5Step::Fail(Socks5Negotiator::reply(ADDRESS_TYPE_NOT_SUPPORTED)),
+        };
+        let request_length = 4 + address_length + 2;
+        if self.buffer.len() < request_length {
+            return Socks5Step::NeedMoreData;
+        }
+        let request: Vec<u8> = self.buffer.drain(0..request_length).collect();
+        let address = &request[4..(4 + address_length)];
+        let hostname = match request[3] {
+            ATYP_IPV4 => format!(
+                "{}",
+                Ipv4Addr::new(address[0], address[1], address[2], address[3])
+            ),
+            ATYP_IPV6 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(address);
+                format!("{}", Ipv6Addr::from(octets))
+            }
+            _ => match String::from_utf8(Vec::from(&address[1..])) {
+                Ok(ref name) if !name.is_empty() => name.clone(),
+                _ => return Socks5Step::Fail(Socks5Negotiator::reply(GENERAL_FAILURE)),
+            },
+        };
+        let port =
+            ((request[request_length - 2] as u16) << 8) | (request[request_length - 1] as u16);
+        Socks5Step::Connect {
+            hostname,
+            port,
+            remainder: self.buffer.drain(..).collect(),
+        }
+    }
+
+    // We don't know the address the exit Node bound for us, so we always report 0.0.0.0:0
+    pub fn reply(reply_code: u8) -> Vec<u8> {
+        vec![
+            SOCKS_VERSION,
+            reply_code,
+            0x00,
+            ATYP_IPV4,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greeted_subject() -> Socks5Negotiator {
+        let mut subject = Socks5Negotiator::new();
+        subject.add_data(&[0x05, 0x01, 0x00]);
+        subject.take_step();
+        subject
+    }
+
+    #[test]
+    fn accepts_greeting_offering_no_authentication() {
+        let mut subject = Socks5Negotiator::new();
+        subject.add_data(&[0x05, 0x02, 0x02, 0x00]);
+
+        let result = subject.take_step();
+
+        assert_eq!(result, Socks5Step::Reply(vec![0x05, 0x00]));
+        assert_eq!(subject.is_greeted(), true);
+    }
+
+    #[test]
+    fn rejects_greeting_without_no_authentication() {
+        let mut subject = Socks5Negotiator::new();
+        subject.add_data(&[0x05, 0x01, 0x02]);
+
+        let result = subject.take_step();
+
+        assert_eq!(result, Socks5Step::Fail(vec![0x05, 0xFF]));
+        assert_eq!(subject.is_greeted(), false);
+    }
+
+    #[test]
+    fn rejects_other_socks_versions_silently() {
+        let mut subject = Socks5Negotiator::new();
+        subject.add_data(&[0x04, 0x01, 0x00, 0x50, 0x01, 0x02, 0x03, 0x04, 0x00]);
+
+        let result = subject.take_step();
+
+        assert_eq!(result, Socks5Step::Fail(vec![]));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_split_greeting_and_request() {
+        let mut subject = Socks5Negotiator::new();
+        let mut results = vec![];
+
+        subject.add_data(&[0x05, 0x02]);
+        results.push(subject.take_step());
+        subject.add_data(&[0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 0x0B]);
+        results.push(subject.take_step());
+        results.push(subject.take_step());
+        subject.add_data(b"example.com");
+        results.push(subject.take_step());
+        subject.add_data(&[0x01, 0xBB]);
+        results.push(subject.take_step());
+
+        assert_eq!(
+            results,
+            vec![
+                Socks5Step::NeedMoreData,
+                Socks5Step::Reply(vec![0x05, 0x00]),
+                Socks5Step::NeedMoreData,
+                Socks5Step::NeedMoreData,
+                Socks5Step::Connect {
+                    hostname: String::from("example.com"),
+                    port: 443,
+                    remainder: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn connects_to_ipv4_address_and_keeps_data_that_follows_the_request() {
+        let mut subject = greeted_subject();
+        subject.add_data(&[
+            0x05, 0x01, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50, b'G', b'E', b'T',
+        ]);
+
+        let result = subject.take_step();
+
+        assert_eq!(
+            result,
+            Socks5Step::Connect {
+                hostname: String::from("1.2.3.4"),
+                port: 80,
+                remainder: b"GET".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn connects_to_ipv6_address() {
+        let mut subject = greeted_subject();
+        let mut request = vec![0x05, 0x01, 0x00, 0x04];
+        request.extend_from_slice(&[
+            0x20, 0x01, 0x0D, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01,
+        ]);
+        request.extend_from_slice(&[0x1F, 0x90]);
+        subject.add_data(&request);
+
+        let result = subject.take_step();
+
+        assert_eq!(
+            result,
+            Socks5Step::Connect {
+                hostname: String::from("2001:db8::1"),
+                port: 8080,
+                remainder: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_commands_other_than_connect() {
+        let mut subject = greeted_subject();
+        subject.add_data(&[0x05, 0x02, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50]);
+
+        let result = subject.take_step();
+
+        assert_eq!(
+            result,
+            Socks5Step::Fail(vec![
+                0x05, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_address_types() {
+        let mut subject = greeted_subject();
+        subject.add_data(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x00, 0x50]);
+
+        let result = subject.take_step();
+
+        assert_eq!(
+            result,
+            Socks5Step::Fail(vec![
+                0x05, 0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_empty_domain_name() {
+        let mut subject = greeted_subject();
+        subject.add_data(&[0x05, 0x01, 0x00, 0x03, 0x00, 0x00, 0x50]);
+
+        let result = subject.take_step();
+
+        assert_eq!(
+            result,
+            Socks5Step::Fail(vec![
+                0x05, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ])
+        );
+    }
+}
@@ -179,6 +179,7 @@ pub enum CryptdecError {
     EmptyKey,
     EmptyData,
     InvalidKey(String),
+    OpeningFailed,
 }
 
 pub trait CryptDE: Send + Sync {
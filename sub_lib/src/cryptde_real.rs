@@ -0,0 +1,362 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use cryptde::CryptDE;
+use cryptde::CryptData;
+use cryptde::CryptdecError;
+use cryptde::Key;
+use cryptde::PlainData;
+use sodiumoxide;
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::sign;
+use sodiumoxide::randombytes::randombytes_into;
+use std::convert::TryFrom;
+
+// A public key is the X25519 encryption key followed by the Ed25519 verification key.
+// A private key is the X25519 decryption key followed by the Ed25519 signing key.
+pub const PUBLIC_KEY_LEN: usize = box_::PUBLICKEYBYTES + sign::PUBLICKEYBYTES;
+pub const PRIVATE_KEY_LEN: usize = box_::SECRETKEYBYTES + sign::SECRETKEYBYTES;
+
+pub struct CryptDEReal {
+    private_key: Key,
+    public_key: Key,
+}
+
+impl CryptDE for CryptDEReal {
+    fn generate_key_pair(&mut self) {
+        let (encryption_public, encryption_secret) = box_::gen_keypair();
+        let (signing_public, signing_secret) = sign::gen_keypair();
+        self.public_key = Key::new(&[&encryption_public.0[..], &signing_public.0[..]].concat()[..]);
+        self.private_key =
+            Key::new(&[&encryption_secret.0[..], &signing_secret.0[..]].concat()[..]);
+    }
+
+    fn encode(&self, public_key: &Key, data: &PlainData) -> Result<CryptData, CryptdecError> {
+        if public_key.data.is_empty() {
+            Err(CryptdecError::EmptyKey)
+        } else if data.data.is_empty() {
+            Err(CryptdecError::EmptyData)
+        } else {
+            let encryption_public = CryptDEReal::encryption_public_key(public_key)?;
+            Ok(CryptData::new(
+                &sealedbox::seal(&data.data[..], &encryption_public)[..],
+            ))
+        }
+    }
+
+    fn decode(&self, data: &CryptData) -> Result<PlainData, CryptdecError> {
+        if self.private_key.data.is_empty() {
+            Err(CryptdecError::EmptyKey)
+        } else if data.data.is_empty() {
+            Err(CryptdecError::EmptyData)
+        } else {
+            let encryption_public = CryptDEReal::encryption_public_key(&self.public_key)?;
+            let encryption_secret = CryptDEReal::encryption_secret_key(&self.private_key)?;
+            match sealedbox::open(&data.data[..], &encryption_public, &encryption_secret) {
+                Ok(plain) => Ok(PlainData::new(&plain[..])),
+                Err(()) => Err(CryptdecError::OpeningFailed),
+            }
+        }
+    }
+
+    fn random(&self, dest: &mut [u8]) {
+        randombytes_into(dest)
+    }
+
+    fn private_key(&self) -> Key {
+        self.private_key.clone()
+    }
+
+    fn public_key(&self) -> Key {
+        self.public_key.clone()
+    }
+
+    // This is dup instead of clone because it returns a Box<CryptDE> instead of a CryptDEReal.
+    fn dup(&self) -> Box<CryptDE> {
+        Box::new(CryptDEReal {
+            private_key: self.private_key.clone(),
+            public_key: self.public_key.clone(),
+        })
+    }
+
+    fn sign(&self, data: &PlainData) -> Result<CryptData, CryptdecError> {
+        let signing_secret = CryptDEReal::signing_secret_key(&self.private_key)?;
+        let signature = sign::sign_detached(&data.data[..], &signing_secret);
+        Ok(CryptData::new(&signature.to_bytes()[..]))
+    }
+
+    fn verify_signature(&self, data: &PlainData, signature: &CryptData, public_key: &Key) -> bool {
+        let signing_public = match CryptDEReal::signing_public_key(public_key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        let signature = match sign::Signature::try_from(&signature.data[..]) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        sign::verify_detached(&signature, &data.data[..], &signing_public)
+    }
+}
+
+impl CryptDEReal {
+    pub fn new() -> CryptDEReal {
+        sodiumoxide::init().expect("Could not initialize libsodium");
+        CryptDEReal {
+            private_key: Key::new(b""),
+            public_key: Key::new(b""),
+        }
+    }
+
+    pub fn from_key_pair(
+        public_key: &Key,
+        private_key: &Key,
+    ) -> Result<CryptDEReal, CryptdecError> {
+        CryptDEReal::check_length(public_key, PUBLIC_KEY_LEN, "public")?;
+        CryptDEReal::check_length(private_key, PRIVATE_KEY_LEN, "private")?;
+        let mut result = CryptDEReal::new();
+        result.public_key = public_key.clone();
+        result.private_key = private_key.clone();
+        Ok(result)
+    }
+
+    fn encryption_public_key(key: &Key) -> Result<box_::PublicKey, CryptdecError> {
+        CryptDEReal::check_length(key, PUBLIC_KEY_LEN, "public")?;
+        Ok(box_::PublicKey::from_slice(&key.data[..box_::PUBLICKEYBYTES]).expect("Internal error"))
+    }
+
+    fn signing_public_key(key: &Key) -> Result<sign::PublicKey, CryptdecError> {
+        CryptDEReal::check_length(key, PUBLIC_KEY_LEN, "public")?;
+        Ok(sign::PublicKey::from_slice(&key.data[box_::PUBLICKEYBYTES..]).expect("Internal error"))
+    }
+
+    fn encryption_secret_key(key: &Key) -> Result<box_::SecretKey, CryptdecError> {
+        CryptDEReal::check_length(key, PRIVATE_KEY_LEN, "private")?;
+        Ok(box_::SecretKey::from_slice(&key.data[..box_::SECRETKEYBYTES]).expect("Internal error"))
+    }
+
+    fn signing_secret_key(key: &Key) -> Result<sign::SecretKey, CryptdecError> {
+        CryptDEReal::check_length(key, PRIVATE_KEY_LEN, "private")?;
+        Ok(sign::SecretKey::from_slice(&key.data[box_::SECRETKEYBYTES..]).expect("Internal error"))
+    }
+
+    fn check_length(key: &Key, expected_len: usize, kind: &str) -> Result<(), CryptdecError> {
+        if key.data.len() == expected_len {
+            Ok(())
+        } else {
+            Err(CryptdecError::InvalidKey(format!(
+                "A {} key must be {} bytes long, not {}",
+                kind,
+                expected_len,
+                key.data.len()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_subject() -> CryptDEReal {
+        let mut subject = CryptDEReal::new();
+        subject.generate_key_pair();
+        subject
+    }
+
+    #[test]
+    fn generated_keys_have_the_right_lengths() {
+        let subject = make_subject();
+
+        assert_eq!(subject.public_key().data.len(), PUBLIC_KEY_LEN);
+        assert_eq!(subject.private_key().data.len(), PRIVATE_KEY_LEN);
+    }
+
+    #[test]
+    fn generation_produces_different_keys_each_time() {
+        let mut subject = make_subject();
+        let first_public = subject.public_key();
+        let first_private = subject.private_key();
+
+        subject.generate_key_pair();
+
+        assert_ne!(subject.public_key(), first_public);
+        assert_ne!(subject.private_key(), first_private);
+    }
+
+    #[test]
+    fn encode_with_empty_key() {
+        let subject = make_subject();
+
+        let result = subject.encode(&Key::new(b""), &PlainData::new(b"data"));
+
+        assert_eq!(result.err().unwrap(), CryptdecError::EmptyKey);
+    }
+
+    #[test]
+    fn encode_with_empty_data() {
+        let subject = make_subject();
+
+        let result = subject.encode(&subject.public_key(), &PlainData::new(b""));
+
+        assert_eq!(result.err().unwrap(), CryptdecError::EmptyData);
+    }
+
+    #[test]
+    fn encode_with_malformed_key() {
+        let subject = make_subject();
+
+        let result = subject.encode(&Key::new(b"key"), &PlainData::new(b"data"));
+
+        assert_eq!(
+            result.err().unwrap(),
+            CryptdecError::InvalidKey(String::from("A public key must be 64 bytes long, not 3"))
+        );
+    }
+
+    #[test]
+    fn decode_with_empty_data() {
+        let subject = make_subject();
+
+        let result = subject.decode(&CryptData::new(b""));
+
+        assert_eq!(result.err().unwrap(), CryptdecError::EmptyData);
+    }
+
+    #[test]
+    fn encoded_data_does_not_contain_plain_data() {
+        let subject = make_subject();
+        let plain_data = PlainData::new(b"These are the times that try men's souls");
+
+        let result = subject.encode(&subject.public_key(), &plain_data).unwrap();
+
+        assert_eq!(
+            result
+                .data
+                .windows(plain_data.data.len())
+                .any(|window| window == &plain_data.data[..]),
+            false
+        );
+    }
+
+    #[test]
+    fn generated_keys_work_with_each_other() {
+        let subject = make_subject();
+        let expected_data = PlainData::new(&b"These are the times that try men's souls"[..]);
+
+        let encrypted_data = subject
+            .encode(&subject.public_key(), &expected_data)
+            .unwrap();
+        let decrypted_data = subject.decode(&encrypted_data).unwrap();
+
+        assert_eq!(decrypted_data, expected_data);
+    }
+
+    #[test]
+    fn data_encoded_for_one_key_cannot_be_decoded_by_another() {
+        let intended = make_subject();
+        let eavesdropper = make_subject();
+        let plain_data = PlainData::new(b"These are the times that try men's souls");
+
+        let encrypted_data = eavesdropper
+            .encode(&intended.public_key(), &plain_data)
+            .unwrap();
+
+        assert_eq!(
+            eavesdropper.decode(&encrypted_data).err().unwrap(),
+            CryptdecError::OpeningFailed
+        );
+        assert_eq!(intended.decode(&encrypted_data).unwrap(), plain_data);
+    }
+
+    #[test]
+    fn from_key_pair_works() {
+        let original = make_subject();
+
+        let subject =
+            CryptDEReal::from_key_pair(&original.public_key(), &original.private_key()).unwrap();
+
+        assert_eq!(subject.public_key(), original.public_key());
+        assert_eq!(subject.private_key(), original.private_key());
+    }
+
+    #[test]
+    fn from_key_pair_rejects_malformed_keys() {
+        let result = CryptDEReal::from_key_pair(&Key::new(b"public"), &Key::new(b"private"));
+
+        assert_eq!(
+            result.err().unwrap(),
+            CryptdecError::InvalidKey(String::from("A public key must be 64 bytes long, not 6"))
+        );
+    }
+
+    #[test]
+    fn random_is_not_predictable() {
+        let subject = make_subject();
+        let mut first: [u8; 32] = [0; 32];
+        let mut second: [u8; 32] = [0; 32];
+
+        subject.random(&mut first[..]);
+        subject.random(&mut second[..]);
+
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn dup_works() {
+        let subject = make_subject();
+
+        let result = subject.dup();
+
+        assert_eq!(result.public_key(), subject.public_key());
+        assert_eq!(result.private_key(), subject.private_key());
+    }
+
+    #[test]
+    fn verifying_a_good_signature_works() {
+        let data = PlainData::new(b"Fourscore and seven years ago");
+        let subject = make_subject();
+
+        let signature = subject.sign(&data).unwrap();
+        let result = subject.verify_signature(&data, &signature, &subject.public_key());
+
+        assert_eq!(result, true);
+    }
+
+    #[test]
+    fn verifying_a_signature_against_altered_data_fails() {
+        let subject = make_subject();
+
+        let signature = subject
+            .sign(&PlainData::new(b"Fourscore and seven years ago"))
+            .unwrap();
+        let result = subject.verify_signature(
+            &PlainData::new(b"Fourscore and eight years ago"),
+            &signature,
+            &subject.public_key(),
+        );
+
+        assert_eq!(result, false);
+    }
+
+    #[test]
+    fn verifying_a_signature_against_someone_elses_key_fails() {
+        let data = PlainData::new(b"Fourscore and seven years ago");
+        let subject = make_subject();
+        let impostor = make_subject();
+
+        let signature = impostor.sign(&data).unwrap();
+        let result = subject.verify_signature(&data, &signature, &subject.public_key());
+
+        assert_eq!(result, false);
+    }
+
+    #[test]
+    fn verifying_a_malformed_signature_fails() {
+        let data = PlainData::new(b"Fourscore and seven years ago");
+        let subject = make_subject();
+
+        let result =
+            subject.verify_signature(&data, &CryptData::new(b"signed"), &subject.public_key());
+
+        assert_eq!(result, false);
+    }
+}
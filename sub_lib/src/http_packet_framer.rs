@@ -74,6 +74,7 @@ pub trait HttpPacketStartFinder: Send {
 pub struct HttpPacketFramer {
     framer_state: HttpFramerState,
     start_finder: Box<HttpPacketStartFinder>,
+    tunneling: bool,
     logger: Logger,
 }
 
@@ -83,7 +84,9 @@ impl Framer for HttpPacketFramer {
     }
 
     fn take_frame(&mut self) -> Option<FramedChunk> {
-        if self.framer_state.transfer_encoding_chunked == ChunkExistenceState::Chunk {
+        if self.tunneling {
+            self.take_tunnel_frame()
+        } else if self.framer_state.transfer_encoding_chunked == ChunkExistenceState::Chunk {
             self.take_chunk_frame()
         } else {
             self.take_packet_frame()
@@ -104,6 +107,7 @@ impl HttpPacketFramer {
                 lines: Vec::new(),
             },
             start_finder,
+            tunneling: false,
             logger: Logger::new("HttpRequestFramer"),
         }
     }
@@ -121,10 +125,14 @@ impl HttpPacketFramer {
         }
         if self.framer_state.packet_progress_state == PacketProgressState::SeekingBodyEnd {
             match self.seek_body_end() {
-                Some(request) => Some(FramedChunk {
-                    chunk: request,
-                    last_chunk: false,
-                }),
+                Some(request) => {
+                    // Whatever follows a CONNECT request is opaque data for the tunnel it opens
+                    self.tunneling = request.starts_with(b"CONNECT ");
+                    Some(FramedChunk {
+                        chunk: request,
+                        last_chunk: false,
+                    })
+                }
                 None => None,
             }
         } else {
@@ -259,6 +267,17 @@ impl HttpPacketFramer {
         true
     }
 
+    fn take_tunnel_frame(&mut self) -> Option<FramedChunk> {
+        if self.framer_state.data_so_far.is_empty() {
+            None
+        } else {
+            Some(FramedChunk {
+                chunk: self.framer_state.data_so_far.drain(..).collect(),
+                last_chunk: false,
+            })
+        }
+    }
+
     fn discard_current_request(&mut self) {
         self.framer_state.packet_progress_state = PacketProgressState::SeekingPacketStart;
         self.framer_state.content_length = 0;
@@ -0,0 +1,396 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use cryptde::CryptDE;
+use cryptde::CryptData;
+use cryptde::Key;
+use serde_cbor;
+use sodiumoxide;
+use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::crypto::secretbox;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const KEYSTORE_FILENAME: &str = "node_identity.cbor";
+pub const PASSPHRASE_ENV_VAR: &str = "SUBSTRATUM_PASSPHRASE";
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum KeystoreError {
+    NotFound(String),
+    Io(String),
+    Corrupt(String),
+    WrongPassphrase,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreContents {
+    public_key: Key,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    encrypted_private_key: CryptData,
+}
+
+// The public key is stored in the clear so that the Node can report its descriptor before the
+// private key is needed; the private key is sealed with a key derived from the operator's passphrase.
+pub struct Keystore {
+    file_path: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(data_directory: &Path) -> Keystore {
+        sodiumoxide::init().expect("Could not initialize libsodium");
+        Keystore {
+            file_path: data_directory.join(KEYSTORE_FILENAME),
+        }
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.file_path.is_file()
+    }
+
+    pub fn load(&self, passphrase: &str) -> Result<(Key, Key), KeystoreError> {
+        let contents = Keystore::read_contents(&self.file_path)?;
+        let private_key = Keystore::unseal(&contents, passphrase)?;
+        Ok((contents.public_key, private_key))
+    }
+
+    pub fn save(
+        &self,
+        public_key: &Key,
+        private_key: &Key,
+        passphrase: &str,
+    ) -> Result<(), KeystoreError> {
+        let salt = argon2id13::gen_salt();
+        let nonce = secretbox::gen_nonce();
+        let sealing_key = Keystore::derive_key(passphrase, &salt)?;
+        let contents = KeystoreContents {
+            public_key: public_key.clone(),
+            salt: salt.0.to_vec(),
+            nonce: nonce.0.to_vec(),
+            encrypted_private_key: CryptData::new(
+                &secretbox::seal(&private_key.data[..], &nonce, &sealing_key)[..],
+            ),
+        };
+        let bytes = serde_cbor::ser::to_vec(&contents).expect("Serialization failed");
+        Keystore::write_file(&self.file_path, &bytes[..])
+    }
+
+    pub fn export(&self, destination: &Path) -> Result<(), KeystoreError> {
+        Keystore::read_contents(&self.file_path)?;
+        let bytes = Keystore::read_file(&self.file_path)?;
+        Keystore::write_file(destination, &bytes[..])
+    }
+
+    pub fn import(&self, source: &Path, passphrase: &str) -> Result<Key, KeystoreError> {
+        let contents = Keystore::read_contents(source)?;
+        Keystore::unseal(&contents, passphrase)?;
+        let bytes = Keystore::read_file(source)?;
+        Keystore::write_file(&self.file_path, &bytes[..])?;
+        Ok(contents.public_key)
+    }
+
+    pub fn rotate(&self, cryptde: &mut CryptDE, passphrase: &str) -> Result<Key, KeystoreError> {
+        if self.exists() {
+            self.load(passphrase)?;
+        }
+        cryptde.generate_key_pair();
+        self.save(&cryptde.public_key(), &cryptde.private_key(), passphrase)?;
+        Ok(cryptde.public_key())
+    }
+
+    fn unseal(contents: &KeystoreContents, passphrase: &str) -> Result<Key, KeystoreError> {
+        let salt = match argon2id13::Salt::from_slice(&contents.salt[..]) {
+            Some(salt) => salt,
+            None => return Err(KeystoreError::Corrupt(String::from("Bad salt"))),
+        };
+        let nonce = match secretbox::Nonce::from_slice(&contents.nonce[..]) {
+            Some(nonce) => nonce,
+            None => return Err(KeystoreError::Corrupt(String::from("Bad nonce"))),
+        };
+        let sealing_key = Keystore::derive_key(passphrase, &salt)?;
+        match secretbox::open(
+            &contents.encrypted_private_key.data[..],
+            &nonce,
+            &sealing_key,
+        ) {
+            Ok(private_key_data) => Ok(Key::new(&private_key_data[..])),
+            Err(()) => Err(KeystoreError::WrongPassphrase),
+        }
+    }
+
+    fn derive_key(
+        passphrase: &str,
+        salt: &argon2id13::Salt,
+    ) -> Result<secretbox::Key, KeystoreError> {
+        let mut key_data = [0u8; secretbox::KEYBYTES];
+        match argon2id13::derive_key(
+            &mut key_data,
+            passphrase.as_bytes(),
+            salt,
+            argon2id13::OPSLIMIT_INTERACTIVE,
+            argon2id13::MEMLIMIT_INTERACTIVE,
+        ) {
+            Ok(_) => Ok(secretbox::Key(key_data)),
+            Err(()) => Err(KeystoreError::Io(String::from(
+                "Could not allocate memory to derive key from passphrase",
+            ))),
+        }
+    }
+
+    fn read_contents(path: &Path) -> Result<KeystoreContents, KeystoreError> {
+        let bytes = Keystore::read_file(path)?;
+        match serde_cbor::de::from_slice::<KeystoreContents>(&bytes[..]) {
+            Ok(contents) => Ok(contents),
+            Err(e) => Err(KeystoreError::Corrupt(format!("{:?}: {}", path, e))),
+        }
+    }
+
+    fn read_file(path: &Path) -> Result<Vec<u8>, KeystoreError> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(bytes),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(KeystoreError::NotFound(format!("{:?}", path)))
+            }
+            Err(e) => Err(KeystoreError::Io(format!("{:?}: {}", path, e))),
+        }
+    }
+
+    fn write_file(path: &Path, bytes: &[u8]) -> Result<(), KeystoreError> {
+        let temp_path = path.with_extension("tmp");
+        let result = Keystore::open_private_file(&temp_path)
+            .and_then(|mut file| file.write_all(bytes))
+            .and_then(|_| fs::rename(&temp_path, path));
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(KeystoreError::Io(format!("{:?}: {}", path, e)))
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn open_private_file(path: &Path) -> io::Result<File> {
+        use std::os::unix::fs::OpenOptionsExt;
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+    }
+
+    #[cfg(not(unix))]
+    fn open_private_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptde_real::CryptDEReal;
+    use test_utils::test_utils::ensure_node_home_directory_exists;
+
+    fn make_key_pair() -> (Key, Key) {
+        let mut cryptde = CryptDEReal::new();
+        cryptde.generate_key_pair();
+        (cryptde.public_key(), cryptde.private_key())
+    }
+
+    #[test]
+    fn keystore_does_not_exist_until_saved() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "does_not_exist_until_saved");
+        let subject = Keystore::new(&home_dir);
+        assert_eq!(subject.exists(), false);
+        let (public_key, private_key) = make_key_pair();
+
+        subject
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+
+        assert_eq!(subject.exists(), true);
+        assert_eq!(
+            subject.file_path(),
+            home_dir.join(KEYSTORE_FILENAME).as_path()
+        );
+    }
+
+    #[test]
+    fn saved_key_pair_can_be_loaded_with_the_same_passphrase() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "save_and_load");
+        let (public_key, private_key) = make_key_pair();
+        Keystore::new(&home_dir)
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+
+        let result = Keystore::new(&home_dir).load("passphrase");
+
+        assert_eq!(result, Ok((public_key, private_key)));
+    }
+
+    #[test]
+    fn private_key_is_not_stored_in_the_clear() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "not_in_the_clear");
+        let subject = Keystore::new(&home_dir);
+        let (public_key, private_key) = make_key_pair();
+
+        subject
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+
+        let bytes = fs::read(subject.file_path()).unwrap();
+        assert_eq!(
+            bytes
+                .windows(private_key.data.len())
+                .any(|window| window == &private_key.data[..]),
+            false
+        );
+    }
+
+    #[test]
+    fn load_with_wrong_passphrase_fails() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "wrong_passphrase");
+        let subject = Keystore::new(&home_dir);
+        let (public_key, private_key) = make_key_pair();
+        subject
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+
+        let result = subject.load("booga");
+
+        assert_eq!(result, Err(KeystoreError::WrongPassphrase));
+    }
+
+    #[test]
+    fn load_without_keystore_file_fails() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "no_keystore_file");
+        let subject = Keystore::new(&home_dir);
+
+        let result = subject.load("passphrase");
+
+        assert_eq!(
+            result,
+            Err(KeystoreError::NotFound(format!(
+                "{:?}",
+                home_dir.join(KEYSTORE_FILENAME)
+            )))
+        );
+    }
+
+    #[test]
+    fn load_with_corrupt_keystore_file_fails() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "corrupt_keystore_file");
+        let subject = Keystore::new(&home_dir);
+        fs::write(subject.file_path(), b"booga").unwrap();
+
+        let result = subject.load("passphrase");
+
+        match result {
+            Err(KeystoreError::Corrupt(_)) => (),
+            x => panic!("Expected Corrupt, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn exported_key_pair_can_be_imported_elsewhere() {
+        let source_dir = ensure_node_home_directory_exists("keystore", "export_source");
+        let target_dir = ensure_node_home_directory_exists("keystore", "export_target");
+        let export_path = source_dir.join("exported.cbor");
+        let source = Keystore::new(&source_dir);
+        let (public_key, private_key) = make_key_pair();
+        source
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+        source.export(&export_path).unwrap();
+        let target = Keystore::new(&target_dir);
+
+        let result = target.import(&export_path, "passphrase");
+
+        assert_eq!(result, Ok(public_key.clone()));
+        assert_eq!(target.load("passphrase"), Ok((public_key, private_key)));
+    }
+
+    #[test]
+    fn import_with_wrong_passphrase_leaves_keystore_alone() {
+        let source_dir = ensure_node_home_directory_exists("keystore", "bad_import_source");
+        let target_dir = ensure_node_home_directory_exists("keystore", "bad_import_target");
+        let export_path = source_dir.join("exported.cbor");
+        let (public_key, private_key) = make_key_pair();
+        let source = Keystore::new(&source_dir);
+        source
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+        source.export(&export_path).unwrap();
+        let target = Keystore::new(&target_dir);
+
+        let result = target.import(&export_path, "booga");
+
+        assert_eq!(result, Err(KeystoreError::WrongPassphrase));
+        assert_eq!(target.exists(), false);
+    }
+
+    #[test]
+    fn export_without_keystore_file_fails() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "export_nothing");
+        let subject = Keystore::new(&home_dir);
+
+        let result = subject.export(&home_dir.join("exported.cbor"));
+
+        assert_eq!(
+            result,
+            Err(KeystoreError::NotFound(format!(
+                "{:?}",
+                home_dir.join(KEYSTORE_FILENAME)
+            )))
+        );
+    }
+
+    #[test]
+    fn rotate_replaces_key_pair() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "rotate");
+        let subject = Keystore::new(&home_dir);
+        let (old_public_key, old_private_key) = make_key_pair();
+        subject
+            .save(&old_public_key, &old_private_key, "passphrase")
+            .unwrap();
+        let mut cryptde = CryptDEReal::new();
+
+        let result = subject.rotate(&mut cryptde, "passphrase");
+
+        let new_public_key = cryptde.public_key();
+        assert_eq!(result, Ok(new_public_key.clone()));
+        assert_ne!(new_public_key, old_public_key);
+        assert_eq!(
+            subject.load("passphrase"),
+            Ok((new_public_key, cryptde.private_key()))
+        );
+    }
+
+    #[test]
+    fn rotate_with_wrong_passphrase_fails() {
+        let home_dir = ensure_node_home_directory_exists("keystore", "rotate_wrong_passphrase");
+        let subject = Keystore::new(&home_dir);
+        let (public_key, private_key) = make_key_pair();
+        subject
+            .save(&public_key, &private_key, "passphrase")
+            .unwrap();
+        let mut cryptde = CryptDEReal::new();
+
+        let result = subject.rotate(&mut cryptde, "booga");
+
+        assert_eq!(result, Err(KeystoreError::WrongPassphrase));
+        assert_eq!(subject.load("passphrase"), Ok((public_key, private_key)));
+    }
+}
@@ -12,6 +12,7 @@ extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
 extern crate sha1;
+extern crate sodiumoxide;
 extern crate tokio;
 
 #[cfg(test)]
@@ -25,7 +26,9 @@ pub mod channel_wrappers;
 pub mod crash_point;
 pub mod cryptde;
 pub mod cryptde_null;
+pub mod cryptde_real;
 pub mod dispatcher;
+pub mod dns_server;
 pub mod framer;
 pub mod framer_utils;
 pub mod hop;
@@ -33,12 +36,14 @@ pub mod hopper;
 pub mod http_packet_framer;
 pub mod http_response_start_finder;
 pub mod http_server_impersonator;
+pub mod keystore;
 pub mod limiter;
 pub mod logger;
 pub mod main_tools;
 pub mod neighborhood;
 pub mod node_addr;
 pub mod parameter_finder;
+pub mod pass_through_framer;
 pub mod peer_actors;
 pub mod proxy_client;
 pub mod proxy_server;
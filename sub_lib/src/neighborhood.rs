@@ -8,12 +8,24 @@ use hopper::ExpiredCoresPackagePackage;
 use node_addr::NodeAddr;
 use peer_actors::BindMessage;
 use route::Route;
+use std::fmt;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
+use std::time::Duration;
 use stream_handler_pool::DispatcherNodeQueryResponse;
 use stream_handler_pool::TransmitDataMsg;
 
 pub const SENTINEL_IP_OCTETS: [u8; 4] = [255, 255, 255, 255];
+pub const DEFAULT_KEEPALIVE_INTERVAL_SECS: u64 = 30;
+pub const DEFAULT_KEEPALIVE_MAX_MISSED: u32 = 3;
+pub const DEFAULT_NEW_NODES_PER_SOURCE: u32 = 100;
+pub const DEFAULT_NEW_NODE_WINDOW_SECS: u64 = 60;
+pub const DEFAULT_NODES_PER_SUBNET: u32 = 16;
+pub const DEFAULT_MAX_DATABASE_SIZE: usize = 5000;
+pub const DEFAULT_MAX_HOPS_PER_SOURCE: usize = 3;
+pub const DEFAULT_MINIMUM_HOP_COUNT: usize = 2;
 
 pub fn sentinel_ip_addr() -> IpAddr {
     IpAddr::V4(Ipv4Addr::new(
@@ -24,6 +36,29 @@ pub fn sentinel_ip_addr() -> IpAddr {
     ))
 }
 
+// Nodes count as neighbors on the network if their IPv4 addresses share a /24 or their IPv6 addresses share a /64
+pub fn subnet_of(ip_addr: IpAddr) -> IpAddr {
+    match ip_addr {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], 0))
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct NeighborhoodConfig {
     pub neighbor_configs: Vec<(Key, NodeAddr)>,
@@ -31,6 +66,11 @@ pub struct NeighborhoodConfig {
     pub is_bootstrap_node: bool,
     pub local_ip_addr: IpAddr,
     pub clandestine_port_list: Vec<u16>,
+    pub exit_policy: ExitPolicy,
+    pub data_directory_opt: Option<PathBuf>,
+    pub keepalive_config: KeepaliveConfig,
+    pub admission_limits: AdmissionLimits,
+    pub introspection_port_opt: Option<u16>,
 }
 
 impl NeighborhoodConfig {
@@ -41,6 +81,220 @@ impl NeighborhoodConfig {
     }
 }
 
+// How often the Neighborhood checks that each of its neighbors is still there, and how many checks in a row a
+// neighbor may fail to answer before it's removed
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeepaliveConfig {
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+impl KeepaliveConfig {
+    pub fn new() -> KeepaliveConfig {
+        KeepaliveConfig {
+            interval: Duration::from_secs(DEFAULT_KEEPALIVE_INTERVAL_SECS),
+            max_missed: DEFAULT_KEEPALIVE_MAX_MISSED,
+        }
+    }
+}
+
+// How much any one source of Gossip is allowed to shape the Neighborhood: how many new Nodes a single sender may
+// introduce per new_node_window, how many Nodes may share an IPv4 /24 or IPv6 /64, how many Nodes the database may
+// hold before it starts evicting, and how many hops of a route may have been introduced by the same sender
+#[derive(Clone, PartialEq, Debug)]
+pub struct AdmissionLimits {
+    pub new_nodes_per_source: u32,
+    pub new_node_window: Duration,
+    pub nodes_per_subnet: u32,
+    pub max_database_size: usize,
+    pub max_hops_per_source: usize,
+}
+
+impl AdmissionLimits {
+    pub fn new() -> AdmissionLimits {
+        AdmissionLimits {
+            new_nodes_per_source: DEFAULT_NEW_NODES_PER_SOURCE,
+            new_node_window: Duration::from_secs(DEFAULT_NEW_NODE_WINDOW_SECS),
+            nodes_per_subnet: DEFAULT_NODES_PER_SUBNET,
+            max_database_size: DEFAULT_MAX_DATABASE_SIZE,
+            max_hops_per_source: DEFAULT_MAX_HOPS_PER_SOURCE,
+        }
+    }
+}
+
+// What a Node is willing to connect to when it's the exit Node for someone else's stream. An empty list
+// allows anything. Hostname patterns are either exact ("example.com") or cover all subdomains ("*.example.com").
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct ExitPolicy {
+    pub allowed_ports: Vec<u16>,
+    pub allowed_hostnames: Vec<String>,
+}
+
+impl ExitPolicy {
+    pub fn allow_all() -> ExitPolicy {
+        ExitPolicy {
+            allowed_ports: vec![],
+            allowed_hostnames: vec![],
+        }
+    }
+
+    pub fn allows(&self, destination: &ExitDestination) -> bool {
+        let port_allowed =
+            self.allowed_ports.is_empty() || self.allowed_ports.contains(&destination.port);
+        let hostname_allowed = self.allowed_hostnames.is_empty()
+            || match destination.hostname_opt {
+                Some(ref hostname) => self
+                    .allowed_hostnames
+                    .iter()
+                    .any(|pattern| hostname_matches(pattern, hostname)),
+                None => false,
+            };
+        port_allowed && hostname_allowed
+    }
+}
+
+// Hostname patterns are either exact ("example.com") or cover all subdomains ("*.example.com")
+pub fn hostname_matches(pattern: &str, hostname: &str) -> bool {
+    let pattern = pattern.trim_right_matches('.').to_lowercase();
+    let hostname = hostname.trim_right_matches('.').to_lowercase();
+    if pattern.starts_with("*.") {
+        hostname.ends_with(&pattern[1..])
+    } else {
+        hostname == pattern
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ExitDestination {
+    pub hostname_opt: Option<String>,
+    pub port: u16,
+}
+
+// Restrictions on the Nodes a route may pass through
+#[derive(Clone, PartialEq, Debug)]
+pub struct RouteConstraint {
+    // If present, the exit Node's ExitPolicy must allow it
+    pub exit_destination_opt: Option<ExitDestination>,
+    // Nodes that may not appear anywhere in the route
+    pub excluded_keys: Vec<Key>,
+}
+
+impl RouteConstraint {
+    pub fn new() -> RouteConstraint {
+        RouteConstraint {
+            exit_destination_opt: None,
+            excluded_keys: vec![],
+        }
+    }
+
+    pub fn for_exit_to(hostname_opt: Option<String>, port: u16) -> RouteConstraint {
+        RouteConstraint {
+            exit_destination_opt: Some(ExitDestination { hostname_opt, port }),
+            excluded_keys: vec![],
+        }
+    }
+}
+
+// The fewest and the most hops one segment of a route may have; no maximum if maximum_opt is None
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HopRange {
+    pub minimum: usize,
+    pub maximum_opt: Option<usize>,
+}
+
+impl HopRange {
+    pub fn at_least(minimum: usize) -> HopRange {
+        HopRange {
+            minimum,
+            maximum_opt: None,
+        }
+    }
+
+    pub fn allows(&self, hop_count: usize) -> bool {
+        (hop_count >= self.minimum)
+            && match self.maximum_opt {
+                Some(maximum) => hop_count <= maximum,
+                None => true,
+            }
+    }
+}
+
+impl fmt::Display for HopRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.maximum_opt {
+            None => write!(f, "{} or more", self.minimum),
+            Some(maximum) if maximum == self.minimum => write!(f, "exactly {}", maximum),
+            Some(maximum) => write!(f, "{} to {}", self.minimum, maximum),
+        }
+    }
+}
+
+// How long the segment out to the exit Node and the segment back may be. A route with no hops at all never
+// leaves this Node; it's chosen only if both segments allow it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RoutePolicy {
+    pub over: HopRange,
+    pub back: HopRange,
+}
+
+impl RoutePolicy {
+    pub fn at_least(minimum: usize) -> RoutePolicy {
+        RoutePolicy {
+            over: HopRange::at_least(minimum),
+            back: HopRange::at_least(minimum),
+        }
+    }
+
+    pub fn zero_hop() -> RoutePolicy {
+        RoutePolicy::at_least(0)
+    }
+
+    pub fn is_zero_hop(&self) -> bool {
+        (self.over.minimum == 0) && (self.back.minimum == 0)
+    }
+}
+
+// A Node's own RoutePolicy, if it has been given one, plus the policies for destinations whose hostnames match
+// particular patterns. The first pattern that matches wins.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RoutePolicies {
+    pub default_policy_opt: Option<RoutePolicy>,
+    pub hostname_policies: Vec<(String, RoutePolicy)>,
+}
+
+impl RoutePolicies {
+    pub fn new(default_policy: RoutePolicy) -> RoutePolicies {
+        RoutePolicies {
+            default_policy_opt: Some(default_policy),
+            hostname_policies: vec![],
+        }
+    }
+
+    pub fn unconfigured() -> RoutePolicies {
+        RoutePolicies {
+            default_policy_opt: None,
+            hostname_policies: vec![],
+        }
+    }
+
+    pub fn policy_for(&self, hostname_opt: Option<&String>) -> RoutePolicy {
+        self.configured_policy_for(hostname_opt)
+            .unwrap_or(RoutePolicy::at_least(DEFAULT_MINIMUM_HOP_COUNT))
+    }
+
+    // None if no policy has been configured that covers the hostname, so that it gets the standard one
+    pub fn configured_policy_for(&self, hostname_opt: Option<&String>) -> Option<RoutePolicy> {
+        hostname_opt
+            .and_then(|hostname| {
+                self.hostname_policies
+                    .iter()
+                    .find(|(pattern, _)| hostname_matches(pattern, hostname))
+                    .map(|(_, policy)| *policy)
+            })
+            .or(self.default_policy_opt)
+    }
+}
+
 #[derive(Clone)]
 pub struct NeighborhoodSubs {
     pub bind: Recipient<Syn, BindMessage>,
@@ -105,8 +359,9 @@ pub struct RouteQueryMessage {
     pub target_type: TargetType,
     pub target_key_opt: Option<Key>,
     pub target_component: Component,
-    pub minimum_hop_count: usize,
+    pub route_policy: RoutePolicy,
     pub return_component_opt: Option<Component>,
+    pub constraint: RouteConstraint,
 }
 
 impl Message for RouteQueryMessage {
@@ -123,19 +378,24 @@ impl RouteQueryMessage {
             target_type: TargetType::Bootstrap,
             target_key_opt: Some(target_key_ref.clone()),
             target_component: Component::Neighborhood,
-            minimum_hop_count,
+            route_policy: RoutePolicy::at_least(minimum_hop_count),
             return_component_opt: None,
+            constraint: RouteConstraint::new(),
         }
     }
 
-    pub fn data_indefinite_route_request(minimum_hop_count: usize) -> RouteQueryMessage {
+    pub fn data_indefinite_route_request(
+        route_policy: RoutePolicy,
+        constraint: RouteConstraint,
+    ) -> RouteQueryMessage {
         RouteQueryMessage {
             route_type: RouteType::RoundTrip,
             target_type: TargetType::Standard,
             target_key_opt: None,
             target_component: Component::ProxyClient,
-            minimum_hop_count,
+            route_policy,
             return_component_opt: Some(Component::ProxyServer),
+            constraint,
         }
     }
 }
@@ -156,6 +416,18 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
+    #[test]
+    fn subnet_of_masks_ipv4_to_24_bits_and_ipv6_to_64_bits() {
+        assert_eq!(
+            subnet_of(IpAddr::from_str("1.2.3.4").unwrap()),
+            IpAddr::from_str("1.2.3.0").unwrap()
+        );
+        assert_eq!(
+            subnet_of(IpAddr::from_str("2001:db8:1:2:3:4:5:6").unwrap()),
+            IpAddr::from_str("2001:db8:1:2::").unwrap()
+        );
+    }
+
     #[test]
     fn gossip_route_request() {
         let target = Key::new(&b"booga"[..]);
@@ -169,15 +441,19 @@ mod tests {
                 target_type: TargetType::Bootstrap,
                 target_key_opt: Some(target),
                 target_component: Component::Neighborhood,
-                minimum_hop_count: 2,
+                route_policy: RoutePolicy::at_least(2),
                 return_component_opt: None,
+                constraint: RouteConstraint::new(),
             }
         );
     }
 
     #[test]
     fn data_indefinite_route_request() {
-        let result = RouteQueryMessage::data_indefinite_route_request(2);
+        let result = RouteQueryMessage::data_indefinite_route_request(
+            RoutePolicy::at_least(2),
+            RouteConstraint::for_exit_to(Some(String::from("booga.com")), 8080),
+        );
 
         assert_eq!(
             result,
@@ -186,8 +462,15 @@ mod tests {
                 target_type: TargetType::Standard,
                 target_key_opt: None,
                 target_component: Component::ProxyClient,
-                minimum_hop_count: 2,
+                route_policy: RoutePolicy::at_least(2),
                 return_component_opt: Some(Component::ProxyServer),
+                constraint: RouteConstraint {
+                    exit_destination_opt: Some(ExitDestination {
+                        hostname_opt: Some(String::from("booga.com")),
+                        port: 8080,
+                    }),
+                    excluded_keys: vec![],
+                },
             }
         );
     }
@@ -201,6 +484,11 @@ mod tests {
             is_bootstrap_node: false,
             local_ip_addr: IpAddr::from_str("1.2.3.4").unwrap(),
             clandestine_port_list: vec![1234],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: None,
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
         };
 
         let result = subject.is_decentralized();
@@ -219,6 +507,11 @@ mod tests {
             is_bootstrap_node: false,
             local_ip_addr: sentinel_ip_addr(),
             clandestine_port_list: vec![1234],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: None,
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
         };
 
         let result = subject.is_decentralized();
@@ -237,6 +530,11 @@ mod tests {
             is_bootstrap_node: false,
             local_ip_addr: IpAddr::from_str("1.2.3.4").unwrap(),
             clandestine_port_list: vec![],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: None,
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
         };
 
         let result = subject.is_decentralized();
@@ -256,6 +554,11 @@ mod tests {
             is_bootstrap_node: false,
             local_ip_addr: IpAddr::from_str("1.2.3.4").unwrap(),
             clandestine_port_list: vec![1234],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: None,
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
         };
 
         let result = subject.is_decentralized();
@@ -275,10 +578,178 @@ mod tests {
             is_bootstrap_node: false,
             local_ip_addr: IpAddr::from_str("1.2.3.4").unwrap(),
             clandestine_port_list: vec![1234],
+            exit_policy: ExitPolicy::allow_all(),
+            data_directory_opt: None,
+            keepalive_config: KeepaliveConfig::new(),
+            admission_limits: AdmissionLimits::new(),
+            introspection_port_opt: None,
         };
 
         let result = subject.is_decentralized();
 
         assert_eq!(result, true);
     }
+
+    #[test]
+    fn exit_policy_with_empty_lists_allows_anything() {
+        let subject = ExitPolicy::allow_all();
+
+        assert_eq!(subject.allows(&destination(Some("booga.com"), 1234)), true);
+        assert_eq!(subject.allows(&destination(None, 80)), true);
+    }
+
+    #[test]
+    fn exit_policy_restricts_ports() {
+        let subject = ExitPolicy {
+            allowed_ports: vec![80, 443],
+            allowed_hostnames: vec![],
+        };
+
+        assert_eq!(subject.allows(&destination(Some("booga.com"), 443)), true);
+        assert_eq!(subject.allows(&destination(Some("booga.com"), 25)), false);
+    }
+
+    #[test]
+    fn exit_policy_restricts_hostnames_exactly_or_by_subdomain() {
+        let subject = ExitPolicy {
+            allowed_ports: vec![],
+            allowed_hostnames: vec![String::from("booga.com"), String::from("*.wooga.com")],
+        };
+
+        assert_eq!(subject.allows(&destination(Some("BOOGA.com."), 80)), true);
+        assert_eq!(
+            subject.allows(&destination(Some("www.booga.com"), 80)),
+            false
+        );
+        assert_eq!(
+            subject.allows(&destination(Some("www.wooga.com"), 80)),
+            true
+        );
+        assert_eq!(
+            subject.allows(&destination(Some("a.b.wooga.com"), 80)),
+            true
+        );
+        assert_eq!(subject.allows(&destination(Some("wooga.com"), 80)), false);
+        assert_eq!(
+            subject.allows(&destination(Some("notwooga.com"), 80)),
+            false
+        );
+        assert_eq!(subject.allows(&destination(None, 80)), false);
+    }
+
+    #[test]
+    fn exit_policy_requires_both_port_and_hostname_to_be_allowed() {
+        let subject = ExitPolicy {
+            allowed_ports: vec![443],
+            allowed_hostnames: vec![String::from("booga.com")],
+        };
+
+        assert_eq!(subject.allows(&destination(Some("booga.com"), 443)), true);
+        assert_eq!(subject.allows(&destination(Some("booga.com"), 80)), false);
+        assert_eq!(subject.allows(&destination(Some("wooga.com"), 443)), false);
+    }
+
+    #[test]
+    fn hop_range_allows_hop_counts_between_its_limits() {
+        let open_ended = HopRange::at_least(2);
+        let bounded = HopRange {
+            minimum: 2,
+            maximum_opt: Some(4),
+        };
+
+        assert_eq!(open_ended.allows(1), false);
+        assert_eq!(open_ended.allows(20), true);
+        assert_eq!(bounded.allows(1), false);
+        assert_eq!(bounded.allows(2), true);
+        assert_eq!(bounded.allows(4), true);
+        assert_eq!(bounded.allows(5), false);
+    }
+
+    #[test]
+    fn hop_range_describes_itself() {
+        let exact = HopRange {
+            minimum: 3,
+            maximum_opt: Some(3),
+        };
+        let bounded = HopRange {
+            minimum: 2,
+            maximum_opt: Some(4),
+        };
+
+        assert_eq!(format!("{}", HopRange::at_least(2)), "2 or more");
+        assert_eq!(format!("{}", exact), "exactly 3");
+        assert_eq!(format!("{}", bounded), "2 to 4");
+    }
+
+    #[test]
+    fn route_policies_choose_the_first_matching_hostname_pattern_or_the_default() {
+        let strict = RoutePolicy {
+            over: HopRange {
+                minimum: 3,
+                maximum_opt: Some(5),
+            },
+            back: HopRange::at_least(3),
+        };
+        let short = RoutePolicy {
+            over: HopRange {
+                minimum: 1,
+                maximum_opt: Some(1),
+            },
+            back: HopRange::at_least(1),
+        };
+        let subject = RoutePolicies {
+            default_policy_opt: Some(RoutePolicy::at_least(3)),
+            hostname_policies: vec![
+                (String::from("*.bank.com"), strict),
+                (String::from("*.com"), short),
+            ],
+        };
+
+        assert_eq!(
+            subject.policy_for(Some(&String::from("www.Bank.com"))),
+            strict
+        );
+        assert_eq!(subject.policy_for(Some(&String::from("booga.com"))), short);
+        assert_eq!(
+            subject.policy_for(Some(&String::from("booga.org"))),
+            RoutePolicy::at_least(3)
+        );
+        assert_eq!(subject.policy_for(None), RoutePolicy::at_least(3));
+    }
+
+    #[test]
+    fn route_policies_fall_back_to_the_standard_policy_when_none_is_configured() {
+        let short = RoutePolicy {
+            over: HopRange {
+                minimum: 1,
+                maximum_opt: Some(1),
+            },
+            back: HopRange::at_least(1),
+        };
+        let mut subject = RoutePolicies::unconfigured();
+        subject
+            .hostname_policies
+            .push((String::from("*.com"), short));
+
+        assert_eq!(
+            subject.configured_policy_for(Some(&String::from("booga.com"))),
+            Some(short)
+        );
+        assert_eq!(
+            subject.configured_policy_for(Some(&String::from("booga.org"))),
+            None
+        );
+        assert_eq!(subject.configured_policy_for(None), None);
+        assert_eq!(
+            subject.policy_for(Some(&String::from("booga.org"))),
+            RoutePolicy::at_least(DEFAULT_MINIMUM_HOP_COUNT)
+        );
+    }
+
+    fn destination(hostname_opt: Option<&str>, port: u16) -> ExitDestination {
+        ExitDestination {
+            hostname_opt: hostname_opt.map(String::from),
+            port,
+        }
+    }
 }
@@ -0,0 +1,66 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use framer::FramedChunk;
+use framer::Framer;
+
+// For streams whose protocol we don't understand: whatever has arrived so far is a frame.
+pub struct PassThroughFramer {
+    data_so_far: Vec<u8>,
+}
+
+impl Framer for PassThroughFramer {
+    fn add_data(&mut self, data: &[u8]) {
+        self.data_so_far.extend(data);
+    }
+
+    fn take_frame(&mut self) -> Option<FramedChunk> {
+        if self.data_so_far.is_empty() {
+            None
+        } else {
+            Some(FramedChunk {
+                chunk: self.data_so_far.drain(..).collect(),
+                last_chunk: false,
+            })
+        }
+    }
+}
+
+impl PassThroughFramer {
+    pub fn new() -> PassThroughFramer {
+        PassThroughFramer {
+            data_so_far: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_nothing_when_there_is_no_data() {
+        let mut subject = PassThroughFramer::new();
+
+        let result = subject.take_frame();
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn returns_all_data_added_so_far_as_one_frame() {
+        let mut subject = PassThroughFramer::new();
+        subject.add_data(&b"booga"[..]);
+        subject.add_data(&b"wooga"[..]);
+
+        let first_result = subject.take_frame();
+        let second_result = subject.take_frame();
+
+        assert_eq!(
+            first_result,
+            Some(FramedChunk {
+                chunk: b"boogawooga".to_vec(),
+                last_chunk: false,
+            })
+        );
+        assert_eq!(second_result, None);
+    }
+}
@@ -2,10 +2,40 @@
 use actix::Recipient;
 use actix::Syn;
 use hopper::ExpiredCoresPackage;
+use neighborhood::hostname_matches;
+use neighborhood::ExitDestination;
+use neighborhood::ExitPolicy;
 use peer_actors::BindMessage;
 use sequence_buffer::SequencedPacket;
+use std::error;
+use std::fmt;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Duration;
 use stream_key::StreamKey;
 
+pub const DEFAULT_DNS_MIN_TTL_SECS: u64 = 30;
+pub const DEFAULT_DNS_MAX_TTL_SECS: u64 = 3600;
+pub const DEFAULT_DNS_NEGATIVE_TTL_SECS: u64 = 60;
+
+pub const DEFAULT_BLOCKED_PORTS: [u16; 1] = [25];
+
+// Loopback, private, link-local, shared, and unspecified addresses, which an exit Node shouldn't reach for strangers
+pub const DEFAULT_BLOCKED_RANGES: [&str; 11] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "::/128",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ClientResponsePayload {
     pub stream_key: StreamKey,
@@ -31,6 +61,226 @@ impl ClientResponsePayload {
     }
 }
 
+// How long the ProxyClient remembers the answers to its DNS lookups: as long as their TTLs say, but no less than
+// min_ttl and no more than max_ttl. Answers saying there's no such host are remembered for negative_ttl.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DnsCacheConfig {
+    pub min_ttl: Duration,
+    pub max_ttl: Duration,
+    pub negative_ttl: Duration,
+}
+
+impl DnsCacheConfig {
+    pub fn new() -> DnsCacheConfig {
+        DnsCacheConfig {
+            min_ttl: Duration::from_secs(DEFAULT_DNS_MIN_TTL_SECS),
+            max_ttl: Duration::from_secs(DEFAULT_DNS_MAX_TTL_SECS),
+            negative_ttl: Duration::from_secs(DEFAULT_DNS_NEGATIVE_TTL_SECS),
+        }
+    }
+
+    pub fn clamp(&self, ttl: Duration) -> Duration {
+        if ttl < self.min_ttl {
+            self.min_ttl
+        } else if ttl > self.max_ttl {
+            self.max_ttl
+        } else {
+            ttl
+        }
+    }
+}
+
+// A block of IP addresses in CIDR notation ("10.0.0.0/8"). A bare address is a block of one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IpRange {
+    pub network: IpAddr,
+    pub prefix_length: u8,
+}
+
+impl IpRange {
+    // IPv4 addresses mapped into IPv6 ("::ffff:10.0.0.1") are treated as the IPv4 addresses they are
+    pub fn contains(&self, ip_addr: IpAddr) -> bool {
+        match (self.network, normalize(ip_addr)) {
+            (IpAddr::V4(network), IpAddr::V4(ip_addr)) => {
+                let mask = mask_u32(self.prefix_length);
+                (u32::from(network) & mask) == (u32::from(ip_addr) & mask)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip_addr)) => {
+                let mask = mask_u128(self.prefix_length);
+                (u128::from(network) & mask) == (u128::from(ip_addr) & mask)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<IpRange, String> {
+        let mut pieces = input.splitn(2, '/');
+        let network = match IpAddr::from_str(pieces.next().unwrap_or("")) {
+            Ok(network) => network,
+            Err(_) => {
+                return Err(format!(
+                    "IP range should be expressed as '<IP address>/<prefix length>', not '{}'",
+                    input
+                ))
+            }
+        };
+        let max_prefix_length = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match pieces.next() {
+            None => max_prefix_length,
+            Some(prefix_length) => match prefix_length.parse::<u8>() {
+                Ok(prefix_length) if prefix_length <= max_prefix_length => prefix_length,
+                _ => {
+                    return Err(format!(
+                        "IP range prefix length must be between 0 and {}, not '{}'",
+                        max_prefix_length, prefix_length
+                    ))
+                }
+            },
+        };
+        Ok(IpRange {
+            network,
+            prefix_length,
+        })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_length)
+    }
+}
+
+fn normalize(ip_addr: IpAddr) -> IpAddr {
+    match ip_addr {
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            if segments[..5].iter().all(|segment| *segment == 0) && (segments[5] == 0xFFFF) {
+                IpAddr::V4(Ipv4Addr::new(
+                    (segments[6] >> 8) as u8,
+                    segments[6] as u8,
+                    (segments[7] >> 8) as u8,
+                    segments[7] as u8,
+                ))
+            } else {
+                ip_addr
+            }
+        }
+        IpAddr::V4(_) => ip_addr,
+    }
+}
+
+fn mask_u32(prefix_length: u8) -> u32 {
+    match prefix_length {
+        0 => 0,
+        n => !0u32 << (32 - u32::from(n.min(32))),
+    }
+}
+
+fn mask_u128(prefix_length: u8) -> u128 {
+    match prefix_length {
+        0 => 0,
+        n => !0u128 << (128 - u32::from(n.min(128))),
+    }
+}
+
+// Why an exit Node won't connect to a destination on someone else's behalf
+#[derive(Clone, PartialEq, Debug)]
+pub enum ExitRefusal {
+    NotAllowed(String, u16),
+    PortBlocked(u16),
+    HostnameBlocked(String),
+    AddressBlocked(IpAddr),
+}
+
+impl fmt::Display for ExitRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitRefusal::NotAllowed(hostname, port) => {
+                write!(f, "{}:{} is outside its exit policy", hostname, port)
+            }
+            ExitRefusal::PortBlocked(port) => write!(f, "port {} is blocked", port),
+            ExitRefusal::HostnameBlocked(hostname) => write!(f, "{} is blocked", hostname),
+            ExitRefusal::AddressBlocked(ip_addr) => {
+                write!(f, "{} is in a blocked address range", ip_addr)
+            }
+        }
+    }
+}
+
+impl error::Error for ExitRefusal {}
+
+// Everything the ProxyClient checks before it connects to a destination for another Node: the ExitPolicy this Node
+// advertises, plus destinations it refuses outright. Hostnames are checked before they're resolved, and the addresses
+// they resolve to are checked afterward, so that a hostname that resolves to a blocked address doesn't get through.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ExitFilter {
+    pub exit_policy: ExitPolicy,
+    pub blocked_ports: Vec<u16>,
+    pub blocked_hostnames: Vec<String>,
+    pub blocked_ranges: Vec<IpRange>,
+}
+
+impl ExitFilter {
+    pub fn new(exit_policy: ExitPolicy) -> ExitFilter {
+        ExitFilter {
+            exit_policy,
+            blocked_ports: DEFAULT_BLOCKED_PORTS.to_vec(),
+            blocked_hostnames: vec![],
+            blocked_ranges: DEFAULT_BLOCKED_RANGES
+                .iter()
+                .map(|range| IpRange::from_str(range).expect("Bad default blocked range"))
+                .collect(),
+        }
+    }
+
+    pub fn allow_all() -> ExitFilter {
+        ExitFilter {
+            exit_policy: ExitPolicy::allow_all(),
+            blocked_ports: vec![],
+            blocked_hostnames: vec![],
+            blocked_ranges: vec![],
+        }
+    }
+
+    // A hostname that is an IP address is checked against the blocked ranges here as well
+    pub fn check_destination(&self, hostname: &str, port: u16) -> Result<(), ExitRefusal> {
+        if self.blocked_ports.contains(&port) {
+            return Err(ExitRefusal::PortBlocked(port));
+        }
+        if self
+            .blocked_hostnames
+            .iter()
+            .any(|pattern| hostname_matches(pattern, hostname))
+        {
+            return Err(ExitRefusal::HostnameBlocked(String::from(hostname)));
+        }
+        if let Ok(ip_addr) = IpAddr::from_str(hostname) {
+            if !self.allows_ip(ip_addr) {
+                return Err(ExitRefusal::AddressBlocked(ip_addr));
+            }
+        }
+        let destination = ExitDestination {
+            hostname_opt: Some(String::from(hostname)),
+            port,
+        };
+        if !self.exit_policy.allows(&destination) {
+            return Err(ExitRefusal::NotAllowed(String::from(hostname), port));
+        }
+        Ok(())
+    }
+
+    pub fn allows_ip(&self, ip_addr: IpAddr) -> bool {
+        !self
+            .blocked_ranges
+            .iter()
+            .any(|range| range.contains(ip_addr))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +304,164 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn dns_cache_config_clamps_ttls_between_its_minimum_and_maximum() {
+        let subject = DnsCacheConfig {
+            min_ttl: Duration::from_secs(30),
+            max_ttl: Duration::from_secs(300),
+            negative_ttl: Duration::from_secs(60),
+        };
+
+        assert_eq!(
+            subject.clamp(Duration::from_secs(5)),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            subject.clamp(Duration::from_secs(120)),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            subject.clamp(Duration::from_secs(86400)),
+            Duration::from_secs(300)
+        );
+    }
+
+    #[test]
+    fn ip_range_parses_cidr_notation_and_bare_addresses() {
+        assert_eq!(
+            IpRange::from_str("10.0.0.0/8"),
+            Ok(IpRange {
+                network: IpAddr::from_str("10.0.0.0").unwrap(),
+                prefix_length: 8,
+            })
+        );
+        assert_eq!(
+            IpRange::from_str("fe80::"),
+            Ok(IpRange {
+                network: IpAddr::from_str("fe80::").unwrap(),
+                prefix_length: 128,
+            })
+        );
+        assert_eq!(
+            IpRange::from_str("example.com/8"),
+            Err(String::from(
+                "IP range should be expressed as '<IP address>/<prefix length>', not 'example.com/8'"
+            ))
+        );
+        assert_eq!(
+            IpRange::from_str("10.0.0.0/33"),
+            Err(String::from(
+                "IP range prefix length must be between 0 and 32, not '33'"
+            ))
+        );
+        assert_eq!(
+            IpRange::from_str("192.168.0.0/16").unwrap().to_string(),
+            String::from("192.168.0.0/16")
+        );
+    }
+
+    #[test]
+    fn ip_range_contains_addresses_that_share_its_prefix() {
+        let v4 = IpRange::from_str("172.16.0.0/12").unwrap();
+        let v6 = IpRange::from_str("fc00::/7").unwrap();
+        let everything = IpRange::from_str("0.0.0.0/0").unwrap();
+
+        assert_eq!(
+            v4.contains(IpAddr::from_str("172.31.255.255").unwrap()),
+            true
+        );
+        assert_eq!(v4.contains(IpAddr::from_str("172.32.0.0").unwrap()), false);
+        assert_eq!(
+            v4.contains(IpAddr::from_str("::ffff:172.17.0.1").unwrap()),
+            true
+        );
+        assert_eq!(v4.contains(IpAddr::from_str("fc00::1").unwrap()), false);
+        assert_eq!(v6.contains(IpAddr::from_str("fdff::1").unwrap()), true);
+        assert_eq!(v6.contains(IpAddr::from_str("fe00::1").unwrap()), false);
+        assert_eq!(
+            everything.contains(IpAddr::from_str("8.8.8.8").unwrap()),
+            true
+        );
+    }
+
+    #[test]
+    fn default_exit_filter_blocks_smtp_and_private_addresses() {
+        let subject = ExitFilter::new(ExitPolicy::allow_all());
+
+        assert_eq!(subject.check_destination("example.com", 80), Ok(()));
+        assert_eq!(
+            subject.check_destination("example.com", 25),
+            Err(ExitRefusal::PortBlocked(25))
+        );
+        assert_eq!(
+            subject.check_destination("192.168.1.1", 80),
+            Err(ExitRefusal::AddressBlocked(
+                IpAddr::from_str("192.168.1.1").unwrap()
+            ))
+        );
+        vec![
+            "127.0.0.1",
+            "10.1.2.3",
+            "169.254.169.254",
+            "::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ]
+        .into_iter()
+        .for_each(|ip| {
+            assert_eq!(
+                subject.allows_ip(IpAddr::from_str(ip).unwrap()),
+                false,
+                "{}",
+                ip
+            )
+        });
+        vec!["8.8.8.8", "172.32.0.1", "2001:4860:4860::8888"]
+            .into_iter()
+            .for_each(|ip| {
+                assert_eq!(
+                    subject.allows_ip(IpAddr::from_str(ip).unwrap()),
+                    true,
+                    "{}",
+                    ip
+                )
+            });
+    }
+
+    #[test]
+    fn exit_filter_refuses_blocked_hostnames_and_destinations_outside_the_exit_policy() {
+        let mut subject = ExitFilter::new(ExitPolicy {
+            allowed_ports: vec![80, 443],
+            allowed_hostnames: vec![],
+        });
+        subject.blocked_hostnames = vec![String::from("*.internal.example.com")];
+
+        assert_eq!(
+            subject.check_destination("db.internal.example.com.", 443),
+            Err(ExitRefusal::HostnameBlocked(String::from(
+                "db.internal.example.com."
+            )))
+        );
+        assert_eq!(
+            subject.check_destination("example.com", 8080),
+            Err(ExitRefusal::NotAllowed(String::from("example.com"), 8080))
+        );
+        assert_eq!(subject.check_destination("example.com", 443), Ok(()));
+        assert_eq!(
+            ExitRefusal::NotAllowed(String::from("example.com"), 8080).to_string(),
+            String::from("example.com:8080 is outside its exit policy")
+        );
+    }
+
+    #[test]
+    fn allow_all_exit_filter_refuses_nothing() {
+        let subject = ExitFilter::allow_all();
+
+        assert_eq!(subject.check_destination("127.0.0.1", 25), Ok(()));
+        assert_eq!(
+            subject.allows_ip(IpAddr::from_str("10.0.0.1").unwrap()),
+            true
+        );
+    }
 }
@@ -12,8 +12,16 @@ use stream_key::StreamKey;
 pub enum ProxyProtocol {
     HTTP,
     TLS,
+    // The stream was opened with a SOCKS5 CONNECT; its data is passed along without interpretation
+    SOCKS5,
+    // The stream was opened with an HTTP CONNECT; its data is passed along without interpretation
+    TUNNEL,
 }
 
+// The ports on which the Node accepts clear data, and the protocol it expects on each
+pub const DEFAULT_PROXIED_PORTS: &[(u16, ProxyProtocol)] =
+    &[(80, ProxyProtocol::HTTP), (443, ProxyProtocol::TLS)];
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ClientRequestPayload {
     pub stream_key: StreamKey,
@@ -1,15 +1,20 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use futures::future::ok;
 use logger::Logger;
+use std::collections::VecDeque;
 use std::io::ErrorKind;
+use std::mem;
 use std::net::IpAddr;
 use std::net::SocketAddr;
-use std::net::TcpStream as StdTcpStream;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::io;
 use tokio::io::AsyncRead;
 use tokio::net::TcpStream;
+use tokio::prelude::Async;
 use tokio::prelude::Future;
-use tokio::reactor::Handle;
+use tokio::prelude::Poll;
+use tokio::timer::Delay;
 use tokio_wrappers::ReadHalfWrapper;
 use tokio_wrappers::ReadHalfWrapperReal;
 use tokio_wrappers::WriteHalfWrapper;
@@ -17,6 +22,27 @@ use tokio_wrappers::WriteHalfWrapperReal;
 
 pub type ConnectionInfoFuture = Box<Future<Item = ConnectionInfo, Error = io::Error> + Send>;
 
+// RFC 8305's recommendation for how long to give one address before racing the next one against it
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+pub const DEFAULT_CONNECTION_ATTEMPT_TIMEOUT_MS: u64 = 10000;
+
+// How connect_one staggers its attempts: a new one starts whenever the last one fails or has gone attempt_delay
+// without succeeding, and each one gives up after attempt_timeout.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ConnectionAttemptConfig {
+    pub attempt_delay: Duration,
+    pub attempt_timeout: Duration,
+}
+
+impl ConnectionAttemptConfig {
+    pub fn new() -> ConnectionAttemptConfig {
+        ConnectionAttemptConfig {
+            attempt_delay: Duration::from_millis(DEFAULT_CONNECTION_ATTEMPT_DELAY_MS),
+            attempt_timeout: Duration::from_millis(DEFAULT_CONNECTION_ATTEMPT_TIMEOUT_MS),
+        }
+    }
+}
+
 pub struct ConnectionInfo {
     pub reader: Box<ReadHalfWrapper>,
     pub writer: Box<WriteHalfWrapper>,
@@ -31,8 +57,9 @@ pub trait StreamConnector {
         ip_addrs: Vec<IpAddr>,
         target_hostname: &String,
         target_port: u16,
+        config: ConnectionAttemptConfig,
         logger: &Logger,
-    ) -> Result<ConnectionInfo, io::Error>;
+    ) -> ConnectionInfoFuture;
     fn split_stream(&self, stream: TcpStream, logger: &Logger) -> ConnectionInfo;
     fn split_stream_fut(&self, stream: TcpStream, logger: &Logger) -> ConnectionInfoFuture;
 }
@@ -42,64 +69,33 @@ pub struct StreamConnectorReal {}
 impl StreamConnector for StreamConnectorReal {
     fn connect(&self, socket_addr: SocketAddr, logger: &Logger) -> ConnectionInfoFuture {
         let future_logger = logger.clone();
-        Box::new(
-            TcpStream::connect(&socket_addr).then(move |result| match result {
-                Ok(stream) => {
-                    let local_addr = stream
-                        .local_addr()
-                        .expect("Connected stream has no local_addr");
-                    let peer_addr = stream
-                        .peer_addr()
-                        .expect("Connected stream has no peer_addr");
-                    let (read_half, write_half) = stream.split();
-                    Ok(ConnectionInfo {
-                        reader: Box::new(ReadHalfWrapperReal::new(read_half)),
-                        writer: Box::new(WriteHalfWrapperReal::new(write_half)),
-                        local_addr,
-                        peer_addr,
-                    })
-                }
-                Err(e) => {
-                    future_logger.error(format!("Could not connect TCP stream to {}", socket_addr));
-                    Err(e)
-                }
-            }),
-        )
+        Box::new(connect_stream(socket_addr).map_err(move |e| {
+            future_logger.error(format!("Could not connect TCP stream to {}", socket_addr));
+            e
+        }))
     }
 
+    // Happy Eyeballs (RFC 8305): the addresses are tried alternating between IPv6 and IPv4, and the first
+    // connection made wins. Whatever the others manage afterward is dropped.
     fn connect_one(
         &self,
         ip_addrs: Vec<IpAddr>,
         target_hostname: &String,
         target_port: u16,
+        config: ConnectionAttemptConfig,
         logger: &Logger,
-    ) -> Result<ConnectionInfo, io::Error> {
-        let mut last_error = io::Error::from(ErrorKind::Other);
-        let mut socket_addrs_tried = vec![];
-
-        for ip_addr in ip_addrs {
-            let socket_addr = SocketAddr::new(ip_addr, target_port);
-
-            match StdTcpStream::connect(&socket_addr) {
-                Ok(stream) => {
-                    logger.debug(format!("Connected new stream to {}", socket_addr));
-                    let tokio_stream = TcpStream::from_std(stream, &Handle::default())
-                        .expect("Tokio could not create a TcpStream");
-                    return Ok(self.split_stream(tokio_stream, logger));
-                }
-                Err(e) => {
-                    last_error = e;
-                    socket_addrs_tried.push(format!("{}", socket_addr));
-                    continue;
-                }
-            };
-        }
-
-        logger.error(format!(
-            "Could not connect to any of the IP addresses supplied for {}: {:?}",
-            target_hostname, socket_addrs_tried
-        ));
-        Err(last_error)
+    ) -> ConnectionInfoFuture {
+        let socket_addrs = interleave_address_families(ip_addrs)
+            .into_iter()
+            .map(|ip_addr| SocketAddr::new(ip_addr, target_port))
+            .collect();
+        Box::new(ConnectionRace::new(
+            socket_addrs,
+            Box::new(connect_stream),
+            target_hostname,
+            config,
+            logger,
+        ))
     }
 
     fn split_stream(&self, stream: TcpStream, _logger: &Logger) -> ConnectionInfo {
@@ -125,10 +121,191 @@ impl StreamConnector for StreamConnectorReal {
     }
 }
 
+fn connect_stream(socket_addr: SocketAddr) -> ConnectionInfoFuture {
+    Box::new(TcpStream::connect(&socket_addr).map(|stream| {
+        let local_addr = stream
+            .local_addr()
+            .expect("Connected stream has no local_addr");
+        let peer_addr = stream
+            .peer_addr()
+            .expect("Connected stream has no peer_addr");
+        let (read_half, write_half) = stream.split();
+        ConnectionInfo {
+            reader: Box::new(ReadHalfWrapperReal::new(read_half)),
+            writer: Box::new(WriteHalfWrapperReal::new(write_half)),
+            local_addr,
+            peer_addr,
+        }
+    }))
+}
+
+type AttemptFuture<T> = Box<Future<Item = T, Error = io::Error> + Send>;
+
+// Drives the attempts for connect_one on whatever task polls it. It's generic over what a connection is only so
+// that it can be tested without a network.
+struct ConnectionRace<T> {
+    connect: Box<FnMut(SocketAddr) -> AttemptFuture<T> + Send>,
+    socket_addrs: VecDeque<SocketAddr>,
+    attempts: Vec<ConnectionAttempt<T>>,
+    // None once an attempt has failed, since then the next one needn't wait
+    next_attempt_delay_opt: Option<Delay>,
+    target_hostname: String,
+    config: ConnectionAttemptConfig,
+    started: Instant,
+    last_error: io::Error,
+    socket_addrs_tried: Vec<String>,
+    logger: Logger,
+}
+
+struct ConnectionAttempt<T> {
+    socket_addr: SocketAddr,
+    connection: AttemptFuture<T>,
+    deadline: Delay,
+}
+
+impl<T> Future for ConnectionRace<T> {
+    type Item = T;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<T, io::Error> {
+        loop {
+            if self.attempts.is_empty() || self.next_attempt_is_due()? {
+                if let Some(socket_addr) = self.socket_addrs.pop_front() {
+                    self.start_attempt(socket_addr);
+                    continue;
+                }
+            }
+            if self.attempts.is_empty() {
+                self.logger.error(format!(
+                    "Could not connect to any of the IP addresses supplied for {}: {:?}",
+                    self.target_hostname, self.socket_addrs_tried
+                ));
+                return Err(mem::replace(
+                    &mut self.last_error,
+                    io::Error::from(ErrorKind::Other),
+                ));
+            }
+            let mut any_failed = false;
+            let mut index = 0;
+            while index < self.attempts.len() {
+                match self.attempts[index].poll() {
+                    Ok(Async::Ready(connection)) => {
+                        self.logger.info(format!(
+                            "Connected to {} for {} after {}ms",
+                            self.attempts[index].socket_addr,
+                            self.target_hostname,
+                            millis(self.started.elapsed())
+                        ));
+                        return Ok(Async::Ready(connection));
+                    }
+                    Ok(Async::NotReady) => index += 1,
+                    Err(e) => {
+                        let attempt = self.attempts.remove(index);
+                        self.logger.warning(format!(
+                            "Could not connect to {} for {} after {}ms: {}",
+                            attempt.socket_addr,
+                            self.target_hostname,
+                            millis(self.started.elapsed()),
+                            e
+                        ));
+                        self.socket_addrs_tried
+                            .push(format!("{}", attempt.socket_addr));
+                        self.last_error = e;
+                        any_failed = true;
+                    }
+                }
+            }
+            if !any_failed {
+                return Ok(Async::NotReady);
+            }
+            self.next_attempt_delay_opt = None;
+        }
+    }
+}
+
+impl<T> ConnectionRace<T> {
+    fn new(
+        socket_addrs: VecDeque<SocketAddr>,
+        connect: Box<FnMut(SocketAddr) -> AttemptFuture<T> + Send>,
+        target_hostname: &String,
+        config: ConnectionAttemptConfig,
+        logger: &Logger,
+    ) -> ConnectionRace<T> {
+        ConnectionRace {
+            connect,
+            socket_addrs,
+            attempts: vec![],
+            next_attempt_delay_opt: None,
+            target_hostname: target_hostname.clone(),
+            config,
+            started: Instant::now(),
+            last_error: io::Error::from(ErrorKind::Other),
+            socket_addrs_tried: vec![],
+            logger: logger.clone(),
+        }
+    }
+
+    fn start_attempt(&mut self, socket_addr: SocketAddr) {
+        let now = Instant::now();
+        self.attempts.push(ConnectionAttempt {
+            socket_addr,
+            connection: (self.connect)(socket_addr),
+            deadline: Delay::new(now + self.config.attempt_timeout),
+        });
+        self.next_attempt_delay_opt = Some(Delay::new(now + self.config.attempt_delay));
+    }
+
+    fn next_attempt_is_due(&mut self) -> Result<bool, io::Error> {
+        match self.next_attempt_delay_opt {
+            None => Ok(true),
+            Some(ref mut delay) => match delay.poll() {
+                Ok(Async::Ready(())) => Ok(true),
+                Ok(Async::NotReady) => Ok(false),
+                Err(e) => Err(io::Error::new(ErrorKind::Other, e)),
+            },
+        }
+    }
+}
+
+impl<T> ConnectionAttempt<T> {
+    fn poll(&mut self) -> Poll<T, io::Error> {
+        if let Async::Ready(connection) = self.connection.poll()? {
+            return Ok(Async::Ready(connection));
+        }
+        match self.deadline.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "connection attempt timed out",
+            )),
+            Err(e) => Err(io::Error::new(ErrorKind::Other, e)),
+        }
+    }
+}
+
+// IPv6 first, then alternating families, keeping the resolver's order within each family
+fn interleave_address_families(ip_addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let (mut ipv6_addrs, mut ipv4_addrs): (Vec<IpAddr>, Vec<IpAddr>) =
+        ip_addrs.into_iter().partition(|ip_addr| ip_addr.is_ipv6());
+    let mut result = vec![];
+    ipv6_addrs.reverse();
+    ipv4_addrs.reverse();
+    while !ipv6_addrs.is_empty() || !ipv4_addrs.is_empty() {
+        result.extend(ipv6_addrs.pop());
+        result.extend(ipv4_addrs.pop());
+    }
+    result
+}
+
+fn millis(duration: Duration) -> u64 {
+    (duration.as_secs() * 1000) + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::future::lazy;
+    use futures::future::empty;
+    use futures::future::err;
     use futures::future::ok;
     use std::io::Read;
     use std::io::Write;
@@ -150,6 +327,7 @@ mod tests {
     use tokio::io::read_exact;
     use tokio::io::write_all;
     use tokio::io::ErrorKind;
+    use tokio::runtime::current_thread::Runtime;
 
     #[test]
     fn stream_connector_can_fail_to_connect() {
@@ -225,16 +403,18 @@ mod tests {
         let ip_addrs = vec![bogus_ip, good_ip];
 
         let (tx, rx) = mpsc::channel();
-        let test_future = lazy(move || {
-            let connection_result = subject.connect_one(
+        let test_future = subject
+            .connect_one(
                 ip_addrs,
                 &"some hostname".to_string(),
                 socket_addr.port(),
+                ConnectionAttemptConfig::new(),
                 &logger,
-            );
-            tx.send(connection_result).unwrap();
-            Ok(())
-        });
+            )
+            .then(move |connection_result| {
+                tx.send(connection_result).unwrap();
+                Ok(())
+            });
 
         thread::spawn(move || {
             tokio::run(test_future);
@@ -246,6 +426,15 @@ mod tests {
         let connection_info = connection_result.unwrap();
         assert_eq!(connection_info.peer_addr, socket_addr);
         assert_eq!(connection_info.local_addr.ip(), socket_addr.ip());
+        let tlh = TestLogHandler::new();
+        tlh.exists_log_containing(&format!(
+            "WARN: test: Could not connect to 255.255.255.255:{} for some hostname after",
+            socket_addr.port()
+        ));
+        tlh.exists_log_containing(&format!(
+            "INFO: test: Connected to {} for some hostname after",
+            socket_addr
+        ));
     }
 
     #[test]
@@ -261,16 +450,18 @@ mod tests {
         let ip_addrs = vec![ip_addr, ip_addr];
 
         let (connection_info_tx, connection_info_rx) = mpsc::channel();
-        let test_future = lazy(move || {
-            let connection_result = subject.connect_one(
+        let test_future = subject
+            .connect_one(
                 ip_addrs,
                 &"some hostname".to_string(),
                 socket_addr.port(),
+                ConnectionAttemptConfig::new(),
                 &logger,
-            );
-            connection_info_tx.send(connection_result).unwrap();
-            Ok(())
-        });
+            )
+            .then(move |connection_result| {
+                connection_info_tx.send(connection_result).unwrap();
+                Ok(())
+            });
 
         thread::spawn(move || {
             tokio::run(test_future);
@@ -297,12 +488,18 @@ mod tests {
         let ip_addrs = vec![bogus_ip];
 
         let (tx, rx) = mpsc::channel();
-        let test_future = lazy(move || {
-            let connection_result =
-                subject.connect_one(ip_addrs, &"some hostname".to_string(), 9876, &logger);
-            tx.send(connection_result).unwrap();
-            Ok(())
-        });
+        let test_future = subject
+            .connect_one(
+                ip_addrs,
+                &"some hostname".to_string(),
+                9876,
+                ConnectionAttemptConfig::new(),
+                &logger,
+            )
+            .then(move |connection_result| {
+                tx.send(connection_result).unwrap();
+                Ok(())
+            });
 
         thread::spawn(move || {
             tokio::run(test_future);
@@ -314,6 +511,145 @@ mod tests {
         TestLogHandler::new().exists_log_matching("Could not connect to any of the IP addresses supplied for some hostname: \\[\"255\\.255\\.255\\.255:\\d+\"\\]");
     }
 
+    #[test]
+    fn connection_race_does_not_wait_for_a_slow_address_before_trying_the_next() {
+        let slow_addr = SocketAddr::from_str("1.2.3.4:80").unwrap();
+        let fast_addr = SocketAddr::from_str("2.3.4.5:80").unwrap();
+        let config = ConnectionAttemptConfig {
+            attempt_delay: Duration::from_millis(10),
+            attempt_timeout: Duration::from_secs(3600),
+        };
+        let subject = ConnectionRace::new(
+            vec![slow_addr, fast_addr].into_iter().collect(),
+            Box::new(move |socket_addr| -> AttemptFuture<SocketAddr> {
+                if socket_addr == slow_addr {
+                    Box::new(empty())
+                } else {
+                    Box::new(ok(socket_addr))
+                }
+            }),
+            &"some hostname".to_string(),
+            config,
+            &Logger::new("test"),
+        );
+
+        let result = run_race(subject);
+
+        assert_eq!(result.unwrap(), fast_addr);
+    }
+
+    #[test]
+    fn connection_race_starts_the_next_attempt_as_soon_as_one_fails() {
+        init_test_logging();
+        let refused_addr = SocketAddr::from_str("1.2.3.4:80").unwrap();
+        let good_addr = SocketAddr::from_str("2.3.4.5:80").unwrap();
+        let config = ConnectionAttemptConfig {
+            attempt_delay: Duration::from_secs(3600),
+            attempt_timeout: Duration::from_secs(3600),
+        };
+        let subject = ConnectionRace::new(
+            vec![refused_addr, good_addr].into_iter().collect(),
+            Box::new(move |socket_addr| -> AttemptFuture<SocketAddr> {
+                if socket_addr == refused_addr {
+                    Box::new(err(io::Error::from(ErrorKind::ConnectionRefused)))
+                } else {
+                    Box::new(ok(socket_addr))
+                }
+            }),
+            &"refusing hostname".to_string(),
+            config,
+            &Logger::new("test"),
+        );
+
+        let result = run_race(subject);
+
+        assert_eq!(result.unwrap(), good_addr);
+        let tlh = TestLogHandler::new();
+        tlh.exists_log_containing(
+            "WARN: test: Could not connect to 1.2.3.4:80 for refusing hostname after",
+        );
+        tlh.exists_log_containing(
+            "INFO: test: Connected to 2.3.4.5:80 for refusing hostname after",
+        );
+    }
+
+    #[test]
+    fn connection_race_makes_no_more_attempts_once_one_succeeds() {
+        let started_arc = Arc::new(Mutex::new(vec![]));
+        let inner_started_arc = started_arc.clone();
+        let first_addr = SocketAddr::from_str("1.2.3.4:80").unwrap();
+        let second_addr = SocketAddr::from_str("2.3.4.5:80").unwrap();
+        let subject = ConnectionRace::new(
+            vec![first_addr, second_addr].into_iter().collect(),
+            Box::new(move |socket_addr| -> AttemptFuture<SocketAddr> {
+                inner_started_arc.lock().unwrap().push(socket_addr);
+                Box::new(ok(socket_addr))
+            }),
+            &"some hostname".to_string(),
+            ConnectionAttemptConfig::new(),
+            &Logger::new("test"),
+        );
+
+        let result = run_race(subject);
+
+        assert_eq!(result.unwrap(), first_addr);
+        assert_eq!(*started_arc.lock().unwrap(), vec![first_addr]);
+    }
+
+    #[test]
+    fn connection_race_gives_up_on_attempts_that_time_out() {
+        init_test_logging();
+        let config = ConnectionAttemptConfig {
+            attempt_delay: Duration::from_secs(3600),
+            attempt_timeout: Duration::from_millis(10),
+        };
+        let subject = ConnectionRace::new(
+            vec![SocketAddr::from_str("1.2.3.4:80").unwrap()]
+                .into_iter()
+                .collect(),
+            Box::new(|_| -> AttemptFuture<SocketAddr> { Box::new(empty()) }),
+            &"silent hostname".to_string(),
+            config,
+            &Logger::new("test"),
+        );
+
+        let result = run_race(subject);
+
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::TimedOut);
+        TestLogHandler::new().exists_log_containing(
+            "ERROR: test: Could not connect to any of the IP addresses supplied for silent hostname: [\"1.2.3.4:80\"]",
+        );
+    }
+
+    #[test]
+    fn address_families_are_interleaved_starting_with_ipv6() {
+        let ip_addrs = vec!["1.1.1.1", "2.2.2.2", "3.3.3.3", "::1", "::2"]
+            .into_iter()
+            .map(|s| IpAddr::from_str(s).unwrap())
+            .collect();
+
+        let result = interleave_address_families(ip_addrs);
+
+        assert_eq!(
+            result,
+            vec!["::1", "1.1.1.1", "::2", "2.2.2.2", "3.3.3.3"]
+                .into_iter()
+                .map(|s| IpAddr::from_str(s).unwrap())
+                .collect::<Vec<IpAddr>>()
+        );
+    }
+
+    // Races need a timer, so they have to run on a tokio runtime
+    fn run_race(race: ConnectionRace<SocketAddr>) -> Result<SocketAddr, io::Error> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = Runtime::new().unwrap().block_on(race);
+            tx.send(result).unwrap();
+        });
+        rx.recv_timeout(Duration::from_secs(10))
+            .expect("Connection race never finished")
+    }
+
     struct FutureAsserter<I: 'static, E: 'static> {
         future: Box<Future<Item = I, Error = E> + Send>,
     }
@@ -7,6 +7,7 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
 use sub_lib::logger::Logger;
+use sub_lib::stream_connector::ConnectionAttemptConfig;
 use sub_lib::stream_connector::ConnectionInfo;
 use sub_lib::stream_connector::ConnectionInfoFuture;
 use sub_lib::stream_connector::StreamConnector;
@@ -32,9 +33,10 @@ impl StreamConnector for StreamConnectorMock {
         _ip_addrs: Vec<IpAddr>,
         _target_hostname: &String,
         _target_port: u16,
+        _config: ConnectionAttemptConfig,
         _logger: &Logger,
-    ) -> Result<ConnectionInfo, io::Error> {
-        self.connect_pair_results.borrow_mut().remove(0)
+    ) -> ConnectionInfoFuture {
+        Box::new(result(self.connect_pair_results.borrow_mut().remove(0)))
     }
 
     fn split_stream(&self, _stream: TcpStream, _logger: &Logger) -> ConnectionInfo {
@@ -1,7 +1,9 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use regex::Regex;
 use std::cmp::min;
+use std::env::temp_dir;
 use std::fmt::Debug;
+use std::fs;
 use std::io;
 use std::io::Error;
 use std::io::Read;
@@ -10,6 +12,7 @@ use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::net::UdpSocket;
+use std::path::PathBuf;
 use std::str::from_utf8;
 use std::str::FromStr;
 use std::sync::mpsc;
@@ -298,6 +301,16 @@ where
     }
 }
 
+pub fn ensure_node_home_directory_exists(module: &str, name: &str) -> PathBuf {
+    let home_dir = temp_dir()
+        .join("SubstratumNode_test")
+        .join(module)
+        .join(name);
+    let _ = fs::remove_dir_all(&home_dir);
+    fs::create_dir_all(&home_dir).expect("Could not create test home directory");
+    home_dir
+}
+
 pub fn assert_contains<T>(haystack: &Vec<T>, needle: &T)
 where
     T: Debug + PartialEq,
@@ -3,12 +3,15 @@
 use std::io;
 use std::io::Read;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
 use sub_lib::tokio_wrappers::ReadHalfWrapper;
+use sub_lib::tokio_wrappers::TokioListenerWrapper;
 use sub_lib::tokio_wrappers::WriteHalfWrapper;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
 use tokio::prelude::Async;
 
 pub struct ReadHalfWrapperMock {
@@ -128,3 +131,47 @@ impl WriteHalfWrapperMock {
         self.shutdown_result(Ok(Async::Ready(())))
     }
 }
+
+pub struct TokioListenerWrapperMock {
+    pub bind_params: Arc<Mutex<Vec<SocketAddr>>>,
+    pub poll_accept_results: Vec<Result<Async<(TcpStream, SocketAddr)>, io::Error>>,
+}
+
+impl TokioListenerWrapper for TokioListenerWrapperMock {
+    fn bind(&mut self, addr: SocketAddr) -> io::Result<()> {
+        self.bind_params.lock().unwrap().push(addr);
+        Ok(())
+    }
+
+    fn poll_accept(&mut self) -> Result<Async<(TcpStream, SocketAddr)>, io::Error> {
+        if self.poll_accept_results.is_empty() {
+            panic!("TokioListenerWrapperMock: poll_accept_results is empty")
+        }
+        self.poll_accept_results.remove(0)
+    }
+}
+
+impl TokioListenerWrapperMock {
+    pub fn new() -> TokioListenerWrapperMock {
+        TokioListenerWrapperMock {
+            bind_params: Arc::new(Mutex::new(vec![])),
+            poll_accept_results: vec![],
+        }
+    }
+
+    pub fn bind_params(
+        mut self,
+        params_arc: &Arc<Mutex<Vec<SocketAddr>>>,
+    ) -> TokioListenerWrapperMock {
+        self.bind_params = params_arc.clone();
+        self
+    }
+
+    pub fn poll_accept_result(
+        mut self,
+        result: Result<Async<(TcpStream, SocketAddr)>, io::Error>,
+    ) -> TokioListenerWrapperMock {
+        self.poll_accept_results.push(result);
+        self
+    }
+}